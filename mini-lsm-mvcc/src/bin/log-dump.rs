@@ -0,0 +1,93 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::path::PathBuf;
+
+use anyhow::Result;
+use clap::{Parser, Subcommand};
+use mini_lsm_mvcc::tools::Encoding;
+use mini_lsm_mvcc::tools::manifest_dump::{CompactionStrategy, ManifestDumpOptions, manifest_dump};
+use mini_lsm_mvcc::tools::wal_dump::{WalDumpOptions, wal_dump};
+
+/// Inspect the manifest or a WAL.
+#[derive(Parser, Debug)]
+struct Args {
+    #[command(subcommand)]
+    command: Command,
+    /// Print the report as JSON.
+    #[arg(long, global = true)]
+    json: bool,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Decode the manifest record by record.
+    Manifest {
+        /// Path of the manifest.
+        path: PathBuf,
+        /// Replay the records to show the shape of the LSM tree after each of them.
+        #[arg(long)]
+        replay: bool,
+        /// The compaction strategy, inferred from the compaction records if not given.
+        #[arg(long, value_enum)]
+        compaction: Option<CompactionStrategy>,
+    },
+    /// Decode a WAL batch by batch.
+    Wal {
+        /// Path of the WAL.
+        path: PathBuf,
+        /// Dump at most this many batches.
+        #[arg(long)]
+        limit: Option<usize>,
+        /// How keys and values are printed.
+        #[arg(long, value_enum, default_value = "utf8")]
+        encoding: Encoding,
+    },
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+    let corrupted = match args.command {
+        Command::Manifest {
+            path,
+            replay,
+            compaction,
+        } => {
+            let report = manifest_dump(&path, &ManifestDumpOptions { replay, compaction })?;
+            if args.json {
+                println!("{}", serde_json::to_string_pretty(&report)?);
+            } else {
+                print!("{}", report);
+            }
+            report.corruption.is_some()
+        }
+        Command::Wal {
+            path,
+            limit,
+            encoding,
+        } => {
+            let report = wal_dump(&path, &WalDumpOptions { limit, encoding })?;
+            if args.json {
+                println!("{}", serde_json::to_string_pretty(&report)?);
+            } else {
+                print!("{}", report);
+            }
+            report.corruption.is_some()
+        }
+    };
+    if corrupted {
+        std::process::exit(1);
+    }
+    Ok(())
+}
@@ -0,0 +1,70 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::path::PathBuf;
+
+use anyhow::Result;
+use clap::Parser;
+use mini_lsm_mvcc::tools::Encoding;
+use mini_lsm_mvcc::tools::sst_dump::{SstDumpOptions, sst_dump};
+
+/// Inspect an SST file.
+#[derive(Parser, Debug)]
+struct Args {
+    /// Path of the SST file.
+    path: PathBuf,
+    /// Read every block and verify its checksum.
+    #[arg(long)]
+    verify: bool,
+    /// List the data blocks with their position and key range.
+    #[arg(long)]
+    index: bool,
+    /// Dump the entries of the data blocks.
+    #[arg(long)]
+    entries: bool,
+    /// Report the size of the filter and measure its false positive rate.
+    #[arg(long)]
+    filter: bool,
+    /// Dump at most this many entries.
+    #[arg(long)]
+    limit: Option<usize>,
+    /// How keys and values are printed.
+    #[arg(long, value_enum, default_value = "utf8")]
+    encoding: Encoding,
+    /// Print the report as JSON.
+    #[arg(long)]
+    json: bool,
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+    let options = SstDumpOptions {
+        verify: args.verify,
+        index: args.index,
+        entries: args.entries,
+        filter: args.filter,
+        limit: args.limit,
+        encoding: args.encoding,
+    };
+    let report = sst_dump(&args.path, &options)?;
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        print!("{}", report);
+    }
+    if report.verify.is_some_and(|x| !x.errors.is_empty()) {
+        std::process::exit(1);
+    }
+    Ok(())
+}
@@ -15,43 +15,172 @@
 mod builder;
 mod iterator;
 
+use anyhow::{Result, bail};
 pub use builder::BlockBuilder;
 use bytes::{Buf, BufMut, Bytes};
 pub use iterator::BlockIterator;
 
+use crate::key::KeySlice;
+
 pub(crate) const SIZEOF_U16: usize = std::mem::size_of::<u16>();
+pub(crate) const SIZEOF_U32: usize = std::mem::size_of::<u32>();
+
+/// Default number of entries between two restart points. Keys are prefix-compressed against the
+/// previous key, and the compression is reset (the full key and timestamp are stored) at each
+/// restart point.
+pub(crate) const RESTART_INTERVAL: usize = 16;
+
+/// Set in the number of restart points if the block has a hash index.
+const HASH_INDEX_FLAG: u32 = 1 << 31;
+
+/// Set in the number of restart points if the block is built with a restart interval other than
+/// `RESTART_INTERVAL`, which is then stored before it.
+const RESTART_INTERVAL_FLAG: u32 = 1 << 30;
+
+/// A hash index bucket that no key maps to.
+pub(crate) const HASH_BUCKET_EMPTY: u16 = u16::MAX;
+
+/// A hash index bucket that keys of different restart intervals map to.
+pub(crate) const HASH_BUCKET_COLLISION: u16 = u16::MAX - 1;
+
+/// Number of keys per hash index bucket.
+pub(crate) const HASH_INDEX_UTIL_RATIO: f64 = 0.75;
 
 /// A block is the smallest unit of read and caching in LSM tree. It is a collection of sorted
 /// key-value pairs.
 pub struct Block {
-    pub(crate) data: Vec<u8>,
-    pub(crate) offsets: Vec<u16>,
+    pub(crate) data: Bytes,
+    /// Offsets of the restart points, i.e., the entries that do not share a prefix with the
+    /// previous entry.
+    pub(crate) offsets: Vec<u32>,
+    /// Buckets of the optional hash index, which map the hash of a key (without timestamp) to the
+    /// restart point of its newest version. Empty if the block has no hash index.
+    pub(crate) hash_index: Vec<u16>,
+    /// Number of entries between two restart points.
+    pub(crate) restart_interval: usize,
 }
 
 impl Block {
     pub fn encode(&self) -> Bytes {
-        let mut buf = self.data.clone();
-        let offsets_len = self.offsets.len();
+        let mut buf = self.data.to_vec();
+        let mut offsets_len = self.offsets.len() as u32;
         for offset in &self.offsets {
-            buf.put_u16(*offset);
+            buf.put_u32(*offset);
+        }
+        if !self.hash_index.is_empty() {
+            for bucket in &self.hash_index {
+                buf.put_u16(*bucket);
+            }
+            buf.put_u32(self.hash_index.len() as u32);
+            offsets_len |= HASH_INDEX_FLAG;
+        }
+        if self.restart_interval != RESTART_INTERVAL {
+            buf.put_u32(self.restart_interval as u32);
+            offsets_len |= RESTART_INTERVAL_FLAG;
         }
-        // Adds number of elements at the end of the block
-        buf.put_u16(offsets_len as u16);
+        // Adds number of restart points at the end of the block
+        buf.put_u32(offsets_len);
         buf.into()
     }
 
     pub fn decode(data: &[u8]) -> Self {
-        // get number of elements in the block
-        let entry_offsets_len = (&data[data.len() - SIZEOF_U16..]).get_u16() as usize;
-        let data_end = data.len() - SIZEOF_U16 - entry_offsets_len * SIZEOF_U16;
-        let offsets_raw = &data[data_end..data.len() - SIZEOF_U16];
+        Self::decode_bytes(Bytes::copy_from_slice(data))
+    }
+
+    /// Decode a block without copying it, so that its entries keep sharing memory with `data`.
+    pub fn decode_bytes(data: Bytes) -> Self {
+        // get number of restart points in the block
+        let mut end = data.len() - SIZEOF_U32;
+        let entry_offsets_len = (&data[end..]).get_u32();
+        let mut restart_interval = RESTART_INTERVAL;
+        if entry_offsets_len & RESTART_INTERVAL_FLAG != 0 {
+            end -= SIZEOF_U32;
+            restart_interval = (&data[end..]).get_u32() as usize;
+        }
+        let mut hash_index = Vec::new();
+        if entry_offsets_len & HASH_INDEX_FLAG != 0 {
+            end -= SIZEOF_U32;
+            let num_buckets = (&data[end..]).get_u32() as usize;
+            end -= num_buckets * SIZEOF_U16;
+            hash_index = data[end..end + num_buckets * SIZEOF_U16]
+                .chunks(SIZEOF_U16)
+                .map(|mut x| x.get_u16())
+                .collect();
+        }
+        let entry_offsets_len =
+            (entry_offsets_len & !(HASH_INDEX_FLAG | RESTART_INTERVAL_FLAG)) as usize;
+        let data_end = end - entry_offsets_len * SIZEOF_U32;
+        let offsets_raw = &data[data_end..end];
         // get offset array
         let offsets = offsets_raw
-            .chunks(SIZEOF_U16)
-            .map(|mut x| x.get_u16())
+            .chunks(SIZEOF_U32)
+            .map(|mut x| x.get_u32())
             .collect();
         // retrieve data
-        let data = data[0..data_end].to_vec();
-        Self { data, offsets }
+        let data = data.slice(0..data_end);
+        Self {
+            data,
+            offsets,
+            hash_index,
+            restart_interval,
+        }
+    }
+
+    /// Decode a data block of a legacy SST, which has no restart points. Its entries are
+    /// `| overlap u16 | rest len u16 | rest | ts u64 | value len u16 | value |` with each key
+    /// prefix-compressed against the first key, and are followed by their `u16` offsets and the
+    /// `u16` number of entries. The entries are re-encoded in the current layout.
+    pub(crate) fn decode_legacy(data: &[u8]) -> Result<Self> {
+        if data.len() < SIZEOF_U16 {
+            bail!("legacy block too short: {} bytes", data.len());
+        }
+        let num_entries = (&data[data.len() - SIZEOF_U16..]).get_u16() as usize;
+        let Some(data_end) = data.len().checked_sub((num_entries + 1) * SIZEOF_U16) else {
+            bail!("legacy block truncated: {} entries", num_entries);
+        };
+        if num_entries == 0 {
+            bail!("legacy block without entries");
+        }
+        // the entries are stored in the order of their offsets
+        let mut buf = &data[..data_end];
+        let mut first_key = Vec::new();
+        let mut builder = BlockBuilder::new(usize::MAX);
+        for idx in 0..num_entries {
+            if buf.remaining() < 2 * SIZEOF_U16 {
+                bail!("legacy block entry {} truncated", idx);
+            }
+            let overlap = buf.get_u16() as usize;
+            let rest_len = buf.get_u16() as usize;
+            if overlap > first_key.len() || buf.remaining() < rest_len + 8 + SIZEOF_U16 {
+                bail!("legacy block entry {} truncated", idx);
+            }
+            let mut key = first_key[..overlap].to_vec();
+            key.extend_from_slice(&buf[..rest_len]);
+            buf.advance(rest_len);
+            let ts = buf.get_u64();
+            let value_len = buf.get_u16() as usize;
+            if buf.remaining() < value_len {
+                bail!("legacy block entry {} truncated", idx);
+            }
+            assert!(builder.add(KeySlice::from_slice(&key, ts), &buf[..value_len]));
+            buf.advance(value_len);
+            if idx == 0 {
+                first_key = key;
+            }
+        }
+        Ok(builder.build())
+    }
+
+    /// Look up the restart point of the newest version of a key in the hash index. Returns `None`
+    /// if the block has no hash index, or the key may be in any restart interval.
+    pub(crate) fn hash_lookup(&self, key: &[u8]) -> Option<usize> {
+        if self.hash_index.is_empty() {
+            return None;
+        }
+        let bucket = farmhash::fingerprint32(key) as usize % self.hash_index.len();
+        match self.hash_index[bucket] {
+            HASH_BUCKET_EMPTY | HASH_BUCKET_COLLISION => None,
+            restart_idx => Some(restart_idx as usize),
+        }
     }
 }
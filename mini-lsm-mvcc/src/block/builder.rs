@@ -15,28 +15,39 @@
 use bytes::BufMut;
 
 use crate::key::{KeySlice, KeyVec};
+use crate::varint::{put_uvarint, uvarint_len, zigzag_encode};
 
-use super::{Block, SIZEOF_U16};
+use super::{
+    Block, HASH_BUCKET_COLLISION, HASH_BUCKET_EMPTY, HASH_INDEX_UTIL_RATIO, RESTART_INTERVAL,
+    SIZEOF_U16, SIZEOF_U32,
+};
 
 /// Builds a block.
 pub struct BlockBuilder {
-    /// Offsets of each key-value entries.
-    offsets: Vec<u16>,
+    /// Offsets of the restart points.
+    offsets: Vec<u32>,
     /// All serialized key-value pairs in the block.
     data: Vec<u8>,
     /// The expected block size.
     block_size: usize,
-    /// The first key in the block
-    first_key: KeyVec,
+    /// Number of key-value pairs in the block.
+    num_entries: usize,
+    /// The last key added to the block, which the next key is prefix-compressed against.
+    last_key: KeyVec,
+    /// Hashes of the distinct keys and the restart points of their newest versions, if the block
+    /// is built with a hash index.
+    hash_entries: Option<Vec<(u32, u16)>>,
+    /// Number of entries between two restart points.
+    restart_interval: usize,
 }
 
-fn compute_overlap(first_key: KeySlice, key: KeySlice) -> usize {
+fn compute_overlap(last_key: KeySlice, key: KeySlice) -> usize {
     let mut i = 0;
     loop {
-        if i >= first_key.key_len() || i >= key.key_len() {
+        if i >= last_key.key_len() || i >= key.key_len() {
             break;
         }
-        if first_key.key_ref()[i] != key.key_ref()[i] {
+        if last_key.key_ref()[i] != key.key_ref()[i] {
             break;
         }
         i += 1;
@@ -51,50 +62,118 @@ impl BlockBuilder {
             offsets: Vec::new(),
             data: Vec::new(),
             block_size,
-            first_key: KeyVec::new(),
+            num_entries: 0,
+            last_key: KeyVec::new(),
+            hash_entries: None,
+            restart_interval: RESTART_INTERVAL,
         }
     }
 
+    /// Creates a new block builder that appends a hash index to the block, so that point lookups
+    /// can find the restart point of a key without a binary search.
+    pub fn new_with_hash_index(block_size: usize) -> Self {
+        Self {
+            hash_entries: Some(Vec::new()),
+            ..Self::new(block_size)
+        }
+    }
+
+    /// Place a restart point every `restart_interval` entries instead of every `RESTART_INTERVAL`.
+    /// Longer intervals make the block smaller, and shorter ones make seeks faster. Must be called
+    /// before adding any entry.
+    pub fn set_restart_interval(&mut self, restart_interval: usize) {
+        assert!(restart_interval > 0, "restart interval must be positive");
+        assert!(self.is_empty(), "block already has entries");
+        self.restart_interval = restart_interval;
+    }
+
     fn estimated_size(&self) -> usize {
-        SIZEOF_U16 /* number of key-value pairs in the block */ +  self.offsets.len() * SIZEOF_U16 /* offsets */ + self.data.len()
+        SIZEOF_U32 /* number of restart points in the block */ +  self.offsets.len() * SIZEOF_U32 /* restart points */ + self.data.len()
         // key-value pairs
+        + self.hash_entries.as_ref().map_or(0, |entries| hash_index_size(entries.len()))
     }
 
-    /// Adds a key-value pair to the block. Returns false when the block is full.
+    /// Adds a key-value pair to the block. Returns false when the block is full. An entry larger
+    /// than the block size is always accepted into an empty block.
     #[must_use]
     pub fn add(&mut self, key: KeySlice, value: &[u8]) -> bool {
+        self.add_entry(key, value, false)
+    }
+
+    /// Adds a key with an encoded value-log pointer in place of the value.
+    #[must_use]
+    pub fn add_value_pointer(&mut self, key: KeySlice, pointer: &[u8]) -> bool {
+        self.add_entry(key, pointer, true)
+    }
+
+    fn add_entry(&mut self, key: KeySlice, value: &[u8], is_value_pointer: bool) -> bool {
         assert!(!key.is_empty(), "key must not be empty");
-        if self.estimated_size() + key.raw_len() + value.len() + SIZEOF_U16 * 3 /* key_len, value_len and offset */ > self.block_size
+        let is_restart = self.num_entries.is_multiple_of(self.restart_interval);
+        let (overlap, last_ts) = if is_restart {
+            (0, 0)
+        } else {
+            (
+                compute_overlap(self.last_key.as_key_slice(), key),
+                self.last_key.ts(),
+            )
+        };
+        // The timestamp is stored as the zigzag-encoded delta to the previous entry.
+        let ts_delta = zigzag_encode(key.ts().wrapping_sub(last_ts) as i64);
+        let unshared = key.key_len() - overlap;
+        // The lowest bit of the value length marks a value-log pointer.
+        let value_len = ((value.len() as u64) << 1) | is_value_pointer as u64;
+        let entry_size = uvarint_len(overlap as u64)
+            + uvarint_len(unshared as u64)
+            + unshared
+            + uvarint_len(ts_delta)
+            + uvarint_len(value_len)
+            + value.len();
+        let restart_size = if is_restart { SIZEOF_U32 } else { 0 };
+        let is_new_key = self.is_empty() || self.last_key.key_ref() != key.key_ref();
+        let hash_size = match &self.hash_entries {
+            Some(entries) if is_new_key => {
+                hash_index_size(entries.len() + 1) - hash_index_size(entries.len())
+            }
+            _ => 0,
+        };
+        if self.estimated_size() + entry_size + restart_size + hash_size > self.block_size
             && !self.is_empty()
         {
             return false;
         }
-        // Add the offset of the data into the offset array.
-        self.offsets.push(self.data.len() as u16);
-        let overlap = compute_overlap(self.first_key.as_key_slice(), key);
+        if is_restart {
+            // Add the offset of the data into the restart array.
+            self.offsets.push(self.data.len() as u32);
+        }
+        if let Some(entries) = &mut self.hash_entries
+            && is_new_key
+        {
+            // restart points that do not fit in a bucket are never looked up by hash
+            let restart_idx = (self.offsets.len() - 1).min(HASH_BUCKET_COLLISION as usize) as u16;
+            entries.push((farmhash::fingerprint32(key.key_ref()), restart_idx));
+        }
         // Encode key overlap.
-        self.data.put_u16(overlap as u16);
+        put_uvarint(&mut self.data, overlap as u64);
         // Encode key length.
-        self.data.put_u16((key.key_len() - overlap) as u16);
+        put_uvarint(&mut self.data, unshared as u64);
         // Encode key content.
         self.data.put(&key.key_ref()[overlap..]);
         // Encode key ts
-        self.data.put_u64(key.ts());
-        // Encode value length.
-        self.data.put_u16(value.len() as u16);
+        put_uvarint(&mut self.data, ts_delta);
+        // Encode value length and kind.
+        put_uvarint(&mut self.data, value_len);
         // Encode value content.
         self.data.put(value);
 
-        if self.first_key.is_empty() {
-            self.first_key = key.to_key_vec();
-        }
+        self.last_key.set_from_slice(key);
+        self.num_entries += 1;
 
         true
     }
 
     /// Check if there are no key-value pairs in the block.
     pub fn is_empty(&self) -> bool {
-        self.offsets.is_empty()
+        self.num_entries == 0
     }
 
     /// Finalize the block.
@@ -102,9 +181,37 @@ impl BlockBuilder {
         if self.is_empty() {
             panic!("block should not be empty");
         }
+        let hash_index = match self.hash_entries {
+            Some(entries) => {
+                let num_buckets = num_hash_buckets(entries.len());
+                let mut buckets = vec![HASH_BUCKET_EMPTY; num_buckets];
+                for (hash, restart_idx) in entries {
+                    let bucket = &mut buckets[hash as usize % num_buckets];
+                    // keys of the same restart interval can share a bucket
+                    if *bucket == HASH_BUCKET_EMPTY {
+                        *bucket = restart_idx;
+                    } else if *bucket != restart_idx {
+                        *bucket = HASH_BUCKET_COLLISION;
+                    }
+                }
+                buckets
+            }
+            None => Vec::new(),
+        };
         Block {
-            data: self.data,
+            data: self.data.into(),
             offsets: self.offsets,
+            hash_index,
+            restart_interval: self.restart_interval,
         }
     }
 }
+
+fn num_hash_buckets(num_keys: usize) -> usize {
+    (num_keys as f64 / HASH_INDEX_UTIL_RATIO) as usize + 1
+}
+
+/// Size of a hash index over the given number of distinct keys, with the number of buckets.
+fn hash_index_size(num_keys: usize) -> usize {
+    num_hash_buckets(num_keys) * SIZEOF_U16 + SIZEOF_U32
+}
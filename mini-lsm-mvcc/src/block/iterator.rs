@@ -17,8 +17,8 @@ use std::sync::Arc;
 use bytes::Buf;
 
 use crate::{
-    block::SIZEOF_U16,
     key::{KeySlice, KeyVec},
+    varint::{get_uvarint, zigzag_decode},
 };
 
 use super::Block;
@@ -31,31 +31,36 @@ pub struct BlockIterator {
     key: KeyVec,
     /// the current value range in the block.data, corresponds to the current key
     value_range: (usize, usize),
-    /// the current index at the iterator position
-    idx: usize,
-    /// the first key in the block
-    first_key: KeyVec,
+    /// whether the current value is a value-log pointer
+    is_value_pointer: bool,
+    /// the index of the restart point that the current entry follows
+    restart_idx: usize,
+    /// the number of entries between the restart point and the current entry
+    entries_after_restart: usize,
 }
 
 impl Block {
-    fn get_first_key(&self) -> KeyVec {
-        let mut buf = &self.data[..];
-        buf.get_u16();
-        let key_len = buf.get_u16() as usize;
+    /// Get the key at the idx-th restart point. Restart points always store the full key.
+    fn restart_key(&self, idx: usize) -> KeySlice<'_> {
+        let mut buf = &self.data[self.offsets[idx] as usize..];
+        get_uvarint(&mut buf);
+        let key_len = get_uvarint(&mut buf) as usize;
         let key = &buf[..key_len];
         buf.advance(key_len);
-        KeyVec::from_vec_with_ts(key.to_vec(), buf.get_u64())
+        let ts = zigzag_decode(get_uvarint(&mut buf)) as u64;
+        KeySlice::from_slice(key, ts)
     }
 }
 
 impl BlockIterator {
     fn new(block: Arc<Block>) -> Self {
         Self {
-            first_key: block.get_first_key(),
             block,
             key: KeyVec::new(),
             value_range: (0, 0),
-            idx: 0,
+            is_value_pointer: false,
+            restart_idx: 0,
+            entries_after_restart: 0,
         }
     }
 
@@ -73,8 +78,23 @@ impl BlockIterator {
         iter
     }
 
+    /// Creates a block iterator and seek to the `idx`-th entry, scanning only within the restart
+    /// interval that contains it.
+    pub(crate) fn create_and_seek_to_entry(block: Arc<Block>, idx: usize) -> Self {
+        let mut iter = Self::new(block);
+        let restart_interval = iter.block.restart_interval;
+        iter.seek_to_restart(idx / restart_interval);
+        for _ in 0..idx % restart_interval {
+            if !iter.is_valid() {
+                break;
+            }
+            iter.next();
+        }
+        iter
+    }
+
     /// Returns the key of the current entry.
-    pub fn key(&self) -> KeySlice {
+    pub fn key(&self) -> KeySlice<'_> {
         debug_assert!(!self.key.is_empty(), "invalid iterator");
         self.key.as_key_slice()
     }
@@ -85,72 +105,111 @@ impl BlockIterator {
         &self.block.data[self.value_range.0..self.value_range.1]
     }
 
+    /// Returns true if the value of the current entry is an encoded value-log pointer.
+    pub fn is_value_pointer(&self) -> bool {
+        self.is_value_pointer
+    }
+
     /// Returns true if the iterator is valid.
     pub fn is_valid(&self) -> bool {
         !self.key.is_empty()
     }
 
+    /// Returns the position of the current entry in the block.
+    pub(crate) fn entry_idx(&self) -> usize {
+        self.restart_idx * self.block.restart_interval + self.entries_after_restart
+    }
+
     /// Seeks to the first key in the block.
     pub fn seek_to_first(&mut self) {
-        self.seek_to(0);
+        self.seek_to_restart(0);
     }
 
-    /// Seeks to the idx-th key in the block.
-    fn seek_to(&mut self, idx: usize) {
+    /// Seeks to the idx-th restart point in the block.
+    fn seek_to_restart(&mut self, idx: usize) {
+        self.key.clear();
+        self.key.set_ts(0);
         if idx >= self.block.offsets.len() {
-            self.key.clear();
             self.value_range = (0, 0);
             return;
         }
         let offset = self.block.offsets[idx] as usize;
+        self.restart_idx = idx;
+        self.entries_after_restart = 0;
         self.seek_to_offset(offset);
-        self.idx = idx;
     }
 
     /// Move to the next key in the block.
     pub fn next(&mut self) {
-        self.idx += 1;
-        self.seek_to(self.idx);
+        let offset = self.value_range.1;
+        if offset >= self.block.data.len() {
+            self.key.clear();
+            self.value_range = (0, 0);
+            return;
+        }
+        if self.block.offsets.get(self.restart_idx + 1) == Some(&(offset as u32)) {
+            // the next entry is a restart point and does not depend on the current key
+            self.restart_idx += 1;
+            self.entries_after_restart = 0;
+            self.key.clear();
+            self.key.set_ts(0);
+        } else {
+            self.entries_after_restart += 1;
+        }
+        self.seek_to_offset(offset);
     }
 
-    /// Seek to the specified position and update the current `key` and `value`
-    /// Index update will be handled by caller
+    /// Decode the entry at the specified position and update the current `key` and `value`.
+    /// The entry is decoded against the current key, which must be the previous entry in the block
+    /// unless the entry is a restart point.
     fn seek_to_offset(&mut self, offset: usize) {
         let mut entry = &self.block.data[offset..];
-        // Since `get_u16()` will automatically move the ptr 2 bytes ahead here,
+        // Since `get_uvarint()` will automatically move the ptr ahead here,
         // we don't need to manually advance it
-        let overlap_len = entry.get_u16() as usize;
-        let key_len = entry.get_u16() as usize;
+        let overlap_len = get_uvarint(&mut entry) as usize;
+        let key_len = get_uvarint(&mut entry) as usize;
         let key = &entry[..key_len];
-        self.key.clear();
-        self.key.append(&self.first_key.key_ref()[..overlap_len]);
+        self.key.truncate(overlap_len);
         self.key.append(key);
         entry.advance(key_len);
-        let ts = entry.get_u64();
+        let ts_delta = zigzag_decode(get_uvarint(&mut entry));
+        let ts = self.key.ts().wrapping_add(ts_delta as u64);
         self.key.set_ts(ts);
-        let value_len = entry.get_u16() as usize;
-        // REMEMBER TO CHANGE THIS every time you change the encoding!
-        let value_offset_begin =
-            offset + SIZEOF_U16 + SIZEOF_U16 + std::mem::size_of::<u64>() + key_len + SIZEOF_U16;
+        let value_len = get_uvarint(&mut entry);
+        self.is_value_pointer = value_len & 1 == 1;
+        let value_len = (value_len >> 1) as usize;
+        let value_offset_begin = self.block.data.len() - entry.remaining();
         let value_offset_end = value_offset_begin + value_len;
         self.value_range = (value_offset_begin, value_offset_end);
-        entry.advance(value_len);
     }
 
     /// Seek to the first key that is >= `key`.
     pub fn seek_to_key(&mut self, key: KeySlice) {
+        // The hash index points to the restart point of the newest version of the key. It is only
+        // a hint: if the key is not found from there, fall back to the binary search.
+        if let Some(restart_idx) = self.block.hash_lookup(key.key_ref()) {
+            self.seek_to_restart(restart_idx);
+            while self.is_valid() && self.key() < key {
+                self.next();
+            }
+            if self.is_valid() && self.key().key_ref() == key.key_ref() {
+                return;
+            }
+        }
+        // Find the last restart point whose key is <= `key`, then scan forward from there.
         let mut low = 0;
         let mut high = self.block.offsets.len();
         while low < high {
             let mid = low + (high - low) / 2;
-            self.seek_to(mid);
-            assert!(self.is_valid());
-            match self.key().cmp(&key) {
-                std::cmp::Ordering::Less => low = mid + 1,
-                std::cmp::Ordering::Greater => high = mid,
-                std::cmp::Ordering::Equal => return,
+            if self.block.restart_key(mid) <= key {
+                low = mid + 1;
+            } else {
+                high = mid;
             }
         }
-        self.seek_to(low);
+        self.seek_to_restart(low.saturating_sub(1));
+        while self.is_valid() && self.key() < key {
+            self.next();
+        }
     }
 }
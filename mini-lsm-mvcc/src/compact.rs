@@ -16,7 +16,7 @@ mod leveled;
 mod simple_leveled;
 mod tiered;
 
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
 use std::time::Duration;
 
@@ -34,8 +34,10 @@ use crate::iterators::merge_iterator::MergeIterator;
 use crate::iterators::two_merge_iterator::TwoMergeIterator;
 use crate::key::KeySlice;
 use crate::lsm_storage::{CompactionFilter, LsmStorageInner, LsmStorageState};
-use crate::manifest::ManifestRecord;
-use crate::table::{SsTable, SsTableBuilder, SsTableIterator};
+use crate::manifest::{NewFile, VersionEdit};
+use crate::range_tombstone::{FragmentedRangeTombstones, key_successor};
+use crate::table::{CompactionReason, SsTable, SsTableBuilder, SsTableIterator};
+use crate::vlog::discard_value_pointer;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub enum CompactionTask {
@@ -49,6 +51,26 @@ pub enum CompactionTask {
 }
 
 impl CompactionTask {
+    pub(crate) fn input_ssts(&self) -> Vec<usize> {
+        match self {
+            CompactionTask::ForceFullCompaction {
+                l0_sstables,
+                l1_sstables,
+            } => [&l0_sstables[..], &l1_sstables[..]].concat(),
+            CompactionTask::Leveled(task) => {
+                [&task.upper_level_sst_ids[..], &task.lower_level_sst_ids[..]].concat()
+            }
+            CompactionTask::Simple(task) => {
+                [&task.upper_level_sst_ids[..], &task.lower_level_sst_ids[..]].concat()
+            }
+            CompactionTask::Tiered(task) => task
+                .tiers
+                .iter()
+                .flat_map(|(_, ssts)| ssts.iter().copied())
+                .collect(),
+        }
+    }
+
     fn compact_to_bottom_level(&self) -> bool {
         match self {
             CompactionTask::ForceFullCompaction { .. } => true,
@@ -57,6 +79,16 @@ impl CompactionTask {
             CompactionTask::Tiered(task) => task.bottom_tier_included,
         }
     }
+
+    /// The reason recorded in the table properties of the output SSTs.
+    fn compaction_reason(&self) -> CompactionReason {
+        match self {
+            CompactionTask::ForceFullCompaction { .. } => CompactionReason::ForceFullCompaction,
+            CompactionTask::Leveled(_) => CompactionReason::Leveled,
+            CompactionTask::Simple(_) => CompactionReason::Simple,
+            CompactionTask::Tiered(_) => CompactionReason::Tiered,
+        }
+    }
 }
 
 pub(crate) enum CompactionController {
@@ -67,6 +99,21 @@ pub(crate) enum CompactionController {
 }
 
 impl CompactionController {
+    pub fn new(options: &CompactionOptions) -> Self {
+        match options {
+            CompactionOptions::Leveled(options) => {
+                CompactionController::Leveled(LeveledCompactionController::new(options.clone()))
+            }
+            CompactionOptions::Tiered(options) => {
+                CompactionController::Tiered(TieredCompactionController::new(options.clone()))
+            }
+            CompactionOptions::Simple(options) => CompactionController::Simple(
+                SimpleLeveledCompactionController::new(options.clone()),
+            ),
+            CompactionOptions::NoCompaction => CompactionController::NoCompaction,
+        }
+    }
+
     pub fn generate_compaction_task(&self, snapshot: &LsmStorageState) -> Option<CompactionTask> {
         match self {
             CompactionController::Leveled(ctrl) => ctrl
@@ -126,21 +173,83 @@ pub enum CompactionOptions {
     NoCompaction,
 }
 
+/// The SSTs written by a compaction.
+struct CompactionOutput {
+    ssts: Vec<Arc<SsTable>>,
+    /// The bytes of each value log that the compaction dropped.
+    value_log_discards: Vec<(usize, u64)>,
+}
+
 impl LsmStorageInner {
+    /// The level of the output SSTs of a compaction, as recorded in the manifest and the table
+    /// properties. Under tiered compaction, it is the ID of the new tier, which is named after its
+    /// first SST.
+    fn output_level(&self, task: &CompactionTask, first_output: usize) -> usize {
+        match task {
+            _ if !self.compaction_controller.flush_to_l0() => first_output,
+            CompactionTask::ForceFullCompaction { .. } => 1,
+            CompactionTask::Leveled(task) => task.lower_level,
+            CompactionTask::Simple(task) => task.lower_level,
+            CompactionTask::Tiered(_) => first_output,
+        }
+    }
+
+    /// Create a builder for the next output SST of the compaction, which streams the SST to its
+    /// file. Returns the id of the SST with the builder, and sets `first_output` to it on the first
+    /// call.
+    fn new_compaction_builder(
+        &self,
+        task: &CompactionTask,
+        first_output: &mut Option<usize>,
+    ) -> Result<(usize, SsTableBuilder)> {
+        let sst_id = self.next_sst_id();
+        let first_output = *first_output.get_or_insert(sst_id);
+        let mut builder = SsTableBuilder::new_with_options(&self.options);
+        if task.compact_to_bottom_level() && self.options.skip_bottom_level_filters {
+            builder.skip_filters();
+        }
+        let level = self.output_level(task, first_output);
+        builder.set_compaction_reason(task.compaction_reason(), level);
+        let max_memtable_id = {
+            let state = self.state.read();
+            task.input_ssts()
+                .iter()
+                .filter_map(|id| state.sstables.get(id)?.properties()?.max_memtable_id)
+                .max()
+        };
+        builder.set_max_memtable_id(max_memtable_id.map(|x| x as usize));
+        builder.stream_to_file(self.path_of_sst(sst_id), self.options.bytes_per_sync)?;
+        Ok((sst_id, builder))
+    }
+
     fn compact_generate_sst_from_iter(
         &self,
         mut iter: impl for<'a> StorageIterator<KeyType<'a> = KeySlice<'a>>,
-        compact_to_bottom_level: bool,
-    ) -> Result<Vec<Arc<SsTable>>> {
+        range_tombstones: FragmentedRangeTombstones,
+        task: &CompactionTask,
+    ) -> Result<CompactionOutput> {
+        let compact_to_bottom_level = task.compact_to_bottom_level();
         let mut builder = None;
         let mut new_sst = Vec::new();
+        let mut first_output = None;
+        let mut value_log_discards = HashMap::new();
         let watermark = self.mvcc().watermark();
+        // tombstones visible to all readers are not needed at the bottom level, as the keys they
+        // cover are dropped below
+        let output_tombstones = if compact_to_bottom_level {
+            range_tombstones.drop_until(watermark)
+        } else {
+            range_tombstones.clone()
+        };
+        // the output SSTs do not overlap, so each one gets the tombstones between the previous
+        // SST and its last key
+        let mut tombstones_lower = None;
         let mut last_key = Vec::<u8>::new();
         let mut first_key_below_watermark = false;
         let compaction_filters = self.compaction_filters.lock().clone();
         'outer: while iter.is_valid() {
             if builder.is_none() {
-                builder = Some(SsTableBuilder::new(self.options.block_size));
+                builder = Some(self.new_compaction_builder(task, &mut first_output)?);
             }
 
             let same_as_last_key = iter.key().key_ref() == last_key;
@@ -148,6 +257,18 @@ impl LsmStorageInner {
                 first_key_below_watermark = true;
             }
 
+            // a range tombstone visible to all readers deletes this version and all older ones
+            if range_tombstones.covers(iter.key(), watermark) {
+                if iter.is_value_pointer() {
+                    discard_value_pointer(&mut value_log_discards, iter.value())?;
+                }
+                last_key.clear();
+                last_key.extend(iter.key().key_ref());
+                iter.next()?;
+                first_key_below_watermark = false;
+                continue;
+            }
+
             if compact_to_bottom_level
                 && !same_as_last_key
                 && iter.key().ts() <= watermark
@@ -162,6 +283,9 @@ impl LsmStorageInner {
 
             if iter.key().ts() <= watermark {
                 if same_as_last_key && !first_key_below_watermark {
+                    if iter.is_value_pointer() {
+                        discard_value_pointer(&mut value_log_discards, iter.value())?;
+                    }
                     iter.next()?;
                     continue;
                 }
@@ -173,6 +297,12 @@ impl LsmStorageInner {
                         match filter {
                             CompactionFilter::Prefix(x) => {
                                 if iter.key().key_ref().starts_with(x) {
+                                    if iter.is_value_pointer() {
+                                        discard_value_pointer(
+                                            &mut value_log_discards,
+                                            iter.value(),
+                                        )?;
+                                    }
                                     iter.next()?;
                                     continue 'outer;
                                 }
@@ -182,22 +312,32 @@ impl LsmStorageInner {
                 }
             }
 
-            let builder_inner = builder.as_mut().unwrap();
+            let (_, builder_inner) = builder.as_mut().unwrap();
 
             if builder_inner.estimated_size() >= self.options.target_sst_size && !same_as_last_key {
-                let sst_id = self.next_sst_id();
-                let old_builder = builder.take().unwrap();
+                let (sst_id, mut old_builder) = builder.take().unwrap();
+                let tombstones_upper = key_successor(&last_key);
+                old_builder.add_range_tombstones(
+                    output_tombstones
+                        .clip(tombstones_lower.as_deref(), Some(&tombstones_upper))
+                        .iter(),
+                );
+                tombstones_lower = Some(tombstones_upper);
                 let sst = Arc::new(old_builder.build(
                     sst_id,
                     Some(self.block_cache.clone()),
                     self.path_of_sst(sst_id),
                 )?);
                 new_sst.push(sst);
-                builder = Some(SsTableBuilder::new(self.options.block_size));
+                builder = Some(self.new_compaction_builder(task, &mut first_output)?);
             }
 
-            let builder_inner = builder.as_mut().unwrap();
-            builder_inner.add(iter.key(), iter.value());
+            let (_, builder_inner) = builder.as_mut().unwrap();
+            if iter.is_value_pointer() {
+                builder_inner.add_value_pointer(iter.key(), iter.value());
+            } else {
+                builder_inner.add(iter.key(), iter.value());
+            }
 
             if !same_as_last_key {
                 last_key.clear();
@@ -206,8 +346,18 @@ impl LsmStorageInner {
 
             iter.next()?;
         }
-        if let Some(builder) = builder {
-            let sst_id = self.next_sst_id(); // lock dropped here
+        let (sst_id, mut builder) = match builder {
+            Some(builder) => builder,
+            None => self.new_compaction_builder(task, &mut first_output)?,
+        };
+        builder.add_range_tombstones(
+            output_tombstones
+                .clip(tombstones_lower.as_deref(), None)
+                .iter(),
+        );
+        if builder.is_empty() {
+            builder.abandon()?;
+        } else {
             let sst = Arc::new(builder.build(
                 sst_id,
                 Some(self.block_cache.clone()),
@@ -215,14 +365,24 @@ impl LsmStorageInner {
             )?);
             new_sst.push(sst);
         }
-        Ok(new_sst)
+        let mut value_log_discards = value_log_discards.into_iter().collect::<Vec<_>>();
+        value_log_discards.sort();
+        Ok(CompactionOutput {
+            ssts: new_sst,
+            value_log_discards,
+        })
     }
 
-    fn compact(&self, task: &CompactionTask) -> Result<Vec<Arc<SsTable>>> {
+    fn compact(&self, task: &CompactionTask) -> Result<CompactionOutput> {
         let snapshot = {
             let state = self.state.read();
             state.clone()
         };
+        let range_tombstones = FragmentedRangeTombstones::new(
+            task.input_ssts()
+                .iter()
+                .flat_map(|id| snapshot.sstables[id].range_tombstones().iter()),
+        );
         match task {
             CompactionTask::ForceFullCompaction {
                 l0_sstables,
@@ -242,7 +402,7 @@ impl LsmStorageInner {
                     MergeIterator::create(l0_iters),
                     SstConcatIterator::create_and_seek_to_first(l1_iters)?,
                 )?;
-                self.compact_generate_sst_from_iter(iter, task.compact_to_bottom_level())
+                self.compact_generate_sst_from_iter(iter, range_tombstones, task)
             }
             CompactionTask::Simple(SimpleLeveledCompactionTask {
                 upper_level,
@@ -271,7 +431,8 @@ impl LsmStorageInner {
                     let lower_iter = SstConcatIterator::create_and_seek_to_first(lower_ssts)?;
                     self.compact_generate_sst_from_iter(
                         TwoMergeIterator::create(upper_iter, lower_iter)?,
-                        task.compact_to_bottom_level(),
+                        range_tombstones,
+                        task,
                     )
                 }
                 None => {
@@ -289,7 +450,8 @@ impl LsmStorageInner {
                     let lower_iter = SstConcatIterator::create_and_seek_to_first(lower_ssts)?;
                     self.compact_generate_sst_from_iter(
                         TwoMergeIterator::create(upper_iter, lower_iter)?,
-                        task.compact_to_bottom_level(),
+                        range_tombstones,
+                        task,
                     )
                 }
             },
@@ -304,7 +466,8 @@ impl LsmStorageInner {
                 }
                 self.compact_generate_sst_from_iter(
                     MergeIterator::create(iters),
-                    task.compact_to_bottom_level(),
+                    range_tombstones,
+                    task,
                 )
             }
         }
@@ -314,6 +477,7 @@ impl LsmStorageInner {
         let CompactionOptions::NoCompaction = self.options.compaction_options else {
             panic!("full compaction can only be called with compaction is not enabled")
         };
+        let _compaction_lock = self.compaction_lock.lock();
 
         let snapshot = {
             let state = self.state.read();
@@ -329,15 +493,28 @@ impl LsmStorageInner {
 
         println!("force full compaction: {:?}", compaction_task);
 
-        let sstables = self.compact(&compaction_task)?;
+        let _pending_outputs = self.pending_outputs();
+        let CompactionOutput {
+            ssts: sstables,
+            value_log_discards,
+        } = self.compact(&compaction_task)?;
         let mut ids = Vec::with_capacity(sstables.len());
+        let level = self.output_level(&compaction_task, sstables.first().map_or(0, |x| x.sst_id()));
+        let edit = VersionEdit {
+            new_files: sstables.iter().map(|x| NewFile::new(level, x)).collect(),
+            deleted_files: compaction_task.input_ssts(),
+            value_log_discards: value_log_discards.clone(),
+            ..Default::default()
+        };
 
         {
             let state_lock = self.state_lock.lock();
             let mut state = self.state.read().as_ref().clone();
+            let mut ssts_to_remove = Vec::with_capacity(l0_sstables.len() + l1_sstables.len());
             for sst in l0_sstables.iter().chain(l1_sstables.iter()) {
                 let result = state.sstables.remove(sst);
                 assert!(result.is_some());
+                ssts_to_remove.push(result.unwrap());
             }
             for new_sst in sstables {
                 ids.push(new_sst.sst_id());
@@ -356,21 +533,25 @@ impl LsmStorageInner {
             assert!(l0_sstables_map.is_empty());
             *self.state.write() = Arc::new(state);
             self.sync_dir()?;
-            self.manifest.as_ref().unwrap().add_record(
-                &state_lock,
-                ManifestRecord::Compaction(compaction_task, ids.clone()),
-            )?;
-        }
-        for sst in l0_sstables.iter().chain(l1_sstables.iter()) {
-            std::fs::remove_file(self.path_of_sst(*sst))?;
+            self.manifest
+                .as_ref()
+                .unwrap()
+                .add_record(&state_lock, edit)?;
+            self.add_value_log_discards(&value_log_discards);
+            // readers holding an older snapshot keep using the inputs until they are done
+            self.add_obsolete_ssts(ssts_to_remove);
+            self.maybe_roll_manifest(&state_lock)?;
         }
+        drop(snapshot);
+        self.delete_obsolete_files()?;
 
         println!("force full compaction done, new SSTs: {:?}", ids);
 
         Ok(())
     }
 
-    fn trigger_compaction(&self) -> Result<()> {
+    pub(crate) fn trigger_compaction(&self) -> Result<()> {
+        let _compaction_lock = self.compaction_lock.lock();
         let snapshot = {
             let state = self.state.read();
             state.clone()
@@ -383,14 +564,23 @@ impl LsmStorageInner {
         };
         self.dump_structure();
         println!("running compaction task: {:?}", task);
-        let sstables = self.compact(&task)?;
+        let _pending_outputs = self.pending_outputs();
+        let CompactionOutput {
+            ssts: sstables,
+            value_log_discards,
+        } = self.compact(&task)?;
         let output = sstables.iter().map(|x| x.sst_id()).collect::<Vec<_>>();
-        let ssts_to_remove = {
+        let level = self.output_level(&task, output.first().copied().unwrap_or_default());
+        let edit = VersionEdit {
+            new_files: sstables.iter().map(|x| NewFile::new(level, x)).collect(),
+            deleted_files: task.input_ssts(),
+            value_log_discards: value_log_discards.clone(),
+            ..Default::default()
+        };
+        let num_removed = {
             let state_lock = self.state_lock.lock();
             let mut snapshot = self.state.read().as_ref().clone();
-            let mut new_sst_ids = Vec::new();
             for file_to_add in sstables {
-                new_sst_ids.push(file_to_add.sst_id());
                 let result = snapshot.sstables.insert(file_to_add.sst_id(), file_to_add);
                 assert!(result.is_none());
             }
@@ -408,20 +598,22 @@ impl LsmStorageInner {
             *state = Arc::new(snapshot);
             drop(state);
             self.sync_dir()?;
-            self.manifest()
-                .add_record(&state_lock, ManifestRecord::Compaction(task, new_sst_ids))?;
-            ssts_to_remove
+            self.manifest().add_record(&state_lock, edit)?;
+            self.add_value_log_discards(&value_log_discards);
+            let num_removed = ssts_to_remove.len();
+            // readers holding an older snapshot keep using the inputs until they are done
+            self.add_obsolete_ssts(ssts_to_remove);
+            self.maybe_roll_manifest(&state_lock)?;
+            num_removed
         };
         println!(
             "compaction finished: {} files removed, {} files added, output={:?}",
-            ssts_to_remove.len(),
+            num_removed,
             output.len(),
             output
         );
-        for sst in ssts_to_remove {
-            std::fs::remove_file(self.path_of_sst(sst.sst_id()))?;
-        }
-        self.sync_dir()?;
+        drop(snapshot);
+        self.delete_obsolete_files()?;
 
         Ok(())
     }
@@ -455,6 +647,13 @@ impl LsmStorageInner {
         let res = {
             let state = self.state.read();
             state.imm_memtables.len() >= self.options.num_memtable_limit
+                // the memtables of all storage engines sharing the budget exceed it
+                || (!state.imm_memtables.is_empty()
+                    && self
+                        .options
+                        .write_buffer_manager
+                        .as_ref()
+                        .is_some_and(|x| x.is_over_budget()))
         };
         if res {
             self.force_flush_next_imm_memtable()?;
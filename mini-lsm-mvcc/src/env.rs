@@ -0,0 +1,87 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+mod disk;
+mod fault;
+mod memory;
+
+use std::fmt::Debug;
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use bytes::Bytes;
+pub use disk::{DiskFileSystem, ReadBackend};
+pub use fault::FaultInjectionFileSystem;
+pub use memory::MemFileSystem;
+
+/// A file that is read at arbitrary offsets, e.g., an SST or a value log.
+pub trait RandomAccessFile: Send + Sync {
+    /// Read exactly `len` bytes at `offset`. The bytes may share memory with the file, e.g., its
+    /// memory mapping.
+    fn read_at(&self, offset: u64, len: u64) -> Result<Bytes>;
+
+    /// Size of the file when it was opened.
+    fn size(&self) -> u64;
+}
+
+/// A file that is written sequentially, e.g., an SST being built, a WAL or the manifest.
+pub trait WritableFile: Send {
+    /// Append data to the file, which may be buffered until the file is flushed.
+    fn append(&mut self, data: &[u8]) -> Result<()>;
+
+    /// Hand the buffered data to the file system, without waiting for it to be durable.
+    fn flush(&mut self) -> Result<()>;
+
+    /// Flush the file and wait for its data to be durable.
+    fn sync(&mut self) -> Result<()>;
+
+    /// Start writing back `len` bytes at `offset` without waiting for them. Does nothing by
+    /// default.
+    fn sync_range(&mut self, _offset: u64, _len: u64) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// The file system the storage engine stores its files in.
+pub trait FileSystem: Debug + Send + Sync {
+    /// Create a file for writing, truncating it if it exists.
+    fn create(&self, path: &Path) -> Result<Box<dyn WritableFile>>;
+
+    /// Open an existing file for appending.
+    fn open_append(&self, path: &Path) -> Result<Box<dyn WritableFile>>;
+
+    /// Open an existing file for reading. File systems may ignore the read backend.
+    fn open(&self, path: &Path, backend: ReadBackend) -> Result<Box<dyn RandomAccessFile>>;
+
+    /// Read the whole file.
+    fn read(&self, path: &Path) -> Result<Vec<u8>> {
+        let file = self.open(path, ReadBackend::Pread)?;
+        Ok(file.read_at(0, file.size())?.into())
+    }
+
+    /// Rename a file, replacing `to` if it exists.
+    fn rename(&self, from: &Path, to: &Path) -> Result<()>;
+
+    fn remove(&self, path: &Path) -> Result<()>;
+
+    /// The paths of the files in a directory, in no particular order.
+    fn list_dir(&self, path: &Path) -> Result<Vec<PathBuf>>;
+
+    fn create_dir_all(&self, path: &Path) -> Result<()>;
+
+    fn exists(&self, path: &Path) -> bool;
+
+    /// Make the creation, removal and renaming of the files in a directory durable.
+    fn sync_dir(&self, path: &Path) -> Result<()>;
+}
@@ -0,0 +1,263 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Result, bail};
+use bytes::Bytes;
+use memmap2::Mmap;
+
+use super::{FileSystem, RandomAccessFile, WritableFile};
+
+/// How SST and value log files are read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ReadBackend {
+    /// Read each range with `pread`.
+    #[default]
+    Pread,
+    /// Map the whole file into memory and hand out ranges of the mapping without copying them or
+    /// a system call for each read. Uncompressed blocks in the block cache keep pointing into the
+    /// mapping.
+    Mmap,
+    /// Read with `O_DIRECT`, bypassing the page cache so that the block cache is the only cache.
+    /// Falls back to `pread` where the file system does not support direct I/O.
+    DirectIo,
+}
+
+/// Offsets and lengths of direct reads must be multiples of the logical block size of the device,
+/// which is at most a page on the devices we run on.
+const DIRECT_IO_ALIGNMENT: usize = 4096;
+
+/// The file system of the local disk.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct DiskFileSystem;
+
+impl FileSystem for DiskFileSystem {
+    fn create(&self, path: &Path) -> Result<Box<dyn WritableFile>> {
+        Ok(Box::new(DiskWritableFile(BufWriter::new(File::create(
+            path,
+        )?))))
+    }
+
+    fn open_append(&self, path: &Path) -> Result<Box<dyn WritableFile>> {
+        let file = File::options().append(true).open(path)?;
+        Ok(Box::new(DiskWritableFile(BufWriter::new(file))))
+    }
+
+    fn open(&self, path: &Path, backend: ReadBackend) -> Result<Box<dyn RandomAccessFile>> {
+        let (reader, size) = FileReader::open(path, backend)?;
+        Ok(Box::new(DiskRandomAccessFile { reader, size }))
+    }
+
+    fn read(&self, path: &Path) -> Result<Vec<u8>> {
+        Ok(std::fs::read(path)?)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> Result<()> {
+        Ok(std::fs::rename(from, to)?)
+    }
+
+    fn remove(&self, path: &Path) -> Result<()> {
+        Ok(std::fs::remove_file(path)?)
+    }
+
+    fn list_dir(&self, path: &Path) -> Result<Vec<PathBuf>> {
+        let mut paths = Vec::new();
+        for entry in std::fs::read_dir(path)? {
+            paths.push(entry?.path());
+        }
+        Ok(paths)
+    }
+
+    fn create_dir_all(&self, path: &Path) -> Result<()> {
+        Ok(std::fs::create_dir_all(path)?)
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        path.exists()
+    }
+
+    fn sync_dir(&self, path: &Path) -> Result<()> {
+        File::open(path)?.sync_all()?;
+        Ok(())
+    }
+}
+
+struct DiskWritableFile(BufWriter<File>);
+
+impl WritableFile for DiskWritableFile {
+    fn append(&mut self, data: &[u8]) -> Result<()> {
+        Ok(self.0.write_all(data)?)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(self.0.flush()?)
+    }
+
+    fn sync(&mut self) -> Result<()> {
+        self.0.flush()?;
+        self.0.get_ref().sync_all()?;
+        Ok(())
+    }
+
+    #[cfg(target_os = "linux")]
+    fn sync_range(&mut self, offset: u64, len: u64) -> Result<()> {
+        use std::os::unix::io::AsRawFd;
+        self.0.flush()?;
+        // SAFETY: the file descriptor is owned by `self.0` and open for the whole call.
+        let ret = unsafe {
+            libc::sync_file_range(
+                self.0.get_ref().as_raw_fd(),
+                offset as libc::off64_t,
+                len as libc::off64_t,
+                libc::SYNC_FILE_RANGE_WRITE,
+            )
+        };
+        if ret != 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        Ok(())
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn sync_range(&mut self, _offset: u64, _len: u64) -> Result<()> {
+        self.0.flush()?;
+        self.0.get_ref().sync_data()?;
+        Ok(())
+    }
+}
+
+struct DiskRandomAccessFile {
+    reader: FileReader,
+    size: u64,
+}
+
+impl RandomAccessFile for DiskRandomAccessFile {
+    fn read_at(&self, offset: u64, len: u64) -> Result<Bytes> {
+        self.reader.read(offset, len)
+    }
+
+    fn size(&self) -> u64 {
+        self.size
+    }
+}
+
+/// An open file read with one of the backends.
+enum FileReader {
+    Pread(File),
+    /// The whole mapping, which reads are sliced from. Empty files cannot be mapped, and are read
+    /// as an empty buffer.
+    Mmap(Bytes),
+    DirectIo(File),
+}
+
+impl FileReader {
+    fn open(path: &Path, backend: ReadBackend) -> Result<(Self, u64)> {
+        let reader = match backend {
+            ReadBackend::Pread => Self::Pread(File::open(path)?),
+            ReadBackend::Mmap => {
+                let file = File::open(path)?;
+                if file.metadata()?.len() == 0 {
+                    Self::Mmap(Bytes::new())
+                } else {
+                    // SAFETY: SST and value log files are never modified after they are written.
+                    Self::Mmap(Bytes::from_owner(unsafe { Mmap::map(&file)? }))
+                }
+            }
+            ReadBackend::DirectIo => Self::open_direct(path)?,
+        };
+        let size = match &reader {
+            Self::Pread(file) | Self::DirectIo(file) => file.metadata()?.len(),
+            Self::Mmap(mmap) => mmap.len() as u64,
+        };
+        Ok((reader, size))
+    }
+
+    #[cfg(target_os = "linux")]
+    fn open_direct(path: &Path) -> Result<Self> {
+        use std::os::unix::fs::OpenOptionsExt;
+        match File::options()
+            .read(true)
+            .custom_flags(libc::O_DIRECT)
+            .open(path)
+        {
+            Ok(file) => Ok(Self::DirectIo(file)),
+            Err(e) if e.raw_os_error() == Some(libc::EINVAL) => Ok(Self::Pread(File::open(path)?)),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn open_direct(path: &Path) -> Result<Self> {
+        Ok(Self::Pread(File::open(path)?))
+    }
+
+    fn read(&self, offset: u64, len: u64) -> Result<Bytes> {
+        match self {
+            Self::Pread(file) => {
+                let mut data = vec![0; len as usize];
+                file.read_exact_at(&mut data[..], offset)?;
+                Ok(data.into())
+            }
+            Self::Mmap(mmap) => {
+                let end = offset.saturating_add(len);
+                if end > mmap.len() as u64 {
+                    bail!(
+                        "read of {} bytes at {} is beyond the end of the file at {}",
+                        len,
+                        offset,
+                        mmap.len()
+                    );
+                }
+                Ok(mmap.slice(offset as usize..end as usize))
+            }
+            Self::DirectIo(file) => Ok(read_direct(file, offset, len)?.into()),
+        }
+    }
+}
+
+/// Read the aligned range around `[offset, offset + len)` into an aligned buffer, and copy the
+/// requested bytes out of it.
+fn read_direct(file: &File, offset: u64, len: u64) -> Result<Vec<u8>> {
+    let align = DIRECT_IO_ALIGNMENT as u64;
+    let aligned_offset = offset / align * align;
+    let aligned_end = (offset + len).div_ceil(align) * align;
+    let aligned_len = (aligned_end - aligned_offset) as usize;
+    let mut buf = vec![0; aligned_len + DIRECT_IO_ALIGNMENT];
+    let start = buf.as_ptr().align_offset(DIRECT_IO_ALIGNMENT);
+    let buf = &mut buf[start..start + aligned_len];
+    // the last block of the file is read short
+    let mut read = 0;
+    while read < aligned_len {
+        match file.read_at(&mut buf[read..], aligned_offset + read as u64) {
+            Ok(0) => break,
+            Ok(n) => read += n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+    let skip = (offset - aligned_offset) as usize;
+    if read < skip + len as usize {
+        bail!(
+            "read of {} bytes at {} is beyond the end of the file at {}",
+            len,
+            offset,
+            aligned_offset + read as u64
+        );
+    }
+    Ok(buf[skip..skip + len as usize].to_vec())
+}
@@ -0,0 +1,324 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{Result, bail};
+use bytes::Bytes;
+use parking_lot::Mutex;
+
+use super::{FileSystem, RandomAccessFile, ReadBackend, WritableFile};
+
+#[derive(Debug, Default)]
+struct FaultState {
+    /// Whether the file system has stopped, i.e., the process has crashed.
+    inactive: bool,
+    /// Number of appends and syncs so far, including directory syncs.
+    num_writes: usize,
+    num_syncs: usize,
+    /// The append that fails, counted from the first one.
+    fail_write_at: Option<usize>,
+    /// The append that writes half of its data and fails.
+    tear_write_at: Option<usize>,
+    /// The sync that fails.
+    fail_sync_at: Option<usize>,
+    /// The length that has been synced of each file written through this file system.
+    synced_len: HashMap<PathBuf, u64>,
+    /// What the paths created, renamed or removed since their directory was last synced hold
+    /// after a power loss: the synced data, or `None` if they did not exist.
+    unsynced_dir_ops: HashMap<PathBuf, Option<Vec<u8>>>,
+}
+
+impl FaultState {
+    fn check_active(&self) -> Result<()> {
+        if self.inactive {
+            bail!("file system is inactive");
+        }
+        Ok(())
+    }
+}
+
+/// A file system over another one that injects failures, to test that no acknowledged write is
+/// lost when the storage engine crashes. It keeps track of the data that has not been synced, so
+/// that a crash can drop it as a power loss would, along with the creations, renames and removals
+/// of files whose directory has not been synced.
+#[derive(Debug, Clone)]
+pub struct FaultInjectionFileSystem {
+    base: Arc<dyn FileSystem>,
+    state: Arc<Mutex<FaultState>>,
+}
+
+impl FaultInjectionFileSystem {
+    pub fn new(base: Arc<dyn FileSystem>) -> Self {
+        Self {
+            base,
+            state: Default::default(),
+        }
+    }
+
+    /// Fail the `n`-th append from now on, starting from 1.
+    pub fn fail_nth_write(&self, n: usize) {
+        let mut state = self.state.lock();
+        state.fail_write_at = Some(state.num_writes + n);
+    }
+
+    /// Write half of the data of the `n`-th append from now on and fail it, starting from 1.
+    pub fn tear_nth_write(&self, n: usize) {
+        let mut state = self.state.lock();
+        state.tear_write_at = Some(state.num_writes + n);
+    }
+
+    /// Fail the `n`-th file or directory sync from now on, starting from 1.
+    pub fn fail_nth_sync(&self, n: usize) {
+        let mut state = self.state.lock();
+        state.fail_sync_at = Some(state.num_syncs + n);
+    }
+
+    /// Fail all operations from now on, as if the process has crashed.
+    pub fn deactivate(&self) {
+        self.state.lock().inactive = true;
+    }
+
+    /// Drop the data that has not been synced: the files created, renamed or removed since their
+    /// directory was last synced are put back as they were, and the other files are truncated to
+    /// the length last synced.
+    pub fn drop_unsynced_data(&self) -> Result<()> {
+        let mut state = self.state.lock();
+        for (path, data) in std::mem::take(&mut state.unsynced_dir_ops) {
+            state.synced_len.remove(&path);
+            match data {
+                Some(data) => {
+                    let mut file = self.base.create(&path)?;
+                    file.append(&data)?;
+                    file.sync()?;
+                }
+                None if self.base.exists(&path) => self.base.remove(&path)?,
+                None => {}
+            }
+        }
+        for (path, synced_len) in state.synced_len.drain() {
+            if !self.base.exists(&path) {
+                continue;
+            }
+            let data = self.base.read(&path)?;
+            if data.len() as u64 > synced_len {
+                let mut file = self.base.create(&path)?;
+                file.append(&data[..synced_len as usize])?;
+                file.sync()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Accept operations again and clear the pending failures.
+    pub fn reset(&self) {
+        let mut state = self.state.lock();
+        state.inactive = false;
+        state.fail_write_at = None;
+        state.tear_write_at = None;
+        state.fail_sync_at = None;
+    }
+
+    /// Number of appends so far.
+    pub fn num_writes(&self) -> usize {
+        self.state.lock().num_writes
+    }
+
+    /// Number of file and directory syncs so far.
+    pub fn num_syncs(&self) -> usize {
+        self.state.lock().num_syncs
+    }
+
+    /// Remember what `path` holds after a power loss before a creation, rename or removal changes
+    /// it, unless it has changed since its directory was last synced.
+    fn record_dir_op(&self, state: &mut FaultState, path: &Path) -> Result<()> {
+        if state.unsynced_dir_ops.contains_key(path) {
+            return Ok(());
+        }
+        let data = if self.base.exists(path) {
+            let mut data = self.base.read(path)?;
+            if let Some(synced_len) = state.synced_len.get(path) {
+                data.truncate(*synced_len as usize);
+            }
+            Some(data)
+        } else {
+            None
+        };
+        state.unsynced_dir_ops.insert(path.to_path_buf(), data);
+        Ok(())
+    }
+
+    fn check_sync(state: &mut FaultState) -> Result<()> {
+        state.check_active()?;
+        state.num_syncs += 1;
+        if state.fail_sync_at == Some(state.num_syncs) {
+            bail!("injected sync error");
+        }
+        Ok(())
+    }
+}
+
+impl FileSystem for FaultInjectionFileSystem {
+    fn create(&self, path: &Path) -> Result<Box<dyn WritableFile>> {
+        let mut state = self.state.lock();
+        state.check_active()?;
+        if !self.base.exists(path) {
+            self.record_dir_op(&mut state, path)?;
+        }
+        let file = self.base.create(path)?;
+        state.synced_len.insert(path.to_path_buf(), 0);
+        Ok(Box::new(FaultInjectionWritableFile {
+            file,
+            path: path.to_path_buf(),
+            len: 0,
+            state: self.state.clone(),
+        }))
+    }
+
+    fn open_append(&self, path: &Path) -> Result<Box<dyn WritableFile>> {
+        let mut state = self.state.lock();
+        state.check_active()?;
+        let len = self.base.open(path, ReadBackend::Pread)?.size();
+        let file = self.base.open_append(path)?;
+        state.synced_len.entry(path.to_path_buf()).or_insert(len);
+        Ok(Box::new(FaultInjectionWritableFile {
+            file,
+            path: path.to_path_buf(),
+            len,
+            state: self.state.clone(),
+        }))
+    }
+
+    fn open(&self, path: &Path, backend: ReadBackend) -> Result<Box<dyn RandomAccessFile>> {
+        self.state.lock().check_active()?;
+        let file = self.base.open(path, backend)?;
+        Ok(Box::new(FaultInjectionRandomAccessFile {
+            file,
+            state: self.state.clone(),
+        }))
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> Result<()> {
+        let mut state = self.state.lock();
+        state.check_active()?;
+        self.record_dir_op(&mut state, from)?;
+        self.record_dir_op(&mut state, to)?;
+        self.base.rename(from, to)?;
+        match state.synced_len.remove(from) {
+            Some(synced_len) => state.synced_len.insert(to.to_path_buf(), synced_len),
+            None => state.synced_len.remove(to),
+        };
+        Ok(())
+    }
+
+    fn remove(&self, path: &Path) -> Result<()> {
+        let mut state = self.state.lock();
+        state.check_active()?;
+        self.record_dir_op(&mut state, path)?;
+        self.base.remove(path)?;
+        state.synced_len.remove(path);
+        Ok(())
+    }
+
+    fn list_dir(&self, path: &Path) -> Result<Vec<PathBuf>> {
+        self.state.lock().check_active()?;
+        self.base.list_dir(path)
+    }
+
+    fn create_dir_all(&self, path: &Path) -> Result<()> {
+        self.state.lock().check_active()?;
+        self.base.create_dir_all(path)
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        self.base.exists(path)
+    }
+
+    fn sync_dir(&self, path: &Path) -> Result<()> {
+        let mut state = self.state.lock();
+        Self::check_sync(&mut state)?;
+        self.base.sync_dir(path)?;
+        state
+            .unsynced_dir_ops
+            .retain(|x, _| x.parent() != Some(path));
+        Ok(())
+    }
+}
+
+struct FaultInjectionWritableFile {
+    file: Box<dyn WritableFile>,
+    path: PathBuf,
+    /// Number of bytes written to the file.
+    len: u64,
+    state: Arc<Mutex<FaultState>>,
+}
+
+impl WritableFile for FaultInjectionWritableFile {
+    fn append(&mut self, data: &[u8]) -> Result<()> {
+        let mut state = self.state.lock();
+        state.check_active()?;
+        state.num_writes += 1;
+        if state.fail_write_at == Some(state.num_writes) {
+            bail!("injected write error");
+        }
+        if state.tear_write_at == Some(state.num_writes) {
+            let half = &data[..data.len() / 2];
+            self.file.append(half)?;
+            self.len += half.len() as u64;
+            bail!("injected torn write");
+        }
+        self.file.append(data)?;
+        self.len += data.len() as u64;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.state.lock().check_active()?;
+        self.file.flush()
+    }
+
+    fn sync(&mut self) -> Result<()> {
+        let mut state = self.state.lock();
+        FaultInjectionFileSystem::check_sync(&mut state)?;
+        self.file.sync()?;
+        // the file may have been removed or replaced since it was opened
+        if let Some(synced_len) = state.synced_len.get_mut(&self.path) {
+            *synced_len = self.len;
+        }
+        Ok(())
+    }
+
+    fn sync_range(&mut self, offset: u64, len: u64) -> Result<()> {
+        self.state.lock().check_active()?;
+        self.file.sync_range(offset, len)
+    }
+}
+
+struct FaultInjectionRandomAccessFile {
+    file: Box<dyn RandomAccessFile>,
+    state: Arc<Mutex<FaultState>>,
+}
+
+impl RandomAccessFile for FaultInjectionRandomAccessFile {
+    fn read_at(&self, offset: u64, len: u64) -> Result<Bytes> {
+        self.state.lock().check_active()?;
+        self.file.read_at(offset, len)
+    }
+
+    fn size(&self) -> u64 {
+        self.file.size()
+    }
+}
@@ -0,0 +1,172 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::{BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{Result, bail};
+use bytes::Bytes;
+use parking_lot::{Mutex, RwLock};
+
+use super::{FileSystem, RandomAccessFile, ReadBackend, WritableFile};
+
+type FileData = Arc<RwLock<Vec<u8>>>;
+
+#[derive(Debug, Default)]
+struct MemFileSystemState {
+    files: HashMap<PathBuf, FileData>,
+    dirs: BTreeSet<PathBuf>,
+}
+
+/// A file system that keeps all files in memory, so that a storage engine can run without
+/// touching the disk. Clones share the same files, so that a storage engine can be reopened on
+/// them.
+#[derive(Debug, Default, Clone)]
+pub struct MemFileSystem {
+    state: Arc<Mutex<MemFileSystemState>>,
+}
+
+impl MemFileSystem {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn file(&self, path: &Path) -> Result<FileData> {
+        match self.state.lock().files.get(path) {
+            Some(data) => Ok(data.clone()),
+            None => bail!("file not found: {}", path.display()),
+        }
+    }
+}
+
+impl FileSystem for MemFileSystem {
+    fn create(&self, path: &Path) -> Result<Box<dyn WritableFile>> {
+        let mut state = self.state.lock();
+        if let Some(parent) = path.parent()
+            && !parent.as_os_str().is_empty()
+            && !state.dirs.contains(parent)
+        {
+            bail!("directory not found: {}", parent.display());
+        }
+        let data = FileData::default();
+        state.files.insert(path.to_path_buf(), data.clone());
+        Ok(Box::new(MemWritableFile(data)))
+    }
+
+    fn open_append(&self, path: &Path) -> Result<Box<dyn WritableFile>> {
+        Ok(Box::new(MemWritableFile(self.file(path)?)))
+    }
+
+    fn open(&self, path: &Path, _backend: ReadBackend) -> Result<Box<dyn RandomAccessFile>> {
+        let data = self.file(path)?;
+        let size = data.read().len() as u64;
+        Ok(Box::new(MemRandomAccessFile { data, size }))
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> Result<()> {
+        let mut state = self.state.lock();
+        let Some(data) = state.files.remove(from) else {
+            bail!("file not found: {}", from.display());
+        };
+        state.files.insert(to.to_path_buf(), data);
+        Ok(())
+    }
+
+    fn remove(&self, path: &Path) -> Result<()> {
+        if self.state.lock().files.remove(path).is_none() {
+            bail!("file not found: {}", path.display());
+        }
+        Ok(())
+    }
+
+    fn list_dir(&self, path: &Path) -> Result<Vec<PathBuf>> {
+        let state = self.state.lock();
+        if !state.dirs.contains(path) {
+            bail!("directory not found: {}", path.display());
+        }
+        Ok(state
+            .files
+            .keys()
+            .chain(state.dirs.iter())
+            .filter(|x| x.parent() == Some(path))
+            .cloned()
+            .collect())
+    }
+
+    fn create_dir_all(&self, path: &Path) -> Result<()> {
+        let mut state = self.state.lock();
+        for dir in path.ancestors() {
+            if !dir.as_os_str().is_empty() {
+                state.dirs.insert(dir.to_path_buf());
+            }
+        }
+        Ok(())
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        let state = self.state.lock();
+        state.files.contains_key(path) || state.dirs.contains(path)
+    }
+
+    fn sync_dir(&self, path: &Path) -> Result<()> {
+        if !self.state.lock().dirs.contains(path) {
+            bail!("directory not found: {}", path.display());
+        }
+        Ok(())
+    }
+}
+
+struct MemWritableFile(FileData);
+
+impl WritableFile for MemWritableFile {
+    fn append(&mut self, data: &[u8]) -> Result<()> {
+        self.0.write().extend_from_slice(data);
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn sync(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Readers keep the data of a removed file, like an open file on disk.
+struct MemRandomAccessFile {
+    data: FileData,
+    size: u64,
+}
+
+impl RandomAccessFile for MemRandomAccessFile {
+    fn read_at(&self, offset: u64, len: u64) -> Result<Bytes> {
+        let data = self.data.read();
+        let end = offset.saturating_add(len);
+        if end > self.size {
+            bail!(
+                "read of {} bytes at {} is beyond the end of the file at {}",
+                len,
+                offset,
+                self.size
+            );
+        }
+        Ok(Bytes::copy_from_slice(&data[offset as usize..end as usize]))
+    }
+
+    fn size(&self) -> u64 {
+        self.size
+    }
+}
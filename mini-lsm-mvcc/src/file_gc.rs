@@ -0,0 +1,218 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Deletes the files that are no longer part of the LSM tree: the inputs of compactions and the
+//! value logs that have been garbage collected, once no reader uses them, and the files a crash
+//! left behind.
+
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Result;
+
+use crate::lsm_storage::LsmStorageInner;
+use crate::manifest::{CURRENT_FILE_NAME, manifest_number};
+use crate::table::SsTable;
+use crate::vlog::ValueLog;
+
+/// How often the file GC thread looks for files to delete.
+const FILE_GC_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Parse the ID and the extension of a file named like `00001.sst`.
+pub(crate) fn parse_file_name(path: &Path) -> Option<(usize, &str)> {
+    let id = path.file_stem()?.to_str()?.parse().ok()?;
+    Some((id, path.extension()?.to_str()?))
+}
+
+#[derive(Default)]
+pub(crate) struct FileGcState {
+    /// SSTs removed from the LSM tree, deleted once the snapshots that refer to them are dropped.
+    obsolete_ssts: Vec<Arc<SsTable>>,
+    obsolete_value_logs: Vec<Arc<ValueLog>>,
+    /// The first ID that each compaction in progress may allocate. Its outputs are not in the
+    /// manifest until it finishes.
+    pending_outputs: Vec<usize>,
+}
+
+/// Keeps the file GC from deleting the SSTs a compaction writes until it is dropped.
+pub(crate) struct PendingOutputs<'a> {
+    inner: &'a LsmStorageInner,
+    first_id: usize,
+}
+
+impl Drop for PendingOutputs<'_> {
+    fn drop(&mut self) {
+        let mut gc = self.inner.file_gc.lock();
+        if let Some(idx) = gc.pending_outputs.iter().position(|x| *x == self.first_id) {
+            gc.pending_outputs.swap_remove(idx);
+        }
+    }
+}
+
+/// Whether a file in the directory of the storage engine is still needed.
+fn is_live(
+    path: &Path,
+    live_ids: &HashSet<(usize, &str)>,
+    min_pending_output: usize,
+    manifest_path: &Path,
+    enable_wal: bool,
+) -> bool {
+    if let Some((id, extension)) = parse_file_name(path) {
+        return match extension {
+            "sst" => id >= min_pending_output || live_ids.contains(&(id, "sst")),
+            // without WAL, the WALs of the memtables in the manifest are not recovered but kept
+            "wal" => !enable_wal || live_ids.contains(&(id, "wal")),
+            "vlog" => live_ids.contains(&(id, "vlog")),
+            _ => true,
+        };
+    }
+    if manifest_number(path).is_some() {
+        return path == manifest_path;
+    }
+    // the temporary files of switching CURRENT and of rewriting a torn manifest
+    if path.extension().is_some_and(|x| x == "tmp")
+        && let Some(stem) = path.file_stem()
+    {
+        let stem = Path::new(stem);
+        return !(stem == Path::new(CURRENT_FILE_NAME) || manifest_number(stem).is_some());
+    }
+    true
+}
+
+impl LsmStorageInner {
+    /// Register a compaction that is about to allocate IDs for its outputs.
+    pub(crate) fn pending_outputs(&self) -> PendingOutputs<'_> {
+        let mut gc = self.file_gc.lock();
+        // IDs are allocated after this one is read, so that the GC never sees an unprotected output
+        let first_id = self.peek_next_sst_id();
+        gc.pending_outputs.push(first_id);
+        PendingOutputs {
+            inner: self,
+            first_id,
+        }
+    }
+
+    /// Delete the SSTs once no reader uses them. They must have been removed from the state.
+    pub(crate) fn add_obsolete_ssts(&self, ssts: Vec<Arc<SsTable>>) {
+        self.file_gc.lock().obsolete_ssts.extend(ssts);
+    }
+
+    /// Delete the value logs once no reader uses them. They must have been removed from the
+    /// value logs of the storage engine.
+    pub(crate) fn add_obsolete_value_logs(&self, value_logs: Vec<Arc<ValueLog>>) {
+        self.file_gc.lock().obsolete_value_logs.extend(value_logs);
+    }
+
+    /// Delete the obsolete files that no snapshot refers to any more.
+    pub(crate) fn delete_obsolete_files(&self) -> Result<()> {
+        let (ssts, value_logs) = {
+            let mut gc = self.file_gc.lock();
+            // only the GC holds a file that is in no snapshot, so no reader can get it again
+            let (ssts, pinned) = std::mem::take(&mut gc.obsolete_ssts)
+                .into_iter()
+                .partition::<Vec<_>, _>(|x| Arc::strong_count(x) == 1);
+            gc.obsolete_ssts = pinned;
+            let (value_logs, pinned) = std::mem::take(&mut gc.obsolete_value_logs)
+                .into_iter()
+                .partition::<Vec<_>, _>(|x| Arc::strong_count(x) == 1);
+            gc.obsolete_value_logs = pinned;
+            (ssts, value_logs)
+        };
+        if ssts.is_empty() && value_logs.is_empty() {
+            return Ok(());
+        }
+        for sst in ssts {
+            self.options
+                .file_system
+                .remove(&self.path_of_sst(sst.sst_id()))?;
+        }
+        for vlog in value_logs {
+            self.options
+                .file_system
+                .remove(&self.path_of_vlog(vlog.id()))?;
+        }
+        self.sync_dir()
+    }
+
+    /// Delete the SSTs, WALs, value logs and manifests in the directory that the storage engine
+    /// does not know of, e.g., those left behind by a crash between recording a compaction in the
+    /// manifest and deleting its inputs. Returns the deleted files.
+    pub(crate) fn purge_orphan_files(&self) -> Result<Vec<PathBuf>> {
+        let orphans = {
+            // flushes and manifest roll-overs create files while holding the state lock
+            let _state_lock = self.state_lock.lock();
+            let files = self.options.file_system.list_dir(&self.path)?;
+            let state = self.state.read().clone();
+            let value_logs = self.value_logs.read().clone();
+            let gc = self.file_gc.lock();
+            let mut live_ids = HashSet::new();
+            live_ids.extend(state.sstables.keys().map(|x| (*x, "sst")));
+            live_ids.extend(gc.obsolete_ssts.iter().map(|x| (x.sst_id(), "sst")));
+            live_ids.insert((state.memtable.id(), "wal"));
+            live_ids.extend(state.imm_memtables.iter().map(|x| (x.id(), "wal")));
+            live_ids.extend(value_logs.keys().map(|x| (*x, "vlog")));
+            live_ids.extend(gc.obsolete_value_logs.iter().map(|x| (x.id(), "vlog")));
+            let min_pending_output = gc.pending_outputs.iter().min().copied();
+            let manifest_path = self.manifest().path();
+            files
+                .into_iter()
+                .filter(|x| {
+                    !is_live(
+                        x,
+                        &live_ids,
+                        min_pending_output.unwrap_or(usize::MAX),
+                        &manifest_path,
+                        self.options.enable_wal,
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+        if orphans.is_empty() {
+            return Ok(orphans);
+        }
+        for path in &orphans {
+            println!("deleting orphan file {}", path.display());
+            self.options.file_system.remove(path)?;
+        }
+        self.sync_dir()?;
+        Ok(orphans)
+    }
+
+    pub(crate) fn trigger_file_gc(&self) -> Result<()> {
+        self.delete_obsolete_files()?;
+        self.purge_orphan_files()?;
+        Ok(())
+    }
+
+    pub(crate) fn spawn_file_gc_thread(
+        self: &Arc<Self>,
+        rx: crossbeam_channel::Receiver<()>,
+    ) -> Result<Option<std::thread::JoinHandle<()>>> {
+        let this = self.clone();
+        let handle = std::thread::spawn(move || {
+            let ticker = crossbeam_channel::tick(FILE_GC_INTERVAL);
+            loop {
+                crossbeam_channel::select! {
+                    recv(ticker) -> _ => if let Err(e) = this.trigger_file_gc() {
+                        eprintln!("file gc failed: {}", e);
+                    },
+                    recv(rx) -> _ => return
+                }
+            }
+        });
+        Ok(Some(handle))
+    }
+}
@@ -30,6 +30,11 @@ pub trait StorageIterator {
     /// Check if the current iterator is valid.
     fn is_valid(&self) -> bool;
 
+    /// Check if the current value is an encoded pointer into the value log instead of the value.
+    fn is_value_pointer(&self) -> bool {
+        false
+    }
+
     /// Move to the next position.
     fn next(&mut self) -> anyhow::Result<()>;
 
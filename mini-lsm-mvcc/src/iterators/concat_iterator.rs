@@ -108,7 +108,7 @@ impl SstConcatIterator {
 impl StorageIterator for SstConcatIterator {
     type KeyType<'a> = KeySlice<'a>;
 
-    fn key(&self) -> KeySlice {
+    fn key(&self) -> KeySlice<'_> {
         self.current.as_ref().unwrap().key()
     }
 
@@ -125,6 +125,10 @@ impl StorageIterator for SstConcatIterator {
         }
     }
 
+    fn is_value_pointer(&self) -> bool {
+        self.current.as_ref().unwrap().is_value_pointer()
+    }
+
     fn next(&mut self) -> Result<()> {
         self.current.as_mut().unwrap().next()?;
         self.move_until_valid()?;
@@ -91,6 +91,14 @@ impl<
         }
     }
 
+    fn is_value_pointer(&self) -> bool {
+        if self.choose_a {
+            self.a.is_value_pointer()
+        } else {
+            self.b.is_value_pointer()
+        }
+    }
+
     fn next(&mut self) -> Result<()> {
         if self.choose_a {
             self.a.next()?;
@@ -15,6 +15,8 @@
 pub mod block;
 pub mod compact;
 pub mod debug;
+pub mod env;
+mod file_gc;
 pub mod iterators;
 pub mod key;
 pub mod lsm_iterator;
@@ -22,8 +24,14 @@ pub mod lsm_storage;
 pub mod manifest;
 pub mod mem_table;
 pub mod mvcc;
+pub mod range_tombstone;
+mod repair;
 pub mod table;
+pub mod tools;
+pub(crate) mod varint;
+pub mod vlog;
 pub mod wal;
+pub mod write_buffer_manager;
 
 #[cfg(test)]
 mod tests;
@@ -12,7 +12,9 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::collections::HashMap;
 use std::ops::Bound;
+use std::sync::Arc;
 
 use anyhow::{Result, bail};
 use bytes::Bytes;
@@ -22,10 +24,12 @@ use crate::iterators::concat_iterator::SstConcatIterator;
 use crate::iterators::merge_iterator::MergeIterator;
 use crate::iterators::two_merge_iterator::TwoMergeIterator;
 use crate::mem_table::MemTableIterator;
+use crate::range_tombstone::FragmentedRangeTombstones;
 use crate::table::SsTableIterator;
+use crate::vlog::{ValueLog, resolve_value_pointer};
 
 /// Represents the internal type for an LSM iterator. This type will be changed across the course for multiple times.
-type LsmIteratorInner = TwoMergeIterator<
+pub(crate) type LsmIteratorInner = TwoMergeIterator<
     TwoMergeIterator<MergeIterator<MemTableIterator>, MergeIterator<SsTableIterator>>,
     MergeIterator<SstConcatIterator>,
 >;
@@ -36,6 +40,12 @@ pub struct LsmIterator {
     is_valid: bool,
     read_ts: u64,
     prev_key: Vec<u8>,
+    /// The value log files that pointers in the snapshot may refer to.
+    value_logs: Arc<HashMap<usize, Arc<ValueLog>>>,
+    /// The value read from the value log if the current entry stores a pointer.
+    resolved_value: Option<Bytes>,
+    /// The range tombstones that may cover keys in the range of the iterator.
+    range_tombstones: FragmentedRangeTombstones,
 }
 
 impl LsmIterator {
@@ -43,29 +53,39 @@ impl LsmIterator {
         iter: LsmIteratorInner,
         end_bound: Bound<Bytes>,
         read_ts: u64,
+        value_logs: Arc<HashMap<usize, Arc<ValueLog>>>,
+        range_tombstones: FragmentedRangeTombstones,
     ) -> Result<Self> {
         let mut iter = Self {
-            is_valid: iter.is_valid(),
+            is_valid: false,
             inner: iter,
             end_bound,
             read_ts,
             prev_key: Vec::new(),
+            value_logs,
+            resolved_value: None,
+            range_tombstones,
         };
+        iter.update_is_valid();
         iter.move_to_key()?;
         Ok(iter)
     }
 
-    fn next_inner(&mut self) -> Result<()> {
-        self.inner.next()?;
+    fn update_is_valid(&mut self) {
         if !self.inner.is_valid() {
             self.is_valid = false;
-            return Ok(());
-        }
-        match self.end_bound.as_ref() {
-            Bound::Unbounded => {}
-            Bound::Included(key) => self.is_valid = self.inner.key().key_ref() <= key.as_ref(),
-            Bound::Excluded(key) => self.is_valid = self.inner.key().key_ref() < key.as_ref(),
+            return;
         }
+        self.is_valid = match self.end_bound.as_ref() {
+            Bound::Unbounded => true,
+            Bound::Included(key) => self.inner.key().key_ref() <= key.as_ref(),
+            Bound::Excluded(key) => self.inner.key().key_ref() < key.as_ref(),
+        };
+    }
+
+    fn next_inner(&mut self) -> Result<()> {
+        self.inner.next()?;
+        self.update_is_valid();
         Ok(())
     }
 
@@ -91,10 +111,17 @@ impl LsmIterator {
             if self.inner.key().key_ref() != self.prev_key {
                 continue;
             }
-            if !self.inner.value().is_empty() {
+            if !self.inner.value().is_empty()
+                && !self.range_tombstones.covers(self.inner.key(), self.read_ts)
+            {
                 break;
             }
         }
+        self.resolved_value = None;
+        if self.is_valid && self.inner.is_value_pointer() {
+            self.resolved_value =
+                Some(resolve_value_pointer(&self.value_logs, self.inner.value())?);
+        }
         Ok(())
     }
 }
@@ -111,7 +138,10 @@ impl StorageIterator for LsmIterator {
     }
 
     fn value(&self) -> &[u8] {
-        self.inner.value()
+        match &self.resolved_value {
+            Some(value) => value,
+            None => self.inner.value(),
+        }
     }
 
     fn next(&mut self) -> Result<()> {
@@ -171,11 +201,11 @@ impl<I: StorageIterator> StorageIterator for FusedIterator<I> {
         if self.has_errored {
             bail!("the iterator is tainted");
         }
-        if self.iter.is_valid() {
-            if let Err(e) = self.iter.next() {
-                self.has_errored = true;
-                return Err(e);
-            }
+        if self.iter.is_valid()
+            && let Err(e) = self.iter.next()
+        {
+            self.has_errored = true;
+            return Err(e);
         }
         Ok(())
     }
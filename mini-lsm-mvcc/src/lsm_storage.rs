@@ -13,35 +13,50 @@
 // limitations under the License.
 
 use std::collections::{BTreeSet, HashMap};
-use std::fs::File;
-use std::ops::Bound;
+use std::ops::{Bound, Deref};
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::sync::atomic::AtomicUsize;
 
-use anyhow::{Context, Result};
+use anyhow::{Context, Result, bail};
 use bytes::Bytes;
 use parking_lot::{Mutex, MutexGuard, RwLock};
 
 use crate::block::Block;
 use crate::compact::{
-    CompactionController, CompactionOptions, LeveledCompactionController, LeveledCompactionOptions,
-    SimpleLeveledCompactionController, SimpleLeveledCompactionOptions, TieredCompactionController,
+    CompactionController, CompactionOptions, LeveledCompactionOptions,
+    SimpleLeveledCompactionOptions,
 };
+use crate::env::{DiskFileSystem, FileSystem, ReadBackend};
+use crate::file_gc::FileGcState;
 use crate::iterators::StorageIterator;
 use crate::iterators::concat_iterator::SstConcatIterator;
 use crate::iterators::merge_iterator::MergeIterator;
 use crate::iterators::two_merge_iterator::TwoMergeIterator;
 use crate::key::{self, KeySlice};
-use crate::lsm_iterator::{FusedIterator, LsmIterator};
-use crate::manifest::{Manifest, ManifestRecord};
-use crate::mem_table::{MemTable, map_bound, map_key_bound_plus_ts};
+use crate::lsm_iterator::{FusedIterator, LsmIterator, LsmIteratorInner};
+use crate::manifest::{
+    Manifest, ManifestReplayer, NewFile, VersionEdit, current_manifest_path, manifest_file_name,
+    set_current_manifest,
+};
+use crate::mem_table::{MemTable, MemTableRepType, map_bound, map_key_bound_plus_ts};
 use crate::mvcc::LsmMvccInner;
 use crate::mvcc::txn::{Transaction, TxnIterator};
-use crate::table::{FileObject, SsTable, SsTableBuilder, SsTableIterator};
+use crate::range_tombstone::{FragmentedRangeTombstones, RangeTombstone};
+use crate::table::{
+    BloomFilterPolicy, CompactionReason, CompressionType, FileObject, FilterPolicy,
+    PrefixExtractor, SsTable, SsTableBuilder, SsTableIterator, prefix_of_range, prefix_upper_bound,
+};
+use crate::vlog::{ValueLog, ValueLogBuilder, ValueLogGcState};
+use crate::wal::Wal;
+use crate::write_buffer_manager::WriteBufferManager;
 
 pub type BlockCache = moka::sync::Cache<(usize, usize), Arc<Block>>;
 
+/// The maximum size of a single key-value pair. Entries are addressed with u32 offsets inside a
+/// block and a WAL batch, so larger entries cannot be stored.
+pub const MAX_ENTRY_SIZE: usize = 1 << 30;
+
 /// Represents the state of the storage engine.
 #[derive(Clone)]
 pub struct LsmStorageState {
@@ -55,17 +70,60 @@ pub struct LsmStorageState {
     /// compaction.
     pub levels: Vec<(usize, Vec<usize>)>,
     /// SST objects.
-    pub sstables: HashMap<usize, Arc<SsTable>>,
+    pub sstables: SsTables,
+}
+
+/// SST objects by ID, which also keeps track of the SSTs with range tombstones, so that reads do
+/// not have to go through all SSTs for them.
+#[derive(Clone, Default)]
+pub struct SsTables {
+    sstables: HashMap<usize, Arc<SsTable>>,
+    range_tombstone_sstables: BTreeSet<usize>,
+}
+
+impl SsTables {
+    /// Add an SST object, returning the one with the same ID if any.
+    pub fn insert(&mut self, sst_id: usize, sst: Arc<SsTable>) -> Option<Arc<SsTable>> {
+        if sst.range_tombstones().is_empty() {
+            self.range_tombstone_sstables.remove(&sst_id);
+        } else {
+            self.range_tombstone_sstables.insert(sst_id);
+        }
+        self.sstables.insert(sst_id, sst)
+    }
+
+    /// Remove an SST object, returning it if it exists.
+    pub fn remove(&mut self, sst_id: &usize) -> Option<Arc<SsTable>> {
+        self.range_tombstone_sstables.remove(sst_id);
+        self.sstables.remove(sst_id)
+    }
+
+    /// The SSTs with range tombstones.
+    pub fn with_range_tombstones(&self) -> impl Iterator<Item = &Arc<SsTable>> {
+        self.range_tombstone_sstables
+            .iter()
+            .map(|id| &self.sstables[id])
+    }
+}
+
+impl Deref for SsTables {
+    type Target = HashMap<usize, Arc<SsTable>>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.sstables
+    }
 }
 
 pub enum WriteBatchRecord<T: AsRef<[u8]>> {
     Put(T, T),
     Del(T),
+    /// Delete all keys in `[start, end)`.
+    DelRange(T, T),
 }
 
 impl LsmStorageState {
-    fn create(options: &LsmStorageOptions) -> Self {
-        let levels = match &options.compaction_options {
+    pub(crate) fn create(compaction_options: &CompactionOptions) -> Self {
+        let levels = match compaction_options {
             CompactionOptions::Leveled(LeveledCompactionOptions { max_levels, .. })
             | CompactionOptions::Simple(SimpleLeveledCompactionOptions { max_levels, .. }) => (1
                 ..=*max_levels)
@@ -88,46 +146,122 @@ impl LsmStorageState {
 pub struct LsmStorageOptions {
     // Block size in bytes
     pub block_size: usize,
+    // Number of keys between two restart points in a data block, where a key is stored in full
+    // instead of prefix-compressed against the previous one
+    pub block_restart_interval: usize,
     // SST size in bytes, also the approximate memtable capacity limit
     pub target_sst_size: usize,
     // Maximum number of memtables in memory, flush to L0 when exceeding this limit
     pub num_memtable_limit: usize,
+    // Flush up to this many immutable memtables together into one L0 SST, `usize::MAX` flushes
+    // all of them at once
+    pub max_memtables_per_flush: usize,
     pub compaction_options: CompactionOptions,
     pub enable_wal: bool,
     pub serializable: bool,
+    // Codec used to compress SST data blocks
+    pub compression: CompressionType,
+    // Values of at least this many bytes are moved to the value log when flushed, `None` keeps
+    // all values in the SSTs
+    pub value_log_threshold: Option<usize>,
+    // Extracts key prefixes for the prefix bloom filters, which let scans within a prefix skip SSTs
+    pub prefix_extractor: Option<Arc<dyn PrefixExtractor>>,
+    // Builds the key and prefix filters of the SSTs, `None` builds no filters
+    pub filter_policy: Option<Arc<dyn FilterPolicy>>,
+    // Builds no filters for SSTs on the bottom level, where most lookups find their key anyway
+    pub skip_bottom_level_filters: bool,
+    // Appends a hash index to each data block, so that point lookups skip the binary search
+    pub block_hash_index: bool,
+    // SSTs are written to disk as they are built; start the writeback every this many bytes,
+    // `None` only syncs an SST when it is finished
+    pub bytes_per_sync: Option<usize>,
+    // How SSTs and value logs are read from disk
+    pub read_backend: ReadBackend,
+    // The file system all files of the storage engine are stored in
+    pub file_system: Arc<dyn FileSystem>,
+    // Roll the manifest over to a new file starting with a snapshot of the state once it grows
+    // beyond this many bytes
+    pub max_manifest_size: u64,
+    // The structure the memtables keep their entries in
+    pub memtable_rep: MemTableRepType,
+    // Limits the memory of the memtables shared with other storage engines, `None` only limits
+    // them by `target_sst_size` and `num_memtable_limit`
+    pub write_buffer_manager: Option<Arc<WriteBufferManager>>,
 }
 
 impl LsmStorageOptions {
     pub fn default_for_week1_test() -> Self {
         Self {
             block_size: 4096,
+            block_restart_interval: 16,
             target_sst_size: 2 << 20,
             compaction_options: CompactionOptions::NoCompaction,
             enable_wal: false,
             num_memtable_limit: 50,
+            max_memtables_per_flush: 1,
             serializable: false,
+            compression: CompressionType::None,
+            value_log_threshold: None,
+            prefix_extractor: None,
+            filter_policy: Some(Arc::new(BloomFilterPolicy::default())),
+            skip_bottom_level_filters: false,
+            block_hash_index: false,
+            bytes_per_sync: None,
+            read_backend: ReadBackend::Pread,
+            file_system: Arc::new(DiskFileSystem),
+            max_manifest_size: 4 << 20,
+            memtable_rep: MemTableRepType::SkipList,
+            write_buffer_manager: None,
         }
     }
 
     pub fn default_for_week1_day6_test() -> Self {
         Self {
             block_size: 4096,
+            block_restart_interval: 16,
             target_sst_size: 2 << 20,
             compaction_options: CompactionOptions::NoCompaction,
             enable_wal: false,
             num_memtable_limit: 2,
+            max_memtables_per_flush: 1,
             serializable: false,
+            compression: CompressionType::None,
+            value_log_threshold: None,
+            prefix_extractor: None,
+            filter_policy: Some(Arc::new(BloomFilterPolicy::default())),
+            skip_bottom_level_filters: false,
+            block_hash_index: false,
+            bytes_per_sync: None,
+            read_backend: ReadBackend::Pread,
+            file_system: Arc::new(DiskFileSystem),
+            max_manifest_size: 4 << 20,
+            memtable_rep: MemTableRepType::SkipList,
+            write_buffer_manager: None,
         }
     }
 
     pub fn default_for_week2_test(compaction_options: CompactionOptions) -> Self {
         Self {
             block_size: 4096,
+            block_restart_interval: 16,
             target_sst_size: 1 << 20, // 1MB
             compaction_options,
             enable_wal: false,
             num_memtable_limit: 2,
+            max_memtables_per_flush: 1,
             serializable: false,
+            compression: CompressionType::None,
+            value_log_threshold: None,
+            prefix_extractor: None,
+            filter_policy: Some(Arc::new(BloomFilterPolicy::default())),
+            skip_bottom_level_filters: false,
+            block_hash_index: false,
+            bytes_per_sync: None,
+            read_backend: ReadBackend::Pread,
+            file_system: Arc::new(DiskFileSystem),
+            max_manifest_size: 4 << 20,
+            memtable_rep: MemTableRepType::SkipList,
+            write_buffer_manager: None,
         }
     }
 }
@@ -163,6 +297,18 @@ fn key_within(user_key: &[u8], table_begin: KeySlice, table_end: KeySlice) -> bo
     table_begin.key_ref() <= user_key && user_key <= table_end.key_ref()
 }
 
+fn check_entry_size(key: &[u8], value: &[u8]) -> Result<()> {
+    if key.len() + value.len() > MAX_ENTRY_SIZE {
+        bail!(
+            "entry too large: key={} bytes, value={} bytes, limit={} bytes",
+            key.len(),
+            value.len(),
+            MAX_ENTRY_SIZE
+        );
+    }
+    Ok(())
+}
+
 #[derive(Clone, Debug)]
 pub enum CompactionFilter {
     Prefix(Bytes),
@@ -172,14 +318,23 @@ pub enum CompactionFilter {
 pub(crate) struct LsmStorageInner {
     pub(crate) state: Arc<RwLock<Arc<LsmStorageState>>>,
     pub(crate) state_lock: Mutex<()>,
-    path: PathBuf,
+    pub(crate) path: PathBuf,
     pub(crate) block_cache: Arc<BlockCache>,
     next_sst_id: AtomicUsize,
     pub(crate) options: Arc<LsmStorageOptions>,
     pub(crate) compaction_controller: CompactionController,
+    /// Only one compaction can run at a time.
+    pub(crate) compaction_lock: Mutex<()>,
     pub(crate) manifest: Option<Manifest>,
     pub(crate) mvcc: Option<LsmMvccInner>,
     pub(crate) compaction_filters: Arc<Mutex<Vec<CompactionFilter>>>,
+    /// Value log files, which share their ids with the SSTs flushed along with them. Only updated
+    /// while holding the write lock of `state`, so that it can be read together with a snapshot.
+    pub(crate) value_logs: RwLock<Arc<HashMap<usize, Arc<ValueLog>>>>,
+    pub(crate) vlog_gc: Mutex<ValueLogGcState>,
+    /// Only one value log GC can run at a time.
+    pub(crate) vlog_gc_lock: Mutex<()>,
+    pub(crate) file_gc: Mutex<FileGcState>,
 }
 
 /// A thin wrapper for `LsmStorageInner` and the user interface for MiniLSM.
@@ -193,12 +348,22 @@ pub struct MiniLsm {
     compaction_notifier: crossbeam_channel::Sender<()>,
     /// The handle for the compaction thread. (In week 2)
     compaction_thread: Mutex<Option<std::thread::JoinHandle<()>>>,
+    /// Notifies the value log GC thread to stop working.
+    vlog_gc_notifier: crossbeam_channel::Sender<()>,
+    /// The handle for the value log GC thread.
+    vlog_gc_thread: Mutex<Option<std::thread::JoinHandle<()>>>,
+    /// Notifies the file GC thread to stop working.
+    file_gc_notifier: crossbeam_channel::Sender<()>,
+    /// The handle for the file GC thread.
+    file_gc_thread: Mutex<Option<std::thread::JoinHandle<()>>>,
 }
 
 impl Drop for MiniLsm {
     fn drop(&mut self) {
         self.compaction_notifier.send(()).ok();
         self.flush_notifier.send(()).ok();
+        self.vlog_gc_notifier.send(()).ok();
+        self.file_gc_notifier.send(()).ok();
     }
 }
 
@@ -207,7 +372,21 @@ impl MiniLsm {
         self.inner.sync_dir()?;
         self.compaction_notifier.send(()).ok();
         self.flush_notifier.send(()).ok();
+        self.vlog_gc_notifier.send(()).ok();
+        self.file_gc_notifier.send(()).ok();
 
+        let mut file_gc_thread = self.file_gc_thread.lock();
+        if let Some(file_gc_thread) = file_gc_thread.take() {
+            file_gc_thread
+                .join()
+                .map_err(|e| anyhow::anyhow!("{:?}", e))?;
+        }
+        let mut vlog_gc_thread = self.vlog_gc_thread.lock();
+        if let Some(vlog_gc_thread) = vlog_gc_thread.take() {
+            vlog_gc_thread
+                .join()
+                .map_err(|e| anyhow::anyhow!("{:?}", e))?;
+        }
         let mut compaction_thread = self.compaction_thread.lock();
         if let Some(compaction_thread) = compaction_thread.take() {
             compaction_thread
@@ -229,10 +408,9 @@ impl MiniLsm {
 
         // create memtable and skip updating manifest
         if !self.inner.state.read().memtable.is_empty() {
+            let memtable = self.inner.create_memtable(self.inner.next_sst_id())?;
             self.inner
-                .freeze_memtable_with_memtable(Arc::new(MemTable::create(
-                    self.inner.next_sst_id(),
-                )))?;
+                .freeze_memtable_with_memtable(Arc::new(memtable))?;
         }
 
         while {
@@ -250,16 +428,27 @@ impl MiniLsm {
     /// not exist.
     pub fn open(path: impl AsRef<Path>, options: LsmStorageOptions) -> Result<Arc<Self>> {
         let inner = Arc::new(LsmStorageInner::open(path, options)?);
+        if let Some(write_buffer_manager) = &inner.options.write_buffer_manager {
+            write_buffer_manager.register(&inner);
+        }
         let (tx1, rx) = crossbeam_channel::unbounded();
         let compaction_thread = inner.spawn_compaction_thread(rx)?;
         let (tx2, rx) = crossbeam_channel::unbounded();
         let flush_thread = inner.spawn_flush_thread(rx)?;
+        let (tx3, rx) = crossbeam_channel::unbounded();
+        let vlog_gc_thread = inner.spawn_vlog_gc_thread(rx)?;
+        let (tx4, rx) = crossbeam_channel::unbounded();
+        let file_gc_thread = inner.spawn_file_gc_thread(rx)?;
         Ok(Arc::new(Self {
             inner,
             flush_notifier: tx2,
             flush_thread: Mutex::new(flush_thread),
             compaction_notifier: tx1,
             compaction_thread: Mutex::new(compaction_thread),
+            vlog_gc_notifier: tx3,
+            vlog_gc_thread: Mutex::new(vlog_gc_thread),
+            file_gc_notifier: tx4,
+            file_gc_thread: Mutex::new(file_gc_thread),
         }))
     }
 
@@ -283,6 +472,13 @@ impl MiniLsm {
         self.inner.delete(key)
     }
 
+    /// Delete all keys in `[lower, upper)` with a single range tombstone. Returns an error in
+    /// serializable mode, where range deletions cannot be checked for conflicts; delete the keys
+    /// one by one in a transaction instead.
+    pub fn delete_range(&self, lower: &[u8], upper: &[u8]) -> Result<()> {
+        self.inner.delete_range(lower, upper)
+    }
+
     pub fn sync(&self) -> Result<()> {
         self.inner.sync()
     }
@@ -295,6 +491,16 @@ impl MiniLsm {
         self.inner.scan(lower, upper)
     }
 
+    /// Scan all keys starting with `prefix`. SSTs are skipped by their prefix bloom filters if
+    /// `prefix` is a prefix produced by the configured extractor.
+    pub fn scan_prefix(&self, prefix: &[u8]) -> Result<TxnIterator> {
+        let upper = prefix_upper_bound(prefix);
+        self.inner.scan(
+            Bound::Included(prefix),
+            upper.as_deref().map_or(Bound::Unbounded, Bound::Excluded),
+        )
+    }
+
     /// Only call this in test cases due to race conditions
     pub fn force_flush(&self) -> Result<()> {
         if !self.inner.state.read().memtable.is_empty() {
@@ -310,6 +516,17 @@ impl MiniLsm {
     pub fn force_full_compaction(&self) -> Result<()> {
         self.inner.force_full_compaction()
     }
+
+    /// Only call this in test cases. Run the value log GC without waiting for the GC thread.
+    pub fn force_vlog_gc(&self) -> Result<()> {
+        self.inner.trigger_vlog_gc()
+    }
+
+    /// Only call this in test cases. Delete the obsolete and orphan files without waiting for the
+    /// file GC thread.
+    pub fn force_file_gc(&self) -> Result<()> {
+        self.inner.trigger_file_gc()
+    }
 }
 
 impl LsmStorageInner {
@@ -318,6 +535,11 @@ impl LsmStorageInner {
             .fetch_add(1, std::sync::atomic::Ordering::SeqCst)
     }
 
+    /// The ID `next_sst_id` would allocate now.
+    pub(crate) fn peek_next_sst_id(&self) -> usize {
+        self.next_sst_id.load(std::sync::atomic::Ordering::SeqCst)
+    }
+
     pub(crate) fn mvcc(&self) -> &LsmMvccInner {
         self.mvcc.as_ref().unwrap()
     }
@@ -329,68 +551,42 @@ impl LsmStorageInner {
     /// Start the storage engine by either loading an existing directory or creating a new one if the directory does
     /// not exist.
     pub(crate) fn open(path: impl AsRef<Path>, options: LsmStorageOptions) -> Result<Self> {
-        let mut state = LsmStorageState::create(&options);
+        let mut state = LsmStorageState::create(&options.compaction_options);
         let path = path.as_ref();
         let mut next_sst_id = 1;
         let block_cache = Arc::new(BlockCache::new(1 << 20)); // 4GB block cache,
         let manifest;
 
-        let compaction_controller = match &options.compaction_options {
-            CompactionOptions::Leveled(options) => {
-                CompactionController::Leveled(LeveledCompactionController::new(options.clone()))
-            }
-            CompactionOptions::Tiered(options) => {
-                CompactionController::Tiered(TieredCompactionController::new(options.clone()))
-            }
-            CompactionOptions::Simple(options) => CompactionController::Simple(
-                SimpleLeveledCompactionController::new(options.clone()),
-            ),
-            CompactionOptions::NoCompaction => CompactionController::NoCompaction,
-        };
+        let compaction_controller = CompactionController::new(&options.compaction_options);
 
-        if !path.exists() {
-            std::fs::create_dir_all(path).context("failed to create DB dir")?;
+        let fs = options.file_system.clone();
+        if !fs.exists(path) {
+            fs.create_dir_all(path).context("failed to create DB dir")?;
         }
-        let manifest_path = path.join("MANIFEST");
+        let manifest_path = current_manifest_path(&*fs, path)?;
         let mut last_commit_ts = 0;
-        if !manifest_path.exists() {
-            if options.enable_wal {
-                state.memtable = Arc::new(MemTable::create_with_wal(
-                    state.memtable.id(),
-                    Self::path_of_wal_static(path, state.memtable.id()),
-                )?);
-            }
-            manifest = Manifest::create(&manifest_path).context("failed to create manifest")?;
-            manifest.add_record_when_init(ManifestRecord::NewMemtable(state.memtable.id()))?;
-        } else {
-            let (m, records) = Manifest::recover(&manifest_path)?;
-            let mut memtables = BTreeSet::new();
-            for record in records {
-                match record {
-                    ManifestRecord::Flush(sst_id) => {
-                        let res = memtables.remove(&sst_id);
-                        assert!(res, "memtable not exist?");
-                        if compaction_controller.flush_to_l0() {
-                            state.l0_sstables.insert(0, sst_id);
-                        } else {
-                            state.levels.insert(0, (sst_id, vec![sst_id]));
-                        }
-                        next_sst_id = next_sst_id.max(sst_id);
-                    }
-                    ManifestRecord::NewMemtable(x) => {
-                        next_sst_id = next_sst_id.max(x);
-                        memtables.insert(x);
-                    }
-                    ManifestRecord::Compaction(task, output) => {
-                        let (new_state, _) = compaction_controller
-                            .apply_compaction_result(&state, &task, &output, true);
-                        // TODO: apply remove again
-                        state = new_state;
-                        next_sst_id =
-                            next_sst_id.max(output.iter().max().copied().unwrap_or_default());
-                    }
-                }
+        let mut value_logs = HashMap::new();
+        let mut vlog_gc = ValueLogGcState::default();
+        // value logs whose rewrites were flushed before the storage engine stopped
+        let mut rewritten_value_logs = Vec::new();
+        if let Some(manifest_path) = manifest_path {
+            let (m, records) = Manifest::recover(&*fs, &manifest_path)?;
+            let mut replayer = ManifestReplayer::new(&options.compaction_options);
+            for record in &records {
+                replayer.apply(record)?;
             }
+            let ManifestReplayer {
+                state: replayed_state,
+                memtables,
+                value_logs: value_log_ids,
+                value_log_discards,
+                rewritten_value_logs: pending,
+                max_id,
+                files,
+                ..
+            } = replayer;
+            state = replayed_state;
+            next_sst_id = next_sst_id.max(max_id);
 
             let mut sst_cnt = 0;
             // recover SSTs
@@ -403,15 +599,39 @@ impl LsmStorageInner {
                 let sst = SsTable::open(
                     table_id,
                     Some(block_cache.clone()),
-                    FileObject::open(&Self::path_of_sst_static(path, table_id))
-                        .context("failed to open SST")?,
+                    FileObject::open_with_fs(
+                        &*fs,
+                        &Self::path_of_sst_static(path, table_id),
+                        options.read_backend,
+                    )
+                    .context("failed to open SST")?,
                 )?;
+                if let Some(file) = files.get(&table_id)
+                    && file.size != sst.table_size()
+                {
+                    bail!(
+                        "SST {} has {} bytes, but the manifest recorded {}",
+                        table_id,
+                        sst.table_size(),
+                        file.size
+                    );
+                }
                 last_commit_ts = last_commit_ts.max(sst.max_ts());
                 state.sstables.insert(table_id, Arc::new(sst));
                 sst_cnt += 1;
             }
             println!("{} SSTs opened", sst_cnt);
 
+            for id in value_log_ids {
+                let file = FileObject::open_with_fs(
+                    &*fs,
+                    &Self::path_of_vlog_static(path, id),
+                    options.read_backend,
+                )
+                .context("failed to open value log")?;
+                value_logs.insert(id, Arc::new(ValueLog::open(id, file)));
+            }
+
             next_sst_id += 1;
 
             // Sort SSTs on each level (only for leveled compaction)
@@ -429,34 +649,68 @@ impl LsmStorageInner {
             }
 
             // recover memtables
+            let mut empty_memtables = Vec::new();
             if options.enable_wal {
                 let mut wal_cnt = 0;
                 for id in memtables.iter() {
-                    let memtable =
-                        MemTable::recover_from_wal(*id, Self::path_of_wal_static(path, *id))?;
-                    let max_ts = memtable
-                        .map
-                        .iter()
-                        .map(|x| x.key().ts())
-                        .max()
-                        .unwrap_or_default();
-                    last_commit_ts = last_commit_ts.max(max_ts);
+                    let memtable = MemTable::recover_from_wal_with_rep(
+                        *id,
+                        &options.memtable_rep,
+                        &*fs,
+                        Self::path_of_wal_static(path, *id),
+                    )?
+                    .with_write_buffer_manager(options.write_buffer_manager.clone());
+                    last_commit_ts = last_commit_ts.max(memtable.max_ts()?);
+                    // recovered memtables only go to the immutable memtables
+                    memtable.freeze();
                     if !memtable.is_empty() {
                         state.imm_memtables.insert(0, Arc::new(memtable));
                         wal_cnt += 1;
+                    } else {
+                        empty_memtables.push(*id);
                     }
                 }
                 println!("{} WALs recovered", wal_cnt);
-                state.memtable = Arc::new(MemTable::create_with_wal(
-                    next_sst_id,
-                    Self::path_of_wal_static(path, next_sst_id),
-                )?);
-            } else {
-                state.memtable = Arc::new(MemTable::create(next_sst_id));
             }
-            m.add_record_when_init(ManifestRecord::NewMemtable(state.memtable.id()))?;
+            let mut pending_deletion = Vec::new();
+            for (id, memtable_id) in pending {
+                if !memtables.contains(&memtable_id) {
+                    rewritten_value_logs.push(id);
+                } else if options.enable_wal {
+                    pending_deletion.push((id, memtable_id));
+                }
+                // without a WAL, the rewrites are lost, and the value log is collected again
+            }
+            vlog_gc = ValueLogGcState::new(value_log_discards, pending_deletion);
+            state.memtable = Arc::new(Self::create_memtable_static(&options, path, next_sst_id)?);
+            // the WAL must be durable before the manifest refers to it
+            fs.sync_dir(path)?;
+            // the WALs of the empty memtables are left for the file GC
+            m.add_record_when_init(VersionEdit {
+                new_memtables: vec![state.memtable.id()],
+                flushed_memtables: empty_memtables,
+                ..Default::default()
+            })?;
             next_sst_id += 1;
             manifest = m;
+        } else {
+            state.memtable = Arc::new(Self::create_memtable_static(
+                &options,
+                path,
+                state.memtable.id(),
+            )?);
+            let manifest_path = path.join(manifest_file_name(1));
+            // a manifest left behind by a crash before CURRENT referred to it holds no data
+            if fs.exists(&manifest_path) {
+                fs.remove(&manifest_path)?;
+            }
+            manifest =
+                Manifest::create(&*fs, &manifest_path).context("failed to create manifest")?;
+            manifest.add_record_when_init(VersionEdit {
+                new_memtables: vec![state.memtable.id()],
+                ..Default::default()
+            })?;
+            set_current_manifest(&*fs, path, &manifest_path)?;
         };
 
         let storage = Self {
@@ -466,12 +720,25 @@ impl LsmStorageInner {
             block_cache,
             next_sst_id: AtomicUsize::new(next_sst_id),
             compaction_controller,
+            compaction_lock: Mutex::new(()),
             manifest: Some(manifest),
             options: options.into(),
             mvcc: Some(LsmMvccInner::new(last_commit_ts)),
             compaction_filters: Arc::new(Mutex::new(Vec::new())),
+            value_logs: RwLock::new(Arc::new(value_logs)),
+            vlog_gc: Mutex::new(vlog_gc),
+            vlog_gc_lock: Mutex::new(()),
+            file_gc: Mutex::new(FileGcState::default()),
         };
         storage.sync_dir()?;
+        {
+            let state_lock = storage.state_lock.lock();
+            if !rewritten_value_logs.is_empty() {
+                storage.delete_value_logs(&state_lock, &rewritten_value_logs)?;
+            }
+            storage.maybe_roll_manifest(&state_lock)?;
+        }
+        storage.purge_orphan_files()?;
 
         Ok(storage)
     }
@@ -491,12 +758,11 @@ impl LsmStorageInner {
         txn.get(key)
     }
 
-    pub(crate) fn get_with_ts(&self, key: &[u8], read_ts: u64) -> Result<Option<Bytes>> {
-        let snapshot = {
-            let guard = self.state.read();
-            Arc::clone(&guard)
-        }; // drop global lock here
-
+    /// Create an iterator over all versions of a key, starting from the latest one.
+    pub(crate) fn key_versions_iter(
+        snapshot: &LsmStorageState,
+        key: &[u8],
+    ) -> Result<LsmIteratorInner> {
         let mut memtable_iters = Vec::with_capacity(snapshot.imm_memtables.len() + 1);
         memtable_iters.push(Box::new(snapshot.memtable.scan(
             Bound::Included(KeySlice::from_slice(key, key::TS_RANGE_BEGIN)),
@@ -555,13 +821,48 @@ impl LsmStorageInner {
             level_iters.push(Box::new(level_iter));
         }
 
+        TwoMergeIterator::create(
+            TwoMergeIterator::create(memtable_iter, l0_iter)?,
+            MergeIterator::create(level_iters),
+        )
+    }
+
+    /// Collect the range tombstones in the memtables and SSTs that may cover keys in the range.
+    pub(crate) fn collect_range_tombstones(
+        snapshot: &LsmStorageState,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+    ) -> FragmentedRangeTombstones {
+        let mut tombstones = Vec::new();
+        for memtable in std::iter::once(&snapshot.memtable).chain(&snapshot.imm_memtables) {
+            tombstones.extend(memtable.range_tombstones().overlapping(lower, upper));
+        }
+        // SSTs skipped by their bloom filters may still have tombstones covering the range
+        for table in snapshot.sstables.with_range_tombstones() {
+            if range_overlap(
+                lower,
+                upper,
+                table.first_key().as_key_slice(),
+                table.last_key().as_key_slice(),
+            ) {
+                tombstones.extend(table.range_tombstones().overlapping(lower, upper));
+            }
+        }
+        FragmentedRangeTombstones::new(tombstones)
+    }
+
+    pub(crate) fn get_with_ts(&self, key: &[u8], read_ts: u64) -> Result<Option<Bytes>> {
+        let (snapshot, value_logs) = {
+            let guard = self.state.read();
+            (Arc::clone(&guard), self.value_logs.read().clone())
+        }; // drop global lock here
+
         let iter = LsmIterator::new(
-            TwoMergeIterator::create(
-                TwoMergeIterator::create(memtable_iter, l0_iter)?,
-                MergeIterator::create(level_iters),
-            )?,
-            Bound::Unbounded,
+            Self::key_versions_iter(&snapshot, key)?,
+            Bound::Included(Bytes::copy_from_slice(key)),
             read_ts,
+            value_logs,
+            Self::collect_range_tombstones(&snapshot, Bound::Included(key), Bound::Included(key)),
         )?;
 
         if iter.is_valid() && iter.key() == key && !iter.value().is_empty() {
@@ -571,15 +872,20 @@ impl LsmStorageInner {
     }
 
     pub fn write_batch_inner<T: AsRef<[u8]>>(&self, batch: &[WriteBatchRecord<T>]) -> Result<u64> {
-        let _lck = self.mvcc().write_lock.lock();
+        if let Some(write_buffer_manager) = &self.options.write_buffer_manager {
+            write_buffer_manager.maybe_stall()?;
+        }
+        let lck = self.mvcc().write_lock.lock();
         let ts = self.mvcc().latest_commit_ts() + 1;
         let mut batch_datas: Vec<(key::Key<&[u8]>, &[u8])> = vec![];
+        let mut range_tombstones = vec![];
         let size;
         for record in batch {
             match record {
                 WriteBatchRecord::Del(key) => {
                     let key = key.as_ref();
                     assert!(!key.is_empty(), "key cannot be empty");
+                    check_entry_size(key, b"")?;
                     batch_datas.push((KeySlice::from_slice(key, ts), b""));
                 }
                 WriteBatchRecord::Put(key, value) => {
@@ -587,18 +893,38 @@ impl LsmStorageInner {
                     let value = value.as_ref();
                     assert!(!key.is_empty(), "key cannot be empty");
                     assert!(!value.is_empty(), "value cannot be empty");
+                    check_entry_size(key, value)?;
                     batch_datas.push((KeySlice::from_slice(key, ts), value));
                 }
+                WriteBatchRecord::DelRange(start, end) => {
+                    let (start, end) = (start.as_ref(), end.as_ref());
+                    if start >= end {
+                        bail!("invalid range deletion: start must be smaller than end");
+                    }
+                    check_entry_size(start, end)?;
+                    range_tombstones.push(RangeTombstone {
+                        start: Bytes::copy_from_slice(start),
+                        end: Bytes::copy_from_slice(end),
+                        ts,
+                    });
+                }
             }
         }
         {
             let guard = self.state.read();
-            guard.memtable.put_batch(&batch_datas)?;
+            guard
+                .memtable
+                .write_batch(&batch_datas, &range_tombstones)?;
             size = guard.memtable.approximate_size();
         }
         self.try_freeze(size)?;
 
         self.mvcc().update_commit_ts(ts);
+        drop(lck);
+        // freezing a memtable of another storage engine must not hold up the writers of this one
+        if let Some(write_buffer_manager) = &self.options.write_buffer_manager {
+            write_buffer_manager.maybe_freeze()?;
+        }
         Ok(ts)
     }
 
@@ -618,6 +944,9 @@ impl LsmStorageInner {
                     WriteBatchRecord::Put(key, value) => {
                         txn.put(key.as_ref(), value.as_ref());
                     }
+                    WriteBatchRecord::DelRange(_, _) => {
+                        bail!("range deletions are not supported in serializable mode");
+                    }
                 }
             }
             txn.commit()?;
@@ -649,7 +978,17 @@ impl LsmStorageInner {
         Ok(())
     }
 
-    fn try_freeze(&self, estimated_size: usize) -> Result<()> {
+    /// Delete all keys in `[lower, upper)` by writing a range tombstone. Range deletions cannot be
+    /// checked for conflicts, so they are not supported in serializable mode.
+    pub fn delete_range(self: &Arc<Self>, lower: &[u8], upper: &[u8]) -> Result<()> {
+        if self.options.serializable {
+            bail!("range deletions are not supported in serializable mode");
+        }
+        self.write_batch_inner(&[WriteBatchRecord::DelRange(lower, upper)])?;
+        Ok(())
+    }
+
+    pub(crate) fn try_freeze(&self, estimated_size: usize) -> Result<()> {
         if estimated_size >= self.options.target_sst_size {
             let state_lock = self.state_lock.lock();
             let guard = self.state.read();
@@ -662,6 +1001,28 @@ impl LsmStorageInner {
         Ok(())
     }
 
+    /// Create a memtable backed by the configured rep, with a WAL if enabled.
+    fn create_memtable_static(
+        options: &LsmStorageOptions,
+        path: &Path,
+        id: usize,
+    ) -> Result<MemTable> {
+        let wal = if options.enable_wal {
+            Some(Wal::create(
+                &*options.file_system,
+                Self::path_of_wal_static(path, id),
+            )?)
+        } else {
+            None
+        };
+        Ok(MemTable::create_with_rep(id, &options.memtable_rep, wal)
+            .with_write_buffer_manager(options.write_buffer_manager.clone()))
+    }
+
+    fn create_memtable(&self, id: usize) -> Result<MemTable> {
+        Self::create_memtable_static(&self.options, &self.path, id)
+    }
+
     pub(crate) fn path_of_sst_static(path: impl AsRef<Path>, id: usize) -> PathBuf {
         path.as_ref().join(format!("{:05}.sst", id))
     }
@@ -678,8 +1039,63 @@ impl LsmStorageInner {
         Self::path_of_wal_static(&self.path, id)
     }
 
+    pub(crate) fn path_of_vlog_static(path: impl AsRef<Path>, id: usize) -> PathBuf {
+        path.as_ref().join(format!("{:05}.vlog", id))
+    }
+
+    pub(crate) fn path_of_vlog(&self, id: usize) -> PathBuf {
+        Self::path_of_vlog_static(&self.path, id)
+    }
+
     pub(super) fn sync_dir(&self) -> Result<()> {
-        File::open(&self.path)?.sync_all()?;
+        self.options.file_system.sync_dir(&self.path)
+    }
+
+    /// Roll the manifest over once it grows beyond `max_manifest_size`, so that recovery does not
+    /// replay the whole history. Only call this after the state reflects all manifest records.
+    pub(crate) fn maybe_roll_manifest(
+        &self,
+        state_lock_observer: &MutexGuard<'_, ()>,
+    ) -> Result<()> {
+        let manifest = self.manifest();
+        if manifest.size() <= self.options.max_manifest_size {
+            return Ok(());
+        }
+        let snapshot = {
+            let state = self.state.read();
+            let mut value_logs = self.value_logs.read().keys().copied().collect::<Vec<_>>();
+            value_logs.sort();
+            // L0 SSTs and tiers are listed from the oldest
+            let mut new_files = Vec::new();
+            for id in state.l0_sstables.iter().rev() {
+                new_files.push(NewFile::new(0, &state.sstables[id]));
+            }
+            if self.compaction_controller.flush_to_l0() {
+                for (level, ids) in &state.levels {
+                    new_files.extend(ids.iter().map(|x| NewFile::new(*level, &state.sstables[x])));
+                }
+            } else {
+                for (tier_id, ids) in state.levels.iter().rev() {
+                    new_files.extend(
+                        ids.iter()
+                            .map(|x| NewFile::new(*tier_id, &state.sstables[x])),
+                    );
+                }
+            }
+            let mut edit = VersionEdit {
+                new_memtables: std::iter::once(state.memtable.id())
+                    .chain(state.imm_memtables.iter().map(|x| x.id()))
+                    .collect(),
+                new_files,
+                new_value_logs: value_logs,
+                next_sst_id: Some(self.peek_next_sst_id()),
+                ..Default::default()
+            };
+            self.vlog_gc.lock().add_to_snapshot(&mut edit);
+            edit
+        };
+        manifest.roll_over(state_lock_observer, &*self.options.file_system, snapshot)?;
+        println!("manifest rolled over to {}", manifest.path().display());
         Ok(())
     }
 
@@ -694,6 +1110,8 @@ impl LsmStorageInner {
         *guard = Arc::new(snapshot);
 
         drop(guard);
+        // writers put into the memtable while holding the state, so none of them can reach it now
+        old_memtable.freeze();
         old_memtable.sync_wal()?;
 
         Ok(())
@@ -702,44 +1120,80 @@ impl LsmStorageInner {
     /// Force freeze the current memtable to an immutable memtable
     pub fn force_freeze_memtable(&self, state_lock_observer: &MutexGuard<'_, ()>) -> Result<()> {
         let memtable_id = self.next_sst_id();
-        let memtable = if self.options.enable_wal {
-            Arc::new(MemTable::create_with_wal(
-                memtable_id,
-                self.path_of_wal(memtable_id),
-            )?)
-        } else {
-            Arc::new(MemTable::create(memtable_id))
-        };
+        let memtable = Arc::new(self.create_memtable(memtable_id)?);
+
+        // the WAL must be durable before the manifest refers to it
+        self.sync_dir()?;
 
         self.freeze_memtable_with_memtable(memtable)?;
 
         self.manifest().add_record(
             state_lock_observer,
-            ManifestRecord::NewMemtable(memtable_id),
+            VersionEdit {
+                new_memtables: vec![memtable_id],
+                ..Default::default()
+            },
         )?;
-        self.sync_dir()?;
 
         Ok(())
     }
 
-    /// Force flush the earliest-created immutable memtable to disk
+    /// Force flush the earliest-created immutable memtables to disk, up to
+    /// `max_memtables_per_flush` of them into one SST
     pub fn force_flush_next_imm_memtable(&self) -> Result<()> {
         let state_lock = self.state_lock.lock();
 
-        let flush_memtable;
+        let flush_memtables;
 
         {
             let guard = self.state.read();
-            flush_memtable = guard
-                .imm_memtables
-                .last()
-                .expect("no imm memtables!")
-                .clone();
+            // another thread may have flushed them before we got the state lock
+            if guard.imm_memtables.is_empty() {
+                return Ok(());
+            }
+            let count = self
+                .options
+                .max_memtables_per_flush
+                .clamp(1, guard.imm_memtables.len());
+            // from the newest to the oldest, like the immutable memtables
+            flush_memtables = guard.imm_memtables[guard.imm_memtables.len() - count..].to_vec();
         }
 
-        let mut builder = SsTableBuilder::new(self.options.block_size);
-        flush_memtable.flush(&mut builder)?;
-        let sst_id = flush_memtable.id();
+        // the SST is named after the newest memtable, so that it is newer than all flushed ones
+        let sst_id = flush_memtables[0].id();
+        let memtable_ids = flush_memtables.iter().map(|x| x.id()).collect::<Vec<_>>();
+        let memtables = flush_memtables
+            .iter()
+            .map(|x| x.as_ref())
+            .collect::<Vec<_>>();
+        // a flushed tier is named after its SST
+        let level = if self.compaction_controller.flush_to_l0() {
+            0
+        } else {
+            sst_id
+        };
+        let mut builder = SsTableBuilder::new_with_options(&self.options);
+        builder.set_compaction_reason(CompactionReason::Flush, level);
+        builder.set_max_memtable_id(Some(sst_id));
+        builder.stream_to_file(self.path_of_sst(sst_id), self.options.bytes_per_sync)?;
+        let mut vlog = None;
+        if let Some(threshold) = self.options.value_log_threshold {
+            let mut vlog_builder = ValueLogBuilder::new(sst_id);
+            MemTable::flush_merged(
+                &memtables,
+                &mut builder,
+                Some((&mut vlog_builder, threshold)),
+            )?;
+            if !vlog_builder.is_empty() {
+                vlog = Some(Arc::new(vlog_builder.build_with_fs(
+                    &*self.options.file_system,
+                    self.path_of_vlog(sst_id),
+                    self.options.read_backend,
+                )?));
+            }
+        } else {
+            MemTable::flush_merged(&memtables, &mut builder, None)?;
+        }
         let sst = Arc::new(builder.build(
             sst_id,
             Some(self.block_cache.clone()),
@@ -750,9 +1204,11 @@ impl LsmStorageInner {
         {
             let mut guard = self.state.write();
             let mut snapshot = guard.as_ref().clone();
-            // Remove the memtable from the immutable memtables.
-            let mem = snapshot.imm_memtables.pop().unwrap();
-            assert_eq!(mem.id(), sst_id);
+            // Remove the memtables from the immutable memtables.
+            for id in memtable_ids.iter().rev() {
+                let mem = snapshot.imm_memtables.pop().unwrap();
+                assert_eq!(mem.id(), *id);
+            }
             // Add L0 table
             if self.compaction_controller.flush_to_l0() {
                 // In leveled compaction or no compaction, simply flush to L0
@@ -761,20 +1217,44 @@ impl LsmStorageInner {
                 // In tiered compaction, create a new tier
                 snapshot.levels.insert(0, (sst_id, vec![sst_id]));
             }
-            println!("flushed {}.sst with size={}", sst_id, sst.table_size());
-            snapshot.sstables.insert(sst_id, sst);
+            println!(
+                "flushed memtables {:?} to {}.sst with size={}",
+                memtable_ids,
+                sst_id,
+                sst.table_size()
+            );
+            snapshot.sstables.insert(sst_id, sst.clone());
+            if let Some(vlog) = &vlog {
+                let mut value_logs = self.value_logs.write();
+                Arc::make_mut(&mut value_logs).insert(sst_id, vlog.clone());
+            }
             // Update the snapshot.
             *guard = Arc::new(snapshot);
         }
 
+        // the SST and the value log must be durable before the manifest refers to them
+        self.sync_dir()?;
+
+        self.manifest().add_record(
+            &state_lock,
+            VersionEdit {
+                flushed_memtables: memtable_ids.clone(),
+                new_files: vec![NewFile::new(level, &sst)],
+                new_value_logs: vlog.iter().map(|_| sst_id).collect(),
+                ..Default::default()
+            },
+        )?;
+
+        // the WALs are needed for recovery until the flush is recorded
         if self.options.enable_wal {
-            std::fs::remove_file(self.path_of_wal(sst_id))?;
+            for id in &memtable_ids {
+                self.options.file_system.remove(&self.path_of_wal(*id))?;
+            }
+            self.sync_dir()?;
         }
 
-        self.manifest()
-            .add_record(&state_lock, ManifestRecord::Flush(sst_id))?;
-
-        self.sync_dir()?;
+        self.delete_flushed_value_logs(&state_lock, sst_id)?;
+        self.maybe_roll_manifest(&state_lock)?;
 
         Ok(())
     }
@@ -784,11 +1264,7 @@ impl LsmStorageInner {
     }
 
     /// Create an iterator over a range of keys.
-    pub fn scan<'a>(
-        self: &'a Arc<Self>,
-        lower: Bound<&[u8]>,
-        upper: Bound<&[u8]>,
-    ) -> Result<TxnIterator> {
+    pub fn scan(self: &Arc<Self>, lower: Bound<&[u8]>, upper: Bound<&[u8]>) -> Result<TxnIterator> {
         let txn = self.mvcc().new_txn(self.clone(), self.options.serializable);
         txn.scan(lower, upper)
     }
@@ -799,9 +1275,9 @@ impl LsmStorageInner {
         upper: Bound<&[u8]>,
         read_ts: u64,
     ) -> Result<FusedIterator<LsmIterator>> {
-        let snapshot = {
+        let (snapshot, value_logs) = {
             let guard = self.state.read();
-            Arc::clone(&guard)
+            (Arc::clone(&guard), self.value_logs.read().clone())
         }; // drop global lock here
 
         let mut memtable_iters = Vec::with_capacity(snapshot.imm_memtables.len() + 1);
@@ -817,15 +1293,26 @@ impl LsmStorageInner {
         }
         let memtable_iter = MergeIterator::create(memtable_iters);
 
-        let mut table_iters = Vec::with_capacity(snapshot.l0_sstables.len());
-        for table_id in snapshot.l0_sstables.iter() {
-            let table = snapshot.sstables[table_id].clone();
-            if range_overlap(
+        // SSTs without the prefix can be skipped if all keys in the range share the same prefix
+        let prefix = self
+            .options
+            .prefix_extractor
+            .as_ref()
+            .and_then(|extractor| Some((extractor, prefix_of_range(&**extractor, lower, upper)?)));
+        let keep_table = |table: &SsTable| {
+            range_overlap(
                 lower,
                 upper,
                 table.first_key().as_key_slice(),
                 table.last_key().as_key_slice(),
-            ) {
+            ) && prefix
+                .is_none_or(|(extractor, prefix)| table.may_contain_prefix(&**extractor, prefix))
+        };
+
+        let mut table_iters = Vec::with_capacity(snapshot.l0_sstables.len());
+        for table_id in snapshot.l0_sstables.iter() {
+            let table = snapshot.sstables[table_id].clone();
+            if keep_table(&table) {
                 let iter = match lower {
                     Bound::Included(key) => SsTableIterator::create_and_seek_to_key(
                         table,
@@ -854,12 +1341,7 @@ impl LsmStorageInner {
             let mut level_ssts = Vec::with_capacity(level_sst_ids.len());
             for table in level_sst_ids {
                 let table = snapshot.sstables[table].clone();
-                if range_overlap(
-                    lower,
-                    upper,
-                    table.first_key().as_key_slice(),
-                    table.last_key().as_key_slice(),
-                ) {
+                if keep_table(&table) {
                     level_ssts.push(table);
                 }
             }
@@ -891,6 +1373,8 @@ impl LsmStorageInner {
             iter,
             map_bound(upper),
             read_ts,
+            value_logs,
+            Self::collect_range_tombstones(&snapshot, lower, upper),
         )?))
     }
 }
@@ -12,9 +12,10 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::fs::{File, OpenOptions};
-use std::io::{Read, Write};
-use std::path::Path;
+mod version_edit;
+
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
 use anyhow::{Context, Result, bail};
@@ -22,78 +23,510 @@ use bytes::{Buf, BufMut};
 use parking_lot::{Mutex, MutexGuard};
 use serde::{Deserialize, Serialize};
 
-use crate::compact::CompactionTask;
+use crate::compact::{CompactionController, CompactionOptions, CompactionTask};
+use crate::env::{FileSystem, WritableFile};
+use crate::lsm_storage::LsmStorageState;
+use crate::wal::LogCorruption;
+use version_edit::EDIT_FORMAT_VERSION;
+pub use version_edit::{NewFile, VersionEdit};
+
+/// The file that names the current manifest, which is switched atomically when the manifest rolls
+/// over.
+pub const CURRENT_FILE_NAME: &str = "CURRENT";
+
+/// The manifest of a storage engine created before the manifest could roll over, which has no
+/// `CURRENT` file.
+const LEGACY_MANIFEST_FILE_NAME: &str = "MANIFEST";
+
+const MANIFEST_FILE_PREFIX: &str = "MANIFEST-";
 
 pub struct Manifest {
-    file: Arc<Mutex<File>>,
+    file: Arc<Mutex<ManifestFile>>,
+}
+
+struct ManifestFile {
+    file: Box<dyn WritableFile>,
+    path: PathBuf,
+    size: u64,
 }
 
-#[derive(Serialize, Deserialize)]
+/// A record of the manifest. Only version edits are written; the other records are the JSON
+/// records of older manifests, which are still replayed.
+#[derive(Debug, Serialize, Deserialize)]
 pub enum ManifestRecord {
+    Edit(VersionEdit),
     Flush(usize),
     NewMemtable(usize),
     Compaction(CompactionTask, Vec<usize>),
 }
 
+pub fn manifest_file_name(number: usize) -> String {
+    format!("{}{:05}", MANIFEST_FILE_PREFIX, number)
+}
+
+/// The number of a manifest named by `manifest_file_name`, or 0 for the legacy manifest.
+pub fn manifest_number(path: &Path) -> Option<usize> {
+    let name = path.file_name()?.to_str()?;
+    if name == LEGACY_MANIFEST_FILE_NAME {
+        return Some(0);
+    }
+    name.strip_prefix(MANIFEST_FILE_PREFIX)?.parse().ok()
+}
+
+/// The path of the current manifest in `dir`, or `None` if the storage engine has not been
+/// created.
+pub fn current_manifest_path(fs: &dyn FileSystem, dir: &Path) -> Result<Option<PathBuf>> {
+    let current_path = dir.join(CURRENT_FILE_NAME);
+    if !fs.exists(&current_path) {
+        let legacy_path = dir.join(LEGACY_MANIFEST_FILE_NAME);
+        return Ok(fs.exists(&legacy_path).then_some(legacy_path));
+    }
+    let current = fs.read(&current_path).context("failed to read CURRENT")?;
+    let name = std::str::from_utf8(&current)
+        .ok()
+        .and_then(|x| x.strip_suffix('\n'))
+        .filter(|x| manifest_number(Path::new(x)).is_some());
+    match name {
+        Some(name) => Ok(Some(dir.join(name))),
+        None => bail!(
+            "invalid CURRENT file: {:?}",
+            current.escape_ascii().to_string()
+        ),
+    }
+}
+
+/// Atomically point the `CURRENT` file at a manifest in the same directory, which must have been
+/// synced.
+pub fn set_current_manifest(fs: &dyn FileSystem, dir: &Path, manifest_path: &Path) -> Result<()> {
+    let name = manifest_path
+        .file_name()
+        .and_then(|x| x.to_str())
+        .context("invalid manifest path")?;
+    let tmp_path = dir.join(format!("{}.tmp", CURRENT_FILE_NAME));
+    let mut file = fs.create(&tmp_path)?;
+    file.append(format!("{}\n", name).as_bytes())?;
+    file.sync()?;
+    drop(file);
+    // the manifest must be durable before CURRENT refers to it
+    fs.sync_dir(dir)?;
+    fs.rename(&tmp_path, &dir.join(CURRENT_FILE_NAME))?;
+    fs.sync_dir(dir)?;
+    Ok(())
+}
+
+/// Encode a record with its length and checksum. Version edits are encoded in binary, and the other
+/// records in JSON.
+pub(crate) fn encode_record(record: &ManifestRecord) -> Result<Vec<u8>> {
+    let payload = match record {
+        ManifestRecord::Edit(edit) => {
+            let mut payload = Vec::new();
+            edit.encode(&mut payload);
+            payload
+        }
+        _ => serde_json::to_vec(record)?,
+    };
+    let mut buf = Vec::with_capacity(8 + payload.len() + 4);
+    buf.put_u64(payload.len() as u64);
+    buf.put_slice(&payload);
+    buf.put_u32(crc32fast::hash(&payload));
+    Ok(buf)
+}
+
 impl Manifest {
-    pub fn create(path: impl AsRef<Path>) -> Result<Self> {
+    pub fn create(fs: &dyn FileSystem, path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        if fs.exists(path) {
+            bail!(
+                "failed to create manifest: {} already exists",
+                path.display()
+            );
+        }
         Ok(Self {
-            file: Arc::new(Mutex::new(
-                OpenOptions::new()
-                    .read(true)
-                    .create_new(true)
-                    .write(true)
-                    .open(path)
-                    .context("failed to create manifest")?,
-            )),
+            file: Arc::new(Mutex::new(ManifestFile {
+                file: fs.create(path).context("failed to create manifest")?,
+                path: path.to_path_buf(),
+                size: 0,
+            })),
         })
     }
 
-    pub fn recover(path: impl AsRef<Path>) -> Result<(Self, Vec<ManifestRecord>)> {
-        let mut file = OpenOptions::new()
-            .read(true)
-            .append(true)
-            .open(path)
-            .context("failed to recover manifest")?;
-        let mut buf = Vec::new();
-        file.read_to_end(&mut buf)?;
-        let mut buf_ptr = buf.as_slice();
-        let mut records = Vec::new();
-        while buf_ptr.has_remaining() {
-            let len = buf_ptr.get_u64();
-            let slice = &buf_ptr[..len as usize];
-            let json = serde_json::from_slice::<ManifestRecord>(slice)?;
-            buf_ptr.advance(len as usize);
-            let checksum = buf_ptr.get_u32();
-            if checksum != crc32fast::hash(slice) {
-                bail!("checksum mismatched!");
+    /// Read the records in the manifest. A crash while writing the last record leaves it torn,
+    /// in which case it is dropped and the manifest is rewritten without it.
+    pub fn recover(
+        fs: &dyn FileSystem,
+        path: impl AsRef<Path>,
+    ) -> Result<(Self, Vec<ManifestRecord>)> {
+        let path = path.as_ref();
+        let buf = fs.read(path).context("failed to recover manifest")?;
+        let (entries, corruption) = decode_records(&buf);
+        if let Some(corruption) = corruption {
+            if !corruption.is_torn_tail() {
+                bail!(
+                    "failed to recover manifest {}: {} in record {}",
+                    path.display(),
+                    corruption,
+                    entries.len()
+                );
+            }
+            let valid_len = corruption.offset() as usize;
+            eprintln!(
+                "dropping torn record of {} bytes at the end of the manifest",
+                buf.len() - valid_len
+            );
+            let tmp_path = path.with_extension("tmp");
+            let mut file = fs.create(&tmp_path)?;
+            file.append(&buf[..valid_len])?;
+            file.sync()?;
+            drop(file);
+            fs.rename(&tmp_path, path)?;
+            if let Some(dir) = path.parent() {
+                fs.sync_dir(dir)?;
             }
-            records.push(json);
         }
+        let file = fs.open_append(path).context("failed to recover manifest")?;
+        let size = entries.last().map(|x| x.offset + x.len).unwrap_or_default();
         Ok((
             Self {
-                file: Arc::new(Mutex::new(file)),
+                file: Arc::new(Mutex::new(ManifestFile {
+                    file,
+                    path: path.to_path_buf(),
+                    size,
+                })),
             },
-            records,
+            entries.into_iter().map(|x| x.record).collect(),
         ))
     }
 
     pub fn add_record(
         &self,
         _state_lock_observer: &MutexGuard<()>,
-        record: ManifestRecord,
+        edit: VersionEdit,
     ) -> Result<()> {
-        self.add_record_when_init(record)
+        self.add_record_when_init(edit)
     }
 
-    pub fn add_record_when_init(&self, record: ManifestRecord) -> Result<()> {
+    pub fn add_record_when_init(&self, edit: VersionEdit) -> Result<()> {
         let mut file = self.file.lock();
-        let mut buf = serde_json::to_vec(&record)?;
-        let hash = crc32fast::hash(&buf);
-        file.write_all(&(buf.len() as u64).to_be_bytes())?;
-        buf.put_u32(hash);
-        file.write_all(&buf)?;
-        file.sync_all()?;
+        let buf = encode_record(&ManifestRecord::Edit(edit))?;
+        file.file.append(&buf)?;
+        file.file.sync()?;
+        file.size += buf.len() as u64;
+        Ok(())
+    }
+
+    pub fn path(&self) -> PathBuf {
+        self.file.lock().path.clone()
+    }
+
+    /// Size of the manifest file in bytes.
+    pub fn size(&self) -> u64 {
+        self.file.lock().size
+    }
+
+    /// Continue in a new manifest file that starts with the snapshot, switch `CURRENT` to it and
+    /// delete the old one. The snapshot is an edit that must reflect all the records written so
+    /// far.
+    pub fn roll_over(
+        &self,
+        _state_lock_observer: &MutexGuard<()>,
+        fs: &dyn FileSystem,
+        snapshot: VersionEdit,
+    ) -> Result<()> {
+        let mut file = self.file.lock();
+        let dir = file
+            .path
+            .parent()
+            .context("invalid manifest path")?
+            .to_path_buf();
+        let number = manifest_number(&file.path).context("invalid manifest path")?;
+        let path = dir.join(manifest_file_name(number + 1));
+        // a manifest left behind by a crash while rolling over is never referred to by CURRENT
+        let mut new_file = fs.create(&path).context("failed to create manifest")?;
+        let buf = encode_record(&ManifestRecord::Edit(snapshot))?;
+        new_file.append(&buf)?;
+        new_file.sync()?;
+        set_current_manifest(fs, &dir, &path)?;
+        let old_file = std::mem::replace(
+            &mut *file,
+            ManifestFile {
+                file: new_file,
+                path,
+                size: buf.len() as u64,
+            },
+        );
+        fs.remove(&old_file.path)?;
+        fs.sync_dir(&dir)?;
+        Ok(())
+    }
+}
+
+/// A record decoded from the manifest, which starts at `offset` and spans `len` bytes with its
+/// header and checksum.
+#[derive(Debug)]
+pub struct ManifestEntry {
+    pub offset: u64,
+    pub len: u64,
+    pub record: ManifestRecord,
+}
+
+/// Decode the records of a manifest, stopping at the first one that is truncated or corrupted.
+pub fn decode_records(buf: &[u8]) -> (Vec<ManifestEntry>, Option<LogCorruption>) {
+    let mut buf_ptr = buf;
+    let mut entries = Vec::new();
+    while buf_ptr.has_remaining() {
+        let offset = (buf.len() - buf_ptr.remaining()) as u64;
+        if buf_ptr.remaining() < 8 {
+            return (entries, Some(LogCorruption::Truncated { offset }));
+        }
+        let len = (&buf_ptr[..8]).get_u64();
+        if (buf_ptr.remaining() as u64 - 8) < len.saturating_add(4) {
+            return (entries, Some(LogCorruption::Truncated { offset }));
+        }
+        let record_len = 8 + len as usize + 4;
+        let slice = &buf_ptr[8..8 + len as usize];
+        let checksum = (&buf_ptr[8 + len as usize..]).get_u32();
+        let actual = crc32fast::hash(slice);
+        if checksum != actual {
+            let corruption = LogCorruption::ChecksumMismatch {
+                offset,
+                expected: checksum,
+                actual,
+                last: buf_ptr.remaining() == record_len,
+            };
+            return (entries, Some(corruption));
+        }
+        let record = if slice.first() == Some(&EDIT_FORMAT_VERSION) {
+            VersionEdit::decode(slice)
+                .map(ManifestRecord::Edit)
+                .map_err(|e| format!("{:#}", e))
+        } else {
+            serde_json::from_slice::<ManifestRecord>(slice).map_err(|e| e.to_string())
+        };
+        let record = match record {
+            Ok(record) => record,
+            Err(reason) => {
+                return (entries, Some(LogCorruption::Malformed { offset, reason }));
+            }
+        };
+        buf_ptr.advance(record_len);
+        entries.push(ManifestEntry {
+            offset,
+            len: record_len as u64,
+            record,
+        });
+    }
+    (entries, None)
+}
+
+/// Replays manifest records into the shape of the LSM tree, i.e., which memtables, SSTs and value
+/// logs are alive and where the SSTs are, without opening any of them.
+pub struct ManifestReplayer {
+    /// The L0 SSTs and the levels or tiers; the memtables and SST objects are left empty.
+    pub state: LsmStorageState,
+    /// The memtables that have not been flushed, whose WALs are needed for recovery.
+    pub memtables: BTreeSet<usize>,
+    pub value_logs: BTreeSet<usize>,
+    /// The dead bytes compaction found in each alive value log.
+    pub value_log_discards: HashMap<usize, u64>,
+    /// The alive value logs whose live records were rewritten into the paired memtable.
+    pub rewritten_value_logs: BTreeMap<usize, usize>,
+    /// The largest memtable or SST ID seen.
+    pub max_id: usize,
+    /// The alive SSTs added by version edits; the SSTs added by older records are not known.
+    pub files: HashMap<usize, NewFile>,
+    compaction_controller: CompactionController,
+}
+
+impl ManifestReplayer {
+    pub fn new(compaction_options: &CompactionOptions) -> Self {
+        Self {
+            state: LsmStorageState::create(compaction_options),
+            memtables: BTreeSet::new(),
+            value_logs: BTreeSet::new(),
+            value_log_discards: HashMap::new(),
+            rewritten_value_logs: BTreeMap::new(),
+            max_id: 0,
+            files: HashMap::new(),
+            compaction_controller: CompactionController::new(compaction_options),
+        }
+    }
+
+    pub fn apply(&mut self, record: &ManifestRecord) -> Result<()> {
+        match record {
+            ManifestRecord::Edit(edit) => self.apply_edit(edit)?,
+            ManifestRecord::Flush(sst_id) => {
+                if !self.memtables.remove(sst_id) {
+                    bail!("flush of memtable {} which does not exist", sst_id);
+                }
+                if self.compaction_controller.flush_to_l0() {
+                    self.state.l0_sstables.insert(0, *sst_id);
+                } else {
+                    self.state.levels.insert(0, (*sst_id, vec![*sst_id]));
+                }
+                self.max_id = self.max_id.max(*sst_id);
+            }
+            ManifestRecord::NewMemtable(x) => {
+                self.max_id = self.max_id.max(*x);
+                self.memtables.insert(*x);
+            }
+            ManifestRecord::Compaction(task, output) => {
+                self.apply_compaction(task, output)?;
+                self.max_id = self
+                    .max_id
+                    .max(output.iter().max().copied().unwrap_or_default());
+            }
+        }
+        Ok(())
+    }
+
+    fn apply_compaction(&mut self, task: &CompactionTask, output: &[usize]) -> Result<()> {
+        let ssts = self
+            .state
+            .l0_sstables
+            .iter()
+            .chain(self.state.levels.iter().flat_map(|(_, files)| files))
+            .copied()
+            .collect::<HashSet<_>>();
+        if let Some(sst_id) = task.input_ssts().into_iter().find(|x| !ssts.contains(x)) {
+            bail!("compaction of SST {} which does not exist", sst_id);
+        }
+        for sst_id in task.input_ssts() {
+            self.files.remove(&sst_id);
+        }
+        let num_levels = self.state.levels.len();
+        match (&self.compaction_controller, task) {
+            (
+                _,
+                CompactionTask::ForceFullCompaction {
+                    l0_sstables,
+                    l1_sstables: _,
+                },
+            ) => {
+                if num_levels == 0 {
+                    bail!("full compaction without a level to compact to");
+                }
+                self.state.l0_sstables.retain(|x| !l0_sstables.contains(x));
+                self.state.levels[0].1 = output.to_vec();
+                return Ok(());
+            }
+            (CompactionController::Leveled(_), CompactionTask::Leveled(task))
+                if task.lower_level <= num_levels => {}
+            (CompactionController::Simple(_), CompactionTask::Simple(task))
+                if task.lower_level <= num_levels => {}
+            (CompactionController::Tiered(_), CompactionTask::Tiered(_)) => {}
+            _ => bail!(
+                "compaction task {:?} does not match the compaction options",
+                task
+            ),
+        }
+        let (state, _) =
+            self.compaction_controller
+                .apply_compaction_result(&self.state, task, output, true);
+        self.state = state;
+        Ok(())
+    }
+
+    fn apply_edit(&mut self, edit: &VersionEdit) -> Result<()> {
+        for id in &edit.new_memtables {
+            self.memtables.insert(*id);
+            self.max_id = self.max_id.max(*id);
+        }
+        for id in &edit.flushed_memtables {
+            if !self.memtables.remove(id) {
+                bail!("flush of memtable {} which does not exist", id);
+            }
+        }
+
+        let ssts = self
+            .state
+            .l0_sstables
+            .iter()
+            .chain(self.state.levels.iter().flat_map(|(_, files)| files))
+            .copied()
+            .collect::<HashSet<_>>();
+        if let Some(id) = edit.deleted_files.iter().find(|x| !ssts.contains(x)) {
+            bail!("deletion of SST {} which does not exist", id);
+        }
+        if let Some(file) = edit.new_files.iter().find(|x| ssts.contains(&x.id)) {
+            bail!("addition of SST {} which already exists", file.id);
+        }
+        let deleted = edit.deleted_files.iter().copied().collect::<HashSet<_>>();
+        self.state.l0_sstables.retain(|x| !deleted.contains(x));
+        // new tiers take the place of the oldest tier files are deleted from
+        let mut oldest_tier = None;
+        for (idx, (_, files)) in self.state.levels.iter_mut().enumerate() {
+            let num_files = files.len();
+            files.retain(|x| !deleted.contains(x));
+            if files.len() < num_files {
+                oldest_tier = Some(idx);
+            }
+        }
+
+        if self.compaction_controller.flush_to_l0() {
+            let num_levels = self.state.levels.len();
+            for file in &edit.new_files {
+                match file.level {
+                    0 => self.state.l0_sstables.insert(0, file.id),
+                    level if level <= num_levels => self.state.levels[level - 1].1.push(file.id),
+                    level => bail!(
+                        "SST {} added to level {} of {} levels",
+                        file.id,
+                        level,
+                        num_levels
+                    ),
+                }
+            }
+        } else {
+            let mut new_tiers: Vec<(usize, Vec<usize>)> = Vec::new();
+            for file in &edit.new_files {
+                match new_tiers.last_mut() {
+                    Some((tier_id, files)) if *tier_id == file.level => files.push(file.id),
+                    // a tier is named after its first SST
+                    _ if file.level != file.id => {
+                        bail!("SST {} starts tier {} of another name", file.id, file.level)
+                    }
+                    _ => new_tiers.push((file.level, vec![file.id])),
+                }
+            }
+            // tiers are ordered from the newest to the oldest
+            new_tiers.reverse();
+            let old_tiers = std::mem::take(&mut self.state.levels);
+            if oldest_tier.is_none() {
+                self.state.levels.append(&mut new_tiers);
+            }
+            for (idx, tier) in old_tiers.into_iter().enumerate() {
+                if !tier.1.is_empty() {
+                    self.state.levels.push(tier);
+                }
+                if oldest_tier == Some(idx) {
+                    self.state.levels.append(&mut new_tiers);
+                }
+            }
+        }
+
+        for id in &edit.deleted_files {
+            self.files.remove(id);
+        }
+        for file in &edit.new_files {
+            self.files.insert(file.id, file.clone());
+            self.max_id = self.max_id.max(file.id);
+        }
+        self.value_logs.extend(&edit.new_value_logs);
+        for (id, bytes) in &edit.value_log_discards {
+            if self.value_logs.contains(id) {
+                *self.value_log_discards.entry(*id).or_default() += bytes;
+            }
+        }
+        self.rewritten_value_logs
+            .extend(edit.rewritten_value_logs.iter().copied());
+        for id in &edit.deleted_value_logs {
+            self.value_logs.remove(id);
+            self.value_log_discards.remove(id);
+            self.rewritten_value_logs.remove(id);
+        }
+        if let Some(next_sst_id) = edit.next_sst_id {
+            self.max_id = self.max_id.max(next_sst_id.saturating_sub(1));
+        }
         Ok(())
     }
 }
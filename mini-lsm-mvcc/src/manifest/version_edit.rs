@@ -0,0 +1,205 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The binary encoding of the changes to the LSM tree recorded in the manifest.
+
+use anyhow::{Context, Result, bail};
+use bytes::BufMut;
+use serde::{Deserialize, Serialize};
+
+use crate::table::SsTable;
+use crate::varint::{put_uvarint, try_get_uvarint};
+
+/// The first byte of an encoded edit, which tells it apart from the JSON records written before
+/// edits existed, as those start with `{` or `"`.
+pub(super) const EDIT_FORMAT_VERSION: u8 = 1;
+
+const TAG_NEW_MEMTABLE: u8 = 1;
+const TAG_FLUSHED_MEMTABLE: u8 = 2;
+const TAG_NEW_FILE: u8 = 3;
+const TAG_DELETED_FILE: u8 = 4;
+const TAG_NEW_VALUE_LOG: u8 = 5;
+const TAG_DELETED_VALUE_LOG: u8 = 6;
+const TAG_NEXT_SST_ID: u8 = 7;
+const TAG_VALUE_LOG_DISCARD: u8 = 8;
+const TAG_REWRITTEN_VALUE_LOG: u8 = 9;
+
+/// An SST added to the LSM tree.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NewFile {
+    /// 0 for L0, the level for leveled compaction, or the ID of the tier for tiered compaction.
+    pub level: usize,
+    pub id: usize,
+    pub first_key: Vec<u8>,
+    pub first_ts: u64,
+    pub last_key: Vec<u8>,
+    pub last_ts: u64,
+    /// Size of the SST file in bytes.
+    pub size: u64,
+    pub max_ts: u64,
+}
+
+impl NewFile {
+    pub fn new(level: usize, sst: &SsTable) -> Self {
+        Self {
+            level,
+            id: sst.sst_id(),
+            first_key: sst.first_key().key_ref().to_vec(),
+            first_ts: sst.first_key().ts(),
+            last_key: sst.last_key().key_ref().to_vec(),
+            last_ts: sst.last_key().ts(),
+            size: sst.table_size(),
+            max_ts: sst.max_ts(),
+        }
+    }
+}
+
+/// A change to the LSM tree that is applied atomically, e.g., a flush or a compaction. It records
+/// the files that come and go rather than how they were produced, so that recovery does not depend
+/// on the compaction tasks.
+///
+/// New files in L0 and new tiers are listed from the oldest to the newest, and go in front of the
+/// existing ones, except that new tiers replacing deleted files take the place of the oldest tier
+/// the files were deleted from. New files in a level are appended to it. An edit that lists all the
+/// files and sets `next_sst_id` is a snapshot of the whole LSM tree.
+///
+/// Each field is encoded after a one-byte tag, with the numbers as varints and the keys prefixed
+/// by their length:
+///
+/// ```text
+/// | version (u8) | tag | field | tag | field | ... |
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VersionEdit {
+    pub new_memtables: Vec<usize>,
+    /// The memtables no longer needed for recovery, usually because they are flushed to the new
+    /// files.
+    pub flushed_memtables: Vec<usize>,
+    pub new_files: Vec<NewFile>,
+    pub deleted_files: Vec<usize>,
+    pub new_value_logs: Vec<usize>,
+    pub deleted_value_logs: Vec<usize>,
+    /// The bytes of each value log that compaction found to be dead, added to those of the
+    /// earlier edits.
+    pub value_log_discards: Vec<(usize, u64)>,
+    /// The value logs whose live records were rewritten into the memtable with the paired ID, to
+    /// be deleted once that memtable is flushed.
+    pub rewritten_value_logs: Vec<(usize, usize)>,
+    pub next_sst_id: Option<usize>,
+}
+
+fn put_bytes(buf: &mut Vec<u8>, data: &[u8]) {
+    put_uvarint(buf, data.len() as u64);
+    buf.put_slice(data);
+}
+
+fn get_u64(buf: &mut &[u8]) -> Result<u64> {
+    try_get_uvarint(buf).context("truncated varint")
+}
+
+fn get_usize(buf: &mut &[u8]) -> Result<usize> {
+    Ok(get_u64(buf)? as usize)
+}
+
+fn get_bytes(buf: &mut &[u8]) -> Result<Vec<u8>> {
+    let len = get_usize(buf)?;
+    if buf.len() < len {
+        bail!("truncated key of {} bytes", len);
+    }
+    let (data, rest) = buf.split_at(len);
+    *buf = rest;
+    Ok(data.to_vec())
+}
+
+impl VersionEdit {
+    pub fn encode(&self, buf: &mut Vec<u8>) {
+        buf.put_u8(EDIT_FORMAT_VERSION);
+        let ids = [
+            (TAG_NEW_MEMTABLE, &self.new_memtables),
+            (TAG_FLUSHED_MEMTABLE, &self.flushed_memtables),
+            (TAG_DELETED_FILE, &self.deleted_files),
+            (TAG_NEW_VALUE_LOG, &self.new_value_logs),
+            (TAG_DELETED_VALUE_LOG, &self.deleted_value_logs),
+        ];
+        for (tag, ids) in ids {
+            for id in ids {
+                buf.put_u8(tag);
+                put_uvarint(buf, *id as u64);
+            }
+        }
+        for file in &self.new_files {
+            buf.put_u8(TAG_NEW_FILE);
+            put_uvarint(buf, file.level as u64);
+            put_uvarint(buf, file.id as u64);
+            put_bytes(buf, &file.first_key);
+            put_uvarint(buf, file.first_ts);
+            put_bytes(buf, &file.last_key);
+            put_uvarint(buf, file.last_ts);
+            put_uvarint(buf, file.size);
+            put_uvarint(buf, file.max_ts);
+        }
+        for (id, bytes) in &self.value_log_discards {
+            buf.put_u8(TAG_VALUE_LOG_DISCARD);
+            put_uvarint(buf, *id as u64);
+            put_uvarint(buf, *bytes);
+        }
+        for (id, memtable_id) in &self.rewritten_value_logs {
+            buf.put_u8(TAG_REWRITTEN_VALUE_LOG);
+            put_uvarint(buf, *id as u64);
+            put_uvarint(buf, *memtable_id as u64);
+        }
+        if let Some(next_sst_id) = self.next_sst_id {
+            buf.put_u8(TAG_NEXT_SST_ID);
+            put_uvarint(buf, next_sst_id as u64);
+        }
+    }
+
+    pub fn decode(mut buf: &[u8]) -> Result<Self> {
+        match buf.split_first() {
+            Some((&EDIT_FORMAT_VERSION, rest)) => buf = rest,
+            Some((version, _)) => bail!("unsupported version edit format {}", version),
+            None => bail!("empty version edit"),
+        }
+        let mut edit = Self::default();
+        while let Some((&tag, rest)) = buf.split_first() {
+            buf = rest;
+            match tag {
+                TAG_NEW_MEMTABLE => edit.new_memtables.push(get_usize(&mut buf)?),
+                TAG_FLUSHED_MEMTABLE => edit.flushed_memtables.push(get_usize(&mut buf)?),
+                TAG_DELETED_FILE => edit.deleted_files.push(get_usize(&mut buf)?),
+                TAG_NEW_VALUE_LOG => edit.new_value_logs.push(get_usize(&mut buf)?),
+                TAG_DELETED_VALUE_LOG => edit.deleted_value_logs.push(get_usize(&mut buf)?),
+                TAG_NEW_FILE => edit.new_files.push(NewFile {
+                    level: get_usize(&mut buf)?,
+                    id: get_usize(&mut buf)?,
+                    first_key: get_bytes(&mut buf)?,
+                    first_ts: get_u64(&mut buf)?,
+                    last_key: get_bytes(&mut buf)?,
+                    last_ts: get_u64(&mut buf)?,
+                    size: get_u64(&mut buf)?,
+                    max_ts: get_u64(&mut buf)?,
+                }),
+                TAG_VALUE_LOG_DISCARD => edit
+                    .value_log_discards
+                    .push((get_usize(&mut buf)?, get_u64(&mut buf)?)),
+                TAG_REWRITTEN_VALUE_LOG => edit
+                    .rewritten_value_logs
+                    .push((get_usize(&mut buf)?, get_usize(&mut buf)?)),
+                TAG_NEXT_SST_ID => edit.next_sst_id = Some(get_usize(&mut buf)?),
+                _ => bail!("unknown tag {} in version edit", tag),
+            }
+        }
+        Ok(edit)
+    }
+}
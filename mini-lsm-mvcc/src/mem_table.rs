@@ -15,28 +15,129 @@
 use std::ops::Bound;
 use std::path::Path;
 use std::sync::Arc;
-use std::sync::atomic::AtomicUsize;
 
 use anyhow::Result;
 use bytes::Bytes;
-use crossbeam_skiplist::SkipMap;
-use crossbeam_skiplist::map::Entry;
-use ouroboros::self_referencing;
+use parking_lot::RwLock;
 
+use crate::env::FileSystem;
 use crate::iterators::StorageIterator;
+use crate::iterators::merge_iterator::MergeIterator;
 use crate::key::{KeyBytes, KeySlice, TS_DEFAULT};
-use crate::table::SsTableBuilder;
+use crate::range_tombstone::{FragmentedRangeTombstones, RangeTombstone};
+use crate::table::{PrefixExtractor, SsTableBuilder};
+use crate::vlog::ValueLogBuilder;
 use crate::wal::Wal;
+use crate::write_buffer_manager::{WriteBufferCharge, WriteBufferManager};
 
-/// A basic mem-table based on crossbeam-skiplist.
+mod arena;
+mod hash_skiplist;
+mod skiplist;
+mod vector;
+
+pub use hash_skiplist::HashSkipListRep;
+pub use skiplist::{Iter, Range, SkipList};
+pub use vector::VectorRep;
+
+/// The structure that a mem-table keeps its key-value pairs in.
+pub trait MemTableRep: Send + Sync {
+    /// Insert a key-value pair, or replace the value if the key is already in the rep. Writers
+    /// call this concurrently until the rep is frozen.
+    fn insert(&self, key: KeySlice, value: &[u8]);
+
+    fn get(&self, key: KeySlice) -> Option<Bytes>;
+
+    /// Get an iterator over a range of keys in order, which keeps the rep alive.
+    fn scan(
+        self: Arc<Self>,
+        lower: Bound<KeySlice>,
+        upper: Bound<KeySlice>,
+    ) -> Box<dyn MemTableRepIterator>;
+
+    /// Called once the mem-table becomes immutable, after which nothing is inserted.
+    fn freeze(&self) {}
+
+    /// The number of entries.
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The bytes taken by the entries, which the mem-table is frozen by.
+    fn memory_usage(&self) -> usize;
+}
+
+/// An iterator over a [`MemTableRep`]. It only exists so that the storage iterators of the reps
+/// can be boxed, and is implemented for all of them.
+pub trait MemTableRepIterator: Send + Sync {
+    fn key(&self) -> KeySlice<'_>;
+
+    fn value(&self) -> &[u8];
+
+    fn is_valid(&self) -> bool;
+
+    fn next(&mut self) -> Result<()>;
+}
+
+impl<I> MemTableRepIterator for I
+where
+    I: 'static + for<'a> StorageIterator<KeyType<'a> = KeySlice<'a>> + Send + Sync,
+{
+    fn key(&self) -> KeySlice<'_> {
+        StorageIterator::key(self)
+    }
+
+    fn value(&self) -> &[u8] {
+        StorageIterator::value(self)
+    }
+
+    fn is_valid(&self) -> bool {
+        StorageIterator::is_valid(self)
+    }
+
+    fn next(&mut self) -> Result<()> {
+        StorageIterator::next(self)
+    }
+}
+
+/// Which [`MemTableRep`] the mem-tables are backed by.
+#[derive(Debug, Clone, Default)]
+pub enum MemTableRepType {
+    /// A [`SkipList`] in an arena, which suits most workloads.
+    #[default]
+    SkipList,
+    /// A [`VectorRep`], sorted when the mem-table is frozen, for bulk loads.
+    Vector,
+    /// A [`HashSkipListRep`] with a skiplist for each prefix returned by the extractor, for
+    /// workloads that read and write within prefixes.
+    HashSkipList(Arc<dyn PrefixExtractor>),
+}
+
+impl MemTableRepType {
+    pub fn create(&self) -> Arc<dyn MemTableRep> {
+        match self {
+            Self::SkipList => Arc::new(SkipList::new()),
+            Self::Vector => Arc::new(VectorRep::new()),
+            Self::HashSkipList(prefix_extractor) => {
+                Arc::new(HashSkipListRep::new(prefix_extractor.clone()))
+            }
+        }
+    }
+}
+
+/// A mem-table backed by one of the [`MemTableRep`]s, a skiplist that allocates its entries from
+/// an arena by default.
 ///
 /// An initial implementation of memtable is part of week 1, day 1. It will be incrementally implemented in other
 /// chapters of week 1 and week 2.
 pub struct MemTable {
-    pub(crate) map: Arc<SkipMap<KeyBytes, Bytes>>,
+    rep: Arc<dyn MemTableRep>,
+    /// The range tombstones, kept fragmented so that reads can use them directly.
+    range_tombstones: RwLock<Arc<FragmentedRangeTombstones>>,
     wal: Option<Wal>,
     id: usize,
-    approximate_size: Arc<AtomicUsize>,
+    write_buffer_charge: Option<WriteBufferCharge>,
 }
 
 /// Create a bound of `Bytes` from a bound of `&[u8]`.
@@ -64,7 +165,7 @@ pub(crate) fn map_key_bound(bound: Bound<KeySlice>) -> Bound<KeyBytes> {
 }
 
 /// Create a bound of `Bytes` from a bound of `KeySlice`.
-pub(crate) fn map_key_bound_plus_ts(bound: Bound<&[u8]>, ts: u64) -> Bound<KeySlice> {
+pub(crate) fn map_key_bound_plus_ts(bound: Bound<&[u8]>, ts: u64) -> Bound<KeySlice<'_>> {
     match bound {
         Bound::Included(x) => Bound::Included(KeySlice::from_slice(x, ts)),
         Bound::Excluded(x) => Bound::Excluded(KeySlice::from_slice(x, ts)),
@@ -75,42 +176,62 @@ pub(crate) fn map_key_bound_plus_ts(bound: Bound<&[u8]>, ts: u64) -> Bound<KeySl
 impl MemTable {
     /// Create a new mem-table.
     pub fn create(id: usize) -> Self {
-        Self {
-            id,
-            map: Arc::new(SkipMap::new()),
-            wal: None,
-            approximate_size: Arc::new(AtomicUsize::new(0)),
-        }
+        Self::create_with_rep(id, &MemTableRepType::default(), None)
     }
 
     /// Create a new mem-table with WAL
-    pub fn create_with_wal(id: usize, path: impl AsRef<Path>) -> Result<Self> {
-        Ok(Self {
+    pub fn create_with_wal(id: usize, fs: &dyn FileSystem, path: impl AsRef<Path>) -> Result<Self> {
+        Ok(Self::create_with_rep(
             id,
-            map: Arc::new(SkipMap::new()),
-            wal: Some(Wal::create(path.as_ref())?),
-            approximate_size: Arc::new(AtomicUsize::new(0)),
-        })
+            &MemTableRepType::default(),
+            Some(Wal::create(fs, path.as_ref())?),
+        ))
+    }
+
+    /// Create a new mem-table backed by the given rep.
+    pub fn create_with_rep(id: usize, rep_type: &MemTableRepType, wal: Option<Wal>) -> Self {
+        Self {
+            id,
+            rep: rep_type.create(),
+            range_tombstones: RwLock::new(Default::default()),
+            wal,
+            write_buffer_charge: None,
+        }
     }
 
     /// Create a memtable from WAL
-    pub fn recover_from_wal(id: usize, path: impl AsRef<Path>) -> Result<Self> {
-        let map = Arc::new(SkipMap::new());
+    pub fn recover_from_wal(
+        id: usize,
+        fs: &dyn FileSystem,
+        path: impl AsRef<Path>,
+    ) -> Result<Self> {
+        Self::recover_from_wal_with_rep(id, &MemTableRepType::default(), fs, path)
+    }
+
+    /// Create a memtable backed by the given rep from WAL.
+    pub fn recover_from_wal_with_rep(
+        id: usize,
+        rep_type: &MemTableRepType,
+        fs: &dyn FileSystem,
+        path: impl AsRef<Path>,
+    ) -> Result<Self> {
+        let rep = rep_type.create();
+        let mut range_tombstones = Vec::new();
+        let wal = Wal::recover(fs, path.as_ref(), &*rep, &mut range_tombstones)?;
         Ok(Self {
             id,
-            wal: Some(Wal::recover(path.as_ref(), &map)?),
-            map,
-            approximate_size: Arc::new(AtomicUsize::new(0)),
+            wal: Some(wal),
+            rep,
+            range_tombstones: RwLock::new(Arc::new(FragmentedRangeTombstones::new(
+                range_tombstones,
+            ))),
+            write_buffer_charge: None,
         })
     }
 
     /// Get a value by key. Should not be used in week 3.
     pub fn get(&self, key: KeySlice) -> Option<Bytes> {
-        let key_bytes = KeyBytes::from_bytes_with_ts(
-            Bytes::from_static(unsafe { std::mem::transmute::<&[u8], &[u8]>(key.key_ref()) }),
-            key.ts(),
-        );
-        self.map.get(&key_bytes).map(|e| e.value().clone())
+        self.rep.get(key)
     }
 
     pub fn for_testing_put_slice(&self, key: &[u8], value: &[u8]) -> Result<()> {
@@ -143,22 +264,51 @@ impl MemTable {
 
     /// Implement this in week 3, day 5.
     pub fn put_batch(&self, data: &[(KeySlice, &[u8])]) -> Result<()> {
-        let mut estimated_size = 0;
+        self.write_batch(data, &[])
+    }
+
+    /// Put key-value pairs and range tombstones into the mem-table, logging them as one WAL batch.
+    pub fn write_batch(
+        &self,
+        data: &[(KeySlice, &[u8])],
+        range_tombstones: &[RangeTombstone],
+    ) -> Result<()> {
         for (key, value) in data {
-            estimated_size += key.raw_len() + value.len();
-            self.map.insert(
-                key.to_key_vec().into_key_bytes(),
-                Bytes::copy_from_slice(value),
+            self.rep.insert(*key, value);
+        }
+        if !range_tombstones.is_empty() {
+            let mut guard = self.range_tombstones.write();
+            let fragmented = FragmentedRangeTombstones::new(
+                guard.iter().chain(range_tombstones.iter().cloned()),
             );
+            *guard = Arc::new(fragmented);
         }
-        self.approximate_size
-            .fetch_add(estimated_size, std::sync::atomic::Ordering::Relaxed);
         if let Some(ref wal) = self.wal {
-            wal.put_batch(data)?;
+            wal.write_batch(data, range_tombstones)?;
         }
+        self.update_write_buffer_charge();
         Ok(())
     }
 
+    /// Charge the memory and the WAL of the mem-table to a write buffer manager.
+    pub fn with_write_buffer_manager(mut self, manager: Option<Arc<WriteBufferManager>>) -> Self {
+        self.write_buffer_charge = manager.map(WriteBufferCharge::new);
+        self.update_write_buffer_charge();
+        self
+    }
+
+    fn update_write_buffer_charge(&self) {
+        if let Some(charge) = &self.write_buffer_charge {
+            let wal_size = self.wal.as_ref().map_or(0, |x| x.size() as usize);
+            charge.update(self.approximate_size(), wal_size);
+        }
+    }
+
+    /// Get the range tombstones of the mem-table.
+    pub fn range_tombstones(&self) -> Arc<FragmentedRangeTombstones> {
+        self.range_tombstones.read().clone()
+    }
+
     pub fn sync_wal(&self) -> Result<()> {
         if let Some(ref wal) = self.wal {
             wal.sync()?;
@@ -168,21 +318,56 @@ impl MemTable {
 
     /// Get an iterator over a range of keys.
     pub fn scan(&self, lower: Bound<KeySlice>, upper: Bound<KeySlice>) -> MemTableIterator {
-        let (lower, upper) = (map_key_bound(lower), map_key_bound(upper));
-        let mut iter = MemTableIteratorBuilder {
-            map: self.map.clone(),
-            iter_builder: |map| map.range((lower, upper)),
-            item: (KeyBytes::new(), Bytes::new()),
+        MemTableIterator {
+            iter: self.rep.clone().scan(lower, upper),
         }
-        .build();
-        iter.next().unwrap();
-        iter
     }
 
     /// Flush the mem-table to SSTable. Implement in week 1 day 6.
     pub fn flush(&self, builder: &mut SsTableBuilder) -> Result<()> {
-        for entry in self.map.iter() {
-            builder.add(entry.key().as_key_slice(), &entry.value()[..]);
+        Self::flush_merged(&[self], builder, None)
+    }
+
+    /// Flush the mem-table to SSTable, moving values of at least `threshold` bytes to the value log.
+    pub fn flush_with_value_log(
+        &self,
+        builder: &mut SsTableBuilder,
+        vlog_builder: &mut ValueLogBuilder,
+        threshold: usize,
+    ) -> Result<()> {
+        Self::flush_merged(&[self], builder, Some((vlog_builder, threshold)))
+    }
+
+    /// Flush several mem-tables, listed from the newest to the oldest, to one SST. A key written
+    /// to more than one of them at the same timestamp keeps the value of the newest. With a value
+    /// log, the values of at least the given number of bytes are moved to it.
+    pub fn flush_merged(
+        memtables: &[&MemTable],
+        builder: &mut SsTableBuilder,
+        mut value_log: Option<(&mut ValueLogBuilder, usize)>,
+    ) -> Result<()> {
+        // boxed, as the merge iterator is also a `MemTableRepIterator` through the blanket impl
+        let mut iter: Box<dyn MemTableRepIterator> = Box::new(MergeIterator::create(
+            memtables
+                .iter()
+                .map(|x| Box::new(x.scan(Bound::Unbounded, Bound::Unbounded)))
+                .collect(),
+        ));
+        while iter.is_valid() {
+            let (key, value) = (iter.key(), iter.value());
+            match value_log.as_mut() {
+                Some((vlog_builder, threshold))
+                    if !value.is_empty() && value.len() >= *threshold =>
+                {
+                    let ptr = vlog_builder.add(key, value);
+                    builder.add_value_pointer(key, &ptr.encode());
+                }
+                _ => builder.add(key, value),
+            }
+            iter.next()?;
+        }
+        for memtable in memtables {
+            builder.add_range_tombstones(memtable.range_tombstones().iter());
         }
         Ok(())
     }
@@ -191,67 +376,65 @@ impl MemTable {
         self.id
     }
 
+    /// The bytes taken by the entries in the rep and by the range tombstones.
     pub fn approximate_size(&self) -> usize {
-        self.approximate_size
-            .load(std::sync::atomic::Ordering::Relaxed)
+        self.rep.memory_usage() + self.range_tombstones.read().approximate_size()
     }
 
     /// Only use this function when closing the database
     pub fn is_empty(&self) -> bool {
-        self.map.is_empty()
+        self.rep.is_empty() && self.range_tombstones.read().is_empty()
+    }
+
+    /// Make the mem-table immutable. Must be called once no writer can reach it any more.
+    pub fn freeze(&self) {
+        self.rep.freeze();
+        if let Some(charge) = &self.write_buffer_charge {
+            charge.mark_immutable();
+        }
     }
-}
 
-type SkipMapRangeIter<'a> = crossbeam_skiplist::map::Range<
-    'a,
-    KeyBytes,
-    (Bound<KeyBytes>, Bound<KeyBytes>),
-    KeyBytes,
-    Bytes,
->;
+    /// The largest timestamp of the keys and range tombstones in the mem-table.
+    pub fn max_ts(&self) -> Result<u64> {
+        let mut max_ts = self
+            .range_tombstones
+            .read()
+            .iter()
+            .map(|x| x.ts)
+            .max()
+            .unwrap_or(0);
+        let mut iter = self.rep.clone().scan(Bound::Unbounded, Bound::Unbounded);
+        while iter.is_valid() {
+            max_ts = max_ts.max(iter.key().ts());
+            iter.next()?;
+        }
+        Ok(max_ts)
+    }
+}
 
-/// An iterator over a range of `SkipMap`. This is a self-referential structure and please refer to week 1, day 2
-/// chapter for more information.
+/// An iterator over a range of a mem-table.
 ///
 /// This is part of week 1, day 2.
-#[self_referencing]
 pub struct MemTableIterator {
-    /// Stores a reference to the skipmap.
-    map: Arc<SkipMap<KeyBytes, Bytes>>,
-    /// Stores a skipmap iterator that refers to the lifetime of `MemTableIterator` itself.
-    #[borrows(map)]
-    #[not_covariant]
-    iter: SkipMapRangeIter<'this>,
-    /// Stores the current key-value pair.
-    item: (KeyBytes, Bytes),
-}
-
-impl MemTableIterator {
-    fn entry_to_item(entry: Option<Entry<'_, KeyBytes, Bytes>>) -> (KeyBytes, Bytes) {
-        entry
-            .map(|x| (x.key().clone(), x.value().clone()))
-            .unwrap_or_else(|| (KeyBytes::new(), Bytes::new()))
-    }
+    iter: Box<dyn MemTableRepIterator>,
 }
 
 impl StorageIterator for MemTableIterator {
     type KeyType<'a> = KeySlice<'a>;
 
     fn value(&self) -> &[u8] {
-        &self.borrow_item().1[..]
+        self.iter.value()
     }
 
-    fn key(&self) -> KeySlice {
-        self.borrow_item().0.as_key_slice()
+    fn key(&self) -> KeySlice<'_> {
+        self.iter.key()
     }
 
     fn is_valid(&self) -> bool {
-        !self.borrow_item().0.is_empty()
+        self.iter.is_valid()
     }
 
     fn next(&mut self) -> Result<()> {
-        let entry = self.with_iter_mut(|iter| MemTableIterator::entry_to_item(iter.next()));
-        self.with_mut(|x| *x.item = entry);
-        Ok(())
+        self.iter.next()
     }
 }
@@ -0,0 +1,118 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A bump allocator for the entries of a memtable, which frees all of them at once when dropped.
+
+use std::alloc::Layout;
+use std::ptr::NonNull;
+use std::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
+
+use parking_lot::Mutex;
+
+/// The alignment of all allocations, enough for the atomics in the skiplist nodes.
+pub(super) const ALIGN: usize = 8;
+
+const CHUNK_SIZE: usize = 64 << 10;
+
+/// Allocations larger than this get a chunk of their own, so that they do not waste the rest of
+/// the current chunk.
+const LARGE_ALLOC_SIZE: usize = CHUNK_SIZE / 4;
+
+struct Chunk {
+    data: NonNull<u8>,
+    layout: Layout,
+    /// The bytes handed out, which go past the end of the chunk once it is full.
+    used: AtomicUsize,
+}
+
+impl Chunk {
+    fn new(size: usize) -> Box<Self> {
+        let layout = Layout::from_size_align(size, ALIGN).unwrap();
+        // zeroed, so that the links of a new node start as null
+        let data = unsafe { std::alloc::alloc_zeroed(layout) };
+        let Some(data) = NonNull::new(data) else {
+            std::alloc::handle_alloc_error(layout);
+        };
+        Box::new(Self {
+            data,
+            layout,
+            used: AtomicUsize::new(0),
+        })
+    }
+}
+
+impl Drop for Chunk {
+    fn drop(&mut self) {
+        unsafe { std::alloc::dealloc(self.data.as_ptr(), self.layout) }
+    }
+}
+
+pub(super) struct Arena {
+    /// The chunk that small allocations are taken from.
+    current: AtomicPtr<Chunk>,
+    /// All the chunks, which are only replaced or added under the lock. They are boxed so that
+    /// `current` stays valid when the vector grows.
+    #[allow(clippy::vec_box)]
+    chunks: Mutex<Vec<Box<Chunk>>>,
+    allocated: AtomicUsize,
+}
+
+unsafe impl Send for Arena {}
+unsafe impl Sync for Arena {}
+
+impl Arena {
+    pub fn new() -> Self {
+        let chunk = Chunk::new(CHUNK_SIZE);
+        Self {
+            current: AtomicPtr::new(&*chunk as *const Chunk as *mut Chunk),
+            chunks: Mutex::new(vec![chunk]),
+            allocated: AtomicUsize::new(0),
+        }
+    }
+
+    /// Allocate `size` zeroed bytes aligned to [`ALIGN`], which are valid until the arena is
+    /// dropped. Allocations from different threads do not block each other unless a chunk is full.
+    pub fn alloc(&self, size: usize) -> NonNull<u8> {
+        let size = size.max(1).next_multiple_of(ALIGN);
+        self.allocated.fetch_add(size, Ordering::Relaxed);
+        if size > LARGE_ALLOC_SIZE {
+            let chunk = Chunk::new(size);
+            let data = chunk.data;
+            self.chunks.lock().push(chunk);
+            return data;
+        }
+        loop {
+            let current = self.current.load(Ordering::Acquire);
+            // chunks are only freed with the arena
+            let chunk = unsafe { &*current };
+            let offset = chunk.used.fetch_add(size, Ordering::Relaxed);
+            if offset + size <= chunk.layout.size() {
+                return unsafe { chunk.data.add(offset) };
+            }
+            // the chunk is full, and the first thread to get here replaces it
+            let mut chunks = self.chunks.lock();
+            if self.current.load(Ordering::Acquire) == current {
+                let chunk = Chunk::new(CHUNK_SIZE);
+                self.current
+                    .store(&*chunk as *const Chunk as *mut Chunk, Ordering::Release);
+                chunks.push(chunk);
+            }
+        }
+    }
+
+    /// The bytes handed out by the arena, including the padding for alignment.
+    pub fn allocated(&self) -> usize {
+        self.allocated.load(Ordering::Relaxed)
+    }
+}
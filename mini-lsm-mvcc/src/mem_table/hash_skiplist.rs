@@ -0,0 +1,131 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::HashMap;
+use std::ops::Bound;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use bytes::Bytes;
+use parking_lot::RwLock;
+
+use super::arena::Arena;
+use super::{MemTableRep, MemTableRepIterator, SkipList};
+use crate::iterators::merge_iterator::MergeIterator;
+use crate::key::KeySlice;
+use crate::table::{PrefixExtractor, prefix_of_range};
+
+/// A mem-table rep with a skiplist for each key prefix, so that reads and writes within a prefix
+/// only search the keys with that prefix. Scans across prefixes merge all the skiplists. The
+/// skiplists share one arena.
+pub struct HashSkipListRep {
+    prefix_extractor: Arc<dyn PrefixExtractor>,
+    arena: Arc<Arena>,
+    buckets: RwLock<HashMap<Bytes, Arc<SkipList>>>,
+    /// The keys without a prefix.
+    no_prefix: Arc<SkipList>,
+    /// The memory taken by the buckets outside of the arena.
+    bucket_memory_usage: AtomicUsize,
+}
+
+impl HashSkipListRep {
+    pub fn new(prefix_extractor: Arc<dyn PrefixExtractor>) -> Self {
+        let arena = Arc::new(Arena::new());
+        Self {
+            prefix_extractor,
+            no_prefix: Arc::new(SkipList::with_arena(arena.clone())),
+            arena,
+            buckets: RwLock::new(HashMap::new()),
+            bucket_memory_usage: AtomicUsize::new(0),
+        }
+    }
+
+    /// Get the skiplist of the keys with the prefix of `key`, if there is one.
+    fn bucket(&self, key: &[u8]) -> Option<Arc<SkipList>> {
+        match self.prefix_extractor.prefix(key) {
+            Some(prefix) => self.buckets.read().get(prefix).cloned(),
+            None => Some(self.no_prefix.clone()),
+        }
+    }
+}
+
+impl MemTableRep for HashSkipListRep {
+    fn insert(&self, key: KeySlice, value: &[u8]) {
+        if let Some(bucket) = self.bucket(key.key_ref()) {
+            bucket.insert(key, value);
+            return;
+        }
+        let prefix = self.prefix_extractor.prefix(key.key_ref()).unwrap();
+        let bucket = self
+            .buckets
+            .write()
+            .entry(Bytes::copy_from_slice(prefix))
+            .or_insert_with(|| {
+                self.bucket_memory_usage.fetch_add(
+                    prefix.len() + size_of::<(Bytes, Arc<SkipList>)>() + size_of::<SkipList>(),
+                    Ordering::Relaxed,
+                );
+                Arc::new(SkipList::with_arena(self.arena.clone()))
+            })
+            .clone();
+        bucket.insert(key, value);
+    }
+
+    fn get(&self, key: KeySlice) -> Option<Bytes> {
+        self.bucket(key.key_ref())?.get(key)
+    }
+
+    fn scan(
+        self: Arc<Self>,
+        lower: Bound<KeySlice>,
+        upper: Bound<KeySlice>,
+    ) -> Box<dyn MemTableRepIterator> {
+        let prefix = prefix_of_range(
+            &*self.prefix_extractor,
+            lower.map(|x| x.key_ref()),
+            upper.map(|x| x.key_ref()),
+        );
+        let mut buckets = match prefix {
+            Some(prefix) => self
+                .buckets
+                .read()
+                .get(prefix)
+                .cloned()
+                .into_iter()
+                .collect(),
+            None => {
+                let mut buckets = self.buckets.read().values().cloned().collect::<Vec<_>>();
+                buckets.push(self.no_prefix.clone());
+                buckets
+            }
+        };
+        if buckets.len() == 1 {
+            return Box::new(buckets.pop().unwrap().range(lower, upper));
+        }
+        let iters = buckets
+            .iter()
+            .map(|x| Box::new(x.range(lower, upper)))
+            .collect();
+        Box::new(MergeIterator::create(iters))
+    }
+
+    fn len(&self) -> usize {
+        let buckets = self.buckets.read();
+        buckets.values().map(|x| x.len()).sum::<usize>() + self.no_prefix.len()
+    }
+
+    fn memory_usage(&self) -> usize {
+        self.arena.allocated() + self.bucket_memory_usage.load(Ordering::Relaxed)
+    }
+}
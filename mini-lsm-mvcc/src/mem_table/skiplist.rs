@@ -0,0 +1,424 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A lock-free skiplist that keeps its nodes, keys and values in an arena. Entries are never
+//! removed, so nodes stay valid as long as the list, and all of them are freed at once with it.
+
+use std::marker::PhantomData;
+use std::ops::Bound;
+use std::ptr::{NonNull, null_mut};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicPtr, AtomicU64, AtomicUsize, Ordering};
+
+use anyhow::Result;
+use bytes::Bytes;
+
+use super::arena::Arena;
+use super::{MemTableRep, MemTableRepIterator};
+use crate::iterators::StorageIterator;
+use crate::key::{KeyBytes, KeySlice};
+
+const MAX_HEIGHT: usize = 12;
+
+/// The header of a node, followed in the arena by `height` links to the next nodes at each level,
+/// the key, and the value the node is created with.
+#[repr(C)]
+struct Node {
+    /// Points to the value, encoded as `| len (u32) | value |`. Putting the key again allocates a
+    /// new value and swaps it in.
+    value: AtomicPtr<u8>,
+    ts: u64,
+    key_len: u32,
+    height: u32,
+}
+
+const LINK_SIZE: usize = size_of::<AtomicPtr<Node>>();
+const VALUE_LEN_SIZE: usize = size_of::<u32>();
+
+/// Write a value encoded as `| len (u32) | value |` to `dst`.
+unsafe fn write_value(dst: *mut u8, value: &[u8]) {
+    unsafe {
+        dst.cast::<u32>().write_unaligned(value.len() as u32);
+        std::ptr::copy_nonoverlapping(value.as_ptr(), dst.add(VALUE_LEN_SIZE), value.len());
+    }
+}
+
+/// Read a value written by [`write_value`].
+unsafe fn read_value<'a>(src: *const u8) -> &'a [u8] {
+    unsafe {
+        let len = src.cast::<u32>().read_unaligned() as usize;
+        std::slice::from_raw_parts(src.add(VALUE_LEN_SIZE), len)
+    }
+}
+
+/// A node in the arena of a skiplist that lives for `'a`.
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct NodeRef<'a> {
+    ptr: NonNull<Node>,
+    _list: PhantomData<&'a SkipList>,
+}
+
+impl<'a> NodeRef<'a> {
+    /// # Safety
+    ///
+    /// The node must be null or in the arena of a skiplist that lives for `'a`.
+    unsafe fn from_ptr(ptr: *mut Node) -> Option<Self> {
+        NonNull::new(ptr).map(|ptr| Self {
+            ptr,
+            _list: PhantomData,
+        })
+    }
+
+    fn height(self) -> usize {
+        unsafe { (*self.ptr.as_ptr()).height as usize }
+    }
+
+    fn links(self) -> *const AtomicPtr<Node> {
+        unsafe { self.ptr.as_ptr().add(1).cast() }
+    }
+
+    fn link(self, level: usize) -> &'a AtomicPtr<Node> {
+        assert!(level < self.height());
+        unsafe { &*self.links().add(level) }
+    }
+
+    fn next(self, level: usize) -> Option<Self> {
+        unsafe { Self::from_ptr(self.link(level).load(Ordering::Acquire)) }
+    }
+
+    fn key(self) -> KeySlice<'a> {
+        unsafe {
+            let node = self.ptr.as_ptr();
+            let key = self.links().add(self.height()).cast::<u8>();
+            KeySlice::from_slice(
+                std::slice::from_raw_parts(key, (*node).key_len as usize),
+                (*node).ts,
+            )
+        }
+    }
+
+    fn value_ptr(self) -> *mut u8 {
+        unsafe { (*self.ptr.as_ptr()).value.load(Ordering::Acquire) }
+    }
+
+    fn set_value_ptr(self, value: *mut u8) {
+        unsafe { (*self.ptr.as_ptr()).value.store(value, Ordering::Release) }
+    }
+
+    fn as_ptr(self) -> *mut Node {
+        self.ptr.as_ptr()
+    }
+}
+
+/// An ordered map from keys to values that supports concurrent inserts. Unlike
+/// `crossbeam_skiplist::SkipMap`, it does not allocate for each entry, and its memory usage is
+/// exactly the bytes taken from the arena.
+pub struct SkipList {
+    /// May be shared with other skiplists, which are then freed together.
+    arena: Arc<Arena>,
+    /// A node without key or value that is before all the others, with links at every level.
+    head: NonNull<Node>,
+    /// Generates the heights of new nodes.
+    seed: AtomicU64,
+    len: AtomicUsize,
+}
+
+unsafe impl Send for SkipList {}
+unsafe impl Sync for SkipList {}
+
+impl Default for SkipList {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SkipList {
+    pub fn new() -> Self {
+        Self::with_arena(Arc::new(Arena::new()))
+    }
+
+    pub(super) fn with_arena(arena: Arc<Arena>) -> Self {
+        let head = Self::new_node(&arena, KeySlice::from_slice(&[], 0), None, MAX_HEIGHT);
+        Self {
+            arena,
+            head,
+            seed: AtomicU64::new(0),
+            len: AtomicUsize::new(0),
+        }
+    }
+
+    fn new_node(
+        arena: &Arena,
+        key: KeySlice,
+        value: Option<&[u8]>,
+        height: usize,
+    ) -> NonNull<Node> {
+        let key_offset = size_of::<Node>() + height * LINK_SIZE;
+        let value_offset = key_offset + key.key_len();
+        let size = value_offset + value.map_or(0, |x| VALUE_LEN_SIZE + x.len());
+        let node = arena.alloc(size);
+        unsafe {
+            // the links are already null as the arena is zeroed
+            let data = node.as_ptr();
+            std::ptr::copy_nonoverlapping(
+                key.key_ref().as_ptr(),
+                data.add(key_offset),
+                key.key_len(),
+            );
+            let value = match value {
+                Some(value) => {
+                    write_value(data.add(value_offset), value);
+                    data.add(value_offset)
+                }
+                None => null_mut(),
+            };
+            let node = node.cast::<Node>();
+            node.write(Node {
+                value: AtomicPtr::new(value),
+                ts: key.ts(),
+                key_len: key.key_len() as u32,
+                height: height as u32,
+            });
+            node
+        }
+    }
+
+    fn head(&self) -> NodeRef<'_> {
+        unsafe { NodeRef::from_ptr(self.head.as_ptr()).unwrap() }
+    }
+
+    fn random_height(&self) -> usize {
+        // splitmix64 over a counter, so that the same writes always build the same list
+        let mut z = self
+            .seed
+            .fetch_add(0x9e3779b97f4a7c15, Ordering::Relaxed)
+            .wrapping_add(0x9e3779b97f4a7c15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^= z >> 31;
+        // each level above the first is taken with a probability of 1/4
+        (1 + z.trailing_zeros() as usize / 2).min(MAX_HEIGHT)
+    }
+
+    /// Find the last node before `key` and the first node not before it at `level`, starting from
+    /// `before`, which must be before `key`.
+    fn find_splice_for_level<'a>(
+        &'a self,
+        key: KeySlice,
+        mut before: NodeRef<'a>,
+        level: usize,
+    ) -> (NodeRef<'a>, Option<NodeRef<'a>>) {
+        loop {
+            match before.next(level) {
+                Some(next) if next.key() < key => before = next,
+                next => return (before, next),
+            }
+        }
+    }
+
+    /// Find the first node not before `key`.
+    fn seek(&self, key: KeySlice) -> Option<NodeRef<'_>> {
+        let mut before = self.head();
+        let mut after = None;
+        for level in (0..MAX_HEIGHT).rev() {
+            (before, after) = self.find_splice_for_level(key, before, level);
+        }
+        after
+    }
+
+    fn seek_bound(&self, lower: Bound<KeySlice>) -> Option<NodeRef<'_>> {
+        match lower {
+            Bound::Included(key) => self.seek(key),
+            Bound::Excluded(key) => self.seek(key).and_then(|node| {
+                if node.key() == key {
+                    node.next(0)
+                } else {
+                    Some(node)
+                }
+            }),
+            Bound::Unbounded => self.head().next(0),
+        }
+    }
+
+    /// Insert a key-value pair, or replace the value if the key is already in the list. Inserts
+    /// from different threads do not block each other.
+    fn insert_entry(&self, key: KeySlice, value: &[u8]) {
+        let mut before = [self.head(); MAX_HEIGHT];
+        let mut after = [None; MAX_HEIGHT];
+        let mut node = self.head();
+        for level in (0..MAX_HEIGHT).rev() {
+            (before[level], after[level]) = self.find_splice_for_level(key, node, level);
+            node = before[level];
+        }
+        if let Some(node) = after[0]
+            && node.key() == key
+        {
+            let value_ptr = self.arena.alloc(VALUE_LEN_SIZE + value.len()).as_ptr();
+            unsafe { write_value(value_ptr, value) };
+            node.set_value_ptr(value_ptr);
+            return;
+        }
+
+        let height = self.random_height();
+        let node = unsafe {
+            NodeRef::from_ptr(Self::new_node(&self.arena, key, Some(value), height).as_ptr())
+                .unwrap()
+        };
+        // the node is in the list once linked at level 0, and the levels above only speed up
+        // searches
+        for level in 0..height {
+            loop {
+                let next = after[level].map_or(null_mut(), NodeRef::as_ptr);
+                node.link(level).store(next, Ordering::Relaxed);
+                if before[level]
+                    .link(level)
+                    .compare_exchange(next, node.as_ptr(), Ordering::Release, Ordering::Relaxed)
+                    .is_ok()
+                {
+                    break;
+                }
+                // another node was linked right here, so search again from the node before
+                (before[level], after[level]) =
+                    self.find_splice_for_level(key, before[level], level);
+                if level == 0
+                    && let Some(other) = after[0]
+                    && other.key() == key
+                {
+                    // the same key was inserted concurrently, and the node is left unused
+                    other.set_value_ptr(node.value_ptr());
+                    return;
+                }
+            }
+        }
+        self.len.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Iterate over all the entries in order.
+    pub fn iter(&self) -> Iter<'_> {
+        Iter {
+            node: self.head().next(0),
+        }
+    }
+
+    /// Get an iterator over a range of keys that keeps the list alive.
+    pub fn range(self: &Arc<Self>, lower: Bound<KeySlice>, upper: Bound<KeySlice>) -> Range {
+        let node = self.seek_bound(lower).map(NodeRef::as_ptr);
+        let mut range = Range {
+            _list: self.clone(),
+            node: None,
+            value: null_mut(),
+            upper: super::map_key_bound(upper),
+        };
+        range.move_to(node.unwrap_or(null_mut()));
+        range
+    }
+}
+
+impl MemTableRep for SkipList {
+    fn insert(&self, key: KeySlice, value: &[u8]) {
+        self.insert_entry(key, value);
+    }
+
+    fn get(&self, key: KeySlice) -> Option<Bytes> {
+        self.seek(key)
+            .filter(|node| node.key() == key)
+            .map(|node| Bytes::copy_from_slice(unsafe { read_value(node.value_ptr()) }))
+    }
+
+    fn scan(
+        self: Arc<Self>,
+        lower: Bound<KeySlice>,
+        upper: Bound<KeySlice>,
+    ) -> Box<dyn MemTableRepIterator> {
+        Box::new(self.range(lower, upper))
+    }
+
+    fn len(&self) -> usize {
+        self.len.load(Ordering::Relaxed)
+    }
+
+    /// The bytes taken from the arena, including those of other lists sharing it.
+    fn memory_usage(&self) -> usize {
+        self.arena.allocated()
+    }
+}
+
+pub struct Iter<'a> {
+    node: Option<NodeRef<'a>>,
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = (KeySlice<'a>, &'a [u8]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let node = self.node?;
+        self.node = node.next(0);
+        Some((node.key(), unsafe { read_value(node.value_ptr()) }))
+    }
+}
+
+/// An iterator over a range of a skiplist, which keeps the value of the current entry it saw
+/// when moving there.
+pub struct Range {
+    /// Keeps the nodes alive.
+    _list: Arc<SkipList>,
+    node: Option<NonNull<Node>>,
+    value: *mut u8,
+    upper: Bound<KeyBytes>,
+}
+
+unsafe impl Send for Range {}
+unsafe impl Sync for Range {}
+
+impl Range {
+    fn current(&self) -> Option<NodeRef<'_>> {
+        unsafe { NodeRef::from_ptr(self.node?.as_ptr()) }
+    }
+
+    fn move_to(&mut self, node: *mut Node) {
+        // the node belongs to the list kept by the range
+        let node = unsafe { NodeRef::from_ptr(node) }.filter(|node| match &self.upper {
+            Bound::Included(upper) => node.key() <= upper.as_key_slice(),
+            Bound::Excluded(upper) => node.key() < upper.as_key_slice(),
+            Bound::Unbounded => true,
+        });
+        self.value = node.map_or(null_mut(), NodeRef::value_ptr);
+        self.node = node.map(|node| node.ptr);
+    }
+}
+
+impl StorageIterator for Range {
+    type KeyType<'a> = KeySlice<'a>;
+
+    fn value(&self) -> &[u8] {
+        assert!(self.node.is_some());
+        unsafe { read_value(self.value) }
+    }
+
+    fn key(&self) -> KeySlice<'_> {
+        self.current().unwrap().key()
+    }
+
+    fn is_valid(&self) -> bool {
+        self.node.is_some()
+    }
+
+    fn next(&mut self) -> Result<()> {
+        if let Some(node) = self.current() {
+            let next = node.link(0).load(Ordering::Acquire);
+            self.move_to(next);
+        }
+        Ok(())
+    }
+}
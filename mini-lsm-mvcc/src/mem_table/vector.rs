@@ -0,0 +1,168 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::ops::Bound;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use anyhow::Result;
+use bytes::Bytes;
+use parking_lot::RwLock;
+
+use super::{MemTableRep, MemTableRepIterator};
+use crate::iterators::StorageIterator;
+use crate::key::{KeyBytes, KeySlice};
+
+/// Sort the entries by key, keeping the last value put for each key.
+fn sort_entries(mut entries: Vec<(KeyBytes, Bytes)>) -> Vec<(KeyBytes, Bytes)> {
+    // the sort is stable, so the values of a key stay in the order they were put
+    entries.sort_by(|x, y| x.0.cmp(&y.0));
+    let mut sorted: Vec<(KeyBytes, Bytes)> = Vec::with_capacity(entries.len());
+    for entry in entries {
+        match sorted.last_mut() {
+            Some(last) if last.0 == entry.0 => *last = entry,
+            _ => sorted.push(entry),
+        }
+    }
+    sorted
+}
+
+enum Entries {
+    /// The entries in the order they were put, before the rep is frozen.
+    Unsorted(Vec<(KeyBytes, Bytes)>),
+    Sorted(Arc<Vec<(KeyBytes, Bytes)>>),
+}
+
+/// A mem-table rep that appends the entries to a vector and sorts it when frozen. Writes are
+/// cheap, but reads of a mutable rep search or sort a copy of the whole vector, so it suits bulk
+/// loads that do not read what they write.
+pub struct VectorRep {
+    entries: RwLock<Entries>,
+    memory_usage: AtomicUsize,
+}
+
+impl Default for VectorRep {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl VectorRep {
+    pub fn new() -> Self {
+        Self {
+            entries: RwLock::new(Entries::Unsorted(Vec::new())),
+            memory_usage: AtomicUsize::new(0),
+        }
+    }
+}
+
+impl MemTableRep for VectorRep {
+    fn insert(&self, key: KeySlice, value: &[u8]) {
+        let Entries::Unsorted(entries) = &mut *self.entries.write() else {
+            panic!("insert into a frozen rep");
+        };
+        entries.push((
+            key.to_key_vec().into_key_bytes(),
+            Bytes::copy_from_slice(value),
+        ));
+        self.memory_usage.fetch_add(
+            key.raw_len() + value.len() + size_of::<(KeyBytes, Bytes)>(),
+            Ordering::Relaxed,
+        );
+    }
+
+    fn get(&self, key: KeySlice) -> Option<Bytes> {
+        match &*self.entries.read() {
+            Entries::Unsorted(entries) => entries
+                .iter()
+                .rev()
+                .find(|(x, _)| x.as_key_slice() == key)
+                .map(|(_, value)| value.clone()),
+            Entries::Sorted(entries) => {
+                let idx = entries
+                    .binary_search_by(|(x, _)| x.as_key_slice().cmp(&key))
+                    .ok()?;
+                Some(entries[idx].1.clone())
+            }
+        }
+    }
+
+    fn scan(
+        self: Arc<Self>,
+        lower: Bound<KeySlice>,
+        upper: Bound<KeySlice>,
+    ) -> Box<dyn MemTableRepIterator> {
+        let entries = match &*self.entries.read() {
+            Entries::Unsorted(entries) => Arc::new(sort_entries(entries.clone())),
+            Entries::Sorted(entries) => entries.clone(),
+        };
+        let idx = match lower {
+            Bound::Included(key) => entries.partition_point(|(x, _)| x.as_key_slice() < key),
+            Bound::Excluded(key) => entries.partition_point(|(x, _)| x.as_key_slice() <= key),
+            Bound::Unbounded => 0,
+        };
+        let end = match upper {
+            Bound::Included(key) => entries.partition_point(|(x, _)| x.as_key_slice() <= key),
+            Bound::Excluded(key) => entries.partition_point(|(x, _)| x.as_key_slice() < key),
+            Bound::Unbounded => entries.len(),
+        };
+        Box::new(VectorRepIterator { entries, idx, end })
+    }
+
+    fn freeze(&self) {
+        let mut guard = self.entries.write();
+        if let Entries::Unsorted(entries) = &mut *guard {
+            *guard = Entries::Sorted(Arc::new(sort_entries(std::mem::take(entries))));
+        }
+    }
+
+    fn len(&self) -> usize {
+        match &*self.entries.read() {
+            // the same key put twice is counted twice until the rep is sorted
+            Entries::Unsorted(entries) => entries.len(),
+            Entries::Sorted(entries) => entries.len(),
+        }
+    }
+
+    fn memory_usage(&self) -> usize {
+        self.memory_usage.load(Ordering::Relaxed)
+    }
+}
+
+/// An iterator over the sorted entries of a [`VectorRep`], or over a sorted copy of them.
+struct VectorRepIterator {
+    entries: Arc<Vec<(KeyBytes, Bytes)>>,
+    idx: usize,
+    end: usize,
+}
+impl StorageIterator for VectorRepIterator {
+    type KeyType<'a> = KeySlice<'a>;
+
+    fn value(&self) -> &[u8] {
+        &self.entries[self.idx].1
+    }
+
+    fn key(&self) -> KeySlice<'_> {
+        self.entries[self.idx].0.as_key_slice()
+    }
+
+    fn is_valid(&self) -> bool {
+        self.idx < self.end
+    }
+
+    fn next(&mut self) -> Result<()> {
+        self.idx += 1;
+        Ok(())
+    }
+}
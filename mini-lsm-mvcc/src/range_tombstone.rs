@@ -0,0 +1,238 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::ops::Bound;
+
+use anyhow::{Result, bail};
+use bytes::{Buf, BufMut, Bytes};
+
+use crate::key::{KeyBytes, KeySlice, TS_RANGE_BEGIN, TS_RANGE_END};
+use crate::varint::{get_uvarint, put_uvarint};
+
+/// Deletes all versions of the keys in `[start, end)` written before `ts`. Writes in the same
+/// batch as the range deletion (with the same timestamp) are not deleted.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RangeTombstone {
+    pub start: Bytes,
+    pub end: Bytes,
+    pub ts: u64,
+}
+
+/// A range of keys and the timestamps of all tombstones covering it, from the latest one.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Fragment {
+    start: Bytes,
+    end: Bytes,
+    ts: Vec<u64>,
+}
+
+/// Range tombstones split into non-overlapping fragments sorted by key, so that the tombstones
+/// covering a key can be found with a binary search.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FragmentedRangeTombstones {
+    fragments: Vec<Fragment>,
+}
+
+/// Returns the smallest key that is larger than `key`.
+pub(crate) fn key_successor(key: &[u8]) -> Bytes {
+    let mut succ = Vec::with_capacity(key.len() + 1);
+    succ.extend_from_slice(key);
+    succ.push(0);
+    succ.into()
+}
+
+impl FragmentedRangeTombstones {
+    pub fn new(tombstones: impl IntoIterator<Item = RangeTombstone>) -> Self {
+        let mut tombstones = tombstones
+            .into_iter()
+            .filter(|x| x.start < x.end)
+            .collect::<Vec<_>>();
+        tombstones.sort_by(|x, y| x.start.cmp(&y.start));
+        let mut bounds = tombstones
+            .iter()
+            .flat_map(|x| [x.start.clone(), x.end.clone()])
+            .collect::<Vec<_>>();
+        bounds.sort();
+        bounds.dedup();
+
+        // sweep the bounds, keeping the tombstones that cover the current fragment
+        let mut fragments = Vec::<Fragment>::new();
+        let mut active = Vec::<&RangeTombstone>::new();
+        let mut next = 0;
+        for window in bounds.windows(2) {
+            let (start, end) = (&window[0], &window[1]);
+            active.retain(|x| x.end > start);
+            while next < tombstones.len() && tombstones[next].start == start {
+                active.push(&tombstones[next]);
+                next += 1;
+            }
+            if active.is_empty() {
+                continue;
+            }
+            let mut ts = active.iter().map(|x| x.ts).collect::<Vec<_>>();
+            ts.sort_by(|x, y| y.cmp(x));
+            ts.dedup();
+            match fragments.last_mut() {
+                Some(last) if last.end == start && last.ts == ts => last.end = end.clone(),
+                _ => fragments.push(Fragment {
+                    start: start.clone(),
+                    end: end.clone(),
+                    ts,
+                }),
+            }
+        }
+        Self { fragments }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fragments.is_empty()
+    }
+
+    /// Iterate over the fragments as tombstones. Tombstones of the same fragment are ordered from
+    /// the latest one.
+    pub fn iter(&self) -> impl Iterator<Item = RangeTombstone> + '_ {
+        self.fragments.iter().flat_map(|fragment| {
+            fragment.ts.iter().map(|ts| RangeTombstone {
+                start: fragment.start.clone(),
+                end: fragment.end.clone(),
+                ts: *ts,
+            })
+        })
+    }
+
+    /// Returns the timestamp of the latest tombstone covering `key` that is visible at `read_ts`.
+    pub fn max_covering_ts(&self, key: &[u8], read_ts: u64) -> Option<u64> {
+        let idx = self
+            .fragments
+            .partition_point(|fragment| fragment.start.as_ref() <= key);
+        let fragment = self.fragments.get(idx.checked_sub(1)?)?;
+        if key >= fragment.end.as_ref() {
+            return None;
+        }
+        fragment.ts.iter().copied().find(|ts| *ts <= read_ts)
+    }
+
+    /// Returns whether the version of a key is deleted by a tombstone visible at `read_ts`.
+    pub fn covers(&self, key: KeySlice, read_ts: u64) -> bool {
+        self.max_covering_ts(key.key_ref(), read_ts)
+            .is_some_and(|ts| key.ts() < ts)
+    }
+
+    /// Iterate over the tombstones that may cover keys in the range.
+    pub(crate) fn overlapping<'a>(
+        &'a self,
+        lower: Bound<&'a [u8]>,
+        upper: Bound<&'a [u8]>,
+    ) -> impl Iterator<Item = RangeTombstone> + 'a {
+        self.iter().filter(move |x| {
+            let after_lower = match lower {
+                Bound::Included(key) | Bound::Excluded(key) => x.end.as_ref() > key,
+                Bound::Unbounded => true,
+            };
+            let before_upper = match upper {
+                Bound::Included(key) => x.start.as_ref() <= key,
+                Bound::Excluded(key) => x.start.as_ref() < key,
+                Bound::Unbounded => true,
+            };
+            after_lower && before_upper
+        })
+    }
+
+    /// Clip the tombstones to the keys in `[lower, upper)`, where `None` means unbounded.
+    pub(crate) fn clip(&self, lower: Option<&[u8]>, upper: Option<&[u8]>) -> Self {
+        Self::new(self.iter().map(|mut x| {
+            if let Some(lower) = lower
+                && x.start.as_ref() < lower
+            {
+                x.start = Bytes::copy_from_slice(lower);
+            }
+            if let Some(upper) = upper
+                && x.end.as_ref() > upper
+            {
+                x.end = Bytes::copy_from_slice(upper);
+            }
+            x
+        }))
+    }
+
+    /// Drop the tombstones written at or before `ts`.
+    pub(crate) fn drop_until(&self, ts: u64) -> Self {
+        Self::new(self.iter().filter(|x| x.ts > ts))
+    }
+
+    /// The smallest and the largest keys covered by the tombstones, which are included in the key
+    /// range of the SST storing them.
+    pub(crate) fn key_range(&self) -> Option<(KeyBytes, KeyBytes)> {
+        let first = self.fragments.first()?;
+        let last = self.fragments.last()?;
+        let first_key = KeyBytes::from_bytes_with_ts(first.start.clone(), TS_RANGE_BEGIN);
+        // the largest key before the exclusive end, which is exact if the end is a successor
+        let last_key = match last.end.split_last() {
+            Some((0, key)) => {
+                KeyBytes::from_bytes_with_ts(last.end.slice(..key.len()), TS_RANGE_END)
+            }
+            _ => KeyBytes::from_bytes_with_ts(last.end.clone(), TS_RANGE_BEGIN),
+        };
+        Some((first_key, last_key))
+    }
+
+    /// Approximate size of the tombstones in memory.
+    pub(crate) fn approximate_size(&self) -> usize {
+        self.fragments
+            .iter()
+            .map(|x| x.start.len() + x.end.len() + x.ts.len() * std::mem::size_of::<u64>())
+            .sum()
+    }
+
+    pub(crate) fn encode(&self, buf: &mut Vec<u8>) {
+        let offset = buf.len();
+        put_uvarint(buf, self.fragments.len() as u64);
+        for fragment in &self.fragments {
+            put_uvarint(buf, fragment.start.len() as u64);
+            buf.put_slice(&fragment.start);
+            put_uvarint(buf, fragment.end.len() as u64);
+            buf.put_slice(&fragment.end);
+            put_uvarint(buf, fragment.ts.len() as u64);
+            for ts in &fragment.ts {
+                buf.put_u64(*ts);
+            }
+        }
+        let checksum = crc32fast::hash(&buf[offset..]);
+        buf.put_u32(checksum);
+    }
+
+    pub(crate) fn decode(buf: &[u8]) -> Result<Self> {
+        if buf.len() < 4 {
+            bail!("range tombstones too short: {} bytes", buf.len());
+        }
+        let (mut buf, mut checksum) = buf.split_at(buf.len() - 4);
+        if checksum.get_u32() != crc32fast::hash(buf) {
+            bail!("checksum mismatched for range tombstones");
+        }
+        let num_fragments = get_uvarint(&mut buf) as usize;
+        let mut fragments = Vec::with_capacity(num_fragments);
+        for _ in 0..num_fragments {
+            let start_len = get_uvarint(&mut buf) as usize;
+            let start = Bytes::copy_from_slice(&buf[..start_len]);
+            buf.advance(start_len);
+            let end_len = get_uvarint(&mut buf) as usize;
+            let end = Bytes::copy_from_slice(&buf[..end_len]);
+            buf.advance(end_len);
+            let num_ts = get_uvarint(&mut buf) as usize;
+            let ts = (0..num_ts).map(|_| buf.get_u64()).collect();
+            fragments.push(Fragment { start, end, ts });
+        }
+        Ok(Self { fragments })
+    }
+}
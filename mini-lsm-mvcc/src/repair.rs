@@ -0,0 +1,230 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Rebuilds the manifest from the files left in the directory of a storage engine.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result, bail};
+
+use crate::compact::CompactionController;
+use crate::env::FileSystem;
+use crate::file_gc::parse_file_name;
+use crate::lsm_storage::{LsmStorageOptions, MiniLsm};
+use crate::manifest::{
+    Manifest, NewFile, VersionEdit, manifest_file_name, manifest_number, set_current_manifest,
+};
+use crate::table::{FileObject, SsTable};
+use crate::wal::decode_batches;
+
+/// The directory unreadable files are moved to, relative to the directory of the storage engine.
+const LOST_DIR: &str = "lost";
+
+/// Moves files the repair cannot use into the lost directory, so that nothing is deleted.
+struct Quarantine<'a> {
+    fs: &'a dyn FileSystem,
+    dir: PathBuf,
+    created: bool,
+}
+
+impl Quarantine<'_> {
+    /// A path in the lost directory for the file, which does not replace the files moved there by
+    /// an earlier repair.
+    fn path_for(&mut self, path: &Path) -> Result<PathBuf> {
+        if !self.created {
+            self.fs.create_dir_all(&self.dir)?;
+            self.created = true;
+        }
+        let name = path.file_name().context("file without a name")?;
+        let mut target = self.dir.join(name);
+        let mut suffix = 1;
+        while self.fs.exists(&target) {
+            target = self
+                .dir
+                .join(format!("{}.{}", name.to_string_lossy(), suffix));
+            suffix += 1;
+        }
+        Ok(target)
+    }
+
+    fn move_file(&mut self, path: &Path, reason: impl std::fmt::Display) -> Result<()> {
+        let target = self.path_for(path)?;
+        println!(
+            "moving {} to {}: {}",
+            path.display(),
+            target.display(),
+            reason
+        );
+        self.fs.rename(path, &target)
+    }
+
+    fn copy_file(&mut self, path: &Path, data: &[u8]) -> Result<()> {
+        let target = self.path_for(path)?;
+        let mut file = self.fs.create(&target)?;
+        file.append(data)?;
+        file.sync()
+    }
+
+    fn sync(&self) -> Result<()> {
+        if self.created {
+            self.fs.sync_dir(&self.dir)?;
+        }
+        Ok(())
+    }
+}
+
+impl MiniLsm {
+    /// Rebuild the manifest of a storage engine that cannot start because its manifest is
+    /// corrupted or lost. Every readable SST is put into L0, or into a tier of its own for tiered
+    /// compaction, ordered by its max timestamp; every readable WAL becomes a memtable to be
+    /// recovered, and the valid prefix is kept of a WAL corrupted in the middle. The new manifest
+    /// becomes current, and unreadable files and the old manifests are moved to the `lost`
+    /// directory.
+    ///
+    /// Like in other LSM trees, the repair may bring back deleted data: an SST left behind by a
+    /// compaction that dropped the tombstones in it is put back with the tombstones gone.
+    pub fn repair(path: impl AsRef<Path>, options: LsmStorageOptions) -> Result<()> {
+        let path = path.as_ref();
+        let fs = &*options.file_system;
+        if !fs.exists(path) {
+            bail!("failed to repair {}: directory not found", path.display());
+        }
+        let mut quarantine = Quarantine {
+            fs,
+            dir: path.join(LOST_DIR),
+            created: false,
+        };
+
+        let mut sst_paths = Vec::new();
+        let mut wal_paths = Vec::new();
+        let mut vlog_paths = Vec::new();
+        let mut manifest_paths = Vec::new();
+        for file in fs.list_dir(path)? {
+            if let Some(number) = manifest_number(&file) {
+                manifest_paths.push((number, file));
+                continue;
+            }
+            match parse_file_name(&file) {
+                Some((id, "sst")) => sst_paths.push((id, file)),
+                Some((id, "wal")) => wal_paths.push((id, file)),
+                Some((id, "vlog")) => vlog_paths.push((id, file)),
+                _ => {}
+            }
+        }
+        sst_paths.sort();
+        wal_paths.sort();
+        vlog_paths.sort();
+
+        let flush_to_l0 = CompactionController::new(&options.compaction_options).flush_to_l0();
+        let mut ssts = Vec::new();
+        // memtables are flushed in order, so the WALs up to the newest flushed one are not needed
+        let mut max_flushed_memtable = None;
+        for (id, file) in sst_paths {
+            let sst = FileObject::open_with_fs(fs, &file, options.read_backend)
+                .and_then(|file| SsTable::open(id, None, file));
+            match sst {
+                Ok(sst) => {
+                    let max_memtable_id = sst.properties().and_then(|x| x.max_memtable_id);
+                    max_flushed_memtable = max_flushed_memtable.max(max_memtable_id);
+                    let level = if flush_to_l0 { 0 } else { id };
+                    ssts.push((sst.max_ts(), id, NewFile::new(level, &sst)));
+                }
+                Err(e) => quarantine.move_file(&file, format!("{:#}", e))?,
+            }
+        }
+        // newer SSTs go above older ones, so that compactions keep the newest versions
+        ssts.sort_by_key(|(max_ts, id, _)| (*max_ts, *id));
+
+        let mut value_logs = Vec::new();
+        for (id, file) in vlog_paths {
+            match FileObject::open_with_fs(fs, &file, options.read_backend) {
+                Ok(_) => value_logs.push(id),
+                Err(e) => quarantine.move_file(&file, format!("{:#}", e))?,
+            }
+        }
+
+        let mut memtables = Vec::new();
+        for (id, file) in wal_paths {
+            // an SST without the property is named after the memtable if it is flushed from it
+            if max_flushed_memtable.is_some_and(|x| id as u64 <= x)
+                || ssts.iter().any(|(_, sst_id, _)| *sst_id == id)
+            {
+                quarantine.move_file(&file, "the memtable has been flushed")?;
+                continue;
+            }
+            let data = match fs.read(&file) {
+                Ok(data) => data,
+                Err(e) => {
+                    quarantine.move_file(&file, format!("{:#}", e))?;
+                    continue;
+                }
+            };
+            if let (batches, Some(corruption)) = decode_batches(&data)
+                && !corruption.is_torn_tail()
+            {
+                // keep the batches before the corruption, and the whole WAL in the lost directory
+                println!(
+                    "keeping {} batches of {}: {}",
+                    batches.len(),
+                    file.display(),
+                    corruption
+                );
+                quarantine.copy_file(&file, &data)?;
+                quarantine.sync()?;
+                let mut wal = fs.create(&file)?;
+                wal.append(&data[..corruption.offset() as usize])?;
+                wal.sync()?;
+            }
+            memtables.push(id);
+        }
+
+        // the old manifests stay until CURRENT refers to the new one
+        manifest_paths.sort();
+        let number = manifest_paths.last().map(|(x, _)| x + 1).unwrap_or(1);
+        let manifest_path = path.join(manifest_file_name(number));
+        let max_id = ssts
+            .iter()
+            .map(|(_, id, _)| *id)
+            .chain(memtables.iter().copied())
+            .chain(value_logs.iter().copied())
+            .max()
+            .unwrap_or_default();
+        let num_ssts = ssts.len();
+        let num_memtables = memtables.len();
+        let num_value_logs = value_logs.len();
+        let manifest = Manifest::create(fs, &manifest_path)?;
+        manifest.add_record_when_init(VersionEdit {
+            new_memtables: memtables,
+            new_files: ssts.into_iter().map(|(_, _, file)| file).collect(),
+            new_value_logs: value_logs,
+            next_sst_id: Some(max_id + 1),
+            ..Default::default()
+        })?;
+        drop(manifest);
+        set_current_manifest(fs, path, &manifest_path)?;
+        for (_, file) in manifest_paths {
+            quarantine.move_file(&file, "replaced by the repaired manifest")?;
+        }
+        quarantine.sync()?;
+        fs.sync_dir(path)?;
+        println!(
+            "repaired {}: {} SSTs, {} WALs, {} value logs",
+            path.display(),
+            num_ssts,
+            num_memtables,
+            num_value_logs
+        );
+        Ok(())
+    }
+}
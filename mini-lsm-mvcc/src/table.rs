@@ -14,49 +14,100 @@
 
 pub(crate) mod bloom;
 mod builder;
+mod compression;
+mod filter;
+pub(crate) mod footer;
 mod iterator;
+mod prefix;
+mod properties;
+mod ribbon;
+mod writer;
 
-use std::fs::File;
 use std::path::Path;
 use std::sync::Arc;
 
 use anyhow::{Result, anyhow, bail};
 pub use builder::SsTableBuilder;
-use bytes::{Buf, BufMut};
+use bytes::{Buf, BufMut, Bytes};
+pub use compression::CompressionType;
+pub use filter::{
+    BlockedBloomFilterPolicy, BloomFilterPolicy, Filter, FilterKind, FilterPolicy,
+    RibbonFilterPolicy,
+};
+use footer::{Footer, LEGACY_FORMAT_VERSION, SST_FORMAT_VERSION};
 pub use iterator::SsTableIterator;
+pub use prefix::{DelimiterPrefixExtractor, FixedPrefixExtractor, PrefixExtractor};
+pub(crate) use prefix::{prefix_of_range, prefix_upper_bound};
+pub use properties::{CompactionReason, TableProperties};
 
-use crate::block::Block;
+use crate::block::{Block, BlockIterator};
+use crate::env::{DiskFileSystem, FileSystem, RandomAccessFile, ReadBackend};
 use crate::key::{KeyBytes, KeySlice};
 use crate::lsm_storage::BlockCache;
+use crate::range_tombstone::FragmentedRangeTombstones;
+use crate::varint::{get_uvarint, put_uvarint, uvarint_len};
 
-use self::bloom::Bloom;
+/// Number of data blocks indexed by each index partition.
+pub(crate) const INDEX_PARTITION_BLOCKS: usize = 128;
 
+/// The offset and key range of a block. The index of an SST is partitioned: each index partition
+/// is a block that maps the last key of each data block to the position of the data block, and
+/// the top-level index, which is kept in memory, is a list of `BlockMeta` of the index partitions.
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct BlockMeta {
-    /// Offset of this data block.
+    /// Offset of this block.
     pub offset: usize,
-    /// The first key of the data block.
+    /// The first key of the block, or of the data blocks in the index partition.
     pub first_key: KeyBytes,
-    /// The last key of the data block.
+    /// The last key of the block, or of the data blocks in the index partition.
     pub last_key: KeyBytes,
 }
 
+/// Position of a data block in the SST file, stored as the value in the index partitions.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) struct BlockHandle {
+    pub(crate) offset: u64,
+    pub(crate) len: u64,
+}
+
+impl BlockHandle {
+    pub(crate) fn encode(&self) -> [u8; 16] {
+        let mut buf = [0; 16];
+        (&mut buf[..8]).put_u64(self.offset);
+        (&mut buf[8..]).put_u64(self.len);
+        buf
+    }
+
+    pub(crate) fn decode(mut buf: &[u8]) -> Self {
+        Self {
+            offset: buf.get_u64(),
+            len: buf.get_u64(),
+        }
+    }
+}
+
 impl BlockMeta {
-    /// Encode block meta to a buffer.
-    pub fn encode_block_meta(block_meta: &[BlockMeta], max_ts: u64, buf: &mut Vec<u8>) {
-        let mut estimated_size = std::mem::size_of::<u32>(); // number of blocks
+    /// Encode the top-level index to a buffer.
+    pub fn encode_block_meta(
+        block_meta: &[BlockMeta],
+        num_of_blocks: usize,
+        max_ts: u64,
+        buf: &mut Vec<u8>,
+    ) {
+        let mut estimated_size = std::mem::size_of::<u32>(); // number of partitions
         for meta in block_meta {
             // The size of offset
-            estimated_size += std::mem::size_of::<u32>();
+            estimated_size += std::mem::size_of::<u64>();
             // The size of key length
-            estimated_size += std::mem::size_of::<u16>();
+            estimated_size += uvarint_len(meta.first_key.key_len() as u64);
             // The size of actual key
             estimated_size += meta.first_key.raw_len();
             // The size of key length
-            estimated_size += std::mem::size_of::<u16>();
+            estimated_size += uvarint_len(meta.last_key.key_len() as u64);
             // The size of actual key
             estimated_size += meta.last_key.raw_len();
         }
+        estimated_size += std::mem::size_of::<u32>(); // number of data blocks
         estimated_size += std::mem::size_of::<u64>(); // max timestamp
         estimated_size += std::mem::size_of::<u32>(); // checksum
 
@@ -66,30 +117,43 @@ impl BlockMeta {
         let original_len = buf.len();
         buf.put_u32(block_meta.len() as u32);
         for meta in block_meta {
-            buf.put_u32(meta.offset as u32);
-            buf.put_u16(meta.first_key.key_len() as u16);
+            buf.put_u64(meta.offset as u64);
+            put_uvarint(buf, meta.first_key.key_len() as u64);
             buf.put_slice(meta.first_key.key_ref());
             buf.put_u64(meta.first_key.ts());
-            buf.put_u16(meta.last_key.key_len() as u16);
+            put_uvarint(buf, meta.last_key.key_len() as u64);
             buf.put_slice(meta.last_key.key_ref());
             buf.put_u64(meta.last_key.ts());
         }
+        buf.put_u32(num_of_blocks as u32);
         buf.put_u64(max_ts);
         buf.put_u32(crc32fast::hash(&buf[original_len + 4..]));
         assert_eq!(estimated_size, buf.len() - original_len);
     }
 
-    /// Decode block meta from a buffer.
-    pub fn decode_block_meta(mut buf: &[u8]) -> Result<(Vec<BlockMeta>, u64)> {
+    /// Decode the top-level index from a buffer, returning the partitions, the number of data
+    /// blocks and the max timestamp. The index of a legacy SST lists the data blocks themselves.
+    pub fn decode_block_meta(mut buf: &[u8], version: u32) -> Result<(Vec<BlockMeta>, usize, u64)> {
+        if version == LEGACY_FORMAT_VERSION {
+            let (block_meta, max_ts) = Self::decode_legacy_block_meta(buf)?;
+            let num_of_blocks = block_meta.len();
+            return Ok((block_meta, num_of_blocks, max_ts));
+        }
+        if buf.len() < 20 {
+            bail!("meta too short: {} bytes", buf.len());
+        }
+        let checksum = (&buf[buf.len() - 4..]).get_u32();
+        if checksum != crc32fast::hash(&buf[4..buf.len() - 4]) {
+            bail!("meta checksum mismatched");
+        }
         let mut block_meta = Vec::new();
         let num = buf.get_u32() as usize;
-        let checksum = crc32fast::hash(&buf[..buf.remaining() - 4]);
         for _ in 0..num {
-            let offset = buf.get_u32() as usize;
-            let first_key_len = buf.get_u16() as usize;
+            let offset = buf.get_u64() as usize;
+            let first_key_len = get_uvarint(&mut buf) as usize;
             let first_key =
                 KeyBytes::from_bytes_with_ts(buf.copy_to_bytes(first_key_len), buf.get_u64());
-            let last_key_len: usize = buf.get_u16() as usize;
+            let last_key_len = get_uvarint(&mut buf) as usize;
             let last_key =
                 KeyBytes::from_bytes_with_ts(buf.copy_to_bytes(last_key_len), buf.get_u64());
             block_meta.push(BlockMeta {
@@ -98,27 +162,89 @@ impl BlockMeta {
                 last_key,
             });
         }
+        let num_of_blocks = buf.get_u32() as usize;
         let max_ts = buf.get_u64();
-        if buf.get_u32() != checksum {
+
+        Ok((block_meta, num_of_blocks, max_ts))
+    }
+
+    /// Decode the index of a legacy SST, which has the `u32` offset, the `u16`-prefixed first key
+    /// and the `u16`-prefixed last key of each data block, and ends with the max timestamp.
+    fn decode_legacy_block_meta(mut buf: &[u8]) -> Result<(Vec<BlockMeta>, u64)> {
+        if buf.len() < 16 {
+            bail!("meta too short: {} bytes", buf.len());
+        }
+        let checksum = (&buf[buf.len() - 4..]).get_u32();
+        if checksum != crc32fast::hash(&buf[4..buf.len() - 4]) {
             bail!("meta checksum mismatched");
         }
-
+        let num = buf.get_u32() as usize;
+        let mut entries = &buf[..buf.len() - 12];
+        let get_key = |buf: &mut &[u8]| -> Result<KeyBytes> {
+            if buf.remaining() < 2 {
+                bail!("meta truncated");
+            }
+            let key_len = buf.get_u16() as usize;
+            if buf.remaining() < key_len + 8 {
+                bail!("meta truncated");
+            }
+            let key = buf.copy_to_bytes(key_len);
+            Ok(KeyBytes::from_bytes_with_ts(key, buf.get_u64()))
+        };
+        let mut block_meta = Vec::with_capacity(num);
+        for _ in 0..num {
+            if entries.remaining() < 4 {
+                bail!("meta truncated");
+            }
+            let offset = entries.get_u32() as usize;
+            let first_key = get_key(&mut entries)?;
+            let last_key = get_key(&mut entries)?;
+            block_meta.push(BlockMeta {
+                offset,
+                first_key,
+                last_key,
+            });
+        }
+        if block_meta.is_empty() {
+            bail!("legacy SST without data blocks");
+        }
+        let max_ts = (&buf[buf.len() - 12..]).get_u64();
         Ok((block_meta, max_ts))
     }
 }
 
+/// A bloom filter over the key prefixes in an SST.
+pub(crate) struct PrefixBloom {
+    /// The name of the extractor that produced the prefixes.
+    pub(crate) extractor: String,
+    pub(crate) bloom: Filter,
+}
+
+impl PrefixBloom {
+    pub(crate) fn encode(&self, buf: &mut Vec<u8>) {
+        put_uvarint(buf, self.extractor.len() as u64);
+        buf.put_slice(self.extractor.as_bytes());
+        self.bloom.encode(buf);
+    }
+
+    /// Decode a prefix bloom filter. An empty buffer means the SST has no prefix filter.
+    pub(crate) fn decode(mut buf: &[u8]) -> Result<Option<Self>> {
+        if buf.is_empty() {
+            return Ok(None);
+        }
+        let name_len = get_uvarint(&mut buf) as usize;
+        let extractor = String::from_utf8(buf[..name_len].to_vec())?;
+        let bloom = Filter::decode(&buf[name_len..])?;
+        Ok(Some(Self { extractor, bloom }))
+    }
+}
+
 /// A file object.
-pub struct FileObject(Option<File>, u64);
+pub struct FileObject(Option<Box<dyn RandomAccessFile>>, u64);
 
 impl FileObject {
-    pub fn read(&self, offset: u64, len: u64) -> Result<Vec<u8>> {
-        use std::os::unix::fs::FileExt;
-        let mut data = vec![0; len as usize];
-        self.0
-            .as_ref()
-            .unwrap()
-            .read_exact_at(&mut data[..], offset)?;
-        Ok(data)
+    pub fn read(&self, offset: u64, len: u64) -> Result<Bytes> {
+        self.0.as_ref().unwrap().read_at(offset, len)
     }
 
     pub fn size(&self) -> u64 {
@@ -127,17 +253,30 @@ impl FileObject {
 
     /// Create a new file object (day 2) and write the file to the disk (day 4).
     pub fn create(path: &Path, data: Vec<u8>) -> Result<Self> {
-        std::fs::write(path, &data)?;
-        File::open(path)?.sync_all()?;
-        Ok(FileObject(
-            Some(File::options().read(true).write(false).open(path)?),
-            data.len() as u64,
-        ))
+        Self::create_with_fs(&DiskFileSystem, path, data, ReadBackend::default())
+    }
+
+    /// Write the file to the file system and open it with the given read backend.
+    pub fn create_with_fs(
+        fs: &dyn FileSystem,
+        path: &Path,
+        data: Vec<u8>,
+        backend: ReadBackend,
+    ) -> Result<Self> {
+        let mut file = fs.create(path)?;
+        file.append(&data)?;
+        file.sync()?;
+        drop(file);
+        Self::open_with_fs(fs, path, backend)
     }
 
     pub fn open(path: &Path) -> Result<Self> {
-        let file = File::options().read(true).write(false).open(path)?;
-        let size = file.metadata()?.len();
+        Self::open_with_fs(&DiskFileSystem, path, ReadBackend::default())
+    }
+
+    pub fn open_with_fs(fs: &dyn FileSystem, path: &Path, backend: ReadBackend) -> Result<Self> {
+        let file = fs.open(path, backend)?;
+        let size = file.size();
         Ok(FileObject(Some(file), size))
     }
 }
@@ -146,16 +285,40 @@ impl FileObject {
 pub struct SsTable {
     /// The actual storage unit of SsTable, the format is as above.
     pub(crate) file: FileObject,
-    /// The meta blocks that hold info for data blocks.
+    /// The top-level index that points to the index partitions, or to the data blocks of a legacy
+    /// SST.
     pub(crate) block_meta: Vec<BlockMeta>,
     /// The offset that indicates the start point of meta blocks in `file`.
     pub(crate) block_meta_offset: usize,
+    num_of_blocks: usize,
     id: usize,
     block_cache: Option<Arc<BlockCache>>,
     first_key: KeyBytes,
     last_key: KeyBytes,
-    pub(crate) bloom: Option<Bloom>,
+    pub(crate) bloom: Option<Filter>,
+    pub(crate) prefix_bloom: Option<PrefixBloom>,
+    range_tombstones: FragmentedRangeTombstones,
+    properties: Option<TableProperties>,
     max_ts: u64,
+    format_version: u32,
+}
+
+/// The key range of an SST, which covers both its data blocks and its range tombstones.
+fn key_range(
+    block_meta: &[BlockMeta],
+    range_tombstones: &FragmentedRangeTombstones,
+) -> (KeyBytes, KeyBytes) {
+    let blocks = block_meta
+        .first()
+        .zip(block_meta.last())
+        .map(|(first, last)| (first.first_key.clone(), last.last_key.clone()));
+    match (blocks, range_tombstones.key_range()) {
+        (Some((first, last)), Some((tombstone_first, tombstone_last))) => {
+            (first.min(tombstone_first), last.max(tombstone_last))
+        }
+        (Some(range), None) | (None, Some(range)) => range,
+        (None, None) => (KeyBytes::new(), KeyBytes::new()),
+    }
 }
 impl SsTable {
     #[cfg(test)]
@@ -165,25 +328,50 @@ impl SsTable {
 
     /// Open SSTable from a file.
     pub fn open(id: usize, block_cache: Option<Arc<BlockCache>>, file: FileObject) -> Result<Self> {
-        let len = file.size();
-        let raw_bloom_offset = file.read(len - 4, 4)?;
-        let bloom_offset = (&raw_bloom_offset[..]).get_u32() as u64;
-        let raw_bloom = file.read(bloom_offset, len - 4 - bloom_offset)?;
-        let bloom_filter = Bloom::decode(&raw_bloom)?;
-        let raw_meta_offset = file.read(bloom_offset - 4, 4)?;
-        let block_meta_offset = (&raw_meta_offset[..]).get_u32() as u64;
-        let raw_meta = file.read(block_meta_offset, bloom_offset - 4 - block_meta_offset)?;
-        let (block_meta, max_ts) = BlockMeta::decode_block_meta(&raw_meta[..])?;
+        let footer = Footer::read(&file)?;
+        let read_section = |handle: BlockHandle| file.read(handle.offset, handle.len);
+        let raw_range_tombstones = read_section(footer.range_tombstones)?;
+        let range_tombstones = if raw_range_tombstones.is_empty() {
+            FragmentedRangeTombstones::default()
+        } else {
+            FragmentedRangeTombstones::decode(&raw_range_tombstones)?
+        };
+        let prefix_bloom = PrefixBloom::decode(&read_section(footer.prefix_bloom)?)?;
+        let raw_bloom = read_section(footer.filter)?;
+        // the filter section is empty if the SST was built without a filter
+        let bloom_filter = if raw_bloom.is_empty() {
+            None
+        } else if footer.version == LEGACY_FORMAT_VERSION {
+            Some(Filter::decode_legacy(&raw_bloom)?)
+        } else {
+            Some(Filter::decode(&raw_bloom)?)
+        };
+        // SSTs written before the properties block have no properties
+        let properties = if footer.properties.len == 0 {
+            None
+        } else {
+            Some(TableProperties::decode(&read_section(footer.properties)?)?)
+        };
+        let raw_meta = read_section(footer.meta)?;
+        let (block_meta, num_of_blocks, max_ts) =
+            BlockMeta::decode_block_meta(&raw_meta[..], footer.version)?;
+        let block_meta_offset = footer.meta.offset;
+        let (first_key, last_key) = key_range(&block_meta, &range_tombstones);
         Ok(Self {
             file,
-            first_key: block_meta.first().unwrap().first_key.clone(),
-            last_key: block_meta.last().unwrap().last_key.clone(),
+            first_key,
+            last_key,
             block_meta,
             block_meta_offset: block_meta_offset as usize,
+            num_of_blocks,
             id,
             block_cache,
-            bloom: Some(bloom_filter),
+            bloom: bloom_filter,
+            prefix_bloom,
+            range_tombstones,
+            properties,
             max_ts,
+            format_version: footer.version,
         })
     }
 
@@ -198,32 +386,80 @@ impl SsTable {
             file: FileObject(None, file_size),
             block_meta: vec![],
             block_meta_offset: 0,
+            num_of_blocks: 0,
             id,
             block_cache: None,
             first_key,
             last_key,
             bloom: None,
+            prefix_bloom: None,
+            range_tombstones: FragmentedRangeTombstones::default(),
+            properties: None,
             max_ts: 0,
+            format_version: SST_FORMAT_VERSION,
         }
     }
 
-    /// Read a block from the disk.
-    pub fn read_block(&self, block_idx: usize) -> Result<Arc<Block>> {
-        let offset = self.block_meta[block_idx].offset;
+    /// Read a block at the given position from the disk, verify its checksum and decompress it.
+    fn read_block_at(&self, offset: u64, len: u64) -> Result<Arc<Block>> {
+        let mut block_data = self.file.read(offset, len)?;
+        let checksum = block_data.split_off(block_data.len() - 4).get_u32();
+        if checksum != crc32fast::hash(&block_data) {
+            bail!("block checksum mismatched");
+        }
+        // blocks of legacy SSTs have neither a codec nor restart points
+        if self.format_version == LEGACY_FORMAT_VERSION {
+            return Ok(Arc::new(Block::decode_legacy(&block_data)?));
+        }
+        let compression = CompressionType::from_id(block_data[block_data.len() - 1])?;
+        block_data.truncate(block_data.len() - 1);
+        // uncompressed blocks share memory with what the file hands out, e.g., its mapping
+        Ok(Arc::new(Block::decode_bytes(
+            compression.decompress(block_data)?,
+        )))
+    }
+
+    /// Read an index partition, with block cache. Partitions are cached after the data blocks.
+    fn read_index_partition(&self, partition_idx: usize) -> Result<Arc<Block>> {
+        let offset = self.block_meta[partition_idx].offset;
         let offset_end = self
             .block_meta
-            .get(block_idx + 1)
+            .get(partition_idx + 1)
             .map_or(self.block_meta_offset, |x| x.offset);
-        let block_len = offset_end - offset - 4;
-        let block_data_with_chksum: Vec<u8> = self
-            .file
-            .read(offset as u64, (offset_end - offset) as u64)?;
-        let block_data = &block_data_with_chksum[..block_len];
-        let checksum = (&block_data_with_chksum[block_len..]).get_u32();
-        if checksum != crc32fast::hash(block_data) {
-            bail!("block checksum mismatched");
+        let read = || self.read_block_at(offset as u64, (offset_end - offset) as u64);
+        if let Some(ref block_cache) = self.block_cache {
+            block_cache
+                .try_get_with((self.id, self.num_of_blocks + partition_idx), read)
+                .map_err(|e| anyhow!("{}", e))
+        } else {
+            read()
         }
-        Ok(Arc::new(Block::decode(block_data)))
+    }
+
+    /// The offset and the length of a data block in the file, including its checksum.
+    pub fn block_location(&self, block_idx: usize) -> Result<(u64, u64)> {
+        if self.format_version == LEGACY_FORMAT_VERSION {
+            let offset = self.block_meta[block_idx].offset;
+            let offset_end = self
+                .block_meta
+                .get(block_idx + 1)
+                .map_or(self.block_meta_offset, |x| x.offset);
+            return Ok((offset as u64, (offset_end - offset) as u64));
+        }
+        let partition = self.read_index_partition(block_idx / INDEX_PARTITION_BLOCKS)?;
+        let iter =
+            BlockIterator::create_and_seek_to_entry(partition, block_idx % INDEX_PARTITION_BLOCKS);
+        if !iter.is_valid() {
+            bail!("block {} not found in the index", block_idx);
+        }
+        let handle = BlockHandle::decode(iter.value());
+        Ok((handle.offset, handle.len))
+    }
+
+    /// Read a block from the disk and decompress it.
+    pub fn read_block(&self, block_idx: usize) -> Result<Arc<Block>> {
+        let (offset, len) = self.block_location(block_idx)?;
+        self.read_block_at(offset, len)
     }
 
     /// Read a block from disk, with block cache.
@@ -238,16 +474,38 @@ impl SsTable {
         }
     }
 
-    /// Find the block that may contain `key`.
-    pub fn find_block_idx(&self, key: KeySlice) -> usize {
-        self.block_meta
-            .partition_point(|meta| meta.first_key.as_key_slice() <= key)
-            .saturating_sub(1)
+    /// Find the block that may contain `key`, i.e., the first block whose last key is >= `key`.
+    /// Returns the last block if all keys in the SST are smaller than `key`.
+    pub fn find_block_idx(&self, key: KeySlice) -> Result<usize> {
+        let partition_idx = self
+            .block_meta
+            .partition_point(|meta| meta.last_key.as_key_slice() < key);
+        if partition_idx >= self.block_meta.len() {
+            return Ok(self.num_of_blocks - 1);
+        }
+        if self.format_version == LEGACY_FORMAT_VERSION {
+            return Ok(partition_idx);
+        }
+        // the last key of the partition is >= `key`, so the iterator is always valid
+        let iter =
+            BlockIterator::create_and_seek_to_key(self.read_index_partition(partition_idx)?, key);
+        Ok(partition_idx * INDEX_PARTITION_BLOCKS + iter.entry_idx())
+    }
+
+    /// Check whether the SST may contain keys with the given prefix. Always returns true if the SST
+    /// has no prefix filter built by the same extractor.
+    pub fn may_contain_prefix(&self, extractor: &dyn PrefixExtractor, prefix: &[u8]) -> bool {
+        match &self.prefix_bloom {
+            Some(prefix_bloom) if prefix_bloom.extractor == extractor.name() => prefix_bloom
+                .bloom
+                .may_contain(farmhash::fingerprint32(prefix)),
+            _ => true,
+        }
     }
 
     /// Get number of data blocks.
     pub fn num_of_blocks(&self) -> usize {
-        self.block_meta.len()
+        self.num_of_blocks
     }
 
     pub fn first_key(&self) -> &KeyBytes {
@@ -269,4 +527,23 @@ impl SsTable {
     pub fn max_ts(&self) -> u64 {
         self.max_ts
     }
+
+    pub fn range_tombstones(&self) -> &FragmentedRangeTombstones {
+        &self.range_tombstones
+    }
+
+    /// The key filter, `None` if the SST was built without one.
+    pub fn filter(&self) -> Option<&Filter> {
+        self.bloom.as_ref()
+    }
+
+    /// The format version of the SST file.
+    pub fn format_version(&self) -> u32 {
+        self.format_version
+    }
+
+    /// The table properties, or `None` if the SST was written before table properties existed.
+    pub fn properties(&self) -> Option<&TableProperties> {
+        self.properties.as_ref()
+    }
 }
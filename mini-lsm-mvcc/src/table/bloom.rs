@@ -14,8 +14,9 @@
 
 // Copyright 2021 TiKV Project Authors. Licensed under Apache-2.0.
 
-use anyhow::{Result, bail};
-use bytes::{Buf, BufMut, Bytes, BytesMut};
+use bytes::{Bytes, BytesMut};
+
+use super::filter::{Filter, FilterKind, mix64};
 
 /// Implements a bloom filter
 pub struct Bloom {
@@ -59,33 +60,9 @@ impl<T: AsMut<[u8]>> BitSliceMut for T {
 }
 
 impl Bloom {
-    /// Decode a bloom filter
-    pub fn decode(buf: &[u8]) -> Result<Self> {
-        let checksum = (&buf[buf.len() - 4..buf.len()]).get_u32();
-        if checksum != crc32fast::hash(&buf[..buf.len() - 4]) {
-            bail!("checksum mismatched for bloom filters");
-        }
-        let filter = &buf[..buf.len() - 5];
-        let k = buf[buf.len() - 5];
-        Ok(Self {
-            filter: filter.to_vec().into(),
-            k,
-        })
-    }
-
-    /// Encode a bloom filter
-    pub fn encode(&self, buf: &mut Vec<u8>) {
-        let offset = buf.len();
-        buf.extend(&self.filter);
-        buf.put_u8(self.k);
-        let checksum = crc32fast::hash(&buf[offset..]);
-        buf.put_u32(checksum);
-    }
-
     /// Get bloom filter bits per key from entries count and FPR
     pub fn bloom_bits_per_key(entries: usize, false_positive_rate: f64) -> usize {
-        let size =
-            -1.0 * (entries as f64) * false_positive_rate.ln() / std::f64::consts::LN_2.powi(2);
+        let size = -(entries as f64) * false_positive_rate.ln() / std::f64::consts::LN_2.powi(2);
         let locs = (size / (entries as f64)).ceil();
         locs as usize
     }
@@ -95,7 +72,7 @@ impl Bloom {
         let k = (bits_per_key as f64 * 0.69) as u32;
         let k = k.clamp(1, 30);
         let nbits = (keys.len() * bits_per_key).max(64);
-        let nbytes = (nbits + 7) / 8;
+        let nbytes = nbits.div_ceil(8);
         let nbits = nbytes * 8;
         let mut filter = BytesMut::with_capacity(nbytes);
         filter.resize(nbytes, 0);
@@ -115,21 +92,69 @@ impl Bloom {
     }
 
     /// Check if a bloom filter may contain some data
-    pub fn may_contain(&self, mut h: u32) -> bool {
-        if self.k > 30 {
-            // potential new encoding for short bloom filters
-            true
-        } else {
-            let nbits = self.filter.bit_len();
-            let delta = h.rotate_left(15);
-            for _ in 0..self.k {
-                let bit_pos = h % (nbits as u32);
-                if !self.filter.get_bit(bit_pos as usize) {
-                    return false;
-                }
-                h = h.wrapping_add(delta);
+    pub fn may_contain(&self, h: u32) -> bool {
+        may_contain(&self.filter, self.k, h)
+    }
+}
+
+pub(crate) fn may_contain(filter: &[u8], k: u8, mut h: u32) -> bool {
+    if k > 30 {
+        // potential new encoding for short bloom filters
+        true
+    } else {
+        let nbits = filter.bit_len();
+        let delta = h.rotate_left(15);
+        for _ in 0..k {
+            let bit_pos = h % (nbits as u32);
+            if !filter.get_bit(bit_pos as usize) {
+                return false;
             }
-            true
+            h = h.wrapping_add(delta);
         }
+        true
+    }
+}
+
+/// Number of bits in a block of a blocked bloom filter, which is the size of a cache line.
+const BLOCK_BITS: usize = 512;
+
+/// Yields the bits of a blocked bloom filter to probe for a key hash: all of them fall into the
+/// block chosen by the high bits of the mixed hash.
+fn blocked_probes(h: u32, k: u8, num_blocks: usize) -> impl Iterator<Item = usize> {
+    let x = mix64(h as u64);
+    let block = (((x >> 32) * num_blocks as u64) >> 32) as usize;
+    let mut pos = x as u32;
+    let delta = pos.rotate_left(15) | 1;
+    (0..k).map(move |_| {
+        let bit_pos = block * BLOCK_BITS + pos as usize % BLOCK_BITS;
+        pos = pos.wrapping_add(delta);
+        bit_pos
+    })
+}
+
+/// Build a cache-line blocked bloom filter from key hashes
+pub(crate) fn build_blocked(keys: &[u32], bits_per_key: usize) -> Filter {
+    let k = ((bits_per_key as f64 * 0.69) as u8).clamp(1, 30);
+    let num_blocks = (keys.len() * bits_per_key).div_ceil(BLOCK_BITS).max(1);
+    let nbytes = num_blocks * BLOCK_BITS / 8;
+    let mut filter = BytesMut::with_capacity(nbytes);
+    filter.resize(nbytes, 0);
+    for h in keys {
+        for bit_pos in blocked_probes(*h, k, num_blocks) {
+            filter.set_bit(bit_pos, true);
+        }
+    }
+    Filter {
+        kind: FilterKind::BlockedBloom,
+        filter: filter.freeze(),
+        k,
+    }
+}
+
+pub(crate) fn blocked_may_contain(filter: &[u8], k: u8, h: u32) -> bool {
+    let num_blocks = filter.bit_len() / BLOCK_BITS;
+    if num_blocks == 0 {
+        return true;
     }
+    blocked_probes(h, k, num_blocks).all(|bit_pos| filter.get_bit(bit_pos))
 }
@@ -14,33 +14,83 @@
 
 use std::path::Path;
 use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
 
-use anyhow::Result;
+use anyhow::{Result, bail};
 use bytes::BufMut;
 
-use super::bloom::Bloom;
-use super::{BlockMeta, FileObject, SsTable};
-use crate::block::BlockBuilder;
+use super::footer::{Footer, SST_FORMAT_VERSION};
+use super::writer::SstFileWriter;
+use super::{
+    BlockHandle, BlockMeta, BloomFilterPolicy, CompactionReason, CompressionType, FileObject,
+    FilterPolicy, INDEX_PARTITION_BLOCKS, PrefixBloom, PrefixExtractor, SsTable, TableProperties,
+    key_range,
+};
+use crate::block::{BlockBuilder, RESTART_INTERVAL};
+use crate::env::{DiskFileSystem, FileSystem, ReadBackend};
 use crate::key::{KeySlice, KeyVec};
-use crate::lsm_storage::BlockCache;
+use crate::lsm_storage::{BlockCache, LsmStorageOptions};
+use crate::range_tombstone::{FragmentedRangeTombstones, RangeTombstone};
+
+/// Where the builder puts the finished data blocks.
+enum BlockSink {
+    /// Kept in memory until the SST is built.
+    Memory(Vec<u8>),
+    /// Appended to the file of the SST as they are finished.
+    File(SstFileWriter),
+}
+
+impl BlockSink {
+    fn len(&self) -> usize {
+        match self {
+            BlockSink::Memory(data) => data.len(),
+            BlockSink::File(writer) => writer.len(),
+        }
+    }
+}
 
 /// Builds an SSTable from key-value pairs.
 pub struct SsTableBuilder {
     builder: BlockBuilder,
     first_key: KeyVec,
     last_key: KeyVec,
-    data: Vec<u8>,
+    /// The finished data blocks.
+    data: BlockSink,
+    /// The meta of the data blocks, which is partitioned into the index when the SST is built.
     pub(crate) meta: Vec<BlockMeta>,
     block_size: usize,
     key_hashes: Vec<u32>,
     max_ts: u64,
+    compression: CompressionType,
+    /// Builds the key and prefix filters, `None` builds no filters.
+    filter_policy: Option<Arc<dyn FilterPolicy>>,
+    prefix_extractor: Option<Arc<dyn PrefixExtractor>>,
+    /// Hashes of the distinct key prefixes, if there is a prefix extractor.
+    prefix_hashes: Vec<u32>,
+    last_prefix: Option<Vec<u8>>,
+    range_tombstones: Vec<RangeTombstone>,
+    /// Whether the data blocks are built with a hash index.
+    block_hash_index: bool,
+    /// Number of entries between two restart points in the data blocks.
+    restart_interval: usize,
+    /// Statistics of the entries added so far, completed when the SST is built.
+    properties: TableProperties,
+    /// How the built SST is read.
+    read_backend: ReadBackend,
+    /// The file system the SST is written to.
+    file_system: Arc<dyn FileSystem>,
 }
 
 impl SsTableBuilder {
     /// Create a builder based on target block size.
     pub fn new(block_size: usize) -> Self {
+        Self::new_with_compression(block_size, CompressionType::None)
+    }
+
+    /// Create a builder that compresses each data block with the given codec.
+    pub fn new_with_compression(block_size: usize, compression: CompressionType) -> Self {
         Self {
-            data: Vec::new(),
+            data: BlockSink::Memory(Vec::new()),
             meta: Vec::new(),
             first_key: KeyVec::new(),
             last_key: KeyVec::new(),
@@ -48,11 +98,125 @@ impl SsTableBuilder {
             builder: BlockBuilder::new(block_size),
             key_hashes: Vec::new(),
             max_ts: 0,
+            compression,
+            filter_policy: Some(Arc::new(BloomFilterPolicy::default())),
+            prefix_extractor: None,
+            prefix_hashes: Vec::new(),
+            last_prefix: None,
+            range_tombstones: Vec::new(),
+            block_hash_index: false,
+            restart_interval: RESTART_INTERVAL,
+            properties: TableProperties {
+                min_ts: u64::MAX,
+                ..Default::default()
+            },
+            read_backend: ReadBackend::default(),
+            file_system: Arc::new(DiskFileSystem),
+        }
+    }
+
+    /// Create a builder with the block size, codec and prefix extractor configured in the storage
+    /// options.
+    pub fn new_with_options(options: &LsmStorageOptions) -> Self {
+        let mut builder = Self::new_with_compression(options.block_size, options.compression);
+        builder.filter_policy = options.filter_policy.clone();
+        builder.prefix_extractor = options.prefix_extractor.clone();
+        builder.read_backend = options.read_backend;
+        builder.file_system = options.file_system.clone();
+        if options.block_hash_index {
+            builder.enable_block_hash_index();
+        }
+        builder.set_restart_interval(options.block_restart_interval);
+        builder
+    }
+
+    /// Build the data blocks with a hash index for point lookups.
+    pub fn enable_block_hash_index(&mut self) {
+        self.block_hash_index = true;
+        self.builder = self.new_block_builder();
+    }
+
+    /// Place a restart point every `restart_interval` entries in the data blocks. The interval is
+    /// stored in each block. Must be called before adding any key.
+    pub fn set_restart_interval(&mut self, restart_interval: usize) {
+        self.restart_interval = restart_interval;
+        self.builder = self.new_block_builder();
+    }
+
+    fn new_block_builder(&self) -> BlockBuilder {
+        let mut builder = if self.block_hash_index {
+            BlockBuilder::new_with_hash_index(self.block_size)
+        } else {
+            BlockBuilder::new(self.block_size)
+        };
+        builder.set_restart_interval(self.restart_interval);
+        builder
+    }
+
+    /// Write the data blocks to the file at `path` as they are finished instead of keeping them in
+    /// memory, starting the writeback every `bytes_per_sync` bytes if set. Must be called before
+    /// adding any key, and the SST must then be built at the same path. The output is the same as
+    /// building in memory.
+    pub fn stream_to_file(
+        &mut self,
+        path: impl AsRef<Path>,
+        bytes_per_sync: Option<usize>,
+    ) -> Result<()> {
+        assert!(
+            self.is_empty(),
+            "stream_to_file must be called before adding any key"
+        );
+        self.data = BlockSink::File(SstFileWriter::create(
+            self.file_system.clone(),
+            path.as_ref(),
+            bytes_per_sync,
+        )?);
+        Ok(())
+    }
+
+    /// Drop a builder that will not be built, removing the file it is streaming to.
+    pub fn abandon(self) -> Result<()> {
+        match self.data {
+            BlockSink::Memory(_) => Ok(()),
+            BlockSink::File(writer) => writer.abandon(),
         }
     }
 
+    /// Build the SST without key or prefix filters.
+    pub fn skip_filters(&mut self) {
+        self.filter_policy = None;
+    }
+
+    /// Record why the SST is written and the level it is written to in the table properties.
+    pub fn set_compaction_reason(&mut self, reason: CompactionReason, level: usize) {
+        self.properties.compaction_reason = reason;
+        self.properties.level = level as u64;
+    }
+
+    /// Record the newest memtable whose entries the SST holds in the table properties.
+    pub fn set_max_memtable_id(&mut self, max_memtable_id: Option<usize>) {
+        self.properties.max_memtable_id = max_memtable_id.map(|x| x as u64);
+    }
+
     /// Adds a key-value pair to SSTable
     pub fn add(&mut self, key: KeySlice, value: &[u8]) {
+        self.add_entry(key, value, false)
+    }
+
+    /// Adds a key whose value is stored in the value log, with the encoded pointer to it.
+    pub fn add_value_pointer(&mut self, key: KeySlice, pointer: &[u8]) {
+        self.add_entry(key, pointer, true)
+    }
+
+    fn add_entry(&mut self, key: KeySlice, value: &[u8], is_value_pointer: bool) {
+        let add_to_block = |builder: &mut BlockBuilder| {
+            if is_value_pointer {
+                builder.add_value_pointer(key, value)
+            } else {
+                builder.add(key, value)
+            }
+        };
+
         if self.first_key.is_empty() {
             self.first_key.set_from_slice(key);
         }
@@ -60,9 +224,23 @@ impl SsTableBuilder {
         if key.ts() > self.max_ts {
             self.max_ts = key.ts();
         }
+        self.properties.min_ts = self.properties.min_ts.min(key.ts());
+        self.properties.num_entries += 1;
+        if value.is_empty() {
+            self.properties.num_deletions += 1;
+        }
+        self.properties.raw_key_size += key.key_len() as u64;
+        self.properties.raw_value_size += value.len() as u64;
         self.key_hashes.push(farmhash::fingerprint32(key.key_ref()));
+        if let Some(extractor) = &self.prefix_extractor
+            && let Some(prefix) = extractor.prefix(key.key_ref())
+            && self.last_prefix.as_deref() != Some(prefix)
+        {
+            self.prefix_hashes.push(farmhash::fingerprint32(prefix));
+            self.last_prefix = Some(prefix.to_vec());
+        }
 
-        if self.builder.add(key, value) {
+        if add_to_block(&mut self.builder) {
             self.last_key.set_from_slice(key);
             return;
         }
@@ -71,59 +249,214 @@ impl SsTableBuilder {
         self.finish_block();
 
         // add the key-value pair to the next block
-        assert!(self.builder.add(key, value));
+        assert!(add_to_block(&mut self.builder));
         self.first_key.set_from_slice(key);
         self.last_key.set_from_slice(key);
     }
 
+    /// Adds range tombstones to the SSTable. The key range of the SSTable covers its tombstones.
+    pub fn add_range_tombstones(&mut self, tombstones: impl IntoIterator<Item = RangeTombstone>) {
+        for tombstone in tombstones {
+            self.max_ts = self.max_ts.max(tombstone.ts);
+            self.properties.min_ts = self.properties.min_ts.min(tombstone.ts);
+            self.range_tombstones.push(tombstone);
+        }
+    }
+
+    /// Returns whether neither a key nor a range tombstone has been added.
+    pub fn is_empty(&self) -> bool {
+        self.meta.is_empty() && self.builder.is_empty() && self.range_tombstones.is_empty()
+    }
+
     /// Get the estimated size of the SSTable.
     pub fn estimated_size(&self) -> usize {
         self.data.len()
     }
 
+    /// Append an encoded block to `data`, followed by the codec id and the checksum.
+    fn write_block(data: &mut Vec<u8>, encoded_block: &[u8], compression: CompressionType) {
+        let offset = data.len();
+        // Fall back to storing the block uncompressed if the codec does not help or fails.
+        match compression.compress(encoded_block) {
+            Ok(compressed) if compressed.len() < encoded_block.len() => {
+                data.extend(compressed);
+                data.put_u8(compression.id());
+            }
+            _ => {
+                data.extend(encoded_block);
+                data.put_u8(CompressionType::None.id());
+            }
+        }
+        let checksum = crc32fast::hash(&data[offset..]);
+        data.put_u32(checksum);
+    }
+
     fn finish_block(&mut self) {
-        let builder = std::mem::replace(&mut self.builder, BlockBuilder::new(self.block_size));
+        let new_builder = self.new_block_builder();
+        let builder = std::mem::replace(&mut self.builder, new_builder);
         let encoded_block = builder.build().encode();
         self.meta.push(BlockMeta {
             offset: self.data.len(),
             first_key: std::mem::take(&mut self.first_key).into_key_bytes(),
             last_key: std::mem::take(&mut self.last_key).into_key_bytes(),
         });
-        let checksum = crc32fast::hash(&encoded_block);
-        self.data.extend(encoded_block);
-        self.data.put_u32(checksum);
+        match &mut self.data {
+            BlockSink::Memory(data) => Self::write_block(data, &encoded_block, self.compression),
+            BlockSink::File(writer) => {
+                let mut data = Vec::new();
+                Self::write_block(&mut data, &encoded_block, self.compression);
+                writer.append(&data);
+            }
+        }
     }
 
     /// Builds the SSTable and writes it to the given path. Use the `FileObject` structure to manipulate the disk objects.
     pub fn build(
+        self,
+        id: usize,
+        block_cache: Option<Arc<BlockCache>>,
+        path: impl AsRef<Path>,
+    ) -> Result<SsTable> {
+        self.build_with_version(id, block_cache, path, SST_FORMAT_VERSION)
+    }
+
+    fn build_with_version(
         mut self,
         id: usize,
         block_cache: Option<Arc<BlockCache>>,
         path: impl AsRef<Path>,
+        version: u32,
     ) -> Result<SsTable> {
-        self.finish_block();
-        let mut buf = self.data;
-        let meta_offset = buf.len();
-        BlockMeta::encode_block_meta(&self.meta, self.max_ts, &mut buf);
-        buf.put_u32(meta_offset as u32);
-        let bloom = Bloom::build_from_key_hashes(
-            &self.key_hashes,
-            Bloom::bloom_bits_per_key(self.key_hashes.len(), 0.01),
-        );
-        let bloom_offset = buf.len();
-        bloom.encode(&mut buf);
-        buf.put_u32(bloom_offset as u32);
-        let file = FileObject::create(path.as_ref(), buf)?;
+        // an SSTable may only have range tombstones and no data blocks
+        if !self.builder.is_empty() {
+            self.finish_block();
+        }
+        // The sections after the data blocks are encoded in memory at position `base` of the file.
+        let (base, mut buf, writer) = match self.data {
+            BlockSink::Memory(data) => (0, data, None),
+            BlockSink::File(writer) => (writer.len(), Vec::new(), Some(writer)),
+        };
+        if let Some(writer) = &writer
+            && writer.path() != path.as_ref()
+        {
+            bail!(
+                "SST streamed to {} but built at {}",
+                writer.path().display(),
+                path.as_ref().display()
+            );
+        }
+        let data_end = base + buf.len();
+        // Each index partition maps the last key of a data block to the position of the block.
+        let mut meta = Vec::with_capacity(self.meta.len().div_ceil(INDEX_PARTITION_BLOCKS));
+        for (partition_idx, blocks) in self.meta.chunks(INDEX_PARTITION_BLOCKS).enumerate() {
+            let mut index_builder = BlockBuilder::new(usize::MAX);
+            for (idx, block) in blocks.iter().enumerate() {
+                let block_idx = partition_idx * INDEX_PARTITION_BLOCKS + idx;
+                let offset_end = self.meta.get(block_idx + 1).map_or(data_end, |x| x.offset);
+                let handle = BlockHandle {
+                    offset: block.offset as u64,
+                    len: (offset_end - block.offset) as u64,
+                };
+                assert!(index_builder.add(block.last_key.as_key_slice(), &handle.encode()));
+            }
+            meta.push(BlockMeta {
+                offset: base + buf.len(),
+                first_key: blocks.first().unwrap().first_key.clone(),
+                last_key: blocks.last().unwrap().last_key.clone(),
+            });
+            Self::write_block(&mut buf, &index_builder.build().encode(), self.compression);
+        }
+        let finish_section = |buf: &Vec<u8>, offset: usize| BlockHandle {
+            offset: offset as u64,
+            len: (base + buf.len() - offset) as u64,
+        };
+        let meta_offset = base + buf.len();
+        BlockMeta::encode_block_meta(&meta, self.meta.len(), self.max_ts, &mut buf);
+        let meta_handle = finish_section(&buf, meta_offset);
+        let bloom = self
+            .filter_policy
+            .as_ref()
+            .map(|policy| policy.build(&self.key_hashes));
+        let bloom_offset = base + buf.len();
+        if let Some(bloom) = &bloom {
+            bloom.encode(&mut buf);
+        }
+        let bloom_handle = finish_section(&buf, bloom_offset);
+        let prefix_bloom =
+            self.prefix_extractor
+                .zip(self.filter_policy.as_ref())
+                .map(|(extractor, policy)| PrefixBloom {
+                    extractor: extractor.name(),
+                    bloom: policy.build(&self.prefix_hashes),
+                });
+        let prefix_bloom_offset = base + buf.len();
+        if let Some(prefix_bloom) = &prefix_bloom {
+            prefix_bloom.encode(&mut buf);
+        }
+        let prefix_bloom_handle = finish_section(&buf, prefix_bloom_offset);
+        let range_tombstones = FragmentedRangeTombstones::new(self.range_tombstones);
+        let range_tombstones_offset = base + buf.len();
+        if !range_tombstones.is_empty() {
+            range_tombstones.encode(&mut buf);
+        }
+        let range_tombstones_handle = finish_section(&buf, range_tombstones_offset);
+        let mut properties = self.properties;
+        if properties.min_ts == u64::MAX {
+            properties.min_ts = 0;
+        }
+        properties.max_ts = self.max_ts;
+        properties.num_range_tombstones = range_tombstones.iter().count() as u64;
+        properties.creation_time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |x| x.as_secs());
+        properties.compression = self.compression;
+        properties.filter_policy = self
+            .filter_policy
+            .as_ref()
+            .map_or_else(String::new, |policy| policy.name());
+        let properties_offset = base + buf.len();
+        let has_properties = version >= SST_FORMAT_VERSION;
+        if has_properties {
+            properties.encode(&mut buf);
+        }
+        let properties_handle = finish_section(&buf, properties_offset);
+        Footer {
+            version,
+            meta: meta_handle,
+            filter: bloom_handle,
+            prefix_bloom: prefix_bloom_handle,
+            range_tombstones: range_tombstones_handle,
+            properties: properties_handle,
+        }
+        .encode(&mut buf);
+        let (first_key, last_key) = key_range(&meta, &range_tombstones);
+        let file = match writer {
+            None => FileObject::create_with_fs(
+                &*self.file_system,
+                path.as_ref(),
+                buf,
+                self.read_backend,
+            )?,
+            Some(mut writer) => {
+                writer.append(&buf);
+                writer.finish(self.read_backend)?
+            }
+        };
         Ok(SsTable {
             id,
             file,
-            first_key: self.meta.first().unwrap().first_key.clone(),
-            last_key: self.meta.last().unwrap().last_key.clone(),
-            block_meta: self.meta,
+            first_key,
+            last_key,
+            block_meta: meta,
             block_meta_offset: meta_offset,
+            num_of_blocks: self.meta.len(),
             block_cache,
-            bloom: Some(bloom),
+            bloom,
+            prefix_bloom,
+            range_tombstones,
+            properties: has_properties.then_some(properties),
             max_ts: self.max_ts,
+            format_version: version,
         })
     }
 
@@ -131,4 +464,14 @@ impl SsTableBuilder {
     pub(crate) fn build_for_test(self, path: impl AsRef<Path>) -> Result<SsTable> {
         self.build(0, None, path)
     }
+
+    /// Build an SST in an older format that has a footer.
+    #[cfg(test)]
+    pub(crate) fn build_with_version_for_test(
+        self,
+        path: impl AsRef<Path>,
+        version: u32,
+    ) -> Result<SsTable> {
+        self.build_with_version(0, None, path, version)
+    }
 }
@@ -0,0 +1,88 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::io::Read;
+
+use anyhow::{Result, anyhow, bail};
+use bytes::Bytes;
+use serde::Serialize;
+
+/// The codec used to compress a data block. The id of the codec is stored alongside each block, so
+/// that SSTs (and blocks) written with different codecs can be read back by the same DB.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize)]
+pub enum CompressionType {
+    #[default]
+    None,
+    Lz4,
+    Snappy,
+    Zstd,
+}
+
+impl CompressionType {
+    /// The id of the codec persisted on disk.
+    pub fn id(&self) -> u8 {
+        match self {
+            CompressionType::None => 0,
+            CompressionType::Lz4 => 1,
+            CompressionType::Snappy => 2,
+            CompressionType::Zstd => 3,
+        }
+    }
+
+    pub fn from_id(id: u8) -> Result<Self> {
+        match id {
+            0 => Ok(CompressionType::None),
+            1 => Ok(CompressionType::Lz4),
+            2 => Ok(CompressionType::Snappy),
+            3 => Ok(CompressionType::Zstd),
+            _ => bail!("unknown compression type: {}", id),
+        }
+    }
+
+    /// Compress the data with this codec.
+    pub fn compress(&self, data: &[u8]) -> Result<Vec<u8>> {
+        match self {
+            CompressionType::None => Ok(data.to_vec()),
+            CompressionType::Lz4 => Ok(lz4_flex::compress_prepend_size(data)),
+            CompressionType::Snappy => snap::raw::Encoder::new()
+                .compress_vec(data)
+                .map_err(|e| anyhow!("snappy compression failed: {}", e)),
+            CompressionType::Zstd => Ok(ruzstd::encoding::compress_to_vec(
+                data,
+                ruzstd::encoding::CompressionLevel::Fastest,
+            )),
+        }
+    }
+
+    /// Decompress the data with this codec.
+    pub fn decompress(&self, data: Bytes) -> Result<Bytes> {
+        let decompressed = match self {
+            CompressionType::None => return Ok(data),
+            CompressionType::Lz4 => lz4_flex::decompress_size_prepended(&data)
+                .map_err(|e| anyhow!("lz4 decompression failed: {}", e))?,
+            CompressionType::Snappy => snap::raw::Decoder::new()
+                .decompress_vec(&data)
+                .map_err(|e| anyhow!("snappy decompression failed: {}", e))?,
+            CompressionType::Zstd => {
+                let mut data = &data[..];
+                let mut decoder = ruzstd::decoding::StreamingDecoder::new(&mut data)
+                    .map_err(|e| anyhow!("zstd decompression failed: {}", e))?;
+                let mut buf = Vec::new();
+                decoder.read_to_end(&mut buf)?;
+                buf
+            }
+        };
+        Ok(decompressed.into())
+    }
+}
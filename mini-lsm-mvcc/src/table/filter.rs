@@ -0,0 +1,211 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::fmt::Debug;
+
+use anyhow::{Result, bail};
+use bytes::{Buf, BufMut, Bytes};
+
+use super::bloom::{self, Bloom};
+use super::ribbon;
+
+/// The kind of a filter. The id of the kind is stored with each filter, so that SSTs written with
+/// different filter policies can be read back by the same DB.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterKind {
+    /// A bloom filter over a flat bit array.
+    Bloom,
+    /// A bloom filter whose probes for a key all fall into one cache line.
+    BlockedBloom,
+    /// A standard ribbon filter, which needs less space than a bloom filter for the same false
+    /// positive rate.
+    Ribbon,
+}
+
+impl FilterKind {
+    /// The id of the filter kind persisted on disk.
+    pub fn id(&self) -> u8 {
+        match self {
+            FilterKind::Bloom => 0,
+            FilterKind::BlockedBloom => 1,
+            FilterKind::Ribbon => 2,
+        }
+    }
+
+    pub fn from_id(id: u8) -> Result<Self> {
+        match id {
+            0 => Ok(FilterKind::Bloom),
+            1 => Ok(FilterKind::BlockedBloom),
+            2 => Ok(FilterKind::Ribbon),
+            _ => bail!("unknown filter kind: {}", id),
+        }
+    }
+}
+
+/// A filter over the key hashes of an SST.
+pub struct Filter {
+    pub(crate) kind: FilterKind,
+    /// data of the filter
+    pub(crate) filter: Bytes,
+    /// number of hash functions of bloom filters, or number of fingerprint bits of ribbon filters
+    pub(crate) k: u8,
+}
+
+impl Filter {
+    /// Decode a filter
+    pub fn decode(buf: &[u8]) -> Result<Self> {
+        if buf.len() < 6 {
+            bail!("filter too short: {} bytes", buf.len());
+        }
+        let checksum = (&buf[buf.len() - 4..]).get_u32();
+        if checksum != crc32fast::hash(&buf[..buf.len() - 4]) {
+            bail!("checksum mismatched for filters");
+        }
+        let kind = FilterKind::from_id(buf[buf.len() - 5])?;
+        let k = buf[buf.len() - 6];
+        let filter = &buf[..buf.len() - 6];
+        Ok(Self {
+            kind,
+            filter: filter.to_vec().into(),
+            k,
+        })
+    }
+
+    /// Decode the bloom filter of a legacy SST, which is stored without the kind of the filter.
+    pub(crate) fn decode_legacy(buf: &[u8]) -> Result<Self> {
+        if buf.len() < 5 {
+            bail!("filter too short: {} bytes", buf.len());
+        }
+        let checksum = (&buf[buf.len() - 4..]).get_u32();
+        if checksum != crc32fast::hash(&buf[..buf.len() - 4]) {
+            bail!("checksum mismatched for filters");
+        }
+        Ok(Self {
+            kind: FilterKind::Bloom,
+            filter: buf[..buf.len() - 5].to_vec().into(),
+            k: buf[buf.len() - 5],
+        })
+    }
+
+    /// Encode a filter
+    pub fn encode(&self, buf: &mut Vec<u8>) {
+        let offset = buf.len();
+        buf.extend(&self.filter);
+        buf.put_u8(self.k);
+        buf.put_u8(self.kind.id());
+        let checksum = crc32fast::hash(&buf[offset..]);
+        buf.put_u32(checksum);
+    }
+
+    pub fn kind(&self) -> FilterKind {
+        self.kind
+    }
+
+    /// Number of hash functions of bloom filters, or number of fingerprint bits of ribbon filters.
+    pub fn k(&self) -> u8 {
+        self.k
+    }
+
+    /// Size of the filter data in bytes.
+    pub fn size(&self) -> usize {
+        self.filter.len()
+    }
+
+    /// Check if a filter may contain some data
+    pub fn may_contain(&self, h: u32) -> bool {
+        match self.kind {
+            FilterKind::Bloom => bloom::may_contain(&self.filter, self.k, h),
+            FilterKind::BlockedBloom => bloom::blocked_may_contain(&self.filter, self.k, h),
+            FilterKind::Ribbon => ribbon::may_contain(&self.filter, self.k, h),
+        }
+    }
+}
+
+impl From<Bloom> for Filter {
+    fn from(bloom: Bloom) -> Self {
+        Self {
+            kind: FilterKind::Bloom,
+            filter: bloom.filter,
+            k: bloom.k,
+        }
+    }
+}
+
+/// Builds the filters of the SSTs from the hashes of their keys.
+pub trait FilterPolicy: Debug + Send + Sync {
+    /// The name of the policy, recorded in the table properties of the SSTs.
+    fn name(&self) -> String;
+
+    fn build(&self, key_hashes: &[u32]) -> Filter;
+}
+
+/// Builds flat bloom filters with the given number of bits per key.
+#[derive(Debug, Clone)]
+pub struct BloomFilterPolicy(pub usize);
+
+impl Default for BloomFilterPolicy {
+    /// 10 bits per key, which is a false positive rate of about 1%.
+    fn default() -> Self {
+        Self(10)
+    }
+}
+
+impl FilterPolicy for BloomFilterPolicy {
+    fn name(&self) -> String {
+        format!("bloom:{}", self.0)
+    }
+
+    fn build(&self, key_hashes: &[u32]) -> Filter {
+        Bloom::build_from_key_hashes(key_hashes, self.0).into()
+    }
+}
+
+/// Builds cache-line blocked bloom filters with the given number of bits per key. A lookup only
+/// touches one cache line, at the cost of a slightly higher false positive rate than a flat bloom
+/// filter of the same size.
+#[derive(Debug, Clone)]
+pub struct BlockedBloomFilterPolicy(pub usize);
+
+impl FilterPolicy for BlockedBloomFilterPolicy {
+    fn name(&self) -> String {
+        format!("blocked_bloom:{}", self.0)
+    }
+
+    fn build(&self, key_hashes: &[u32]) -> Filter {
+        bloom::build_blocked(key_hashes, self.0)
+    }
+}
+
+/// Builds ribbon filters with about the given number of bits per key.
+#[derive(Debug, Clone)]
+pub struct RibbonFilterPolicy(pub usize);
+
+impl FilterPolicy for RibbonFilterPolicy {
+    fn name(&self) -> String {
+        format!("ribbon:{}", self.0)
+    }
+
+    fn build(&self, key_hashes: &[u32]) -> Filter {
+        ribbon::build(key_hashes, self.0)
+    }
+}
+
+/// Mixes a 64-bit value into 64 well-distributed bits (the splitmix64 finalizer). Used to derive
+/// more hash bits from the 32-bit key hashes.
+pub(crate) fn mix64(x: u64) -> u64 {
+    let mut x = x.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
@@ -0,0 +1,182 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use anyhow::{Result, bail};
+use bytes::{Buf, BufMut};
+
+use super::{BlockHandle, FileObject};
+
+/// The magic number at the end of every SST with a footer, "minilsm\0" in ASCII.
+pub(crate) const SST_MAGIC: u64 = 0x6d69_6e69_6c73_6d00;
+
+/// Version of SSTs written before the footer existed, which end with
+/// `| meta | meta offset u32 | bloom filter | bloom filter offset u32 |`. Their index is a flat list
+/// of data blocks, and their blocks have neither restart points nor a codec.
+pub(crate) const LEGACY_FORMAT_VERSION: u32 = 0;
+
+/// Version of SSTs whose footer has no table properties.
+pub(crate) const FOOTER_FORMAT_VERSION: u32 = 1;
+
+/// Version of the SSTs written by this build.
+pub(crate) const SST_FORMAT_VERSION: u32 = 2;
+
+/// Size of the footer after the section handles: the version, the checksum and the magic number.
+const FOOTER_TRAILER_SIZE: u64 = 4 + 4 + 8;
+
+/// Size of the footer of the given version.
+pub(crate) fn footer_size(version: u32) -> u64 {
+    num_handles(version) as u64 * 16 + FOOTER_TRAILER_SIZE
+}
+
+fn num_handles(version: u32) -> usize {
+    if version == FOOTER_FORMAT_VERSION {
+        4
+    } else {
+        5
+    }
+}
+
+/// The fixed-size footer at the end of an SST, which locates the sections after the data blocks
+/// and index partitions.
+///
+/// ```text
+/// | meta | filter | prefix bloom | range tombstones | properties | 5 x (offset u64, len u64) | version u32 | checksum u32 | magic u64 |
+/// ```
+///
+/// SSTs of version 1 have no properties section and no handle to it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct Footer {
+    pub(crate) version: u32,
+    /// The top-level index.
+    pub(crate) meta: BlockHandle,
+    /// The key filter, empty if the SST has no filter.
+    pub(crate) filter: BlockHandle,
+    /// The prefix filter, empty if the SST has no prefix filter.
+    pub(crate) prefix_bloom: BlockHandle,
+    /// The range tombstones, empty if the SST has no range tombstones.
+    pub(crate) range_tombstones: BlockHandle,
+    /// The table properties, empty if the SST has no properties.
+    pub(crate) properties: BlockHandle,
+}
+
+impl Footer {
+    pub(crate) fn encode(&self, buf: &mut Vec<u8>) {
+        let offset = buf.len();
+        for handle in &self.handles()[..num_handles(self.version)] {
+            buf.put_slice(&handle.encode());
+        }
+        buf.put_u32(self.version);
+        let checksum = crc32fast::hash(&buf[offset..]);
+        buf.put_u32(checksum);
+        buf.put_u64(SST_MAGIC);
+    }
+
+    /// Read the footer of an SST, or the trailing offsets of a legacy SST without a footer.
+    pub(crate) fn read(file: &FileObject) -> Result<Self> {
+        let len = file.size();
+        if len < 8 {
+            bail!("SST too short: {} bytes", len);
+        }
+        let magic = (&file.read(len - 8, 8)?[..]).get_u64();
+        if magic != SST_MAGIC {
+            return Self::read_legacy(file);
+        }
+        if len < FOOTER_TRAILER_SIZE {
+            bail!("SST truncated: {} bytes is smaller than the footer", len);
+        }
+        let version = (&file.read(len - FOOTER_TRAILER_SIZE, 4)?[..]).get_u32();
+        if version != FOOTER_FORMAT_VERSION && version != SST_FORMAT_VERSION {
+            bail!("unsupported SST format version: {}", version);
+        }
+        let footer_size = footer_size(version);
+        if len < footer_size {
+            bail!("SST truncated: {} bytes is smaller than the footer", len);
+        }
+        let footer_offset = len - footer_size;
+        let raw_footer = file.read(footer_offset, footer_size - 8)?;
+        let (data, mut checksum) = raw_footer.split_at(raw_footer.len() - 4);
+        if checksum.get_u32() != crc32fast::hash(data) {
+            bail!("footer checksum mismatched");
+        }
+        let mut handles = [BlockHandle::default(); 5];
+        for (idx, handle) in handles.iter_mut().take(num_handles(version)).enumerate() {
+            *handle = BlockHandle::decode(&data[idx * 16..]);
+        }
+        let [meta, filter, prefix_bloom, range_tombstones, properties] = handles;
+        let footer = Self {
+            version,
+            meta,
+            filter,
+            prefix_bloom,
+            range_tombstones,
+            properties,
+        };
+        for handle in footer.handles() {
+            if handle.offset.saturating_add(handle.len) > footer_offset {
+                bail!(
+                    "SST section at {} with {} bytes is beyond the footer at {}",
+                    handle.offset,
+                    handle.len,
+                    footer_offset
+                );
+            }
+        }
+        Ok(footer)
+    }
+
+    fn handles(&self) -> [BlockHandle; 5] {
+        [
+            self.meta,
+            self.filter,
+            self.prefix_bloom,
+            self.range_tombstones,
+            self.properties,
+        ]
+    }
+
+    /// A legacy SST ends its index and its bloom filter with their `u32` offsets, so the sections
+    /// are read from the end of the file.
+    fn read_legacy(file: &FileObject) -> Result<Self> {
+        let mut end = file.size();
+        let mut read_section = || -> Result<BlockHandle> {
+            if end < 4 {
+                bail!("SST truncated: missing section offset at {}", end);
+            }
+            let offset = (&file.read(end - 4, 4)?[..]).get_u32() as u64;
+            if offset > end - 4 {
+                bail!(
+                    "SST truncated: section offset {} is beyond {}",
+                    offset,
+                    end - 4
+                );
+            }
+            let handle = BlockHandle {
+                offset,
+                len: end - 4 - offset,
+            };
+            end = offset;
+            Ok(handle)
+        };
+        let filter = read_section()?;
+        let meta = read_section()?;
+        Ok(Self {
+            version: LEGACY_FORMAT_VERSION,
+            meta,
+            filter,
+            prefix_bloom: BlockHandle::default(),
+            range_tombstones: BlockHandle::default(),
+            properties: BlockHandle::default(),
+        })
+    }
+}
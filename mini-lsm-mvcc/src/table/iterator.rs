@@ -15,9 +15,10 @@
 use std::sync::Arc;
 
 use anyhow::Result;
+use bytes::Bytes;
 
 use super::SsTable;
-use crate::block::BlockIterator;
+use crate::block::{Block, BlockIterator, RESTART_INTERVAL};
 use crate::iterators::StorageIterator;
 use crate::key::KeySlice;
 
@@ -29,7 +30,20 @@ pub struct SsTableIterator {
 }
 
 impl SsTableIterator {
+    /// An SST with only range tombstones has no data blocks, and the iterator is never valid.
+    fn empty_block_iter() -> BlockIterator {
+        BlockIterator::create_and_seek_to_first(Arc::new(Block {
+            data: Bytes::new(),
+            offsets: Vec::new(),
+            hash_index: Vec::new(),
+            restart_interval: RESTART_INTERVAL,
+        }))
+    }
+
     fn seek_to_first_inner(table: &Arc<SsTable>) -> Result<(usize, BlockIterator)> {
+        if table.num_of_blocks() == 0 {
+            return Ok((0, Self::empty_block_iter()));
+        }
         Ok((
             0,
             BlockIterator::create_and_seek_to_first(table.read_block_cached(0)?),
@@ -56,7 +70,10 @@ impl SsTableIterator {
     }
 
     fn seek_to_key_inner(table: &Arc<SsTable>, key: KeySlice) -> Result<(usize, BlockIterator)> {
-        let mut blk_idx = table.find_block_idx(key);
+        if table.num_of_blocks() == 0 {
+            return Ok((0, Self::empty_block_iter()));
+        }
+        let mut blk_idx = table.find_block_idx(key)?;
         let mut blk_iter =
             BlockIterator::create_and_seek_to_key(table.read_block_cached(blk_idx)?, key);
         if !blk_iter.is_valid() {
@@ -96,7 +113,7 @@ impl StorageIterator for SsTableIterator {
         self.blk_iter.value()
     }
 
-    fn key(&self) -> KeySlice {
+    fn key(&self) -> KeySlice<'_> {
         self.blk_iter.key()
     }
 
@@ -104,6 +121,10 @@ impl StorageIterator for SsTableIterator {
         self.blk_iter.is_valid()
     }
 
+    fn is_value_pointer(&self) -> bool {
+        self.blk_iter.is_value_pointer()
+    }
+
     fn next(&mut self) -> Result<()> {
         self.blk_iter.next();
         if !self.blk_iter.is_valid() {
@@ -0,0 +1,94 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::fmt::Debug;
+use std::ops::Bound;
+
+/// Extracts the prefix of a user key. The prefixes of all keys in an SST are stored in a prefix
+/// bloom filter, so that scans within a single prefix can skip the SSTs without that prefix.
+///
+/// An extractor must be consistent: a key that starts with a prefix returned by the extractor
+/// must be mapped to that same prefix.
+pub trait PrefixExtractor: Debug + Send + Sync {
+    /// The name of the extractor, persisted in the SSTs. A prefix filter is only used if it was
+    /// built by an extractor with the same name.
+    fn name(&self) -> String;
+
+    /// Returns the prefix of the key, or `None` if the key is not in the domain of the extractor.
+    fn prefix<'a>(&self, key: &'a [u8]) -> Option<&'a [u8]>;
+}
+
+/// Uses the first `len` bytes of a key as its prefix. Shorter keys have no prefix.
+#[derive(Debug, Clone)]
+pub struct FixedPrefixExtractor(pub usize);
+
+impl PrefixExtractor for FixedPrefixExtractor {
+    fn name(&self) -> String {
+        format!("fixed:{}", self.0)
+    }
+
+    fn prefix<'a>(&self, key: &'a [u8]) -> Option<&'a [u8]> {
+        key.get(..self.0)
+    }
+}
+
+/// Uses a key up to and including the first occurrence of the delimiter as its prefix, e.g.,
+/// `user:42/` for `user:42/profile` with `/` as the delimiter. Keys without the delimiter have no
+/// prefix.
+#[derive(Debug, Clone)]
+pub struct DelimiterPrefixExtractor(pub u8);
+
+impl PrefixExtractor for DelimiterPrefixExtractor {
+    fn name(&self) -> String {
+        format!("delimiter:{}", self.0)
+    }
+
+    fn prefix<'a>(&self, key: &'a [u8]) -> Option<&'a [u8]> {
+        let pos = key.iter().position(|x| *x == self.0)?;
+        Some(&key[..=pos])
+    }
+}
+
+/// Returns the smallest key that is larger than all keys starting with `prefix`, or `None` if
+/// there is no such key.
+pub(crate) fn prefix_upper_bound(prefix: &[u8]) -> Option<Vec<u8>> {
+    let mut upper = prefix.to_vec();
+    while let Some(last) = upper.pop() {
+        if last != u8::MAX {
+            upper.push(last + 1);
+            return Some(upper);
+        }
+    }
+    None
+}
+
+/// Returns the prefix shared by all keys in the range, if the range lies within a single prefix.
+pub(crate) fn prefix_of_range<'a>(
+    extractor: &dyn PrefixExtractor,
+    lower: Bound<&'a [u8]>,
+    upper: Bound<&[u8]>,
+) -> Option<&'a [u8]> {
+    let prefix = match lower {
+        Bound::Included(key) | Bound::Excluded(key) => extractor.prefix(key)?,
+        Bound::Unbounded => return None,
+    };
+    let within = match upper {
+        Bound::Included(key) => key.starts_with(prefix),
+        Bound::Excluded(key) => {
+            key.starts_with(prefix) || prefix_upper_bound(prefix).is_some_and(|x| x == key)
+        }
+        Bound::Unbounded => false,
+    };
+    within.then_some(prefix)
+}
@@ -0,0 +1,195 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use anyhow::{Result, bail};
+use bytes::{Buf, BufMut};
+use serde::Serialize;
+
+use super::CompressionType;
+use crate::varint::{get_uvarint, put_uvarint};
+
+/// Why an SST was written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize)]
+pub enum CompactionReason {
+    /// Built outside of the storage engine, e.g., by tests and tools.
+    #[default]
+    Unknown,
+    /// Flushed from a memtable.
+    Flush,
+    /// Written by a full compaction.
+    ForceFullCompaction,
+    /// Written by a leveled compaction.
+    Leveled,
+    /// Written by a simple leveled compaction.
+    Simple,
+    /// Written by a tiered compaction.
+    Tiered,
+}
+
+impl CompactionReason {
+    /// The id of the reason persisted on disk.
+    pub fn id(&self) -> u8 {
+        match self {
+            CompactionReason::Unknown => 0,
+            CompactionReason::Flush => 1,
+            CompactionReason::ForceFullCompaction => 2,
+            CompactionReason::Leveled => 3,
+            CompactionReason::Simple => 4,
+            CompactionReason::Tiered => 5,
+        }
+    }
+
+    /// Reasons written by newer versions are read as `Unknown`.
+    pub fn from_id(id: u8) -> Self {
+        match id {
+            1 => CompactionReason::Flush,
+            2 => CompactionReason::ForceFullCompaction,
+            3 => CompactionReason::Leveled,
+            4 => CompactionReason::Simple,
+            5 => CompactionReason::Tiered,
+            _ => CompactionReason::Unknown,
+        }
+    }
+}
+
+/// Statistics of an SST, recorded by the builder in their own meta block.
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize)]
+pub struct TableProperties {
+    /// Number of key-value pairs, including deletions.
+    pub num_entries: u64,
+    /// Number of point deletions, i.e., entries with an empty value.
+    pub num_deletions: u64,
+    /// Number of range tombstone fragments.
+    pub num_range_tombstones: u64,
+    /// Total size of the keys without timestamps.
+    pub raw_key_size: u64,
+    /// Total size of the values as stored in the SST, where a value in the value log counts as
+    /// the size of its pointer.
+    pub raw_value_size: u64,
+    /// Smallest timestamp of the entries and range tombstones.
+    pub min_ts: u64,
+    /// Largest timestamp of the entries and range tombstones.
+    pub max_ts: u64,
+    /// When the SST was built, in seconds since the Unix epoch.
+    pub creation_time: u64,
+    pub compaction_reason: CompactionReason,
+    /// The level the SST is written to: 0 for flushes to L0, the lower level for leveled
+    /// compactions, and the ID of the tier under tiered compaction.
+    pub level: u64,
+    /// The codec of the data blocks.
+    pub compression: CompressionType,
+    /// Name of the filter policy, empty if the SST has no filters.
+    pub filter_policy: String,
+    /// The newest memtable flushed into the SST or into the SSTs it is compacted from, whose WAL
+    /// and those of all older memtables are no longer needed. `None` if not known.
+    pub max_memtable_id: Option<u64>,
+}
+
+// Properties are stored by name, so that properties can be added without changing the format.
+const NUM_ENTRIES: &str = "num_entries";
+const NUM_DELETIONS: &str = "num_deletions";
+const NUM_RANGE_TOMBSTONES: &str = "num_range_tombstones";
+const RAW_KEY_SIZE: &str = "raw_key_size";
+const RAW_VALUE_SIZE: &str = "raw_value_size";
+const MIN_TS: &str = "min_ts";
+const MAX_TS: &str = "max_ts";
+const CREATION_TIME: &str = "creation_time";
+const COMPACTION_REASON: &str = "compaction_reason";
+const LEVEL: &str = "level";
+const COMPRESSION: &str = "compression";
+const FILTER_POLICY: &str = "filter_policy";
+const MAX_MEMTABLE_ID: &str = "max_memtable_id";
+
+impl TableProperties {
+    /// Encode the properties as a list of names and values, followed by a checksum. Numbers are
+    /// stored as varints and strings as raw bytes.
+    pub(crate) fn encode(&self, buf: &mut Vec<u8>) {
+        let mut properties = [
+            (NUM_ENTRIES, self.num_entries),
+            (NUM_DELETIONS, self.num_deletions),
+            (NUM_RANGE_TOMBSTONES, self.num_range_tombstones),
+            (RAW_KEY_SIZE, self.raw_key_size),
+            (RAW_VALUE_SIZE, self.raw_value_size),
+            (MIN_TS, self.min_ts),
+            (MAX_TS, self.max_ts),
+            (CREATION_TIME, self.creation_time),
+            (COMPACTION_REASON, self.compaction_reason.id() as u64),
+            (LEVEL, self.level),
+            (COMPRESSION, self.compression.id() as u64),
+        ]
+        .into_iter()
+        .map(|(name, value)| {
+            let mut encoded = Vec::new();
+            put_uvarint(&mut encoded, value);
+            (name, encoded)
+        })
+        .collect::<Vec<_>>();
+        properties.push((FILTER_POLICY, self.filter_policy.as_bytes().to_vec()));
+        if let Some(max_memtable_id) = self.max_memtable_id {
+            let mut encoded = Vec::new();
+            put_uvarint(&mut encoded, max_memtable_id);
+            properties.push((MAX_MEMTABLE_ID, encoded));
+        }
+
+        let offset = buf.len();
+        put_uvarint(buf, properties.len() as u64);
+        for (name, value) in properties {
+            put_uvarint(buf, name.len() as u64);
+            buf.put_slice(name.as_bytes());
+            put_uvarint(buf, value.len() as u64);
+            buf.put_slice(&value);
+        }
+        let checksum = crc32fast::hash(&buf[offset..]);
+        buf.put_u32(checksum);
+    }
+
+    /// Decode the properties. Unknown properties are skipped and missing ones are left as default.
+    pub(crate) fn decode(buf: &[u8]) -> Result<Self> {
+        if buf.len() < 4 {
+            bail!("table properties too short: {} bytes", buf.len());
+        }
+        let (mut buf, mut checksum) = buf.split_at(buf.len() - 4);
+        if checksum.get_u32() != crc32fast::hash(buf) {
+            bail!("checksum mismatched for table properties");
+        }
+        let mut properties = Self::default();
+        let num_properties = get_uvarint(&mut buf);
+        for _ in 0..num_properties {
+            let name_len = get_uvarint(&mut buf) as usize;
+            let name = buf.copy_to_bytes(name_len);
+            let value_len = get_uvarint(&mut buf) as usize;
+            let value = buf.copy_to_bytes(value_len);
+            let as_u64 = || get_uvarint(&mut value.clone());
+            match std::str::from_utf8(&name)? {
+                NUM_ENTRIES => properties.num_entries = as_u64(),
+                NUM_DELETIONS => properties.num_deletions = as_u64(),
+                NUM_RANGE_TOMBSTONES => properties.num_range_tombstones = as_u64(),
+                RAW_KEY_SIZE => properties.raw_key_size = as_u64(),
+                RAW_VALUE_SIZE => properties.raw_value_size = as_u64(),
+                MIN_TS => properties.min_ts = as_u64(),
+                MAX_TS => properties.max_ts = as_u64(),
+                CREATION_TIME => properties.creation_time = as_u64(),
+                COMPACTION_REASON => {
+                    properties.compaction_reason = CompactionReason::from_id(as_u64() as u8)
+                }
+                LEVEL => properties.level = as_u64(),
+                COMPRESSION => properties.compression = CompressionType::from_id(as_u64() as u8)?,
+                FILTER_POLICY => properties.filter_policy = String::from_utf8(value.to_vec())?,
+                MAX_MEMTABLE_ID => properties.max_memtable_id = Some(as_u64()),
+                _ => {}
+            }
+        }
+        Ok(properties)
+    }
+}
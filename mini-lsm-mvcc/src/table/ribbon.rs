@@ -0,0 +1,171 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A standard ribbon filter (Dillinger and Walzer, 2021).
+//!
+//! Each key is mapped to a start slot, a 64-bit coefficient row and an `r`-bit fingerprint. The
+//! filter is a solution `S` of `r`-bit slots such that, for every key, the XOR of the slots
+//! `S[start + j]` for all bits `j` set in the coefficient row equals the fingerprint of the key.
+//! A lookup computes the same XOR and compares it with the fingerprint, so the false positive
+//! rate is about `2^-r`.
+//!
+//! The solution is stored in blocks of 64 slots. Each block is `r` little-endian `u64`s, the
+//! `b`-th of which holds bit `b` of the 64 slots, so that a lookup is a few shifts and popcounts.
+
+use bytes::Bytes;
+
+use super::filter::{Filter, FilterKind, mix64};
+
+/// Number of slots covered by a coefficient row.
+const WIDTH: usize = 64;
+
+/// Space overhead of the slots over the number of keys, on top of one extra block.
+const SLOT_OVERHEAD: f64 = 0.1;
+
+struct RibbonHash {
+    start: usize,
+    coeff: u64,
+    result: u32,
+}
+
+fn ribbon_hash(h: u32, r: u8, num_starts: usize) -> RibbonHash {
+    let x = mix64(h as u64);
+    let start = ((x as u128 * num_starts as u128) >> 64) as usize;
+    // the lowest bit is always set, so that the row of a key starts at its start slot
+    let coeff = mix64(x) | 1;
+    let result = (mix64(x ^ 0x5851_f42d_4c95_7f2d) as u32) & result_mask(r);
+    RibbonHash {
+        start,
+        coeff,
+        result,
+    }
+}
+
+fn result_mask(r: u8) -> u32 {
+    if r >= 32 { u32::MAX } else { (1 << r) - 1 }
+}
+
+/// Solves the system of a key per row with Gaussian elimination on the banded matrix, returning
+/// the solution slots, or `None` if the system has no solution with this number of slots.
+fn solve(keys: &[u32], r: u8, num_slots: usize) -> Option<Vec<u32>> {
+    let num_starts = num_slots - WIDTH + 1;
+    let mut coeff_rows = vec![0u64; num_slots];
+    let mut result_rows = vec![0u32; num_slots];
+    for h in keys {
+        let RibbonHash {
+            mut start,
+            mut coeff,
+            mut result,
+        } = ribbon_hash(*h, r, num_starts);
+        loop {
+            if coeff_rows[start] == 0 {
+                coeff_rows[start] = coeff;
+                result_rows[start] = result;
+                break;
+            }
+            coeff ^= coeff_rows[start];
+            result ^= result_rows[start];
+            if coeff == 0 {
+                // the row is a combination of the inserted rows, which is fine for a duplicated
+                // key but unsolvable otherwise
+                if result == 0 {
+                    break;
+                }
+                return None;
+            }
+            let shift = coeff.trailing_zeros() as usize;
+            start += shift;
+            coeff >>= shift;
+        }
+    }
+    // back substitution; free slots are filled with arbitrary values
+    let mut solution = vec![0u32; num_slots];
+    for i in (0..num_slots).rev() {
+        let coeff = coeff_rows[i];
+        if coeff == 0 {
+            solution[i] = (mix64(i as u64) as u32) & result_mask(r);
+            continue;
+        }
+        let mut value = result_rows[i];
+        for (j, slot) in solution[i..].iter().enumerate().take(WIDTH).skip(1) {
+            if coeff >> j & 1 != 0 {
+                value ^= slot;
+            }
+        }
+        solution[i] = value;
+    }
+    Some(solution)
+}
+
+/// Build a ribbon filter from key hashes with about `bits_per_key` bits per key.
+pub(crate) fn build(keys: &[u32], bits_per_key: usize) -> Filter {
+    let r = ((bits_per_key as f64 / (1.0 + SLOT_OVERHEAD)).round() as u8).clamp(1, 32);
+    let mut num_slots =
+        ((keys.len() as f64 * (1.0 + SLOT_OVERHEAD)) as usize + WIDTH).next_multiple_of(WIDTH);
+    let solution = loop {
+        if let Some(solution) = solve(keys, r, num_slots) {
+            break solution;
+        }
+        // a system with more slots is more likely to be solvable
+        num_slots = (num_slots + num_slots / 8).next_multiple_of(WIDTH);
+    };
+    let r = r as usize;
+    let mut filter = vec![0u64; num_slots / WIDTH * r];
+    for (i, value) in solution.iter().enumerate() {
+        for b in 0..r {
+            if value >> b & 1 != 0 {
+                filter[i / WIDTH * r + b] |= 1 << (i % WIDTH);
+            }
+        }
+    }
+    let filter = filter
+        .iter()
+        .flat_map(|word| word.to_le_bytes())
+        .collect::<Vec<_>>();
+    Filter {
+        kind: FilterKind::Ribbon,
+        filter: Bytes::from(filter),
+        k: r as u8,
+    }
+}
+
+pub(crate) fn may_contain(filter: &[u8], r: u8, h: u32) -> bool {
+    let num_blocks = filter.len() / (8 * r.max(1) as usize);
+    if num_blocks == 0 {
+        return true;
+    }
+    let num_starts = num_blocks * WIDTH - WIDTH + 1;
+    let RibbonHash {
+        start,
+        coeff,
+        result,
+    } = ribbon_hash(h, r, num_starts);
+    let word = |block: usize, b: usize| {
+        let offset = (block * r as usize + b) * 8;
+        u64::from_le_bytes(filter[offset..offset + 8].try_into().unwrap())
+    };
+    let (block, shift) = (start / WIDTH, start % WIDTH);
+    for b in 0..r as usize {
+        // the slots from `start` to `start + 63`, which span at most two blocks
+        let mut slots = word(block, b) >> shift;
+        if shift > 0 {
+            slots |= word(block + 1, b) << (WIDTH - shift);
+        }
+        let bit = (slots & coeff).count_ones() & 1;
+        if bit != result >> b & 1 {
+            return false;
+        }
+    }
+    true
+}
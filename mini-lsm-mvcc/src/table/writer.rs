@@ -0,0 +1,108 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+
+use super::FileObject;
+use crate::env::{FileSystem, ReadBackend, WritableFile};
+
+/// Appends the blocks of an SST to its file while the SST is built, so that the builder does not
+/// keep the data blocks in memory.
+pub(crate) struct SstFileWriter {
+    fs: Arc<dyn FileSystem>,
+    file: Box<dyn WritableFile>,
+    path: PathBuf,
+    /// Number of bytes written so far.
+    len: u64,
+    /// Start writing back the written bytes every this many bytes.
+    bytes_per_sync: Option<u64>,
+    /// Number of bytes whose writeback has been started.
+    synced_len: u64,
+    /// The first error when writing the file, which is reported when the SST is built.
+    error: Option<anyhow::Error>,
+}
+
+impl SstFileWriter {
+    pub(crate) fn create(
+        fs: Arc<dyn FileSystem>,
+        path: &Path,
+        bytes_per_sync: Option<usize>,
+    ) -> Result<Self> {
+        let file = fs
+            .create(path)
+            .with_context(|| format!("failed to create SST {}", path.display()))?;
+        Ok(Self {
+            fs,
+            file,
+            path: path.to_path_buf(),
+            len: 0,
+            bytes_per_sync: bytes_per_sync.map(|x| x as u64),
+            synced_len: 0,
+            error: None,
+        })
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.len as usize
+    }
+
+    /// Append data to the file. Errors are kept until the file is finished.
+    pub(crate) fn append(&mut self, data: &[u8]) {
+        self.len += data.len() as u64;
+        if self.error.is_some() {
+            return;
+        }
+        if let Err(e) = self.file.append(data) {
+            self.error = Some(e);
+            return;
+        }
+        if let Some(bytes_per_sync) = self.bytes_per_sync
+            && self.len - self.synced_len >= bytes_per_sync
+        {
+            // start writing back the bytes written since the last sync without waiting for them,
+            // so that the final sync does not have to write the whole file at once
+            if let Err(e) = self
+                .file
+                .sync_range(self.synced_len, self.len - self.synced_len)
+            {
+                self.error = Some(e);
+            }
+            self.synced_len = self.len;
+        }
+    }
+
+    /// Sync the file, and open it for reading with the given backend.
+    pub(crate) fn finish(mut self, read_backend: ReadBackend) -> Result<FileObject> {
+        if let Some(e) = self.error.take() {
+            return Err(e).with_context(|| format!("failed to write SST {}", self.path.display()));
+        }
+        self.file.sync()?;
+        drop(self.file);
+        FileObject::open_with_fs(&*self.fs, &self.path, read_backend)
+    }
+
+    /// Remove the file of an SST that is not built.
+    pub(crate) fn abandon(self) -> Result<()> {
+        drop(self.file);
+        self.fs.remove(&self.path)?;
+        Ok(())
+    }
+}
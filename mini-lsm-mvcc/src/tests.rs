@@ -12,7 +12,33 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+mod arena_skiplist;
+mod block_encoding;
+mod block_hash_index;
+mod compression;
+mod crash_recovery;
+mod file_gc;
+mod filter_policy;
+mod flush;
 mod harness;
+mod large_entry;
+mod legacy_db;
+mod log_dump;
+mod manifest_rollover;
+mod mem_file_system;
+mod memtable_rep;
+mod merged_flush;
+mod partitioned_index;
+mod prefix_bloom;
+mod range_tombstone;
+mod read_backend;
+mod repair;
+mod sst_dump;
+mod sst_footer;
+mod streaming_builder;
+mod table_properties;
+mod value_log;
+mod version_edit;
 mod week1_day1;
 mod week1_day2;
 mod week1_day3;
@@ -33,3 +59,4 @@ mod week3_day4;
 mod week3_day5;
 mod week3_day6;
 mod week3_day7;
+mod write_buffer_manager;
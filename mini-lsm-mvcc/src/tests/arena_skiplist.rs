@@ -0,0 +1,148 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::ops::Bound;
+use std::sync::Arc;
+
+use bytes::Bytes;
+
+use crate::iterators::StorageIterator;
+use crate::key::KeySlice;
+use crate::mem_table::{MemTable, MemTableRep, SkipList};
+
+fn key_of(idx: usize) -> Vec<u8> {
+    format!("key_{:05}", idx).into_bytes()
+}
+
+#[test]
+fn test_skiplist_concurrent_insert() {
+    let list = Arc::new(SkipList::new());
+    let threads = (0..4)
+        .map(|thread| {
+            let list = list.clone();
+            std::thread::spawn(move || {
+                for idx in (thread..1000).step_by(4) {
+                    // every key with two versions, and the even keys written by two threads
+                    for ts in [1, 2] {
+                        let value = format!("value_{}_{}", idx, ts);
+                        list.insert(KeySlice::from_slice(&key_of(idx), ts), value.as_bytes());
+                        if idx % 2 == 0 {
+                            let idx = (idx + 2) % 1000;
+                            let value = format!("value_{}_{}", idx, ts);
+                            list.insert(KeySlice::from_slice(&key_of(idx), ts), value.as_bytes());
+                        }
+                    }
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+
+    assert_eq!(list.len(), 2000);
+    let entries = list.iter().collect::<Vec<_>>();
+    assert_eq!(entries.len(), 2000);
+    for (idx, (key, value)) in entries.iter().enumerate() {
+        // newer versions of a key come first
+        let ts = 2 - idx as u64 % 2;
+        assert_eq!(*key, KeySlice::from_slice(&key_of(idx / 2), ts));
+        assert_eq!(*value, format!("value_{}_{}", idx / 2, ts).as_bytes());
+    }
+
+    // replacing a value takes the new one
+    list.insert(KeySlice::from_slice(&key_of(10), 1), b"new");
+    assert_eq!(list.len(), 2000);
+    assert_eq!(
+        list.get(KeySlice::from_slice(&key_of(10), 1)),
+        Some(Bytes::from_static(b"new"))
+    );
+    assert_eq!(list.get(KeySlice::from_slice(&key_of(10), 3)), None);
+
+    let lower = key_of(10);
+    let upper = key_of(12);
+    let (first, last) = (key_of(0), key_of(14));
+    let collect = |lower, upper| {
+        let mut range = list.range(lower, upper);
+        let mut keys = Vec::new();
+        while range.is_valid() {
+            keys.push((range.key().key_ref().to_vec(), range.key().ts()));
+            range.next().unwrap();
+        }
+        keys
+    };
+    assert_eq!(
+        collect(
+            Bound::Excluded(KeySlice::from_slice(&lower, 2)),
+            Bound::Included(KeySlice::from_slice(&upper, 2))
+        ),
+        vec![
+            (key_of(10), 1),
+            (key_of(11), 2),
+            (key_of(11), 1),
+            (key_of(12), 2)
+        ]
+    );
+    assert_eq!(
+        collect(
+            Bound::Included(KeySlice::from_slice(&upper, 1)),
+            Bound::Excluded(KeySlice::from_slice(&last, 2))
+        ),
+        vec![(key_of(12), 1), (key_of(13), 2), (key_of(13), 1)]
+    );
+    assert!(
+        collect(
+            Bound::Unbounded,
+            Bound::Excluded(KeySlice::from_slice(&first, 2))
+        )
+        .is_empty()
+    );
+}
+
+#[test]
+fn test_memtable_memory_usage() {
+    let memtable = MemTable::create(0);
+    let empty_size = memtable.approximate_size();
+    assert!(memtable.is_empty());
+
+    // an entry takes its key, its value and a node of a few links
+    memtable.for_testing_put_slice(b"key", b"value").unwrap();
+    let entry_size = memtable.approximate_size() - empty_size;
+    assert!(entry_size >= 3 + 8 + 5);
+    assert!(entry_size < 3 + 8 + 5 + 256);
+    assert_eq!(entry_size % 8, 0);
+
+    // a new value of the key is allocated next to the old one
+    let size = memtable.approximate_size();
+    memtable.for_testing_put_slice(b"key", b"value2").unwrap();
+    assert_eq!(memtable.approximate_size(), size + 16);
+
+    // a value larger than a chunk of the arena is counted exactly too
+    let size = memtable.approximate_size();
+    let large = vec![b'x'; 1 << 20];
+    memtable.for_testing_put_slice(b"key", &large).unwrap();
+    assert_eq!(memtable.approximate_size(), size + (1 << 20) + 8);
+    assert_eq!(
+        memtable.for_testing_get_slice(b"key"),
+        Some(Bytes::from(large))
+    );
+
+    // an iterator keeps the entries alive after the memtable is freed
+    let mut iter = memtable.for_testing_scan_slice(Bound::Unbounded, Bound::Unbounded);
+    drop(memtable);
+    assert_eq!(iter.key().key_ref(), b"key");
+    assert_eq!(iter.value().len(), 1 << 20);
+    iter.next().unwrap();
+    assert!(!iter.is_valid());
+}
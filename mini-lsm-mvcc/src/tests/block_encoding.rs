@@ -0,0 +1,162 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::Arc;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::block::{Block, BlockBuilder, BlockIterator};
+use crate::compact::CompactionOptions;
+use crate::key::{KeySlice, KeyVec};
+use crate::lsm_storage::{LsmStorageOptions, MiniLsm};
+
+pub(super) fn key_of(idx: usize) -> Vec<u8> {
+    format!("/tenant/0042/collection/users/documents/{:05}", idx * 2).into_bytes()
+}
+
+pub(super) fn ts_of(idx: usize, version: usize) -> u64 {
+    (1000 + idx * 7 - version * 3) as u64
+}
+
+pub(super) fn num_of_keys() -> usize {
+    100
+}
+
+pub(super) fn generate_block() -> Block {
+    generate_block_with(BlockBuilder::new(65536))
+}
+
+/// Add `num_of_keys()` keys with three versions each to the block.
+pub(super) fn generate_block_with(mut builder: BlockBuilder) -> Block {
+    for idx in 0..num_of_keys() {
+        for version in 0..3 {
+            assert!(builder.add(
+                KeySlice::for_testing_from_slice_with_ts(&key_of(idx), ts_of(idx, version)),
+                format!("value_{}_{}", idx, version).as_bytes()
+            ));
+        }
+    }
+    builder.build()
+}
+
+#[test]
+fn test_block_restart_points() {
+    let block = generate_block();
+    // every key is stored in full only at the restart points
+    assert_eq!(block.offsets.len(), (num_of_keys() * 3).div_ceil(16));
+    assert!(block.data.len() < num_of_keys() * 3 * key_of(0).len() / 2);
+    let block = Arc::new(Block::decode(&block.encode()));
+    let mut iter = BlockIterator::create_and_seek_to_first(block);
+    for idx in 0..num_of_keys() {
+        for version in 0..3 {
+            assert!(iter.is_valid());
+            assert_eq!(iter.key().for_testing_key_ref(), key_of(idx));
+            assert_eq!(iter.key().for_testing_ts(), ts_of(idx, version));
+            assert_eq!(
+                iter.value(),
+                format!("value_{}_{}", idx, version).as_bytes()
+            );
+            iter.next();
+        }
+    }
+    assert!(!iter.is_valid());
+}
+
+#[test]
+fn test_block_restart_seek_key() {
+    let block = Arc::new(generate_block());
+    let mut iter = BlockIterator::create_and_seek_to_first(block);
+    for idx in 0..num_of_keys() {
+        for version in 0..3 {
+            let key = KeyVec::from_vec_with_ts(key_of(idx), ts_of(idx, version));
+            iter.seek_to_key(key.as_key_slice());
+            assert!(iter.is_valid());
+            assert_eq!(iter.key(), key.as_key_slice());
+            // seek to a version between two existing versions
+            let key = KeyVec::from_vec_with_ts(key_of(idx), ts_of(idx, version) + 1);
+            iter.seek_to_key(key.as_key_slice());
+            assert_eq!(iter.key().for_testing_key_ref(), key_of(idx));
+            assert_eq!(iter.key().for_testing_ts(), ts_of(idx, version));
+        }
+        // seek to a key in between two existing keys
+        let mut key = key_of(idx);
+        key.push(b'0');
+        iter.seek_to_key(KeySlice::for_testing_from_slice_with_ts(&key, 0));
+        if idx + 1 == num_of_keys() {
+            assert!(!iter.is_valid());
+        } else {
+            assert_eq!(iter.key().for_testing_key_ref(), key_of(idx + 1));
+            assert_eq!(iter.key().for_testing_ts(), ts_of(idx + 1, 0));
+        }
+    }
+    iter.seek_to_key(KeySlice::for_testing_from_slice_with_ts(b"/", 0));
+    assert_eq!(iter.key().for_testing_key_ref(), key_of(0));
+}
+
+#[test]
+fn test_block_restart_interval() {
+    let mut builder = BlockBuilder::new(65536);
+    builder.set_restart_interval(5);
+    let block = generate_block_with(builder);
+    assert_eq!(block.offsets.len(), (num_of_keys() * 3).div_ceil(5));
+    // the interval is stored in the block, so that readers do not assume the default one
+    let block = Arc::new(Block::decode(&block.encode()));
+    assert_eq!(block.restart_interval, 5);
+    for idx in 0..num_of_keys() {
+        for version in 0..3 {
+            let key = KeyVec::from_vec_with_ts(key_of(idx), ts_of(idx, version));
+            let iter = BlockIterator::create_and_seek_to_key(block.clone(), key.as_key_slice());
+            assert_eq!(iter.key(), key.as_key_slice());
+            assert_eq!(iter.entry_idx(), idx * 3 + version);
+            let iter = BlockIterator::create_and_seek_to_entry(block.clone(), idx * 3 + version);
+            assert_eq!(iter.key(), key.as_key_slice());
+        }
+    }
+    for idx in num_of_keys() * 3..num_of_keys() * 3 + 10 {
+        assert!(!BlockIterator::create_and_seek_to_entry(block.clone(), idx).is_valid());
+    }
+}
+
+#[test]
+fn test_block_restart_interval_in_storage() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.block_restart_interval = 1;
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    for idx in 0..num_of_keys() {
+        storage.put(&key_of(idx), b"value").unwrap();
+    }
+    storage.force_flush().unwrap();
+    {
+        let state = storage.inner.state.read();
+        let sst = &state.sstables[&state.l0_sstables[0]];
+        let block = sst.read_block(0).unwrap();
+        assert_eq!(block.restart_interval, 1);
+        // every key is stored in full
+        let mut iter = BlockIterator::create_and_seek_to_first(block.clone());
+        let mut num_entries = 0;
+        while iter.is_valid() {
+            num_entries += 1;
+            iter.next();
+        }
+        assert_eq!(block.offsets.len(), num_entries);
+    }
+    for idx in 0..num_of_keys() {
+        assert_eq!(
+            storage.get(&key_of(idx)).unwrap(),
+            Some(Bytes::from_static(b"value"))
+        );
+    }
+}
@@ -0,0 +1,106 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::Arc;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use super::block_encoding::{generate_block, generate_block_with, key_of, num_of_keys, ts_of};
+use crate::block::{Block, BlockBuilder, BlockIterator};
+use crate::compact::CompactionOptions;
+use crate::key::KeySlice;
+use crate::lsm_storage::{LsmStorageOptions, MiniLsm};
+
+#[test]
+fn test_block_hash_index() {
+    let block = generate_block();
+    assert!(block.hash_index.is_empty());
+    let hash_block = generate_block_with(BlockBuilder::new_with_hash_index(65536));
+    assert!(hash_block.hash_index.len() > num_of_keys());
+    let hash_block = Block::decode(&hash_block.encode());
+    assert!(hash_block.hash_index.len() > num_of_keys());
+    assert_eq!(hash_block.offsets, block.offsets);
+    assert_eq!(hash_block.data, block.data);
+    assert!(
+        (0..num_of_keys()).any(|idx| hash_block.hash_lookup(&key_of(idx)).is_some()),
+        "all buckets collide"
+    );
+
+    // the hash index gives the same results as the binary search, including for absent keys and
+    // timestamps between the versions
+    let block = Arc::new(block);
+    let hash_block = Arc::new(hash_block);
+    for idx in 0..num_of_keys() {
+        let mut absent_key = key_of(idx);
+        absent_key.push(b'0');
+        for key in [key_of(idx), absent_key] {
+            for ts in [
+                u64::MAX,
+                ts_of(idx, 0),
+                ts_of(idx, 1) + 1,
+                ts_of(idx, 2) - 1,
+                0,
+            ] {
+                let key = KeySlice::for_testing_from_slice_with_ts(&key, ts);
+                let iter = BlockIterator::create_and_seek_to_key(block.clone(), key);
+                let hash_iter = BlockIterator::create_and_seek_to_key(hash_block.clone(), key);
+                assert_eq!(iter.is_valid(), hash_iter.is_valid());
+                if iter.is_valid() {
+                    assert_eq!(iter.key(), hash_iter.key());
+                    assert_eq!(iter.value(), hash_iter.value());
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn test_block_hash_index_in_storage() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.block_hash_index = true;
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    for round in 0..3 {
+        for idx in 0..1000 {
+            storage
+                .put(&key_of(idx), format!("value_{}_{}", idx, round).as_bytes())
+                .unwrap();
+        }
+    }
+    let txn = storage.new_txn().unwrap();
+    for idx in 0..1000 {
+        storage
+            .put(&key_of(idx), format!("value_{}_3", idx).as_bytes())
+            .unwrap();
+    }
+    storage.force_flush().unwrap();
+    let snapshot = storage.inner.state.read().clone();
+    let sst = snapshot.sstables[&snapshot.l0_sstables[0]].clone();
+    assert!(!sst.read_block(0).unwrap().hash_index.is_empty());
+    for idx in 0..1000 {
+        assert_eq!(
+            storage.get(&key_of(idx)).unwrap(),
+            Some(Bytes::from(format!("value_{}_3", idx)))
+        );
+        assert_eq!(
+            txn.get(&key_of(idx)).unwrap(),
+            Some(Bytes::from(format!("value_{}_2", idx)))
+        );
+    }
+    // keys of odd indices are absent
+    let mut absent_key = key_of(0);
+    *absent_key.last_mut().unwrap() = b'1';
+    assert_eq!(storage.get(&absent_key).unwrap(), None);
+}
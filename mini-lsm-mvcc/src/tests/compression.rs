@@ -0,0 +1,105 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::Arc;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::compact::CompactionOptions;
+use crate::key::KeySlice;
+use crate::lsm_storage::{LsmStorageOptions, MiniLsm};
+use crate::table::{CompressionType, FileObject, SsTable, SsTableBuilder, SsTableIterator};
+
+use super::harness::check_iter_result_by_key_and_ts;
+
+fn generate_test_data() -> Vec<((Bytes, u64), Bytes)> {
+    (0..500)
+        .map(|id| {
+            (
+                (Bytes::from(format!("key{:05}", id)), 1),
+                Bytes::from(format!(
+                    r#"{{"id": {:05}, "name": "value", "tags": []}}"#,
+                    id
+                )),
+            )
+        })
+        .collect()
+}
+
+#[test]
+fn test_sst_build_with_compression() {
+    let dir = tempdir().unwrap();
+    let data = generate_test_data();
+    let mut sizes = Vec::new();
+    for compression in [
+        CompressionType::None,
+        CompressionType::Lz4,
+        CompressionType::Snappy,
+        CompressionType::Zstd,
+    ] {
+        let path = dir.path().join(format!("{}.sst", compression.id()));
+        let mut builder = SsTableBuilder::new_with_compression(4096, compression);
+        for ((key, ts), value) in &data {
+            builder.add(KeySlice::for_testing_from_slice_with_ts(key, *ts), value);
+        }
+        let sst = builder.build_for_test(&path).unwrap();
+        sizes.push(sst.table_size());
+        let sst = Arc::new(SsTable::open_for_test(FileObject::open(&path).unwrap()).unwrap());
+        check_iter_result_by_key_and_ts(
+            &mut SsTableIterator::create_and_seek_to_first(sst).unwrap(),
+            data.clone(),
+        );
+    }
+    for size in &sizes[1..] {
+        assert!(*size < sizes[0], "compressed sizes {:?}", sizes);
+    }
+}
+
+#[test]
+fn test_mixed_compression_in_one_db() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    for (idx, compression) in [
+        CompressionType::Lz4,
+        CompressionType::Snappy,
+        CompressionType::Zstd,
+        CompressionType::None,
+    ]
+    .into_iter()
+    .enumerate()
+    {
+        options.compression = compression;
+        let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+        storage
+            .put(format!("key{}", idx).as_bytes(), b"value")
+            .unwrap();
+        storage.force_flush().unwrap();
+        storage.close().unwrap();
+    }
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    for idx in 0..4 {
+        assert_eq!(
+            storage.get(format!("key{}", idx).as_bytes()).unwrap(),
+            Some(Bytes::from("value"))
+        );
+    }
+    storage.force_full_compaction().unwrap();
+    for idx in 0..4 {
+        assert_eq!(
+            storage.get(format!("key{}", idx).as_bytes()).unwrap(),
+            Some(Bytes::from("value"))
+        );
+    }
+}
@@ -0,0 +1,230 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::BTreeMap;
+use std::ops::Bound;
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::Result;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::compact::{CompactionOptions, SimpleLeveledCompactionOptions};
+use crate::env::{FaultInjectionFileSystem, FileSystem, MemFileSystem};
+use crate::iterators::StorageIterator;
+use crate::lsm_storage::{LsmStorageInner, LsmStorageOptions, MiniLsm};
+
+type Model = BTreeMap<Vec<u8>, Vec<u8>>;
+
+fn key_of(idx: usize) -> Vec<u8> {
+    format!("key_{:03}", idx).into_bytes()
+}
+
+fn value_of(idx: usize, version: usize) -> Vec<u8> {
+    // some values are large enough to be moved to the value log
+    format!("value_{:03}_{:05}", idx, version)
+        .repeat(version % 8 + 1)
+        .into_bytes()
+}
+
+fn options(fs: &FaultInjectionFileSystem) -> LsmStorageOptions {
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::Simple(
+        SimpleLeveledCompactionOptions {
+            size_ratio_percent: 200,
+            level0_file_num_compaction_trigger: 2,
+            max_levels: 3,
+        },
+    ));
+    options.block_size = 256;
+    options.target_sst_size = 4 << 10;
+    options.num_memtable_limit = 100;
+    options.enable_wal = true;
+    options.value_log_threshold = Some(64);
+    options.file_system = Arc::new(fs.clone());
+    // roll the manifest over often, so that crashes also hit the rollovers
+    options.max_manifest_size = 1 << 10;
+    options
+}
+
+fn read_all(storage: &MiniLsm) -> Model {
+    let mut iter = storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+    let mut contents = Model::new();
+    while iter.is_valid() {
+        contents.insert(iter.key().to_vec(), iter.value().to_vec());
+        iter.next().unwrap();
+    }
+    contents
+}
+
+#[test]
+fn test_fault_injection_file_system() {
+    let fs = FaultInjectionFileSystem::new(Arc::new(MemFileSystem::new()));
+    let dir = Path::new("/db");
+    fs.create_dir_all(dir).unwrap();
+    let mut synced = fs.create(&dir.join("synced")).unwrap();
+    synced.append(b"hello").unwrap();
+    synced.sync().unwrap();
+    fs.sync_dir(dir).unwrap();
+    synced.append(b", world").unwrap();
+    let mut unsynced = fs.create(&dir.join("unsynced")).unwrap();
+    unsynced.append(b"hello").unwrap();
+    unsynced.sync().unwrap();
+
+    // the n-th operation from now on fails, and a torn write writes half of its data
+    fs.fail_nth_write(2);
+    synced.append(b"!").unwrap();
+    assert!(synced.append(b"!").is_err());
+    synced.append(b"!").unwrap();
+    fs.tear_nth_write(1);
+    assert!(synced.append(b"abcd").is_err());
+    fs.fail_nth_sync(1);
+    assert!(fs.sync_dir(dir).is_err());
+    assert_eq!(fs.read(&dir.join("synced")).unwrap(), b"hello, world!!ab");
+
+    // a crash drops the unsynced data and the files not synced in their directory
+    fs.deactivate();
+    assert!(synced.append(b"!").is_err());
+    assert!(fs.read(&dir.join("synced")).is_err());
+    drop((synced, unsynced));
+    fs.drop_unsynced_data().unwrap();
+    fs.reset();
+    assert_eq!(fs.read(&dir.join("synced")).unwrap(), b"hello");
+    assert!(!fs.exists(&dir.join("unsynced")));
+}
+
+#[test]
+fn test_fault_injection_directory_operations() {
+    let fs = FaultInjectionFileSystem::new(Arc::new(MemFileSystem::new()));
+    let dir = Path::new("/db");
+    fs.create_dir_all(dir).unwrap();
+    for name in ["a", "b", "c"] {
+        let mut file = fs.create(&dir.join(name)).unwrap();
+        file.append(name.as_bytes()).unwrap();
+        file.sync().unwrap();
+    }
+    fs.sync_dir(dir).unwrap();
+
+    // renames and removals are lost unless the directory is synced
+    let mut tmp = fs.create(&dir.join("tmp")).unwrap();
+    tmp.append(b"new").unwrap();
+    tmp.sync().unwrap();
+    fs.rename(&dir.join("tmp"), &dir.join("a")).unwrap();
+    fs.rename(&dir.join("b"), &dir.join("d")).unwrap();
+    fs.remove(&dir.join("c")).unwrap();
+    fs.deactivate();
+    fs.drop_unsynced_data().unwrap();
+    fs.reset();
+    let mut paths = fs.list_dir(dir).unwrap();
+    paths.sort();
+    assert_eq!(paths, vec![dir.join("a"), dir.join("b"), dir.join("c")]);
+    for name in ["a", "b", "c"] {
+        assert_eq!(fs.read(&dir.join(name)).unwrap(), name.as_bytes());
+    }
+
+    // and durable once it is
+    fs.rename(&dir.join("b"), &dir.join("d")).unwrap();
+    fs.remove(&dir.join("c")).unwrap();
+    fs.sync_dir(dir).unwrap();
+    fs.drop_unsynced_data().unwrap();
+    let mut paths = fs.list_dir(dir).unwrap();
+    paths.sort();
+    assert_eq!(paths, vec![dir.join("a"), dir.join("d")]);
+    assert_eq!(fs.read(&dir.join("d")).unwrap(), b"b");
+}
+
+/// Run random operations and crash, possibly after an injected failure. Returns the contents after
+/// each operation since the last one known to be durable, one of which must be recovered.
+fn run_until_crash(
+    rng: &mut StdRng,
+    fs: &FaultInjectionFileSystem,
+    dir: &Path,
+    model: &mut Model,
+    version: &mut usize,
+) -> Vec<Model> {
+    let storage = Arc::new(LsmStorageInner::open(dir, options(fs)).unwrap());
+    match rng.gen_range(0..4) {
+        0 => fs.fail_nth_write(rng.gen_range(1..400)),
+        1 => fs.tear_nth_write(rng.gen_range(1..400)),
+        2 => fs.fail_nth_sync(rng.gen_range(1..40)),
+        _ => {}
+    }
+    let mut candidates = vec![model.clone()];
+    for _ in 0..rng.gen_range(1..300) {
+        *version += 1;
+        let idx = rng.gen_range(0..64);
+        let (result, durable): (Result<()>, bool) = match rng.gen_range(0..20) {
+            0..=11 => {
+                model.insert(key_of(idx), value_of(idx, *version));
+                (storage.put(&key_of(idx), &value_of(idx, *version)), false)
+            }
+            12..=14 => {
+                model.remove(&key_of(idx));
+                (storage.delete(&key_of(idx)), false)
+            }
+            15..=16 => (storage.sync(), true),
+            17..=18 => {
+                // freezing a memtable syncs its WAL
+                let result = storage.force_freeze_memtable(&storage.state_lock.lock());
+                (
+                    result.and_then(|_| storage.force_flush_next_imm_memtable()),
+                    true,
+                )
+            }
+            _ => (storage.trigger_compaction(), false),
+        };
+        candidates.push(model.clone());
+        if result.is_err() {
+            break;
+        }
+        if durable {
+            candidates = vec![model.clone()];
+        }
+    }
+    fs.deactivate();
+    drop(storage);
+    // a crash of the process keeps the unsynced data, while a power loss drops it
+    if rng.gen_bool(0.5) {
+        fs.drop_unsynced_data().unwrap();
+    }
+    fs.reset();
+    candidates
+}
+
+#[test]
+fn test_crash_recovery() {
+    for seed in 0..24 {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let fs = FaultInjectionFileSystem::new(Arc::new(MemFileSystem::new()));
+        let dir = Path::new("/db");
+        let mut model = Model::new();
+        let mut version = 0;
+        for round in 0..4 {
+            let candidates = run_until_crash(&mut rng, &fs, dir, &mut model, &mut version);
+            let storage = MiniLsm::open(dir, options(&fs)).unwrap();
+            let recovered = read_all(&storage);
+            assert!(
+                candidates.contains(&recovered),
+                "seed {} round {}: recovered {} keys, expected one of {} states, the last with {} keys",
+                seed,
+                round,
+                recovered.len(),
+                candidates.len(),
+                candidates.last().unwrap().len()
+            );
+            storage.close().unwrap();
+            model = recovered;
+        }
+    }
+}
@@ -0,0 +1,125 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::ops::Bound;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::compact::{CompactionOptions, SimpleLeveledCompactionOptions};
+use crate::iterators::StorageIterator;
+use crate::lsm_storage::{LsmStorageOptions, MiniLsm};
+
+fn key_of(idx: usize) -> Vec<u8> {
+    format!("key_{:05}", idx).into_bytes()
+}
+
+fn value_of(idx: usize, round: usize) -> Vec<u8> {
+    format!("value_{:05}_{}", idx, round).into_bytes()
+}
+
+#[test]
+fn test_purge_orphan_files_on_open() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::Simple(
+        SimpleLeveledCompactionOptions {
+            size_ratio_percent: 200,
+            level0_file_num_compaction_trigger: 2,
+            max_levels: 3,
+        },
+    ));
+    options.enable_wal = true;
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    for round in 0..4 {
+        for idx in 0..100 {
+            storage.put(&key_of(idx), &value_of(idx, round)).unwrap();
+        }
+        storage.force_flush().unwrap();
+        storage.inner.trigger_compaction().unwrap();
+    }
+    let live_sst = *storage.inner.state.read().sstables.keys().next().unwrap();
+    storage.close().unwrap();
+    drop(storage);
+
+    // files left behind by crashes, e.g., between recording a compaction and deleting its inputs
+    let sst = std::fs::read(dir.path().join(format!("{:05}.sst", live_sst))).unwrap();
+    let orphans = [
+        "00900.sst",
+        "00901.wal",
+        "00902.vlog",
+        "MANIFEST-00900",
+        "CURRENT.tmp",
+    ];
+    for orphan in orphans {
+        std::fs::write(dir.path().join(orphan), &sst).unwrap();
+    }
+    // files the storage engine does not know of are left alone
+    std::fs::write(dir.path().join("notes.txt"), b"hello").unwrap();
+
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    for orphan in orphans {
+        assert!(!dir.path().join(orphan).exists(), "{}", orphan);
+    }
+    assert!(dir.path().join("notes.txt").exists());
+    assert!(dir.path().join(format!("{:05}.sst", live_sst)).exists());
+    for idx in 0..100 {
+        assert_eq!(
+            storage.get(&key_of(idx)).unwrap(),
+            Some(Bytes::from(value_of(idx, 3)))
+        );
+    }
+    storage.close().unwrap();
+}
+
+#[test]
+fn test_pinned_files() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    for round in 0..3 {
+        for idx in 0..100 {
+            storage.put(&key_of(idx), &value_of(idx, round)).unwrap();
+        }
+        storage.force_flush().unwrap();
+    }
+    let inputs = storage.inner.state.read().l0_sstables.clone();
+    let sst_path = |id: usize| dir.path().join(format!("{:05}.sst", id));
+
+    // an iterator keeps the inputs of a compaction until it is dropped
+    let mut iter = storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+    storage.force_full_compaction().unwrap();
+    storage.force_file_gc().unwrap();
+    assert!(inputs.iter().all(|x| sst_path(*x).exists()));
+    let mut num_keys = 0;
+    while iter.is_valid() {
+        assert_eq!(iter.value(), value_of(num_keys, 2));
+        num_keys += 1;
+        iter.next().unwrap();
+    }
+    assert_eq!(num_keys, 100);
+    drop(iter);
+    storage.force_file_gc().unwrap();
+    assert!(inputs.iter().all(|x| !sst_path(*x).exists()));
+
+    // the outputs of a compaction in progress are not in the manifest yet
+    let pending_outputs = storage.inner.pending_outputs();
+    let output = sst_path(storage.inner.next_sst_id());
+    std::fs::write(&output, b"output").unwrap();
+    storage.force_file_gc().unwrap();
+    assert!(output.exists());
+    drop(pending_outputs);
+    storage.force_file_gc().unwrap();
+    assert!(!output.exists());
+    storage.close().unwrap();
+}
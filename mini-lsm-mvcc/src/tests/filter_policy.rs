@@ -0,0 +1,108 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::Arc;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::compact::CompactionOptions;
+use crate::lsm_storage::{LsmStorageOptions, MiniLsm};
+use crate::table::{
+    BlockedBloomFilterPolicy, BloomFilterPolicy, FileObject, Filter, FilterKind, FilterPolicy,
+    RibbonFilterPolicy, SsTable,
+};
+
+fn key_of(idx: usize) -> Vec<u8> {
+    format!("key_{:010}", idx * 5).into_bytes()
+}
+
+fn num_of_keys() -> usize {
+    10000
+}
+
+#[test]
+fn test_filter_policies() {
+    let key_hashes = (0..num_of_keys())
+        .map(|idx| farmhash::fingerprint32(&key_of(idx)))
+        .collect::<Vec<_>>();
+    let policies: [(Box<dyn FilterPolicy>, FilterKind, f64); 3] = [
+        (Box::new(BloomFilterPolicy(10)), FilterKind::Bloom, 0.02),
+        (
+            Box::new(BlockedBloomFilterPolicy(10)),
+            FilterKind::BlockedBloom,
+            0.03,
+        ),
+        (Box::new(RibbonFilterPolicy(10)), FilterKind::Ribbon, 0.01),
+    ];
+    for (policy, kind, max_fpr) in policies {
+        let filter = policy.build(&key_hashes);
+        let mut buf = Vec::new();
+        filter.encode(&mut buf);
+        let filter = Filter::decode(&buf).unwrap();
+        assert_eq!(filter.kind, kind);
+        // about the requested bits per key
+        assert!(filter.filter.len() * 8 < num_of_keys() * 12);
+        for h in &key_hashes {
+            assert!(filter.may_contain(*h), "false negative in {:?}", kind);
+        }
+        let false_positives = (num_of_keys()..num_of_keys() * 2)
+            .filter(|idx| filter.may_contain(farmhash::fingerprint32(&key_of(*idx))))
+            .count();
+        let fpr = false_positives as f64 / num_of_keys() as f64;
+        assert!(fpr < max_fpr, "false positive rate of {:?}: {}", kind, fpr);
+    }
+}
+
+#[test]
+fn test_filter_policy_in_storage() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.filter_policy = Some(Arc::new(RibbonFilterPolicy(10)));
+    options.skip_bottom_level_filters = true;
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    for idx in 0..1000 {
+        storage.put(&key_of(idx), b"value").unwrap();
+    }
+    storage.force_flush().unwrap();
+    let sst_id = storage.inner.state.read().l0_sstables[0];
+    // the filter kind is read back from the SST
+    let sst = SsTable::open(
+        sst_id,
+        None,
+        FileObject::open(&storage.inner.path_of_sst(sst_id)).unwrap(),
+    )
+    .unwrap();
+    assert_eq!(sst.bloom.as_ref().unwrap().kind, FilterKind::Ribbon);
+    assert_eq!(
+        storage.get(&key_of(42)).unwrap(),
+        Some(Bytes::from("value"))
+    );
+    assert_eq!(storage.get(b"key_absent").unwrap(), None);
+
+    // the full compaction writes to the bottom level, whose SSTs have no filters
+    storage.force_full_compaction().unwrap();
+    let snapshot = storage.inner.state.read().clone();
+    assert!(!snapshot.levels[0].1.is_empty());
+    for sst_id in &snapshot.levels[0].1 {
+        assert!(snapshot.sstables[sst_id].bloom.is_none());
+    }
+    for idx in 0..1000 {
+        assert_eq!(
+            storage.get(&key_of(idx)).unwrap(),
+            Some(Bytes::from("value"))
+        );
+    }
+    assert_eq!(storage.get(b"key_absent").unwrap(), None);
+}
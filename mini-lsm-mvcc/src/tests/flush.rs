@@ -0,0 +1,81 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::compact::CompactionOptions;
+use crate::lsm_storage::{LsmStorageOptions, MiniLsm};
+
+fn key_of(idx: usize) -> Vec<u8> {
+    format!("key_{:03}", idx).into_bytes()
+}
+
+fn freeze(storage: &MiniLsm) {
+    storage
+        .inner
+        .force_freeze_memtable(&storage.inner.state_lock.lock())
+        .unwrap();
+}
+
+#[test]
+fn test_flush_without_imm_memtables() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    storage.put(&key_of(0), b"value").unwrap();
+    freeze(&storage);
+    storage.inner.force_flush_next_imm_memtable().unwrap();
+    // the memtable was flushed by another caller, so there is nothing left to flush
+    storage.inner.force_flush_next_imm_memtable().unwrap();
+    assert_eq!(storage.inner.state.read().l0_sstables.len(), 1);
+    assert_eq!(
+        storage.get(&key_of(0)).unwrap(),
+        Some(Bytes::from_static(b"value"))
+    );
+}
+
+#[test]
+fn test_concurrent_flush() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    // only the tests flush memtables
+    options.num_memtable_limit = 1000;
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    for idx in 0..8 {
+        storage.put(&key_of(idx), b"value").unwrap();
+        freeze(&storage);
+    }
+    // more flushes than immutable memtables race for them
+    std::thread::scope(|s| {
+        for _ in 0..4 {
+            s.spawn(|| {
+                for _ in 0..4 {
+                    storage.inner.force_flush_next_imm_memtable().unwrap();
+                }
+            });
+        }
+    });
+    {
+        let state = storage.inner.state.read();
+        assert!(state.imm_memtables.is_empty());
+        assert_eq!(state.l0_sstables.len(), 8);
+    }
+    for idx in 0..8 {
+        assert_eq!(
+            storage.get(&key_of(idx)).unwrap(),
+            Some(Bytes::from_static(b"value"))
+        );
+    }
+}
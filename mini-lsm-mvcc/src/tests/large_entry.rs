@@ -0,0 +1,69 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::Arc;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::block::{Block, BlockBuilder, BlockIterator};
+use crate::compact::CompactionOptions;
+use crate::key::KeySlice;
+use crate::lsm_storage::{LsmStorageOptions, MiniLsm};
+
+fn large_value(idx: usize, size: usize) -> Vec<u8> {
+    (0..size).map(|i| ((i + idx) % 251) as u8).collect()
+}
+
+#[test]
+fn test_block_large_entry() {
+    let key = vec![b'k'; 70000];
+    let value = large_value(0, 200000);
+    let mut builder = BlockBuilder::new(4096);
+    // the first entry is always accepted even if it is larger than the block size
+    assert!(builder.add(KeySlice::for_testing_from_slice_with_ts(&key, 1), &value));
+    assert!(!builder.add(KeySlice::for_testing_from_slice_with_ts(b"z", 1), b"v"));
+    let block = Arc::new(Block::decode(&builder.build().encode()));
+    let iter = BlockIterator::create_and_seek_to_first(block);
+    assert!(iter.is_valid());
+    assert_eq!(iter.key().for_testing_key_ref(), &key[..]);
+    assert_eq!(iter.value(), &value[..]);
+}
+
+#[test]
+fn test_large_entry_wal_and_sst() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.enable_wal = true;
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    for idx in 0..3 {
+        storage
+            .put(
+                format!("key{}", idx).as_bytes(),
+                &large_value(idx, 100000 + idx),
+            )
+            .unwrap();
+    }
+    storage.force_flush().unwrap();
+    storage.put(b"key3", &large_value(3, 100003)).unwrap();
+    storage.close().unwrap();
+    // key3 only lives in the WAL when the engine is reopened
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    for idx in 0..4 {
+        assert_eq!(
+            storage.get(format!("key{}", idx).as_bytes()).unwrap(),
+            Some(Bytes::from(large_value(idx, 100000 + idx)))
+        );
+    }
+}
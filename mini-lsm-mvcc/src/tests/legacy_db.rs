@@ -0,0 +1,131 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::BTreeMap;
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
+
+use bytes::Bytes;
+use tempfile::{TempDir, tempdir};
+
+use crate::compact::CompactionOptions;
+use crate::env::DiskFileSystem;
+use crate::key::KeySlice;
+use crate::lsm_storage::{LsmStorageOptions, MiniLsm};
+use crate::mem_table::{MemTableRep, SkipList};
+use crate::wal::{Wal, decode_batches};
+
+use super::harness::check_lsm_iter_result_by_key;
+
+/// A DB written by the storage engine before the format changes: two SSTs without a footer, a
+/// JSON manifest, and the WALs of an immutable and a mutable memtable without a header.
+fn fixture_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("src/tests/fixtures/baseline_db")
+}
+
+fn copy_fixture() -> TempDir {
+    let dir = tempdir().unwrap();
+    for entry in std::fs::read_dir(fixture_dir()).unwrap() {
+        let path = entry.unwrap().path();
+        std::fs::copy(&path, dir.path().join(path.file_name().unwrap())).unwrap();
+    }
+    dir
+}
+
+fn key_of(idx: usize) -> Bytes {
+    Bytes::from(format!("key_{:05}", idx))
+}
+
+/// The contents of the fixture DB.
+fn expected() -> Vec<(Bytes, Bytes)> {
+    (0..230)
+        .filter_map(|idx| {
+            let value = match idx {
+                1 | 3 => format!("value_{}_v3", idx),
+                201 => return None,
+                0..=199 if idx % 5 == 0 => return None,
+                0..=199 if idx % 2 == 0 => format!("value_{}_v2", idx),
+                _ => format!("value_{}_v1", idx),
+            };
+            Some((key_of(idx), Bytes::from(value)))
+        })
+        .collect()
+}
+
+fn options() -> LsmStorageOptions {
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.enable_wal = true;
+    options
+}
+
+fn check(storage: &MiniLsm, expected: Vec<(Bytes, Bytes)>) {
+    check_lsm_iter_result_by_key(
+        &mut storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap(),
+        expected,
+    );
+}
+
+#[test]
+fn test_open_legacy_db() {
+    let dir = copy_fixture();
+    let storage = MiniLsm::open(&dir, options()).unwrap();
+    check(&storage, expected());
+    assert_eq!(storage.get(&key_of(42)).unwrap().unwrap(), "value_42_v2");
+    assert_eq!(storage.get(&key_of(40)).unwrap(), None);
+
+    // new writes go to WALs with a header, and compactions rewrite the legacy SSTs
+    storage.put(&key_of(40), b"value_40_v4").unwrap();
+    storage.force_flush().unwrap();
+    storage.force_full_compaction().unwrap();
+    storage.put(&key_of(41), b"value_41_v4").unwrap();
+    storage.close().unwrap();
+    drop(storage);
+
+    let storage = MiniLsm::open(&dir, options()).unwrap();
+    let mut expected = expected().into_iter().collect::<BTreeMap<_, _>>();
+    expected.insert(key_of(40), Bytes::from("value_40_v4"));
+    expected.insert(key_of(41), Bytes::from("value_41_v4"));
+    check(&storage, expected.into_iter().collect());
+    let state = storage.inner.state.read().clone();
+    for sst in state.sstables.values() {
+        assert_ne!(sst.format_version(), 0);
+    }
+}
+
+#[test]
+fn test_legacy_wal() {
+    let path = fixture_dir().join("00004.wal");
+    let (batches, corruption) = decode_batches(&std::fs::read(&path).unwrap());
+    assert!(corruption.is_none());
+    assert_eq!(batches.len(), 12);
+    assert_eq!(batches[0].offset, 0);
+    assert_eq!(batches[10].entries[0].key, key_of(3));
+    assert_eq!(batches[10].entries[0].value, "value_3_v3");
+    // a deletion
+    assert_eq!(batches[11].entries[0].key, key_of(201));
+    assert!(batches[11].entries[0].value.is_empty());
+
+    // legacy WALs are recovered, but not appended to
+    let dir = copy_fixture();
+    let skiplist = SkipList::new();
+    let wal = Wal::recover(
+        &DiskFileSystem,
+        dir.path().join("00004.wal"),
+        &skiplist,
+        &mut Vec::new(),
+    )
+    .unwrap();
+    assert_eq!(skiplist.len(), 12);
+    assert!(wal.put(KeySlice::from_slice(b"key", 1), b"value").is_err());
+}
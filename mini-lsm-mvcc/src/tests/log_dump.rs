@@ -0,0 +1,247 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use tempfile::tempdir;
+
+use crate::compact::{CompactionOptions, SimpleLeveledCompactionOptions};
+use crate::env::DiskFileSystem;
+use crate::key::KeySlice;
+use crate::lsm_storage::{LsmStorageOptions, MiniLsm};
+use crate::manifest::ManifestRecord;
+use crate::mem_table::{MemTableRep, SkipList};
+use crate::range_tombstone::RangeTombstone;
+use crate::tools::Encoding;
+use crate::tools::manifest_dump::{CompactionStrategy, ManifestDumpOptions, manifest_dump};
+use crate::tools::wal_dump::{WalDumpOptions, wal_dump};
+use crate::wal::Wal;
+
+fn key_of(idx: usize) -> Vec<u8> {
+    format!("key_{:05}", idx).into_bytes()
+}
+
+fn value_of(idx: usize) -> Vec<u8> {
+    format!("value_{:05}", idx).into_bytes()
+}
+
+#[test]
+fn test_manifest_dump() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::Simple(
+        SimpleLeveledCompactionOptions {
+            size_ratio_percent: 200,
+            level0_file_num_compaction_trigger: 2,
+            max_levels: 3,
+        },
+    ));
+    options.enable_wal = true;
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    for round in 0..6 {
+        for idx in 0..100 {
+            storage
+                .put(&key_of(idx), &value_of(round * 100 + idx))
+                .unwrap();
+        }
+        storage.force_flush().unwrap();
+        storage.inner.trigger_compaction().unwrap();
+    }
+    storage.close().unwrap();
+    let state = storage.inner.state.read().clone();
+    let manifest_path = dir.path().join("MANIFEST-00001");
+
+    let options = ManifestDumpOptions {
+        replay: true,
+        compaction: None,
+    };
+    let report = manifest_dump(&manifest_path, &options).unwrap();
+    assert!(report.corruption.is_none());
+    // version edits replay the same for leveled and simple leveled compaction
+    assert_eq!(report.compaction, Some(CompactionStrategy::Leveled));
+    assert!(report.records.iter().any(
+        |x| matches!(&x.record, ManifestRecord::Edit(edit) if !edit.deleted_files.is_empty())
+    ));
+    let shape = report.records.last().unwrap().shape.as_ref().unwrap();
+    assert_eq!(shape.l0_sstables, state.l0_sstables);
+    // the levels below the lowest compacted one are unknown to the manifest
+    let num_levels = shape.levels.len();
+    assert_eq!(shape.levels[..], state.levels[..num_levels]);
+    assert!(state.levels[num_levels..].iter().all(|x| x.1.is_empty()));
+    assert!(shape.memtables.contains(&state.memtable.id()));
+    for (prev, record) in report.records.iter().zip(report.records.iter().skip(1)) {
+        assert_eq!(prev.offset + prev.len, record.offset);
+    }
+    serde_json::to_string(&report).unwrap();
+
+    // the torn last record is reported and dropped by recovery
+    let data = std::fs::read(&manifest_path).unwrap();
+    let last = report.records.last().unwrap();
+    std::fs::write(&manifest_path, &data[..data.len() - 3]).unwrap();
+    let report = manifest_dump(&manifest_path, &options).unwrap();
+    let corruption = report.corruption.as_ref().unwrap();
+    assert_eq!(corruption.offset, last.offset);
+    assert!(corruption.torn_tail);
+
+    // a record corrupted in the middle fails recovery, which tells where
+    let mut corrupted = data.clone();
+    let second = &report.records[1];
+    corrupted[(second.offset + 10) as usize] ^= 0xff;
+    std::fs::write(&manifest_path, &corrupted).unwrap();
+    let report = manifest_dump(&manifest_path, &options).unwrap();
+    assert_eq!(report.records.len(), 1);
+    let corruption = report.corruption.as_ref().unwrap();
+    assert_eq!((corruption.idx, corruption.offset), (1, second.offset));
+    assert!(!corruption.torn_tail);
+    assert!(corruption.reason.contains("checksum mismatch"));
+    let error = MiniLsm::open(&dir, storage.inner.options.as_ref().clone())
+        .err()
+        .unwrap();
+    assert!(
+        format!("{:#}", error).contains(&format!("offset {}", second.offset)),
+        "{:#}",
+        error
+    );
+
+    // replaying with the wrong compaction strategy reports the record that fails
+    std::fs::write(&manifest_path, &data).unwrap();
+    let report = manifest_dump(
+        &manifest_path,
+        &ManifestDumpOptions {
+            replay: true,
+            compaction: Some(CompactionStrategy::Tiered),
+        },
+    )
+    .unwrap();
+    let failed = report
+        .records
+        .iter()
+        .find(|x| x.replay_error.is_some())
+        .unwrap();
+    assert!(matches!(failed.record, ManifestRecord::Edit(..)));
+    assert!(failed.replay_error.as_ref().unwrap().contains("tier"));
+}
+
+#[test]
+fn test_wal_dump() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("1.wal");
+    let wal = Wal::create(&DiskFileSystem, &path).unwrap();
+    for idx in 0..10 {
+        let key = key_of(idx);
+        let value = value_of(idx);
+        wal.put(
+            KeySlice::for_testing_from_slice_with_ts(&key, idx as u64 + 1),
+            &value,
+        )
+        .unwrap();
+    }
+    wal.write_batch(
+        &[(KeySlice::for_testing_from_slice_with_ts(b"\xff", 11), b"")],
+        &[RangeTombstone {
+            start: key_of(2).into(),
+            end: key_of(5).into(),
+            ts: 11,
+        }],
+    )
+    .unwrap();
+    wal.sync().unwrap();
+    drop(wal);
+
+    let options = WalDumpOptions {
+        limit: None,
+        encoding: Encoding::Utf8,
+    };
+    let report = wal_dump(&path, &options).unwrap();
+    assert!(report.corruption.is_none());
+    assert_eq!((report.num_batches, report.num_entries), (11, 12));
+    assert_eq!(report.batches[3].entries[0].key, "key_00003");
+    assert_eq!(report.batches[3].entries[0].ts, 4);
+    assert_eq!(report.batches[3].entries[0].value, "value_00003");
+    let last = &report.batches[10];
+    assert_eq!(last.entries[0].key, "\\xff");
+    assert!(last.entries[1].range_tombstone);
+    assert_eq!(last.entries[1].value, "key_00005");
+    serde_json::to_string(&report).unwrap();
+    let report = wal_dump(
+        &path,
+        &WalDumpOptions {
+            limit: Some(2),
+            encoding: Encoding::Hex,
+        },
+    )
+    .unwrap();
+    assert_eq!((report.batches.len(), report.num_batches), (2, 11));
+    assert_eq!(report.batches[1].entries[0].key, "6b65795f3030303031");
+
+    // a torn last batch is reported and dropped by recovery
+    let data = std::fs::read(&path).unwrap();
+    std::fs::write(&path, &data[..data.len() - 1]).unwrap();
+    let report = wal_dump(&path, &options).unwrap();
+    let corruption = report.corruption.unwrap();
+    assert_eq!((corruption.idx, corruption.offset), (10, last.offset));
+    assert!(corruption.torn_tail);
+    let skiplist = SkipList::new();
+    Wal::recover(&DiskFileSystem, &path, &skiplist, &mut Vec::new()).unwrap();
+    assert_eq!(skiplist.len(), 10);
+
+    // a batch corrupted in the middle fails recovery, which tells where
+    let mut corrupted = data.clone();
+    let offset = report.batches[4].offset;
+    corrupted[offset as usize + 6] ^= 0xff;
+    std::fs::write(&path, &corrupted).unwrap();
+    let report = wal_dump(&path, &options).unwrap();
+    assert_eq!(report.num_batches, 4);
+    let corruption = report.corruption.unwrap();
+    assert_eq!((corruption.idx, corruption.offset), (4, offset));
+    assert!(!corruption.torn_tail);
+    let error = Wal::recover(&DiskFileSystem, &path, &SkipList::new(), &mut Vec::new())
+        .err()
+        .unwrap();
+    assert!(
+        error.to_string().contains(&format!("offset {}", offset)),
+        "{}",
+        error
+    );
+}
+
+#[test]
+fn test_wal_dump_malformed_batch() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("1.wal");
+    let wal = Wal::create(&DiskFileSystem, &path).unwrap();
+    wal.put(KeySlice::for_testing_from_slice_with_ts(b"a", 1), b"1")
+        .unwrap();
+    wal.sync().unwrap();
+    drop(wal);
+    let data = std::fs::read(&path).unwrap();
+
+    // batches with valid checksums whose key or value length is a truncated varint
+    let mut value_truncated = vec![1, b'b'];
+    value_truncated.extend_from_slice(&2u64.to_be_bytes());
+    value_truncated.push(0x80);
+    for (batch, reason) in [
+        (vec![0x80], "invalid key length"),
+        (value_truncated, "invalid value length"),
+    ] {
+        let mut corrupted = data.clone();
+        corrupted.extend_from_slice(&(batch.len() as u32).to_be_bytes());
+        corrupted.extend_from_slice(&batch);
+        corrupted.extend_from_slice(&crc32fast::hash(&batch).to_be_bytes());
+        std::fs::write(&path, &corrupted).unwrap();
+        let report = wal_dump(&path, &WalDumpOptions::default()).unwrap();
+        assert_eq!(report.num_batches, 1);
+        let corruption = report.corruption.unwrap();
+        assert_eq!(corruption.offset, data.len() as u64);
+        assert!(corruption.reason.contains(reason), "{}", corruption.reason);
+        assert!(Wal::recover(&DiskFileSystem, &path, &SkipList::new(), &mut Vec::new()).is_err());
+    }
+}
@@ -0,0 +1,172 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::path::{Path, PathBuf};
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::compact::{CompactionOptions, SimpleLeveledCompactionOptions};
+use crate::env::DiskFileSystem;
+use crate::lsm_storage::{LsmStorageOptions, MiniLsm};
+use crate::manifest::{ManifestRecord, current_manifest_path, decode_records};
+
+fn key_of(idx: usize) -> Vec<u8> {
+    format!("key_{:05}", idx).into_bytes()
+}
+
+fn value_of(idx: usize, round: usize) -> Vec<u8> {
+    format!("value_{:05}_{}", idx, round)
+        .repeat(idx % 4 + 1)
+        .into_bytes()
+}
+
+fn options() -> LsmStorageOptions {
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::Simple(
+        SimpleLeveledCompactionOptions {
+            size_ratio_percent: 200,
+            level0_file_num_compaction_trigger: 2,
+            max_levels: 3,
+        },
+    ));
+    options.enable_wal = true;
+    options.value_log_threshold = Some(32);
+    options
+}
+
+fn manifest_files(dir: &Path) -> Vec<PathBuf> {
+    let mut files = std::fs::read_dir(dir)
+        .unwrap()
+        .map(|x| x.unwrap().path())
+        .filter(|x| {
+            x.file_name()
+                .unwrap()
+                .to_string_lossy()
+                .starts_with("MANIFEST")
+        })
+        .collect::<Vec<_>>();
+    files.sort();
+    files
+}
+
+fn check(storage: &MiniLsm, rounds: usize) {
+    for idx in 0..100 {
+        let expected = (idx != rounds - 1).then(|| Bytes::from(value_of(idx, rounds - 1)));
+        assert_eq!(storage.get(&key_of(idx)).unwrap(), expected, "key {}", idx);
+    }
+}
+
+#[test]
+fn test_manifest_rollover() {
+    let dir = tempdir().unwrap();
+    let mut options = options();
+    options.max_manifest_size = 1 << 10;
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    for round in 0..10 {
+        for idx in 0..100 {
+            storage.put(&key_of(idx), &value_of(idx, round)).unwrap();
+        }
+        storage.delete(&key_of(round)).unwrap();
+        storage.force_flush().unwrap();
+        storage.inner.trigger_compaction().unwrap();
+    }
+    let state = storage.inner.state.read().clone();
+    storage.close().unwrap();
+    drop(storage);
+
+    // only the current manifest is left, which starts with a snapshot
+    let manifest_path = current_manifest_path(&DiskFileSystem, dir.path())
+        .unwrap()
+        .unwrap();
+    assert_eq!(manifest_files(dir.path()), vec![manifest_path.clone()]);
+    assert_ne!(manifest_path, dir.path().join("MANIFEST-00001"));
+    let (entries, corruption) = decode_records(&std::fs::read(&manifest_path).unwrap());
+    assert!(corruption.is_none());
+    let ManifestRecord::Edit(snapshot) = &entries[0].record else {
+        panic!("manifest starts with {:?}", entries[0].record);
+    };
+    assert!(entries.len() < 20);
+    assert!(snapshot.next_sst_id.is_some());
+    assert!(!snapshot.new_memtables.is_empty());
+
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    {
+        let recovered = storage.inner.state.read();
+        assert_eq!(recovered.l0_sstables, state.l0_sstables);
+        assert_eq!(recovered.levels, state.levels);
+    }
+    check(&storage, 10);
+    storage.close().unwrap();
+    drop(storage);
+
+    // a large limit keeps appending to the current manifest
+    options.max_manifest_size = 1 << 20;
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    storage.put(&key_of(0), &value_of(0, 10)).unwrap();
+    storage.force_flush().unwrap();
+    storage.close().unwrap();
+    drop(storage);
+    assert_eq!(manifest_files(dir.path()), vec![manifest_path]);
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    assert_eq!(
+        storage.get(&key_of(0)).unwrap(),
+        Some(Bytes::from(value_of(0, 10)))
+    );
+    storage.close().unwrap();
+}
+
+#[test]
+fn test_legacy_manifest() {
+    let dir = tempdir().unwrap();
+    let mut options = options();
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    for round in 0..3 {
+        for idx in 0..100 {
+            storage.put(&key_of(idx), &value_of(idx, round)).unwrap();
+        }
+        storage.delete(&key_of(round)).unwrap();
+        storage.force_flush().unwrap();
+    }
+    storage.close().unwrap();
+    drop(storage);
+
+    // a storage engine created before the manifest could roll over has no CURRENT
+    std::fs::remove_file(dir.path().join("CURRENT")).unwrap();
+    std::fs::rename(
+        dir.path().join("MANIFEST-00001"),
+        dir.path().join("MANIFEST"),
+    )
+    .unwrap();
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    check(&storage, 3);
+    storage.close().unwrap();
+    drop(storage);
+    assert_eq!(
+        manifest_files(dir.path()),
+        vec![dir.path().join("MANIFEST")]
+    );
+
+    // it is replaced once it rolls over
+    options.max_manifest_size = 0;
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    storage.close().unwrap();
+    drop(storage);
+    assert_eq!(
+        manifest_files(dir.path()),
+        vec![dir.path().join("MANIFEST-00001")]
+    );
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    check(&storage, 3);
+    storage.close().unwrap();
+}
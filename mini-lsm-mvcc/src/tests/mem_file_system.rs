@@ -0,0 +1,122 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::path::Path;
+use std::sync::Arc;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::compact::{CompactionOptions, SimpleLeveledCompactionOptions};
+use crate::env::{FileSystem, MemFileSystem, ReadBackend};
+use crate::lsm_storage::{LsmStorageOptions, MiniLsm};
+
+fn key_of(idx: usize) -> Vec<u8> {
+    format!("key_{:05}", idx).into_bytes()
+}
+
+fn value_of(idx: usize) -> Vec<u8> {
+    format!("value_{:05}", idx).repeat(idx % 8 + 1).into_bytes()
+}
+
+#[test]
+fn test_mem_file_system() {
+    let fs = MemFileSystem::new();
+    let dir = Path::new("/db");
+    assert!(fs.create(&dir.join("1.sst")).is_err());
+    fs.create_dir_all(dir).unwrap();
+    assert!(fs.exists(Path::new("/")));
+
+    let mut file = fs.create(&dir.join("1.sst")).unwrap();
+    file.append(b"hello, ").unwrap();
+    file.append(b"world").unwrap();
+    file.sync().unwrap();
+    let reader = fs.open(&dir.join("1.sst"), ReadBackend::Mmap).unwrap();
+    assert_eq!(reader.size(), 12);
+    assert_eq!(reader.read_at(7, 5).unwrap(), b"world"[..]);
+    assert!(reader.read_at(7, 6).is_err());
+
+    // appends are visible to readers opened later
+    let mut file = fs.open_append(&dir.join("1.sst")).unwrap();
+    file.append(b"!").unwrap();
+    assert_eq!(fs.read(&dir.join("1.sst")).unwrap(), b"hello, world!");
+    // creating a file truncates it
+    fs.create(&dir.join("1.sst")).unwrap();
+    assert!(fs.read(&dir.join("1.sst")).unwrap().is_empty());
+
+    fs.rename(&dir.join("1.sst"), &dir.join("2.sst")).unwrap();
+    assert!(!fs.exists(&dir.join("1.sst")));
+    fs.create_dir_all(&dir.join("sub")).unwrap();
+    let mut paths = fs.list_dir(dir).unwrap();
+    paths.sort();
+    assert_eq!(paths, vec![dir.join("2.sst"), dir.join("sub")]);
+
+    // open files can still be read after they are removed
+    fs.remove(&dir.join("2.sst")).unwrap();
+    assert_eq!(reader.read_at(0, 5).unwrap(), b"hello"[..]);
+    assert!(fs.remove(&dir.join("2.sst")).is_err());
+    assert!(fs.open(&dir.join("2.sst"), ReadBackend::Pread).is_err());
+}
+
+#[test]
+fn test_storage_on_mem_file_system() {
+    // the storage never touches the directory on disk
+    let disk_dir = tempdir().unwrap();
+    let dir = disk_dir.path().join("db");
+    let fs = MemFileSystem::new();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::Simple(
+        SimpleLeveledCompactionOptions {
+            size_ratio_percent: 200,
+            level0_file_num_compaction_trigger: 2,
+            max_levels: 3,
+        },
+    ));
+    options.block_size = 256;
+    options.target_sst_size = 16 << 10;
+    options.enable_wal = true;
+    options.value_log_threshold = Some(64);
+    options.file_system = Arc::new(fs.clone());
+
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    for idx in 0..2000 {
+        storage.put(&key_of(idx), &value_of(idx)).unwrap();
+    }
+    storage.delete(&key_of(10)).unwrap();
+    storage.force_flush().unwrap();
+    storage.put(&key_of(2000), &value_of(2000)).unwrap();
+    storage.close().unwrap();
+    drop(storage);
+    assert!(!dir.exists());
+    let files = fs.list_dir(&dir).unwrap();
+    assert!(files.contains(&dir.join("CURRENT")));
+    assert!(files.contains(&dir.join("MANIFEST-00001")));
+    assert!(
+        files
+            .iter()
+            .any(|x| x.extension().is_some_and(|x| x == "sst"))
+    );
+    assert!(
+        files
+            .iter()
+            .any(|x| x.extension().is_some_and(|x| x == "wal"))
+    );
+
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    for idx in [0, 10, 11, 1000, 1999, 2000] {
+        let expected = (idx != 10).then(|| Bytes::from(value_of(idx)));
+        assert_eq!(storage.get(&key_of(idx)).unwrap(), expected);
+    }
+    storage.close().unwrap();
+    assert!(!dir.exists());
+}
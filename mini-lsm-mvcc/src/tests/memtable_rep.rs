@@ -0,0 +1,170 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::ops::Bound;
+use std::sync::Arc;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::compact::CompactionOptions;
+use crate::iterators::StorageIterator;
+use crate::key::KeySlice;
+use crate::lsm_storage::{LsmStorageOptions, MiniLsm};
+use crate::mem_table::{MemTable, MemTableRepType};
+use crate::table::FixedPrefixExtractor;
+
+fn rep_types() -> Vec<MemTableRepType> {
+    vec![
+        MemTableRepType::SkipList,
+        MemTableRepType::Vector,
+        MemTableRepType::HashSkipList(Arc::new(FixedPrefixExtractor(4))),
+    ]
+}
+
+fn key_of(idx: usize) -> Vec<u8> {
+    // keys in 5 prefixes, and a few keys too short to have one
+    if idx % 10 == 9 {
+        format!("{}", idx).into_bytes()
+    } else {
+        format!("{:03}:key_{:05}", idx % 5, idx).into_bytes()
+    }
+}
+
+fn collect(memtable: &MemTable, lower: Bound<KeySlice>, upper: Bound<KeySlice>) -> Vec<Vec<u8>> {
+    let mut iter = memtable.scan(lower, upper);
+    let mut keys = Vec::new();
+    while iter.is_valid() {
+        assert_eq!(iter.value(), iter.key().key_ref());
+        keys.push(iter.key().key_ref().to_vec());
+        iter.next().unwrap();
+    }
+    keys
+}
+
+#[test]
+fn test_memtable_reps() {
+    for rep_type in rep_types() {
+        let memtable = MemTable::create_with_rep(0, &rep_type, None);
+        for idx in (0..100).rev() {
+            let key = key_of(idx);
+            memtable.put(KeySlice::from_slice(&key, 1), b"old").unwrap();
+            memtable.put(KeySlice::from_slice(&key, 1), &key).unwrap();
+        }
+        let mut expected = (0..100).map(key_of).collect::<Vec<_>>();
+        expected.sort();
+        let prefix = expected
+            .iter()
+            .filter(|x| x.starts_with(b"002:"))
+            .cloned()
+            .collect::<Vec<_>>();
+
+        // the vector rep is only sorted when frozen
+        for frozen in [false, true] {
+            if frozen {
+                memtable.freeze();
+            }
+            for idx in 0..100 {
+                let key = key_of(idx);
+                assert_eq!(
+                    memtable.get(KeySlice::from_slice(&key, 1)),
+                    Some(Bytes::from(key.clone())),
+                    "{:?}",
+                    rep_type
+                );
+                assert_eq!(memtable.get(KeySlice::from_slice(&key, 2)), None);
+            }
+            assert_eq!(
+                collect(&memtable, Bound::Unbounded, Bound::Unbounded),
+                expected,
+                "{:?}",
+                rep_type
+            );
+            // a scan within a prefix only reads its skiplist in the hash rep
+            assert_eq!(
+                collect(
+                    &memtable,
+                    Bound::Included(KeySlice::from_slice(b"002:", 1)),
+                    Bound::Excluded(KeySlice::from_slice(b"003:", 1))
+                ),
+                prefix
+            );
+            assert_eq!(
+                collect(
+                    &memtable,
+                    Bound::Excluded(KeySlice::from_slice(&prefix[0], 1)),
+                    Bound::Included(KeySlice::from_slice(&prefix[2], 1))
+                ),
+                prefix[1..3]
+            );
+        }
+        assert!(memtable.approximate_size() > 100 * 16);
+        assert!(!memtable.is_empty());
+    }
+}
+
+#[test]
+fn test_memtable_rep_recovery() {
+    for rep_type in rep_types() {
+        let dir = tempdir().unwrap();
+        let mut options =
+            LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+        options.enable_wal = true;
+        options.memtable_rep = rep_type.clone();
+        let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+        for idx in 0..100 {
+            storage.put(&key_of(idx), b"flushed").unwrap();
+        }
+        storage.force_flush().unwrap();
+        for idx in 0..100 {
+            storage.put(&key_of(idx), &key_of(idx)).unwrap();
+        }
+        storage
+            .inner
+            .force_freeze_memtable(&storage.inner.state_lock.lock())
+            .unwrap();
+        for idx in (0..100).step_by(2) {
+            storage.delete(&key_of(idx)).unwrap();
+        }
+        storage.close().unwrap();
+        drop(storage);
+
+        // the memtables are recovered into the rep from their WALs
+        let storage = MiniLsm::open(&dir, options).unwrap();
+        assert!(!storage.inner.state.read().imm_memtables.is_empty());
+        for idx in 0..100 {
+            let expected = (idx % 2 == 1).then(|| Bytes::from(key_of(idx)));
+            assert_eq!(
+                storage.get(&key_of(idx)).unwrap(),
+                expected,
+                "{:?}",
+                rep_type
+            );
+        }
+        let mut iter = storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+        let mut num_keys = 0;
+        while iter.is_valid() {
+            assert_eq!(iter.key(), iter.value());
+            num_keys += 1;
+            iter.next().unwrap();
+        }
+        assert_eq!(num_keys, 50);
+        storage.force_flush().unwrap();
+        assert_eq!(
+            storage.get(&key_of(1)).unwrap(),
+            Some(Bytes::from(key_of(1)))
+        );
+        storage.close().unwrap();
+    }
+}
@@ -0,0 +1,163 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::ops::Bound;
+use std::path::Path;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::compact::CompactionOptions;
+use crate::lsm_storage::{LsmStorageOptions, MiniLsm};
+
+use super::harness::check_lsm_iter_result_by_key;
+
+fn options(max_memtables_per_flush: usize) -> LsmStorageOptions {
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    // only the tests flush memtables
+    options.num_memtable_limit = 1000;
+    options.max_memtables_per_flush = max_memtables_per_flush;
+    options
+}
+
+fn freeze(storage: &MiniLsm) {
+    storage
+        .inner
+        .force_freeze_memtable(&storage.inner.state_lock.lock())
+        .unwrap();
+}
+
+fn imm_memtable_ids(storage: &MiniLsm) -> Vec<usize> {
+    let state = storage.inner.state.read();
+    state.imm_memtables.iter().map(|x| x.id()).collect()
+}
+
+fn file_exists(dir: impl AsRef<Path>, id: usize, extension: &str) -> bool {
+    dir.as_ref()
+        .join(format!("{:05}.{}", id, extension))
+        .exists()
+}
+
+#[test]
+fn test_merged_flush() {
+    let dir = tempdir().unwrap();
+    let mut options = options(usize::MAX);
+    options.enable_wal = true;
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    storage.put(b"a", b"1").unwrap();
+    storage.put(b"b", b"1").unwrap();
+    storage.put(b"e", b"1").unwrap();
+    let snapshot = storage.new_txn().unwrap();
+    freeze(&storage);
+    storage.put(b"a", b"2").unwrap();
+    storage.put(b"c", b"2").unwrap();
+    freeze(&storage);
+    storage.delete(b"b").unwrap();
+    storage.delete_range(b"d", b"f").unwrap();
+    freeze(&storage);
+    storage.put(b"d", b"4").unwrap();
+    freeze(&storage);
+
+    // all the immutable memtables go to one SST named after the newest of them
+    let memtable_ids = imm_memtable_ids(&storage);
+    assert_eq!(memtable_ids.len(), 4);
+    storage.inner.force_flush_next_imm_memtable().unwrap();
+    {
+        let state = storage.inner.state.read();
+        assert!(state.imm_memtables.is_empty());
+        assert_eq!(state.l0_sstables, vec![memtable_ids[0]]);
+    }
+    for id in &memtable_ids {
+        assert!(!file_exists(&dir, *id, "wal"));
+    }
+
+    let check = |storage: &MiniLsm| {
+        check_lsm_iter_result_by_key(
+            &mut storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap(),
+            vec![
+                (Bytes::from_static(b"a"), Bytes::from_static(b"2")),
+                (Bytes::from_static(b"c"), Bytes::from_static(b"2")),
+                (Bytes::from_static(b"d"), Bytes::from_static(b"4")),
+            ],
+        );
+    };
+    check(&storage);
+    // the older versions are flushed too
+    check_lsm_iter_result_by_key(
+        &mut snapshot.scan(Bound::Unbounded, Bound::Unbounded).unwrap(),
+        vec![
+            (Bytes::from_static(b"a"), Bytes::from_static(b"1")),
+            (Bytes::from_static(b"b"), Bytes::from_static(b"1")),
+            (Bytes::from_static(b"e"), Bytes::from_static(b"1")),
+        ],
+    );
+    drop(snapshot);
+
+    // the manifest records the flush of all the memtables
+    storage.close().unwrap();
+    drop(storage);
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    assert!(
+        storage
+            .inner
+            .state
+            .read()
+            .l0_sstables
+            .contains(&memtable_ids[0])
+    );
+    check(&storage);
+}
+
+#[test]
+fn test_merged_flush_count_limit() {
+    let dir = tempdir().unwrap();
+    let mut options = options(2);
+    options.value_log_threshold = Some(100);
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    let value_of = |idx: usize, version: usize| format!("{:05}@{}", idx, version).repeat(50);
+    for version in 0..5 {
+        for idx in 0..20 {
+            storage
+                .put(
+                    format!("key{:05}", idx).as_bytes(),
+                    value_of(idx, version).as_bytes(),
+                )
+                .unwrap();
+        }
+        freeze(&storage);
+    }
+
+    // the oldest memtables are flushed first, at most two at a time
+    let memtable_ids = imm_memtable_ids(&storage);
+    let mut flushes = 0;
+    while !storage.inner.state.read().imm_memtables.is_empty() {
+        storage.inner.force_flush_next_imm_memtable().unwrap();
+        flushes += 1;
+    }
+    assert_eq!(flushes, 3);
+    let l0_sstables = storage.inner.state.read().l0_sstables.clone();
+    assert_eq!(
+        l0_sstables,
+        vec![memtable_ids[0], memtable_ids[1], memtable_ids[3]]
+    );
+    for id in l0_sstables {
+        assert!(file_exists(&dir, id, "vlog"));
+    }
+    for idx in 0..20 {
+        assert_eq!(
+            storage.get(format!("key{:05}", idx).as_bytes()).unwrap(),
+            Some(Bytes::from(value_of(idx, 4)))
+        );
+    }
+}
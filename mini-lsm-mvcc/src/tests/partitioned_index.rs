@@ -0,0 +1,120 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::Arc;
+
+use tempfile::tempdir;
+
+use crate::block::BlockIterator;
+use crate::iterators::StorageIterator;
+use crate::key::KeySlice;
+use crate::lsm_storage::BlockCache;
+use crate::table::{FileObject, INDEX_PARTITION_BLOCKS, SsTable, SsTableBuilder, SsTableIterator};
+
+fn key_of(idx: usize) -> Vec<u8> {
+    format!("key_{:05}", idx * 2).into_bytes()
+}
+
+fn value_of(idx: usize) -> Vec<u8> {
+    format!("value_{:05}", idx).into_bytes()
+}
+
+fn num_of_keys() -> usize {
+    3000
+}
+
+fn generate_sst(path: &std::path::Path, block_cache: Option<Arc<BlockCache>>) -> SsTable {
+    // a small block size so that the index has several partitions
+    let mut builder = SsTableBuilder::new(64);
+    for idx in 0..num_of_keys() {
+        builder.add(
+            KeySlice::for_testing_from_slice_no_ts(&key_of(idx)),
+            &value_of(idx),
+        );
+    }
+    builder.build(1, block_cache, path).unwrap()
+}
+
+#[test]
+fn test_partitioned_index_layout() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("1.sst");
+    let sst = generate_sst(&path, None);
+    assert!(sst.num_of_blocks() > INDEX_PARTITION_BLOCKS * 3);
+    assert_eq!(
+        sst.block_meta.len(),
+        sst.num_of_blocks().div_ceil(INDEX_PARTITION_BLOCKS)
+    );
+    let sst = SsTable::open(1, None, FileObject::open(&path).unwrap()).unwrap();
+    assert_eq!(sst.first_key().for_testing_key_ref(), key_of(0));
+    assert_eq!(
+        sst.last_key().for_testing_key_ref(),
+        key_of(num_of_keys() - 1)
+    );
+    let sst = Arc::new(sst);
+    let mut iter = SsTableIterator::create_and_seek_to_first(sst).unwrap();
+    for idx in 0..num_of_keys() {
+        assert!(iter.is_valid());
+        assert_eq!(iter.key().for_testing_key_ref(), key_of(idx));
+        assert_eq!(iter.value(), value_of(idx));
+        iter.next().unwrap();
+    }
+    assert!(!iter.is_valid());
+}
+
+#[test]
+fn test_partitioned_index_seek() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("1.sst");
+    let block_cache = Arc::new(BlockCache::new(1 << 10));
+    generate_sst(&path, None);
+    let sst =
+        Arc::new(SsTable::open(1, Some(block_cache), FileObject::open(&path).unwrap()).unwrap());
+    let mut iter = SsTableIterator::create_and_seek_to_first(sst.clone()).unwrap();
+    for idx in 0..num_of_keys() {
+        let key = key_of(idx);
+        iter.seek_to_key(KeySlice::for_testing_from_slice_no_ts(&key))
+            .unwrap();
+        assert_eq!(iter.key().for_testing_key_ref(), key);
+        let block_idx = sst
+            .find_block_idx(KeySlice::for_testing_from_slice_no_ts(&key))
+            .unwrap();
+        let block_iter = BlockIterator::create_and_seek_to_key(
+            sst.read_block_cached(block_idx).unwrap(),
+            KeySlice::for_testing_from_slice_no_ts(&key),
+        );
+        assert_eq!(block_iter.key().for_testing_key_ref(), key);
+        // seek to a key between two existing keys
+        let mut key = key;
+        key.push(b'0');
+        iter.seek_to_key(KeySlice::for_testing_from_slice_no_ts(&key))
+            .unwrap();
+        if idx + 1 == num_of_keys() {
+            assert!(!iter.is_valid());
+        } else {
+            assert_eq!(iter.key().for_testing_key_ref(), key_of(idx + 1));
+            assert_eq!(iter.value(), value_of(idx + 1));
+        }
+    }
+    assert_eq!(
+        sst.find_block_idx(KeySlice::for_testing_from_slice_no_ts(b"a"))
+            .unwrap(),
+        0
+    );
+    assert_eq!(
+        sst.find_block_idx(KeySlice::for_testing_from_slice_no_ts(b"z"))
+            .unwrap(),
+        sst.num_of_blocks() - 1
+    );
+}
@@ -0,0 +1,113 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::Arc;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::compact::CompactionOptions;
+use crate::iterators::StorageIterator;
+use crate::key::KeySlice;
+use crate::lsm_storage::{LsmStorageOptions, MiniLsm};
+use crate::table::{
+    DelimiterPrefixExtractor, FileObject, FixedPrefixExtractor, SsTable, SsTableBuilder,
+};
+
+use super::harness::check_lsm_iter_result_by_key;
+
+fn tenant_prefix(tenant: usize) -> String {
+    format!("tenant:{:02}/", tenant)
+}
+
+fn key_of(tenant: usize, idx: usize) -> Bytes {
+    Bytes::from(format!("{}{:03}", tenant_prefix(tenant), idx))
+}
+
+#[test]
+fn test_sst_prefix_bloom() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("1.sst");
+    let mut options = LsmStorageOptions::default_for_week1_test();
+    options.prefix_extractor = Some(Arc::new(DelimiterPrefixExtractor(b'/')));
+    let mut builder = SsTableBuilder::new_with_options(&options);
+    for tenant in (0..100).step_by(2) {
+        for idx in 0..10 {
+            builder.add(
+                KeySlice::for_testing_from_slice_no_ts(&key_of(tenant, idx)),
+                b"value",
+            );
+        }
+    }
+    builder.build_for_test(&path).unwrap();
+    let sst = SsTable::open_for_test(FileObject::open(&path).unwrap()).unwrap();
+    let extractor = DelimiterPrefixExtractor(b'/');
+    let mut false_positives = 0;
+    for tenant in 0..100 {
+        let may_contain = sst.may_contain_prefix(&extractor, tenant_prefix(tenant).as_bytes());
+        if tenant % 2 == 0 {
+            assert!(may_contain);
+        } else if may_contain {
+            false_positives += 1;
+        }
+    }
+    assert!(false_positives < 5, "too many false positives");
+    // a filter built by another extractor is never used
+    assert!(sst.may_contain_prefix(&FixedPrefixExtractor(10), b"tenant:01/"));
+}
+
+#[test]
+fn test_scan_prefix_skips_ssts() {
+    let num_tenants = 24;
+    let num_ssts = 6;
+    let scan_tenant = |with_extractor: bool, tenant: usize| {
+        let dir = tempdir().unwrap();
+        let mut options =
+            LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+        if with_extractor {
+            options.prefix_extractor = Some(Arc::new(DelimiterPrefixExtractor(b'/')));
+        }
+        let storage = MiniLsm::open(&dir, options).unwrap();
+        // each SST holds every 6th tenant, so that the key ranges of the SSTs overlap
+        for sst in 0..num_ssts {
+            for tenant in (sst..num_tenants).step_by(num_ssts) {
+                for idx in 0..10 {
+                    storage.put(&key_of(tenant, idx), b"value").unwrap();
+                }
+            }
+            storage.force_flush().unwrap();
+        }
+        let mut iter = storage
+            .scan_prefix(tenant_prefix(tenant).as_bytes())
+            .unwrap();
+        let num_active_iterators = iter.num_active_iterators();
+        check_lsm_iter_result_by_key(
+            &mut iter,
+            (0..10)
+                .map(|idx| (key_of(tenant, idx), Bytes::from("value")))
+                .collect(),
+        );
+        num_active_iterators
+    };
+    for tenant in [1, 9, 14] {
+        let with_filter = scan_tenant(true, tenant);
+        let without_filter = scan_tenant(false, tenant);
+        assert!(
+            with_filter < without_filter,
+            "{} iterators with prefix bloom, {} without",
+            with_filter,
+            without_filter
+        );
+    }
+}
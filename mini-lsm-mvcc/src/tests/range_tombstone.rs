@@ -0,0 +1,219 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::ops::Bound;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use super::harness::check_lsm_iter_result_by_key;
+use crate::compact::CompactionOptions;
+use crate::iterators::StorageIterator;
+use crate::key::KeySlice;
+use crate::lsm_storage::{LsmStorageOptions, MiniLsm};
+use crate::range_tombstone::{FragmentedRangeTombstones, RangeTombstone};
+use crate::table::SsTableIterator;
+
+fn key_of(idx: usize) -> Vec<u8> {
+    format!("key_{:03}", idx).into_bytes()
+}
+
+fn value_of(idx: usize) -> Vec<u8> {
+    format!("value_{:03}", idx).into_bytes()
+}
+
+fn tombstone(start: &str, end: &str, ts: u64) -> RangeTombstone {
+    RangeTombstone {
+        start: Bytes::copy_from_slice(start.as_bytes()),
+        end: Bytes::copy_from_slice(end.as_bytes()),
+        ts,
+    }
+}
+
+fn expected(range: impl Iterator<Item = usize>) -> Vec<(Bytes, Bytes)> {
+    range
+        .map(|idx| (Bytes::from(key_of(idx)), Bytes::from(value_of(idx))))
+        .collect()
+}
+
+fn flush_all(storage: &MiniLsm) {
+    while !storage.inner.state.read().memtable.is_empty()
+        || !storage.inner.state.read().imm_memtables.is_empty()
+    {
+        storage.force_flush().unwrap();
+    }
+}
+
+#[test]
+fn test_fragment_range_tombstones() {
+    let tombstones = FragmentedRangeTombstones::new([
+        tombstone("a", "e", 10),
+        tombstone("c", "g", 20),
+        tombstone("x", "z", 5),
+    ]);
+    assert_eq!(
+        tombstones.iter().collect::<Vec<_>>(),
+        vec![
+            tombstone("a", "c", 10),
+            tombstone("c", "e", 20),
+            tombstone("c", "e", 10),
+            tombstone("e", "g", 20),
+            tombstone("x", "z", 5),
+        ]
+    );
+    assert_eq!(tombstones.max_covering_ts(b"d", u64::MAX), Some(20));
+    assert_eq!(tombstones.max_covering_ts(b"d", 15), Some(10));
+    assert_eq!(tombstones.max_covering_ts(b"g", u64::MAX), None);
+    assert_eq!(tombstones.max_covering_ts(b"h", u64::MAX), None);
+    // a tombstone only deletes the versions written before it
+    assert!(tombstones.covers(KeySlice::from_slice(b"b", 9), u64::MAX));
+    assert!(!tombstones.covers(KeySlice::from_slice(b"b", 10), u64::MAX));
+    assert!(!tombstones.covers(KeySlice::from_slice(b"f", 1), 15));
+
+    let mut buf = Vec::new();
+    tombstones.encode(&mut buf);
+    assert_eq!(FragmentedRangeTombstones::decode(&buf).unwrap(), tombstones);
+    buf[0] ^= 1;
+    assert!(FragmentedRangeTombstones::decode(&buf).is_err());
+}
+
+#[test]
+fn test_delete_range_mvcc() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.enable_wal = true;
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    for idx in 0..100 {
+        storage.put(&key_of(idx), &value_of(idx)).unwrap();
+    }
+    let txn = storage.new_txn().unwrap();
+    storage.delete_range(&key_of(20), &key_of(50)).unwrap();
+    assert!(storage.delete_range(&key_of(50), &key_of(20)).is_err());
+    // writes after the range deletion are visible
+    storage.put(&key_of(30), &value_of(30)).unwrap();
+
+    let check = |storage: &MiniLsm| {
+        assert_eq!(storage.get(&key_of(19)).unwrap(), Some(value_of(19).into()));
+        assert_eq!(storage.get(&key_of(20)).unwrap(), None);
+        assert_eq!(storage.get(&key_of(30)).unwrap(), Some(value_of(30).into()));
+        assert_eq!(storage.get(&key_of(49)).unwrap(), None);
+        assert_eq!(storage.get(&key_of(50)).unwrap(), Some(value_of(50).into()));
+        check_lsm_iter_result_by_key(
+            &mut storage
+                .scan(Bound::Included(&key_of(10)), Bound::Excluded(&key_of(60)))
+                .unwrap(),
+            expected((10..20).chain([30]).chain(50..60)),
+        );
+    };
+    check(&storage);
+    // the snapshot taken before the range deletion still sees the keys
+    assert_eq!(txn.get(&key_of(20)).unwrap(), Some(value_of(20).into()));
+    check_lsm_iter_result_by_key(
+        &mut txn
+            .scan(Bound::Included(&key_of(10)), Bound::Excluded(&key_of(60)))
+            .unwrap(),
+        expected(10..60),
+    );
+    drop(txn);
+
+    // the range tombstones are recovered from the WAL
+    storage.close().unwrap();
+    drop(storage);
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    check(&storage);
+
+    // and read from the SSTs after a flush
+    storage.force_flush().unwrap();
+    check(&storage);
+}
+
+#[test]
+fn test_delete_range_last_write_before_crash() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.enable_wal = true;
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    for idx in 0..100 {
+        storage.put(&key_of(idx), &value_of(idx)).unwrap();
+    }
+    storage.delete_range(&key_of(20), &key_of(50)).unwrap();
+    storage.sync().unwrap();
+    // crash without closing the storage
+    drop(storage);
+
+    // the read timestamp after recovery covers the range tombstone
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    assert_eq!(storage.get(&key_of(19)).unwrap(), Some(value_of(19).into()));
+    assert_eq!(storage.get(&key_of(20)).unwrap(), None);
+    assert_eq!(storage.get(&key_of(49)).unwrap(), None);
+    check_lsm_iter_result_by_key(
+        &mut storage
+            .scan(Bound::Included(&key_of(10)), Bound::Excluded(&key_of(60)))
+            .unwrap(),
+        expected((10..20).chain(50..60)),
+    );
+}
+
+#[test]
+fn test_delete_range_compaction() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.block_size = 256;
+    options.target_sst_size = 1024;
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    for idx in 0..300 {
+        storage.put(&key_of(idx), &value_of(idx)).unwrap();
+    }
+    flush_all(&storage);
+    storage.delete_range(&key_of(100), &key_of(200)).unwrap();
+    flush_all(&storage);
+    // an SST with only range tombstones
+    let snapshot = storage.inner.state.read().clone();
+    let sst = snapshot.sstables[&snapshot.l0_sstables[0]].clone();
+    assert_eq!(sst.num_of_blocks(), 0);
+    assert!(!sst.range_tombstones().is_empty());
+    // only the SSTs with tombstones are checked for them on reads
+    let ids = snapshot
+        .sstables
+        .with_range_tombstones()
+        .map(|x| x.sst_id())
+        .collect::<Vec<_>>();
+    assert_eq!(ids, vec![sst.sst_id()]);
+    check_lsm_iter_result_by_key(
+        &mut storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap(),
+        expected((0..100).chain(200..300)),
+    );
+
+    // the full compaction drops the deleted keys and the tombstones
+    storage.force_full_compaction().unwrap();
+    let snapshot = storage.inner.state.read().clone();
+    assert!(snapshot.levels[0].1.len() > 1);
+    assert_eq!(snapshot.sstables.with_range_tombstones().count(), 0);
+    let mut num_keys = 0;
+    for sst_id in &snapshot.levels[0].1 {
+        let sst = snapshot.sstables[sst_id].clone();
+        assert!(sst.range_tombstones().is_empty());
+        let mut iter = SsTableIterator::create_and_seek_to_first(sst).unwrap();
+        while iter.is_valid() {
+            num_keys += 1;
+            iter.next().unwrap();
+        }
+    }
+    assert_eq!(num_keys, 200);
+    check_lsm_iter_result_by_key(
+        &mut storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap(),
+        expected((0..100).chain(200..300)),
+    );
+    assert_eq!(storage.get(&key_of(150)).unwrap(), None);
+}
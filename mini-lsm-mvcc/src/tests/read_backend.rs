@@ -0,0 +1,143 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::compact::CompactionOptions;
+use crate::env::{DiskFileSystem, ReadBackend};
+use crate::iterators::StorageIterator;
+use crate::key::KeySlice;
+use crate::lsm_storage::{LsmStorageOptions, MiniLsm};
+use crate::table::{FileObject, SsTable, SsTableBuilder};
+
+const BACKENDS: [ReadBackend; 3] = [ReadBackend::Pread, ReadBackend::Mmap, ReadBackend::DirectIo];
+
+fn key_of(idx: usize) -> Vec<u8> {
+    format!("key_{:05}", idx).into_bytes()
+}
+
+fn value_of(idx: usize) -> Vec<u8> {
+    format!("value_{:05}", idx).repeat(idx % 8 + 1).into_bytes()
+}
+
+#[test]
+fn test_file_object_read_backends() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("data");
+    let data = (0..10000).map(|x| (x % 251) as u8).collect::<Vec<_>>();
+    std::fs::write(&path, &data).unwrap();
+    let empty_path = dir.path().join("empty");
+    std::fs::write(&empty_path, []).unwrap();
+    for backend in BACKENDS {
+        let file = FileObject::open_with_fs(&DiskFileSystem, &path, backend).unwrap();
+        assert_eq!(file.size(), data.len() as u64);
+        for (offset, len) in [
+            (0, 0),
+            (0, 10000),
+            (1, 4095),
+            (4095, 2),
+            (4096, 4096),
+            (9990, 10),
+        ] {
+            assert_eq!(
+                file.read(offset, len).unwrap(),
+                &data[offset as usize..(offset + len) as usize],
+                "{:?} at {} with {} bytes",
+                backend,
+                offset,
+                len
+            );
+        }
+        assert!(file.read(9990, 11).is_err(), "{:?}", backend);
+        assert!(file.read(20000, 1).is_err(), "{:?}", backend);
+
+        let file = FileObject::open_with_fs(&DiskFileSystem, &empty_path, backend).unwrap();
+        assert_eq!(file.size(), 0);
+        assert!(file.read(0, 0).unwrap().is_empty());
+        assert!(file.read(0, 1).is_err());
+    }
+}
+
+#[test]
+fn test_mmap_reads_share_memory() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("1.sst");
+    let mut builder = SsTableBuilder::new(256);
+    for idx in 0..100 {
+        builder.add(
+            KeySlice::for_testing_from_slice_with_ts(&key_of(idx), 1),
+            &value_of(idx),
+        );
+    }
+    builder.build_for_test(&path).unwrap();
+    let file = FileObject::open_with_fs(&DiskFileSystem, &path, ReadBackend::Mmap).unwrap();
+    assert_eq!(
+        file.read(10, 100).unwrap().as_ptr(),
+        file.read(10, 100).unwrap().as_ptr()
+    );
+    // uncompressed blocks point into the mapping rather than a copy of it
+    let sst = SsTable::open(1, None, file).unwrap();
+    let (offset, _) = sst.block_location(1).unwrap();
+    let mapping = sst.file.read(0, sst.table_size()).unwrap();
+    let block = sst.read_block(1).unwrap();
+    assert_eq!(block.data.as_ptr(), mapping[offset as usize..].as_ptr());
+}
+
+#[test]
+fn test_read_backends_in_storage() {
+    for backend in BACKENDS {
+        let dir = tempdir().unwrap();
+        let mut options =
+            LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+        options.block_size = 256;
+        options.target_sst_size = 16 << 10;
+        options.value_log_threshold = Some(64);
+        options.read_backend = backend;
+        let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+        for idx in 0..2000 {
+            storage.put(&key_of(idx), &value_of(idx)).unwrap();
+        }
+        storage.delete(&key_of(10)).unwrap();
+        while !storage.inner.state.read().memtable.is_empty()
+            || !storage.inner.state.read().imm_memtables.is_empty()
+        {
+            storage.force_flush().unwrap();
+        }
+        storage.close().unwrap();
+        drop(storage);
+
+        let storage = MiniLsm::open(&dir, options).unwrap();
+        storage.force_full_compaction().unwrap();
+        for idx in [0, 10, 11, 1000, 1999] {
+            let expected = (idx != 10).then(|| Bytes::from(value_of(idx)));
+            assert_eq!(
+                storage.get(&key_of(idx)).unwrap(),
+                expected,
+                "{:?}",
+                backend
+            );
+        }
+        let mut iter = storage
+            .scan(std::ops::Bound::Unbounded, std::ops::Bound::Unbounded)
+            .unwrap();
+        let mut count = 0;
+        while iter.is_valid() {
+            assert_eq!(iter.value(), &value_of(count + (count >= 10) as usize)[..]);
+            count += 1;
+            iter.next().unwrap();
+        }
+        assert_eq!(count, 1999, "{:?}", backend);
+    }
+}
@@ -0,0 +1,248 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::path::Path;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::compact::{CompactionOptions, SimpleLeveledCompactionOptions, TieredCompactionOptions};
+use crate::lsm_storage::{LsmStorageOptions, MiniLsm};
+use crate::tools::wal_dump::{WalDumpOptions, wal_dump};
+
+fn key_of(idx: usize) -> Vec<u8> {
+    format!("key_{:05}", idx).into_bytes()
+}
+
+fn value_of(idx: usize, round: usize) -> Vec<u8> {
+    format!("value_{:05}_{}", idx, round)
+        .repeat(idx % 4 + 1)
+        .into_bytes()
+}
+
+fn files_with_extension(dir: &Path, extension: &str) -> Vec<std::path::PathBuf> {
+    let mut files = std::fs::read_dir(dir)
+        .unwrap()
+        .map(|x| x.unwrap().path())
+        .filter(|x| x.extension().is_some_and(|x| x == extension))
+        .collect::<Vec<_>>();
+    files.sort();
+    files
+}
+
+#[test]
+fn test_repair_lost_manifest() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::Simple(
+        SimpleLeveledCompactionOptions {
+            size_ratio_percent: 200,
+            level0_file_num_compaction_trigger: 2,
+            max_levels: 3,
+        },
+    ));
+    options.enable_wal = true;
+    options.value_log_threshold = Some(32);
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    for round in 0..5 {
+        for idx in 0..200 {
+            storage.put(&key_of(idx), &value_of(idx, round)).unwrap();
+        }
+        storage.delete(&key_of(round)).unwrap();
+        storage.force_flush().unwrap();
+        storage.inner.trigger_compaction().unwrap();
+    }
+    // left in the WALs
+    for idx in 100..150 {
+        storage.put(&key_of(idx), &value_of(idx, 5)).unwrap();
+    }
+    storage.delete(&key_of(199)).unwrap();
+    storage.close().unwrap();
+    drop(storage);
+    assert!(!files_with_extension(dir.path(), "wal").is_empty());
+    assert!(!files_with_extension(dir.path(), "vlog").is_empty());
+
+    std::fs::remove_file(dir.path().join("CURRENT")).unwrap();
+    std::fs::remove_file(dir.path().join("MANIFEST-00001")).unwrap();
+    std::fs::write(dir.path().join("99999.sst"), b"not an SST").unwrap();
+    MiniLsm::repair(&dir, options.clone()).unwrap();
+    assert!(dir.path().join("lost").join("99999.sst").exists());
+    assert!(!dir.path().join("99999.sst").exists());
+
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    for idx in 0..200 {
+        let expected = match idx {
+            4 | 199 => None,
+            100..150 => Some(value_of(idx, 5)),
+            _ => Some(value_of(idx, 4)),
+        };
+        assert_eq!(
+            storage.get(&key_of(idx)).unwrap(),
+            expected.map(Bytes::from),
+            "key {}",
+            idx
+        );
+    }
+    // the repaired storage keeps working across reopens
+    storage.put(&key_of(0), &value_of(0, 6)).unwrap();
+    storage.force_flush().unwrap();
+    storage.close().unwrap();
+    drop(storage);
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    assert_eq!(
+        storage.get(&key_of(0)).unwrap(),
+        Some(Bytes::from(value_of(0, 6)))
+    );
+    storage.close().unwrap();
+}
+
+#[test]
+fn test_repair_corrupted_manifest_and_wal() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::Tiered(
+        TieredCompactionOptions {
+            num_tiers: 3,
+            max_size_amplification_percent: 200,
+            size_ratio: 1,
+            min_merge_width: 2,
+            max_merge_width: None,
+        },
+    ));
+    options.enable_wal = true;
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    for round in 0..3 {
+        for idx in 0..100 {
+            storage.put(&key_of(idx), &value_of(idx, round)).unwrap();
+        }
+        storage.force_flush().unwrap();
+    }
+    for idx in 0..100 {
+        storage.put(&key_of(idx), &value_of(idx, 3)).unwrap();
+    }
+    storage.close().unwrap();
+    drop(storage);
+
+    // corrupt the first manifest record and the 50th batch of the WAL
+    let manifest_path = dir.path().join("MANIFEST-00001");
+    let mut manifest = std::fs::read(&manifest_path).unwrap();
+    manifest[10] ^= 0xff;
+    std::fs::write(&manifest_path, &manifest).unwrap();
+    assert!(MiniLsm::open(&dir, options.clone()).is_err());
+    let wal_path = files_with_extension(dir.path(), "wal").pop().unwrap();
+    let report = wal_dump(&wal_path, &WalDumpOptions::default()).unwrap();
+    assert_eq!(report.num_batches, 100);
+    let offset = report.batches[50].offset;
+    let mut wal = std::fs::read(&wal_path).unwrap();
+    wal[offset as usize + 6] ^= 0xff;
+    std::fs::write(&wal_path, &wal).unwrap();
+
+    MiniLsm::repair(&dir, options.clone()).unwrap();
+    let lost = dir.path().join("lost");
+    assert_eq!(
+        std::fs::read(lost.join("MANIFEST-00001")).unwrap(),
+        manifest
+    );
+    assert_eq!(
+        std::fs::read_to_string(dir.path().join("CURRENT")).unwrap(),
+        "MANIFEST-00002\n"
+    );
+    assert_eq!(
+        std::fs::read(lost.join(wal_path.file_name().unwrap())).unwrap(),
+        wal
+    );
+    assert_eq!(std::fs::read(&wal_path).unwrap(), &wal[..offset as usize]);
+
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    {
+        let state = storage.inner.state.read();
+        // each SST is a tier of its own
+        assert!(state.l0_sstables.is_empty());
+        assert!(state.levels.iter().all(|(_, ssts)| ssts.len() == 1));
+    }
+    for idx in 0..100 {
+        let round = if idx < 50 { 3 } else { 2 };
+        assert_eq!(
+            storage.get(&key_of(idx)).unwrap(),
+            Some(Bytes::from(value_of(idx, round))),
+            "key {}",
+            idx
+        );
+    }
+    storage.close().unwrap();
+    drop(storage);
+
+    // a second repair keeps what the first one moved to the lost directory
+    MiniLsm::repair(&dir, options).unwrap();
+    assert!(lost.join("MANIFEST-00001").exists() && lost.join("MANIFEST-00002").exists());
+    assert!(dir.path().join("MANIFEST-00003").exists());
+}
+
+#[test]
+fn test_repair_merged_flush() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.enable_wal = true;
+    options.num_memtable_limit = 1000;
+    options.max_memtables_per_flush = usize::MAX;
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    for round in 0..3 {
+        for idx in 0..100 {
+            storage.put(&key_of(idx), &value_of(idx, round)).unwrap();
+        }
+        storage
+            .inner
+            .force_freeze_memtable(&storage.inner.state_lock.lock())
+            .unwrap();
+    }
+    // the storage crashes after the flush but before the WALs are removed
+    let mut wals = files_with_extension(dir.path(), "wal");
+    // the WAL of the active memtable
+    wals.pop();
+    let wals = wals
+        .into_iter()
+        .map(|x| {
+            let content = std::fs::read(&x).unwrap();
+            (x, content)
+        })
+        .collect::<Vec<_>>();
+    storage.inner.force_flush_next_imm_memtable().unwrap();
+    storage.put(&key_of(0), &value_of(0, 3)).unwrap();
+    storage.close().unwrap();
+    drop(storage);
+    for (path, content) in &wals {
+        std::fs::write(path, content).unwrap();
+    }
+
+    std::fs::remove_file(dir.path().join("CURRENT")).unwrap();
+    std::fs::remove_file(dir.path().join("MANIFEST-00001")).unwrap();
+    MiniLsm::repair(&dir, options.clone()).unwrap();
+    // all the merged memtables are flushed, not only the one the SST is named after
+    let lost = dir.path().join("lost");
+    assert_eq!(wals.len(), 3);
+    for (path, _) in &wals {
+        assert!(lost.join(path.file_name().unwrap()).exists());
+        assert!(!path.exists());
+    }
+
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    for idx in 0..100 {
+        let round = if idx == 0 { 3 } else { 2 };
+        assert_eq!(
+            storage.get(&key_of(idx)).unwrap(),
+            Some(Bytes::from(value_of(idx, round))),
+            "key {}",
+            idx
+        );
+    }
+    storage.close().unwrap();
+}
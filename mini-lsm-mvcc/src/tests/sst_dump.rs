@@ -0,0 +1,130 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use tempfile::tempdir;
+
+use crate::key::KeySlice;
+use crate::range_tombstone::RangeTombstone;
+use crate::table::SsTableBuilder;
+use crate::tools::Encoding;
+use crate::tools::sst_dump::{SstDumpOptions, sst_dump};
+
+fn key_of(idx: usize) -> Vec<u8> {
+    format!("key_{:05}", idx).into_bytes()
+}
+
+fn build_sst(path: &std::path::Path) -> crate::table::SsTable {
+    let mut builder = SsTableBuilder::new(256);
+    for idx in 0..1000 {
+        builder.add(
+            KeySlice::for_testing_from_slice_with_ts(&key_of(idx), idx as u64 + 1),
+            format!("value_{:05}", idx).as_bytes(),
+        );
+    }
+    builder.add_range_tombstones([RangeTombstone {
+        start: bytes::Bytes::from_static(b"key_00100"),
+        end: bytes::Bytes::from_static(b"key_00200"),
+        ts: 2000,
+    }]);
+    builder.build_for_test(path).unwrap()
+}
+
+#[test]
+fn test_sst_dump() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("1.sst");
+    let sst = build_sst(&path);
+    let options = SstDumpOptions {
+        verify: true,
+        index: true,
+        entries: true,
+        filter: true,
+        limit: Some(10),
+        encoding: Encoding::Utf8,
+    };
+    let report = sst_dump(&path, &options).unwrap();
+    assert_eq!(report.num_blocks, sst.num_of_blocks());
+    assert!(report.num_blocks > 1);
+    assert_eq!(report.first_key.key, "key_00000");
+    assert_eq!(report.first_key.ts, 1);
+    assert_eq!(report.last_key.key, "key_00999");
+    assert_eq!(report.max_ts, 2000);
+    assert_eq!(report.range_tombstones.len(), 1);
+    assert_eq!(report.properties.as_ref().unwrap().num_entries, 1000);
+    assert!(report.verify.as_ref().unwrap().errors.is_empty());
+
+    let index = report.index.as_ref().unwrap();
+    assert_eq!(index.len(), sst.num_of_blocks());
+    assert_eq!(
+        index.iter().map(|x| x.num_entries.unwrap()).sum::<usize>(),
+        1000
+    );
+    for pair in index.windows(2) {
+        assert!(pair[0].last_key.as_ref().unwrap().key < pair[1].first_key.as_ref().unwrap().key);
+        assert_eq!(
+            pair[0].offset.unwrap() + pair[0].len.unwrap(),
+            pair[1].offset.unwrap()
+        );
+    }
+
+    let entries = report.entries.as_ref().unwrap();
+    assert_eq!(entries.len(), 10);
+    assert_eq!(entries[3].key.key, "key_00003");
+    assert_eq!(entries[3].value, "value_00003");
+
+    let filter = report.filter.as_ref().unwrap();
+    assert!(filter.k > 0);
+    assert!(filter.size > 0);
+    assert!(filter.false_positive_rate < 0.05);
+
+    let json: serde_json::Value = serde_json::to_value(&report).unwrap();
+    assert_eq!(json["num_blocks"], sst.num_of_blocks());
+    assert_eq!(json["entries"][0]["key"]["key"], "key_00000");
+    assert!(report.to_string().contains("verified"));
+
+    let options = SstDumpOptions {
+        entries: true,
+        limit: Some(1),
+        encoding: Encoding::Hex,
+        ..Default::default()
+    };
+    let report = sst_dump(&path, &options).unwrap();
+    assert!(report.verify.is_none() && report.index.is_none() && report.filter.is_none());
+    assert_eq!(report.entries.unwrap()[0].key.key, "6b65795f3030303030");
+}
+
+#[test]
+fn test_sst_dump_corrupted_block() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("1.sst");
+    let sst = build_sst(&path);
+    let (offset, _) = sst.block_location(2).unwrap();
+    drop(sst);
+    let mut data = std::fs::read(&path).unwrap();
+    data[offset as usize + 1] ^= 0xff;
+    std::fs::write(&path, data).unwrap();
+
+    let options = SstDumpOptions {
+        verify: true,
+        index: true,
+        ..Default::default()
+    };
+    let report = sst_dump(&path, &options).unwrap();
+    let errors = &report.verify.as_ref().unwrap().errors;
+    assert_eq!(errors.len(), 1);
+    assert!(errors[0].starts_with("block 2:"));
+    let index = report.index.unwrap();
+    assert!(index[2].error.is_some());
+    assert!(index[1].error.is_none() && index[3].error.is_none());
+}
@@ -0,0 +1,219 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use bytes::{Buf, BufMut, Bytes};
+use tempfile::tempdir;
+
+use crate::iterators::StorageIterator;
+use crate::key::KeySlice;
+use crate::range_tombstone::RangeTombstone;
+use crate::table::footer::{
+    FOOTER_FORMAT_VERSION, LEGACY_FORMAT_VERSION, SST_FORMAT_VERSION, SST_MAGIC, footer_size,
+};
+use crate::table::{FileObject, SsTable, SsTableBuilder, SsTableIterator};
+
+fn key_of(idx: usize) -> Vec<u8> {
+    format!("key_{:05}", idx).into_bytes()
+}
+
+fn value_of(idx: usize) -> Vec<u8> {
+    format!("value_{:05}", idx).into_bytes()
+}
+
+fn num_of_keys() -> usize {
+    1000
+}
+
+fn sst_builder() -> SsTableBuilder {
+    let mut builder = SsTableBuilder::new(128);
+    for idx in 0..num_of_keys() {
+        builder.add(
+            KeySlice::for_testing_from_slice_no_ts(&key_of(idx)),
+            &value_of(idx),
+        );
+    }
+    builder.add_range_tombstones([RangeTombstone {
+        start: Bytes::from("key_a"),
+        end: Bytes::from("key_b"),
+        ts: 1,
+    }]);
+    builder
+}
+
+fn check_sst(path: &Path) {
+    let sst = SsTable::open(1, None, FileObject::open(path).unwrap()).unwrap();
+    assert!(sst.bloom.is_some());
+    assert_eq!(sst.range_tombstones().iter().count(), 1);
+    let sst = Arc::new(sst);
+    let mut iter = SsTableIterator::create_and_seek_to_first(sst).unwrap();
+    for idx in 0..num_of_keys() {
+        assert!(iter.is_valid());
+        assert_eq!(iter.key().for_testing_key_ref(), key_of(idx));
+        assert_eq!(iter.value(), value_of(idx));
+        iter.next().unwrap();
+    }
+    assert!(!iter.is_valid());
+}
+
+#[test]
+fn test_sst_footer() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("1.sst");
+    sst_builder().build_for_test(&path).unwrap();
+    let data = std::fs::read(&path).unwrap();
+    assert_eq!((&data[data.len() - 8..]).get_u64(), SST_MAGIC);
+    let version = (&data[data.len() - 16..]).get_u32();
+    assert_eq!(version, SST_FORMAT_VERSION);
+    check_sst(&path);
+
+    // SSTs written before the table properties are still readable
+    let path = dir.path().join(format!("v{}.sst", FOOTER_FORMAT_VERSION));
+    sst_builder()
+        .build_with_version_for_test(&path, FOOTER_FORMAT_VERSION)
+        .unwrap();
+    check_sst(&path);
+}
+
+/// An SST written by the storage engine before SSTs had a footer.
+fn legacy_sst_path(id: usize) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join(format!("src/tests/fixtures/baseline_db/{:05}.sst", id))
+}
+
+#[test]
+fn test_legacy_sst_footer() {
+    let path = legacy_sst_path(2);
+    let data = std::fs::read(&path).unwrap();
+    assert_ne!((&data[data.len() - 8..]).get_u64(), SST_MAGIC);
+    let sst = SsTable::open(2, None, FileObject::open(&path).unwrap()).unwrap();
+    assert_eq!(sst.format_version(), LEGACY_FORMAT_VERSION);
+    assert_eq!(sst.num_of_blocks(), 2);
+    assert_eq!(sst.first_key().for_testing_key_ref(), key_of(0));
+    assert_eq!(sst.last_key().for_testing_key_ref(), key_of(199));
+    assert_eq!(sst.max_ts(), 200);
+    assert!(sst.properties().is_none());
+    assert!(sst.range_tombstones().is_empty());
+    let bloom = sst.bloom.as_ref().unwrap();
+    for idx in 0..200 {
+        assert!(bloom.may_contain(farmhash::fingerprint32(&key_of(idx))));
+    }
+
+    // the index lists the data blocks
+    let mut offset = 0;
+    for block_idx in 0..sst.num_of_blocks() {
+        let location = sst.block_location(block_idx).unwrap();
+        assert_eq!(location.0, offset);
+        offset += location.1;
+    }
+    assert_eq!(
+        sst.find_block_idx(KeySlice::from_slice(&key_of(0), 1))
+            .unwrap(),
+        0
+    );
+    assert_eq!(
+        sst.find_block_idx(KeySlice::from_slice(&key_of(199), 200))
+            .unwrap(),
+        1
+    );
+}
+
+#[test]
+fn test_sst_footer_corruption() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("1.sst");
+    sst_builder().build_for_test(&path).unwrap();
+    let data = std::fs::read(&path).unwrap();
+    let open = |data: &[u8]| {
+        let path = dir.path().join("corrupted.sst");
+        std::fs::write(&path, data).unwrap();
+        SsTable::open(1, None, FileObject::open(&path).unwrap())
+            .err()
+            .map(|e| e.to_string())
+    };
+
+    // truncated files
+    for len in [0, 4, 8, data.len() / 2, data.len() - 1] {
+        assert!(open(&data[..len]).is_some(), "opened SST of {} bytes", len);
+    }
+    let mut footer_only = Vec::new();
+    footer_only.put_u64(SST_MAGIC);
+    assert!(open(&footer_only).unwrap().contains("truncated"));
+
+    // a corrupted footer
+    let mut corrupted = data.clone();
+    let footer_offset = data.len() - footer_size(SST_FORMAT_VERSION) as usize;
+    corrupted[footer_offset] ^= 1;
+    assert!(open(&corrupted).unwrap().contains("checksum"));
+
+    // a footer of an unknown version
+    let mut unknown_version = data[..footer_offset].to_vec();
+    let mut footer = data[footer_offset..data.len() - 16].to_vec();
+    footer.put_u32(SST_FORMAT_VERSION + 1);
+    let checksum = crc32fast::hash(&footer);
+    unknown_version.extend(footer);
+    unknown_version.put_u32(checksum);
+    unknown_version.put_u64(SST_MAGIC);
+    assert!(open(&unknown_version).unwrap().contains("version"));
+}
+
+#[test]
+fn test_legacy_sst_blocks() {
+    let open = |id| {
+        let file = FileObject::open(&legacy_sst_path(id)).unwrap();
+        Arc::new(SsTable::open(id, None, file).unwrap())
+    };
+    let check = |sst, expected: Vec<(usize, u64, String)>| {
+        let mut iter = SsTableIterator::create_and_seek_to_first(sst).unwrap();
+        for (idx, ts, value) in expected {
+            assert!(iter.is_valid());
+            assert_eq!(iter.key().key_ref(), key_of(idx));
+            assert_eq!(iter.key().ts(), ts);
+            assert_eq!(iter.value(), value.as_bytes());
+            iter.next().unwrap();
+        }
+        assert!(!iter.is_valid());
+    };
+
+    // the first 200 puts, compacted to L1
+    check(
+        open(2),
+        (0..200)
+            .map(|idx| (idx, idx as u64 + 1, format!("value_{}_v1", idx)))
+            .collect(),
+    );
+    // every other key overwritten and every fifth key deleted afterwards
+    let mut expected = Vec::new();
+    for idx in 0..200 {
+        if idx % 5 == 0 {
+            expected.push((idx, 301 + idx as u64 / 5, String::new()));
+        }
+        if idx % 2 == 0 {
+            expected.push((idx, 201 + idx as u64 / 2, format!("value_{}_v2", idx)));
+        }
+    }
+    check(open(1), expected);
+
+    // seeks go through the re-encoded blocks too
+    let mut iter = SsTableIterator::create_and_seek_to_key(
+        open(2),
+        KeySlice::from_slice(&key_of(150), crate::key::TS_RANGE_BEGIN),
+    )
+    .unwrap();
+    assert_eq!(iter.key().key_ref(), key_of(150));
+    iter.next().unwrap();
+    assert_eq!(iter.key().key_ref(), key_of(151));
+}
@@ -0,0 +1,139 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::path::Path;
+use std::sync::Arc;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::compact::CompactionOptions;
+use crate::key::KeySlice;
+use crate::lsm_storage::{LsmStorageOptions, MiniLsm};
+use crate::range_tombstone::RangeTombstone;
+use crate::table::{CompressionType, FixedPrefixExtractor, SsTable, SsTableBuilder};
+
+fn key_of(idx: usize) -> Vec<u8> {
+    format!("key_{:05}", idx).into_bytes()
+}
+
+fn value_of(idx: usize) -> Vec<u8> {
+    format!("value_{:05}", idx).into_bytes()
+}
+
+fn num_of_keys() -> usize {
+    20000
+}
+
+fn sst_builder() -> SsTableBuilder {
+    let mut options = LsmStorageOptions::default_for_week1_test();
+    options.block_size = 256;
+    options.compression = CompressionType::Lz4;
+    options.prefix_extractor = Some(Arc::new(FixedPrefixExtractor(6)));
+    options.block_hash_index = true;
+    SsTableBuilder::new_with_options(&options)
+}
+
+fn build_sst(mut builder: SsTableBuilder, path: &Path) -> SsTable {
+    for idx in 0..num_of_keys() {
+        builder.add(
+            KeySlice::from_slice(&key_of(idx), idx as u64),
+            &value_of(idx),
+        );
+    }
+    builder.add_range_tombstones([RangeTombstone {
+        start: Bytes::from("key_1"),
+        end: Bytes::from("key_2"),
+        ts: 100,
+    }]);
+    builder.build(1, None, path).unwrap()
+}
+
+#[test]
+fn test_streaming_builder() {
+    let dir = tempdir().unwrap();
+    let memory_path = dir.path().join("1.sst");
+    let streaming_path = dir.path().join("2.sst");
+    // the creation time is in seconds, so build again if the two builds are in different seconds
+    loop {
+        let memory_sst = build_sst(sst_builder(), &memory_path);
+        let mut builder = sst_builder();
+        builder.stream_to_file(&streaming_path, Some(4096)).unwrap();
+        let streaming_sst = build_sst(builder, &streaming_path);
+        if memory_sst.properties() == streaming_sst.properties() {
+            break;
+        }
+    }
+    assert_eq!(
+        std::fs::read(&memory_path).unwrap(),
+        std::fs::read(&streaming_path).unwrap()
+    );
+
+    // the blocks are on disk before the SST is built
+    let path = dir.path().join("3.sst");
+    let mut builder = sst_builder();
+    builder.stream_to_file(&path, Some(4096)).unwrap();
+    for idx in 0..num_of_keys() {
+        builder.add(
+            KeySlice::from_slice(&key_of(idx), idx as u64),
+            &value_of(idx),
+        );
+    }
+    let written = std::fs::metadata(&path).unwrap().len() as usize;
+    assert!(written + 4096 + 8192 >= builder.estimated_size());
+    // the SST must be built at the path it is streamed to
+    assert!(builder.build(3, None, dir.path().join("4.sst")).is_err());
+
+    // an abandoned builder removes its file
+    let path = dir.path().join("5.sst");
+    let mut builder = sst_builder();
+    builder.stream_to_file(&path, None).unwrap();
+    assert!(path.exists());
+    builder.abandon().unwrap();
+    assert!(!path.exists());
+}
+
+#[test]
+fn test_streaming_builder_in_storage() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.block_size = 256;
+    options.target_sst_size = 16 << 10;
+    options.bytes_per_sync = Some(4096);
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    for idx in 0..num_of_keys() / 4 {
+        storage.put(&key_of(idx), &value_of(idx)).unwrap();
+    }
+    storage.delete_range(&key_of(0), &key_of(100)).unwrap();
+    while !storage.inner.state.read().memtable.is_empty()
+        || !storage.inner.state.read().imm_memtables.is_empty()
+    {
+        storage.force_flush().unwrap();
+    }
+    storage.force_full_compaction().unwrap();
+    let snapshot = storage.inner.state.read().clone();
+    assert!(snapshot.levels[0].1.len() > 1);
+    for idx in [0, 99, 100, 1000, num_of_keys() / 4 - 1] {
+        let expected = (idx >= 100).then(|| Bytes::from(value_of(idx)));
+        assert_eq!(storage.get(&key_of(idx)).unwrap(), expected);
+    }
+    // no files are left behind by the compaction
+    for entry in std::fs::read_dir(&dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().is_some_and(|x| x == "sst") {
+            let sst_id = path.file_stem().unwrap().to_str().unwrap().parse().unwrap();
+            assert!(snapshot.sstables.contains_key(&sst_id), "{:?}", path);
+        }
+    }
+}
@@ -0,0 +1,175 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::compact::{CompactionOptions, TieredCompactionOptions};
+use crate::key::KeySlice;
+use crate::lsm_storage::{LsmStorageOptions, MiniLsm};
+use crate::range_tombstone::RangeTombstone;
+use crate::table::footer::FOOTER_FORMAT_VERSION;
+use crate::table::{
+    CompactionReason, CompressionType, FileObject, SsTable, SsTableBuilder, TableProperties,
+};
+
+fn key_of(idx: usize) -> Vec<u8> {
+    format!("key_{:05}", idx).into_bytes()
+}
+
+fn value_of(idx: usize) -> Vec<u8> {
+    format!("value_{:05}", idx).into_bytes()
+}
+
+fn sst_builder() -> SsTableBuilder {
+    let mut builder = SsTableBuilder::new_with_compression(128, CompressionType::Lz4);
+    builder.set_compaction_reason(CompactionReason::Leveled, 3);
+    for idx in 0..100 {
+        // every tenth key is deleted
+        let value = if idx % 10 == 0 { vec![] } else { value_of(idx) };
+        builder.add(KeySlice::from_slice(&key_of(idx), 10 + idx as u64), &value);
+    }
+    builder.add_range_tombstones([
+        RangeTombstone {
+            start: Bytes::from("key_a"),
+            end: Bytes::from("key_c"),
+            ts: 5,
+        },
+        RangeTombstone {
+            start: Bytes::from("key_b"),
+            end: Bytes::from("key_d"),
+            ts: 200,
+        },
+    ]);
+    builder
+}
+
+#[test]
+fn test_sst_properties() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("1.sst");
+    let sst = sst_builder().build_for_test(&path).unwrap();
+    let expected = TableProperties {
+        num_entries: 100,
+        num_deletions: 10,
+        // the tombstones are split into three fragments, and the middle one has both timestamps
+        num_range_tombstones: 4,
+        raw_key_size: 100 * 9,
+        raw_value_size: 90 * 11,
+        min_ts: 5,
+        max_ts: 200,
+        creation_time: sst.properties().unwrap().creation_time,
+        compaction_reason: CompactionReason::Leveled,
+        level: 3,
+        compression: CompressionType::Lz4,
+        filter_policy: "bloom:10".to_string(),
+        max_memtable_id: None,
+    };
+    assert_eq!(sst.properties(), Some(&expected));
+    assert!(expected.creation_time > 0);
+    let sst = SsTable::open(1, None, FileObject::open(&path).unwrap()).unwrap();
+    assert_eq!(sst.properties(), Some(&expected));
+
+    // an SST built without filters or entries
+    let mut builder = SsTableBuilder::new(128);
+    builder.skip_filters();
+    builder.add_range_tombstones([RangeTombstone {
+        start: Bytes::from("a"),
+        end: Bytes::from("b"),
+        ts: 7,
+    }]);
+    let sst = builder.build_for_test(dir.path().join("2.sst")).unwrap();
+    let properties = sst.properties().unwrap();
+    assert_eq!(properties.num_entries, 0);
+    assert_eq!((properties.min_ts, properties.max_ts), (7, 7));
+    assert_eq!(properties.filter_policy, "");
+    assert_eq!(properties.compaction_reason, CompactionReason::Unknown);
+
+    // SSTs written before the table properties have none
+    let path = dir.path().join("3.sst");
+    sst_builder()
+        .build_with_version_for_test(&path, FOOTER_FORMAT_VERSION)
+        .unwrap();
+    let sst = SsTable::open(3, None, FileObject::open(&path).unwrap()).unwrap();
+    assert!(sst.properties().is_none());
+    assert_eq!(sst.max_ts(), 200);
+}
+
+#[test]
+fn test_properties_in_storage() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.compression = CompressionType::Snappy;
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    for idx in 0..100 {
+        storage.put(&key_of(idx), &value_of(idx)).unwrap();
+    }
+    storage.delete(&key_of(0)).unwrap();
+    storage.force_flush().unwrap();
+    let snapshot = storage.inner.state.read().clone();
+    let properties = snapshot.sstables[&snapshot.l0_sstables[0]]
+        .properties()
+        .unwrap()
+        .clone();
+    assert_eq!(properties.compaction_reason, CompactionReason::Flush);
+    assert_eq!(properties.level, 0);
+    assert_eq!(properties.num_entries, 101);
+    assert_eq!(properties.num_deletions, 1);
+    assert_eq!((properties.min_ts, properties.max_ts), (1, 101));
+    assert_eq!(properties.compression, CompressionType::Snappy);
+    assert_eq!(properties.filter_policy, "bloom:10");
+
+    storage.force_full_compaction().unwrap();
+    let snapshot = storage.inner.state.read().clone();
+    for sst_id in &snapshot.levels[0].1 {
+        let properties = snapshot.sstables[sst_id].properties().unwrap();
+        assert_eq!(
+            properties.compaction_reason,
+            CompactionReason::ForceFullCompaction
+        );
+        assert_eq!(properties.level, 1);
+    }
+}
+
+#[test]
+fn test_properties_of_tiers() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::Tiered(
+        TieredCompactionOptions {
+            num_tiers: 3,
+            max_size_amplification_percent: 200,
+            size_ratio: 1,
+            min_merge_width: 2,
+            max_merge_width: None,
+        },
+    ));
+    options.block_size = 256;
+    options.target_sst_size = 1 << 10;
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    for round in 0..4 {
+        for idx in 0..1000 {
+            storage.put(&key_of(idx), &value_of(idx + round)).unwrap();
+        }
+        storage.force_flush().unwrap();
+        storage.inner.trigger_compaction().unwrap();
+    }
+    // the level of an SST in a tier is the ID of the tier
+    let snapshot = storage.inner.state.read().clone();
+    for (tier_id, ssts) in &snapshot.levels {
+        for sst_id in ssts {
+            let properties = snapshot.sstables[sst_id].properties().unwrap();
+            assert_eq!(properties.level, *tier_id as u64);
+        }
+    }
+}
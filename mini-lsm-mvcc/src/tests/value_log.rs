@@ -0,0 +1,210 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::ops::Bound;
+use std::path::Path;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::compact::CompactionOptions;
+use crate::key::KeySlice;
+use crate::lsm_storage::{LsmStorageOptions, MiniLsm};
+use crate::table::FileObject;
+use crate::vlog::{ValueLog, ValueLogBuilder};
+
+use super::harness::check_lsm_iter_result_by_key;
+
+fn value_of(idx: usize, version: usize) -> Bytes {
+    Bytes::from(format!("{:05}@{}", idx, version).repeat(50))
+}
+
+fn key_of(idx: usize) -> Bytes {
+    Bytes::from(format!("key{:05}", idx))
+}
+
+fn options() -> LsmStorageOptions {
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.value_log_threshold = Some(100);
+    options
+}
+
+fn vlog_exists(dir: impl AsRef<Path>, id: usize) -> bool {
+    dir.as_ref().join(format!("{:05}.vlog", id)).exists()
+}
+
+#[test]
+fn test_value_log_read() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(&dir, options()).unwrap();
+    for idx in 0..100 {
+        storage.put(&key_of(idx), &value_of(idx, 0)).unwrap();
+    }
+    storage.put(b"small", b"inline").unwrap();
+    storage.force_flush().unwrap();
+    let sst_id = storage.inner.state.read().l0_sstables[0];
+    assert!(vlog_exists(&dir, sst_id));
+    // only the pointers are stored in the SST
+    let sst_size = storage.inner.state.read().sstables[&sst_id].table_size() as usize;
+    assert!(sst_size < 100 * value_of(0, 0).len() / 4);
+
+    let check = |storage: &MiniLsm| {
+        for idx in 0..100 {
+            assert_eq!(storage.get(&key_of(idx)).unwrap(), Some(value_of(idx, 0)));
+        }
+        assert_eq!(
+            storage.get(b"small").unwrap(),
+            Some(Bytes::from_static(b"inline"))
+        );
+        let mut expected = (0..100)
+            .map(|idx| (key_of(idx), value_of(idx, 0)))
+            .collect::<Vec<_>>();
+        expected.push((Bytes::from("small"), Bytes::from("inline")));
+        check_lsm_iter_result_by_key(
+            &mut storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap(),
+            expected,
+        );
+    };
+    check(&storage);
+    storage.close().unwrap();
+    drop(storage);
+
+    let storage = MiniLsm::open(&dir, options()).unwrap();
+    check(&storage);
+    storage.force_full_compaction().unwrap();
+    check(&storage);
+}
+
+#[test]
+fn test_value_log_gc() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(&dir, options()).unwrap();
+    for idx in 0..100 {
+        storage.put(&key_of(idx), &value_of(idx, 0)).unwrap();
+    }
+    storage.force_flush().unwrap();
+    let first_vlog = storage.inner.state.read().l0_sstables[0];
+    // a snapshot taken here keeps the first versions alive
+    let txn = storage.new_txn().unwrap();
+    for idx in 0..50 {
+        storage.put(&key_of(idx), &value_of(idx, 1)).unwrap();
+    }
+    storage.force_flush().unwrap();
+    storage.force_full_compaction().unwrap();
+
+    storage.force_vlog_gc().unwrap();
+    assert!(vlog_exists(&dir, first_vlog));
+    for idx in 0..50 {
+        assert_eq!(txn.get(&key_of(idx)).unwrap(), Some(value_of(idx, 0)));
+    }
+    txn.commit().unwrap();
+    drop(txn);
+
+    // the overwritten half of the first value log is dead once the snapshot is released
+    storage.force_full_compaction().unwrap();
+    storage.force_vlog_gc().unwrap();
+    // the live half is rewritten, and the file is deleted once the rewrites are flushed
+    assert!(vlog_exists(&dir, first_vlog));
+    storage.force_flush().unwrap();
+    assert!(!vlog_exists(&dir, first_vlog));
+    for idx in 0..100 {
+        let version = if idx < 50 { 1 } else { 0 };
+        assert_eq!(
+            storage.get(&key_of(idx)).unwrap(),
+            Some(value_of(idx, version))
+        );
+    }
+}
+
+#[test]
+fn test_value_log_scan() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("00001.vlog");
+    let mut builder = ValueLogBuilder::new(1);
+    // the records span several chunks of the scan
+    let ptrs = (0..1000)
+        .map(|idx| {
+            builder.add(
+                KeySlice::for_testing_from_slice_with_ts(&key_of(idx), idx as u64),
+                &value_of(idx, 0),
+            )
+        })
+        .collect::<Vec<_>>();
+    let vlog = builder.build(&path).unwrap();
+    let records = vlog.scan().collect::<anyhow::Result<Vec<_>>>().unwrap();
+    assert_eq!(records.len(), 1000);
+    for (idx, (key, value, ptr)) in records.into_iter().enumerate() {
+        assert_eq!(key.key_ref(), key_of(idx));
+        assert_eq!(key.ts(), idx as u64);
+        assert_eq!(value, value_of(idx, 0));
+        assert_eq!(ptr, ptrs[idx]);
+    }
+
+    // a truncated or corrupted record is an error rather than a panic
+    let data = std::fs::read(&path).unwrap();
+    let last = ptrs[999].offset as usize;
+    let mut corrupted = data.clone();
+    corrupted[last + 1] ^= 1;
+    let truncated = [last + 1, last + 12, data.len() - 1].map(|len| data[..len].to_vec());
+    for (idx, data) in truncated.into_iter().chain([corrupted]).enumerate() {
+        let path = dir.path().join(format!("{:05}.vlog", idx + 2));
+        std::fs::write(&path, data).unwrap();
+        let vlog = ValueLog::open(idx + 2, FileObject::open(&path).unwrap());
+        let records = vlog.scan().collect::<Vec<_>>();
+        assert_eq!(records.len(), 1000);
+        assert!(records[..999].iter().all(|x| x.is_ok()));
+        assert!(records[999].is_err());
+    }
+}
+
+#[test]
+fn test_value_log_gc_after_restart() {
+    let dir = tempdir().unwrap();
+    let mut options = options();
+    options.enable_wal = true;
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    for idx in 0..100 {
+        storage.put(&key_of(idx), &value_of(idx, 0)).unwrap();
+    }
+    storage.force_flush().unwrap();
+    let first_vlog = storage.inner.state.read().l0_sstables[0];
+    for idx in 0..50 {
+        storage.put(&key_of(idx), &value_of(idx, 1)).unwrap();
+    }
+    storage.force_flush().unwrap();
+    storage.force_full_compaction().unwrap();
+    storage.close().unwrap();
+    drop(storage);
+
+    // the dead bytes found by the compaction are recovered from the manifest
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    storage.force_vlog_gc().unwrap();
+    assert!(vlog_exists(&dir, first_vlog));
+    storage.close().unwrap();
+    drop(storage);
+
+    // so is the pending deletion, which happens once the recovered rewrites are flushed
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    while !storage.inner.state.read().imm_memtables.is_empty() {
+        storage.inner.force_flush_next_imm_memtable().unwrap();
+    }
+    assert!(!vlog_exists(&dir, first_vlog));
+    for idx in 0..100 {
+        let version = if idx < 50 { 1 } else { 0 };
+        assert_eq!(
+            storage.get(&key_of(idx)).unwrap(),
+            Some(value_of(idx, version))
+        );
+    }
+}
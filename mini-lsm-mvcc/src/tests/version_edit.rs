@@ -0,0 +1,176 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::compact::{CompactionOptions, CompactionTask, TieredCompactionOptions};
+use crate::lsm_storage::{LsmStorageOptions, MiniLsm};
+use crate::manifest::{ManifestRecord, NewFile, VersionEdit, decode_records, encode_record};
+
+fn key_of(idx: usize) -> Vec<u8> {
+    format!("key_{:05}", idx).into_bytes()
+}
+
+fn value_of(idx: usize, round: usize) -> Vec<u8> {
+    format!("value_{:05}_{}", idx, round).into_bytes()
+}
+
+fn check(storage: &MiniLsm, round: usize) {
+    for idx in 0..100 {
+        assert_eq!(
+            storage.get(&key_of(idx)).unwrap(),
+            Some(Bytes::from(value_of(idx, round))),
+            "key {}",
+            idx
+        );
+    }
+}
+
+#[test]
+fn test_version_edit_encoding() {
+    let edit = VersionEdit {
+        new_memtables: vec![9],
+        flushed_memtables: vec![4],
+        new_files: vec![NewFile {
+            level: 2,
+            id: 300,
+            first_key: b"a".to_vec(),
+            first_ts: 1,
+            last_key: b"z".repeat(200),
+            last_ts: u64::MAX,
+            size: 4096,
+            max_ts: 1 << 40,
+        }],
+        deleted_files: vec![1, 2, 3],
+        new_value_logs: vec![4],
+        deleted_value_logs: vec![],
+        value_log_discards: vec![(4, 1 << 33)],
+        rewritten_value_logs: vec![(5, 9)],
+        next_sst_id: Some(10),
+    };
+    let mut buf = Vec::new();
+    edit.encode(&mut buf);
+    assert_eq!(VersionEdit::decode(&buf).unwrap(), edit);
+    for len in [0, buf.len() - 1] {
+        assert!(VersionEdit::decode(&buf[..len]).is_err(), "len {}", len);
+    }
+
+    // tiers are recovered in order from the edits and the snapshots they roll over into
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::Tiered(
+        TieredCompactionOptions {
+            num_tiers: 3,
+            max_size_amplification_percent: 200,
+            size_ratio: 1,
+            min_merge_width: 2,
+            max_merge_width: None,
+        },
+    ));
+    options.max_manifest_size = 1 << 8;
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    for round in 0..8 {
+        for idx in 0..100 {
+            storage.put(&key_of(idx), &value_of(idx, round)).unwrap();
+        }
+        storage.force_flush().unwrap();
+        storage.inner.trigger_compaction().unwrap();
+    }
+    let state = storage.inner.state.read().clone();
+    assert!(state.levels.len() > 1);
+    storage.close().unwrap();
+    drop(storage);
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    assert_eq!(storage.inner.state.read().levels, state.levels);
+    check(&storage, 7);
+    storage.close().unwrap();
+}
+
+#[test]
+fn test_legacy_json_manifest() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    let mut flushed = Vec::new();
+    for round in 0..5 {
+        for idx in 0..100 {
+            storage.put(&key_of(idx), &value_of(idx, round)).unwrap();
+        }
+        storage.force_flush().unwrap();
+        flushed.push(storage.inner.state.read().l0_sstables[0]);
+        if round == 2 {
+            storage.force_full_compaction().unwrap();
+        }
+    }
+    let state = storage.inner.state.read().clone();
+    storage.close().unwrap();
+    drop(storage);
+
+    // rewrite the manifest as a storage engine would have before version edits
+    let mut records = Vec::new();
+    for (round, id) in flushed.iter().enumerate() {
+        records.push(ManifestRecord::NewMemtable(*id));
+        records.push(ManifestRecord::Flush(*id));
+        if round == 2 {
+            let task = CompactionTask::ForceFullCompaction {
+                l0_sstables: flushed[..3].iter().rev().copied().collect(),
+                l1_sstables: vec![],
+            };
+            records.push(ManifestRecord::Compaction(task, state.levels[0].1.clone()));
+        }
+    }
+    records.push(ManifestRecord::NewMemtable(state.memtable.id()));
+    let mut data = Vec::new();
+    for record in &records {
+        data.extend(encode_record(record).unwrap());
+    }
+    for entry in std::fs::read_dir(dir.path()).unwrap() {
+        let path = entry.unwrap().path();
+        let name = path.file_name().unwrap().to_string_lossy().to_string();
+        if name == "CURRENT" || name.starts_with("MANIFEST") {
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+    let manifest_path = dir.path().join("MANIFEST");
+    std::fs::write(&manifest_path, &data).unwrap();
+
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    {
+        let recovered = storage.inner.state.read();
+        assert_eq!(recovered.l0_sstables, state.l0_sstables);
+        assert_eq!(recovered.levels, state.levels);
+    }
+    check(&storage, 4);
+    for idx in 0..100 {
+        storage.put(&key_of(idx), &value_of(idx, 5)).unwrap();
+    }
+    storage.force_flush().unwrap();
+    storage.close().unwrap();
+    drop(storage);
+
+    // the edits are appended after the JSON records, and the binary ones are smaller
+    let (entries, corruption) = decode_records(&std::fs::read(&manifest_path).unwrap());
+    assert!(corruption.is_none());
+    assert_eq!(entries.len(), records.len() + 3);
+    let flush_edit = entries.last().unwrap();
+    assert!(matches!(&flush_edit.record, ManifestRecord::Edit(edit) if edit.new_files.len() == 1));
+    let json_compaction = entries
+        .iter()
+        .find(|x| matches!(x.record, ManifestRecord::Compaction(..)))
+        .unwrap();
+    assert!(flush_edit.len < json_compaction.len);
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    check(&storage, 5);
+    storage.close().unwrap();
+}
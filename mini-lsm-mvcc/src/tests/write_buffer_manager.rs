@@ -0,0 +1,141 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::compact::CompactionOptions;
+use crate::lsm_storage::{LsmStorageOptions, MiniLsm};
+use crate::write_buffer_manager::WriteBufferManager;
+
+fn key_of(idx: usize) -> Vec<u8> {
+    format!("key_{:05}", idx).into_bytes()
+}
+
+fn value_of(idx: usize) -> Vec<u8> {
+    format!("value_{:05}_{}", idx, "x".repeat(100)).into_bytes()
+}
+
+fn options(write_buffer_manager: &Arc<WriteBufferManager>) -> LsmStorageOptions {
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    // only the write buffer manager freezes and flushes memtables
+    options.target_sst_size = 64 << 20;
+    options.num_memtable_limit = 1000;
+    options.write_buffer_manager = Some(write_buffer_manager.clone());
+    options
+}
+
+/// Wait for the flush threads to bring the memtables within the budget.
+fn wait_until(mut condition: impl FnMut() -> bool) {
+    let start = Instant::now();
+    while !condition() {
+        assert!(start.elapsed() < Duration::from_secs(10), "timed out");
+        std::thread::sleep(Duration::from_millis(10));
+    }
+}
+
+fn memtable_memory_usage(storage: &MiniLsm) -> usize {
+    let state = storage.inner.state.read();
+    state.memtable.approximate_size()
+        + state
+            .imm_memtables
+            .iter()
+            .map(|x| x.approximate_size())
+            .sum::<usize>()
+}
+
+#[test]
+fn test_write_buffer_manager_flush() {
+    let write_buffer_manager = Arc::new(WriteBufferManager::new(256 << 10, None, false));
+    let (dir1, dir2) = (tempdir().unwrap(), tempdir().unwrap());
+    let storage1 = MiniLsm::open(&dir1, options(&write_buffer_manager)).unwrap();
+    let storage2 = MiniLsm::open(&dir2, options(&write_buffer_manager)).unwrap();
+
+    // the budget is shared, so the larger memtable is the one frozen and flushed
+    for idx in 0..100 {
+        storage2.put(&key_of(idx), &value_of(idx)).unwrap();
+    }
+    for idx in 0..5000 {
+        storage1.put(&key_of(idx), &value_of(idx)).unwrap();
+    }
+    wait_until(|| write_buffer_manager.memory_usage() < write_buffer_manager.buffer_size());
+    assert!(!storage1.inner.state.read().l0_sstables.is_empty());
+    assert!(storage2.inner.state.read().l0_sstables.is_empty());
+    assert_eq!(
+        write_buffer_manager.memory_usage(),
+        memtable_memory_usage(&storage1) + memtable_memory_usage(&storage2)
+    );
+    for idx in 0..5000 {
+        assert_eq!(
+            storage1.get(&key_of(idx)).unwrap(),
+            Some(Bytes::from(value_of(idx)))
+        );
+    }
+
+    // the memtables of closed storage engines are released
+    storage1.close().unwrap();
+    storage2.close().unwrap();
+    drop((storage1, storage2));
+    assert_eq!(write_buffer_manager.memory_usage(), 0);
+    assert_eq!(write_buffer_manager.mutable_memory_usage(), 0);
+}
+
+#[test]
+fn test_write_buffer_manager_stall_and_wal_size() {
+    let write_buffer_manager = Arc::new(WriteBufferManager::new(128 << 10, Some(64 << 10), true));
+    let dirs = [tempdir().unwrap(), tempdir().unwrap()];
+    let storages = dirs
+        .iter()
+        .map(|dir| {
+            let mut options = options(&write_buffer_manager);
+            options.enable_wal = true;
+            MiniLsm::open(dir, options).unwrap()
+        })
+        .collect::<Vec<_>>();
+
+    // stalled writers go on once the flush threads free memtables
+    let threads = storages
+        .iter()
+        .map(|storage| {
+            let (storage, write_buffer_manager) = (storage.clone(), write_buffer_manager.clone());
+            std::thread::spawn(move || {
+                for idx in 0..3000 {
+                    storage.put(&key_of(idx), &value_of(idx)).unwrap();
+                    // a writer that got through fills at most one more batch
+                    assert!(write_buffer_manager.memory_usage() < (256 << 10));
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+
+    // the WALs are kept within their budget too, and are released with their memtables
+    wait_until(|| write_buffer_manager.wal_size() <= 64 << 10);
+    for storage in &storages {
+        assert!(!storage.inner.state.read().l0_sstables.is_empty());
+        assert_eq!(
+            storage.get(&key_of(2999)).unwrap(),
+            Some(Bytes::from(value_of(2999)))
+        );
+        storage.close().unwrap();
+    }
+    drop(storages);
+    assert_eq!(write_buffer_manager.memory_usage(), 0);
+    assert_eq!(write_buffer_manager.wal_size(), 0);
+}
@@ -0,0 +1,78 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Offline tools to inspect the files of a storage engine.
+
+pub mod manifest_dump;
+pub mod sst_dump;
+pub mod wal_dump;
+
+use serde::Serialize;
+
+use crate::wal::LogCorruption;
+
+/// How keys and values are printed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum Encoding {
+    /// UTF-8 strings, with invalid UTF-8 escaped byte by byte.
+    #[default]
+    Utf8,
+    Hex,
+}
+
+impl Encoding {
+    pub fn encode(&self, data: &[u8]) -> String {
+        match self {
+            Encoding::Utf8 => match std::str::from_utf8(data) {
+                Ok(s) => s.escape_debug().to_string(),
+                Err(_) => data.escape_ascii().to_string(),
+            },
+            Encoding::Hex => data.iter().map(|x| format!("{:02x}", x)).collect(),
+        }
+    }
+}
+
+/// Where and why a WAL or the manifest cannot be decoded to its end.
+#[derive(Debug, Clone, Serialize)]
+pub struct CorruptionReport {
+    /// Index of the first record or batch that cannot be decoded.
+    pub idx: usize,
+    pub offset: u64,
+    pub reason: String,
+    /// Whether it is the torn last record left by a crash, which recovery drops. Recovery fails
+    /// on any other corruption.
+    pub torn_tail: bool,
+}
+
+impl CorruptionReport {
+    fn new(idx: usize, corruption: &LogCorruption) -> Self {
+        Self {
+            idx,
+            offset: corruption.offset(),
+            reason: corruption.to_string(),
+            torn_tail: corruption.is_torn_tail(),
+        }
+    }
+}
+
+impl std::fmt::Display for CorruptionReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "record {}: {}", self.idx, self.reason)?;
+        if self.torn_tail {
+            write!(f, " (torn tail, dropped by recovery)")
+        } else {
+            write!(f, " (recovery fails)")
+        }
+    }
+}
@@ -0,0 +1,250 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Decodes a manifest record by record and replays it.
+
+use std::fmt;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+
+use super::CorruptionReport;
+use crate::compact::{
+    CompactionOptions, CompactionTask, LeveledCompactionOptions, SimpleLeveledCompactionOptions,
+    TieredCompactionOptions,
+};
+use crate::manifest::{ManifestRecord, ManifestReplayer, decode_records};
+
+/// The compaction strategy the manifest was written with, which decides where flushed and
+/// compacted SSTs go.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum CompactionStrategy {
+    Leveled,
+    Simple,
+    Tiered,
+    None,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ManifestDumpOptions {
+    /// Replay the records to show the shape of the LSM tree after each of them.
+    pub replay: bool,
+    /// The compaction strategy, inferred from the compaction records if not given.
+    pub compaction: Option<CompactionStrategy>,
+}
+
+/// The alive memtables, SSTs and value logs.
+#[derive(Debug, Clone, Serialize)]
+pub struct LsmShape {
+    pub memtables: Vec<usize>,
+    pub l0_sstables: Vec<usize>,
+    /// Levels for leveled compaction, or tiers for tiered compaction.
+    pub levels: Vec<(usize, Vec<usize>)>,
+    pub value_logs: Vec<usize>,
+}
+
+impl LsmShape {
+    fn new(replayer: &ManifestReplayer) -> Self {
+        Self {
+            memtables: replayer.memtables.iter().copied().collect(),
+            l0_sstables: replayer.state.l0_sstables.clone(),
+            levels: replayer.state.levels.clone(),
+            value_logs: replayer.value_logs.iter().copied().collect(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct RecordReport {
+    pub idx: usize,
+    pub offset: u64,
+    pub len: u64,
+    pub record: ManifestRecord,
+    /// The shape after the record is replayed.
+    pub shape: Option<LsmShape>,
+    /// Why the record cannot be replayed.
+    pub replay_error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ManifestReport {
+    pub path: String,
+    pub file_size: u64,
+    pub compaction: Option<CompactionStrategy>,
+    pub records: Vec<RecordReport>,
+    pub corruption: Option<CorruptionReport>,
+}
+
+/// Infer the compaction strategy, and the number of levels for leveled compaction, from the
+/// compaction records, or from where the version edits put the SSTs: the first SST of a tier names
+/// it, and edits do not tell leveled from simple leveled compaction, which replay the same. A
+/// manifest with neither is replayed as if it had no compaction.
+fn compaction_options(
+    records: &[&ManifestRecord],
+    strategy: Option<CompactionStrategy>,
+) -> (CompactionStrategy, CompactionOptions) {
+    let mut max_levels = 1;
+    let mut inferred = None;
+    for record in records {
+        if let ManifestRecord::Edit(edit) = record {
+            for file in edit.new_files.iter().filter(|x| x.level > 0) {
+                if file.level == file.id {
+                    inferred.get_or_insert(CompactionStrategy::Tiered);
+                } else {
+                    max_levels = max_levels.max(file.level);
+                    // a single level replays the same as no compaction
+                    if file.level > 1 {
+                        inferred.get_or_insert(CompactionStrategy::Leveled);
+                    }
+                }
+            }
+        }
+        if let ManifestRecord::Compaction(task, _) = record {
+            match task {
+                CompactionTask::Leveled(task) => {
+                    inferred.get_or_insert(CompactionStrategy::Leveled);
+                    max_levels = max_levels.max(task.lower_level);
+                }
+                CompactionTask::Simple(task) => {
+                    inferred.get_or_insert(CompactionStrategy::Simple);
+                    max_levels = max_levels.max(task.lower_level);
+                }
+                CompactionTask::Tiered(_) => {
+                    inferred.get_or_insert(CompactionStrategy::Tiered);
+                }
+                CompactionTask::ForceFullCompaction { .. } => {}
+            }
+        }
+    }
+    let strategy = strategy.or(inferred).unwrap_or(CompactionStrategy::None);
+    // only the number of levels matters for replaying
+    let options = match strategy {
+        CompactionStrategy::Leveled => CompactionOptions::Leveled(LeveledCompactionOptions {
+            level_size_multiplier: 2,
+            level0_file_num_compaction_trigger: 2,
+            max_levels,
+            base_level_size_mb: 128,
+        }),
+        CompactionStrategy::Simple => CompactionOptions::Simple(SimpleLeveledCompactionOptions {
+            size_ratio_percent: 200,
+            level0_file_num_compaction_trigger: 2,
+            max_levels,
+        }),
+        CompactionStrategy::Tiered => CompactionOptions::Tiered(TieredCompactionOptions {
+            num_tiers: 3,
+            max_size_amplification_percent: 200,
+            size_ratio: 1,
+            min_merge_width: 2,
+            max_merge_width: None,
+        }),
+        CompactionStrategy::None => CompactionOptions::NoCompaction,
+    };
+    (strategy, options)
+}
+
+/// Decode a manifest and optionally replay it. Unlike recovery, decoding goes as far as it can
+/// and reports where and why it stopped.
+pub fn manifest_dump(path: &Path, options: &ManifestDumpOptions) -> Result<ManifestReport> {
+    let buf = std::fs::read(path).with_context(|| format!("failed to read {}", path.display()))?;
+    let (entries, corruption) = decode_records(&buf);
+    let corruption = corruption.map(|x| CorruptionReport::new(entries.len(), &x));
+
+    let mut compaction = None;
+    let mut shapes = Vec::new();
+    if options.replay {
+        let records = entries.iter().map(|x| &x.record).collect::<Vec<_>>();
+        let (strategy, compaction_options) = compaction_options(&records, options.compaction);
+        compaction = Some(strategy);
+        let mut replayer = ManifestReplayer::new(&compaction_options);
+        for record in records {
+            match replayer.apply(record) {
+                Ok(()) => shapes.push((Some(LsmShape::new(&replayer)), None)),
+                Err(e) => {
+                    // the shape is unknown after a record fails to replay
+                    shapes.push((None, Some(format!("{:#}", e))));
+                    break;
+                }
+            }
+        }
+    }
+    shapes.resize_with(entries.len(), || (None, None));
+
+    let records = entries
+        .into_iter()
+        .zip(shapes)
+        .enumerate()
+        .map(|(idx, (entry, (shape, replay_error)))| RecordReport {
+            idx,
+            offset: entry.offset,
+            len: entry.len,
+            record: entry.record,
+            shape,
+            replay_error,
+        })
+        .collect();
+    Ok(ManifestReport {
+        path: path.display().to_string(),
+        file_size: buf.len() as u64,
+        compaction,
+        records,
+        corruption,
+    })
+}
+
+impl fmt::Display for LsmShape {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "memtables={:?} L0={:?}",
+            self.memtables, self.l0_sstables
+        )?;
+        for (level, ssts) in &self.levels {
+            write!(f, " L{}={:?}", level, ssts)?;
+        }
+        if !self.value_logs.is_empty() {
+            write!(f, " value_logs={:?}", self.value_logs)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for ManifestReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "manifest: {}", self.path)?;
+        writeln!(f, "file size: {} bytes", self.file_size)?;
+        if let Some(compaction) = self.compaction {
+            writeln!(f, "compaction: {:?}", compaction)?;
+        }
+        for record in &self.records {
+            writeln!(
+                f,
+                "#{} offset={} len={}: {:?}",
+                record.idx, record.offset, record.len, record.record
+            )?;
+            if let Some(shape) = &record.shape {
+                writeln!(f, "  {}", shape)?;
+            }
+            if let Some(error) = &record.replay_error {
+                writeln!(f, "  replay failed: {}", error)?;
+            }
+        }
+        match &self.corruption {
+            Some(corruption) => writeln!(f, "corrupted at {}", corruption)?,
+            None => writeln!(f, "{} records, no corruption", self.records.len())?,
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,347 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Inspects a single SST file.
+
+use std::fmt;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+
+use super::Encoding;
+use crate::block::BlockIterator;
+use crate::key::KeySlice;
+use crate::table::{FileObject, SsTable, TableProperties};
+use crate::vlog::ValuePointer;
+
+/// Number of keys that are not in the SST probed to measure the false positive rate of the filter.
+const NUM_FILTER_PROBES: usize = 100_000;
+
+/// What to include in the report besides the summary of the SST.
+#[derive(Debug, Clone, Default)]
+pub struct SstDumpOptions {
+    /// Read every block and verify its checksum.
+    pub verify: bool,
+    /// List the data blocks with their position and key range.
+    pub index: bool,
+    /// Dump the entries of the data blocks.
+    pub entries: bool,
+    /// Report the size of the filter and measure its false positive rate.
+    pub filter: bool,
+    /// Dump at most this many entries.
+    pub limit: Option<usize>,
+    pub encoding: Encoding,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct KeyReport {
+    pub key: String,
+    pub ts: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct VerifyReport {
+    pub num_blocks_verified: usize,
+    /// The blocks that cannot be read, e.g., because their checksum mismatched.
+    pub errors: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BlockReport {
+    pub idx: usize,
+    pub offset: Option<u64>,
+    pub len: Option<u64>,
+    pub num_entries: Option<usize>,
+    pub first_key: Option<KeyReport>,
+    pub last_key: Option<KeyReport>,
+    /// Why the block cannot be read.
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct EntryReport {
+    pub key: KeyReport,
+    /// The value, or the position of the value in the value log.
+    pub value: String,
+    pub value_pointer: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RangeTombstoneReport {
+    pub start: String,
+    pub end: String,
+    pub ts: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FilterReport {
+    pub kind: String,
+    pub size: usize,
+    pub k: u8,
+    pub num_probes: usize,
+    pub false_positives: usize,
+    pub false_positive_rate: f64,
+}
+
+/// Everything `sst_dump` found out about an SST.
+#[derive(Debug, Clone, Serialize)]
+pub struct SstReport {
+    pub path: String,
+    pub file_size: u64,
+    pub format_version: u32,
+    pub num_blocks: usize,
+    pub first_key: KeyReport,
+    pub last_key: KeyReport,
+    pub max_ts: u64,
+    pub properties: Option<TableProperties>,
+    pub range_tombstones: Vec<RangeTombstoneReport>,
+    pub verify: Option<VerifyReport>,
+    pub index: Option<Vec<BlockReport>>,
+    pub entries: Option<Vec<EntryReport>>,
+    pub filter: Option<FilterReport>,
+}
+
+/// Open an SST and report on it. Opening the SST verifies the checksums of the footer, the index,
+/// the filters, the range tombstones and the properties; the data blocks are verified on request.
+pub fn sst_dump(path: &Path, options: &SstDumpOptions) -> Result<SstReport> {
+    let file =
+        FileObject::open(path).with_context(|| format!("failed to open {}", path.display()))?;
+    let file_size = file.size();
+    let sst = SsTable::open(0, None, file)
+        .with_context(|| format!("failed to open SST {}", path.display()))?;
+    let encoding = options.encoding;
+    let key_report = |key: KeySlice| KeyReport {
+        key: encoding.encode(key.key_ref()),
+        ts: key.ts(),
+    };
+
+    let mut blocks = Vec::with_capacity(sst.num_of_blocks());
+    if options.verify || options.index || options.entries {
+        for block_idx in 0..sst.num_of_blocks() {
+            let location = sst.block_location(block_idx);
+            let block = location
+                .as_ref()
+                .map_err(|e| format!("{:#}", e))
+                .and_then(|_| sst.read_block(block_idx).map_err(|e| format!("{:#}", e)));
+            blocks.push((location.ok(), block));
+        }
+    }
+
+    let verify = options.verify.then(|| VerifyReport {
+        num_blocks_verified: blocks.len(),
+        errors: blocks
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, (_, block))| {
+                block
+                    .as_ref()
+                    .err()
+                    .map(|e| format!("block {}: {}", idx, e))
+            })
+            .collect(),
+    });
+
+    let index = options.index.then(|| {
+        blocks
+            .iter()
+            .enumerate()
+            .map(|(idx, (location, block))| {
+                let mut report = BlockReport {
+                    idx,
+                    offset: location.map(|x| x.0),
+                    len: location.map(|x| x.1),
+                    num_entries: None,
+                    first_key: None,
+                    last_key: None,
+                    error: block.as_ref().err().cloned(),
+                };
+                if let Ok(block) = block {
+                    let mut iter = BlockIterator::create_and_seek_to_first(block.clone());
+                    let mut num_entries = 0;
+                    report.first_key = iter.is_valid().then(|| key_report(iter.key()));
+                    while iter.is_valid() {
+                        num_entries += 1;
+                        report.last_key = Some(key_report(iter.key()));
+                        iter.next();
+                    }
+                    report.num_entries = Some(num_entries);
+                }
+                report
+            })
+            .collect()
+    });
+
+    let entries = options.entries.then(|| {
+        let limit = options.limit.unwrap_or(usize::MAX);
+        let mut entries = Vec::new();
+        for block in blocks.iter().filter_map(|(_, block)| block.as_ref().ok()) {
+            let mut iter = BlockIterator::create_and_seek_to_first(block.clone());
+            while iter.is_valid() && entries.len() < limit {
+                let value = if iter.is_value_pointer() {
+                    match ValuePointer::decode(iter.value()) {
+                        Ok(ptr) => format!(
+                            "value log {} at {} ({} bytes)",
+                            ptr.file_id, ptr.offset, ptr.len
+                        ),
+                        Err(e) => format!("{:#}", e),
+                    }
+                } else {
+                    encoding.encode(iter.value())
+                };
+                entries.push(EntryReport {
+                    key: key_report(iter.key()),
+                    value,
+                    value_pointer: iter.is_value_pointer(),
+                });
+                iter.next();
+            }
+        }
+        entries
+    });
+
+    let filter = match sst.filter() {
+        Some(filter) if options.filter => {
+            // the probes are unlikely to be keys of the SST
+            let false_positives = (0..NUM_FILTER_PROBES)
+                .filter(|idx| {
+                    let probe = format!("\u{fffe}sst_dump_probe_{}", idx);
+                    filter.may_contain(farmhash::fingerprint32(probe.as_bytes()))
+                })
+                .count();
+            Some(FilterReport {
+                kind: format!("{:?}", filter.kind()),
+                size: filter.size(),
+                k: filter.k(),
+                num_probes: NUM_FILTER_PROBES,
+                false_positives,
+                false_positive_rate: false_positives as f64 / NUM_FILTER_PROBES as f64,
+            })
+        }
+        _ => None,
+    };
+
+    Ok(SstReport {
+        path: path.display().to_string(),
+        file_size,
+        format_version: sst.format_version(),
+        num_blocks: sst.num_of_blocks(),
+        first_key: key_report(sst.first_key().as_key_slice()),
+        last_key: key_report(sst.last_key().as_key_slice()),
+        max_ts: sst.max_ts(),
+        properties: sst.properties().cloned(),
+        range_tombstones: sst
+            .range_tombstones()
+            .iter()
+            .map(|x| RangeTombstoneReport {
+                start: encoding.encode(&x.start),
+                end: encoding.encode(&x.end),
+                ts: x.ts,
+            })
+            .collect(),
+        verify,
+        index,
+        entries,
+        filter,
+    })
+}
+
+fn fmt_key(key: &KeyReport) -> String {
+    format!("{}@{}", key.key, key.ts)
+}
+
+impl fmt::Display for SstReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "SST: {}", self.path)?;
+        writeln!(f, "file size: {} bytes", self.file_size)?;
+        writeln!(f, "format version: {}", self.format_version)?;
+        writeln!(f, "blocks: {}", self.num_blocks)?;
+        writeln!(
+            f,
+            "key range: {} .. {}",
+            fmt_key(&self.first_key),
+            fmt_key(&self.last_key)
+        )?;
+        writeln!(f, "max ts: {}", self.max_ts)?;
+        match &self.properties {
+            Some(properties) => writeln!(f, "properties: {:?}", properties)?,
+            None => writeln!(f, "properties: none")?,
+        }
+        for tombstone in &self.range_tombstones {
+            writeln!(
+                f,
+                "range tombstone: [{}, {})@{}",
+                tombstone.start, tombstone.end, tombstone.ts
+            )?;
+        }
+        if let Some(verify) = &self.verify {
+            if verify.errors.is_empty() {
+                writeln!(f, "verified {} blocks: OK", verify.num_blocks_verified)?;
+            } else {
+                writeln!(
+                    f,
+                    "verified {} blocks: {} corrupted",
+                    verify.num_blocks_verified,
+                    verify.errors.len()
+                )?;
+                for error in &verify.errors {
+                    writeln!(f, "  {}", error)?;
+                }
+            }
+        }
+        if let Some(index) = &self.index {
+            writeln!(f, "index:")?;
+            for block in index {
+                let location = match (block.offset, block.len) {
+                    (Some(offset), Some(len)) => format!("offset={} len={}", offset, len),
+                    _ => "offset=? len=?".to_string(),
+                };
+                match (&block.first_key, &block.last_key, &block.error) {
+                    (_, _, Some(error)) => {
+                        writeln!(f, "  block {} {}: {}", block.idx, location, error)?
+                    }
+                    (Some(first_key), Some(last_key), None) => writeln!(
+                        f,
+                        "  block {} {} entries={}: {} .. {}",
+                        block.idx,
+                        location,
+                        block.num_entries.unwrap_or_default(),
+                        fmt_key(first_key),
+                        fmt_key(last_key)
+                    )?,
+                    _ => writeln!(f, "  block {} {}: empty", block.idx, location)?,
+                }
+            }
+        }
+        if let Some(entries) = &self.entries {
+            writeln!(f, "entries:")?;
+            for entry in entries {
+                writeln!(f, "  {} => {}", fmt_key(&entry.key), entry.value)?;
+            }
+        }
+        if let Some(filter) = &self.filter {
+            writeln!(
+                f,
+                "filter: {} size={} bytes k={} false positive rate={:.4} ({}/{})",
+                filter.kind,
+                filter.size,
+                filter.k,
+                filter.false_positive_rate,
+                filter.false_positives,
+                filter.num_probes
+            )?;
+        }
+        Ok(())
+    }
+}
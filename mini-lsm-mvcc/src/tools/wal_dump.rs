@@ -0,0 +1,132 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Decodes a WAL batch by batch.
+
+use std::fmt;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+
+use super::{CorruptionReport, Encoding};
+use crate::wal::decode_batches;
+
+#[derive(Debug, Clone, Default)]
+pub struct WalDumpOptions {
+    /// Dump at most this many batches.
+    pub limit: Option<usize>,
+    pub encoding: Encoding,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct WalEntryReport {
+    pub key: String,
+    pub ts: u64,
+    /// The value, or the end key of a range tombstone.
+    pub value: String,
+    pub range_tombstone: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct WalBatchReport {
+    pub idx: usize,
+    pub offset: u64,
+    pub len: u64,
+    pub entries: Vec<WalEntryReport>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct WalReport {
+    pub path: String,
+    pub file_size: u64,
+    /// Number of batches decoded, including those not dumped because of the limit.
+    pub num_batches: usize,
+    pub num_entries: usize,
+    pub batches: Vec<WalBatchReport>,
+    pub corruption: Option<CorruptionReport>,
+}
+
+/// Decode a WAL. Unlike recovery, decoding goes as far as it can and reports where and why it
+/// stopped.
+pub fn wal_dump(path: &Path, options: &WalDumpOptions) -> Result<WalReport> {
+    let buf = std::fs::read(path).with_context(|| format!("failed to read {}", path.display()))?;
+    let (batches, corruption) = decode_batches(&buf);
+    let corruption = corruption.map(|x| CorruptionReport::new(batches.len(), &x));
+    let encoding = options.encoding;
+    Ok(WalReport {
+        path: path.display().to_string(),
+        file_size: buf.len() as u64,
+        num_batches: batches.len(),
+        num_entries: batches.iter().map(|x| x.entries.len()).sum(),
+        batches: batches
+            .into_iter()
+            .take(options.limit.unwrap_or(usize::MAX))
+            .enumerate()
+            .map(|(idx, batch)| WalBatchReport {
+                idx,
+                offset: batch.offset,
+                len: batch.len,
+                entries: batch
+                    .entries
+                    .iter()
+                    .map(|x| WalEntryReport {
+                        key: encoding.encode(&x.key),
+                        ts: x.ts,
+                        value: encoding.encode(&x.value),
+                        range_tombstone: x.is_range_tombstone,
+                    })
+                    .collect(),
+            })
+            .collect(),
+        corruption,
+    })
+}
+
+impl fmt::Display for WalReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "WAL: {}", self.path)?;
+        writeln!(f, "file size: {} bytes", self.file_size)?;
+        for batch in &self.batches {
+            writeln!(
+                f,
+                "#{} offset={} len={} entries={}",
+                batch.idx,
+                batch.offset,
+                batch.len,
+                batch.entries.len()
+            )?;
+            for entry in &batch.entries {
+                if entry.range_tombstone {
+                    writeln!(
+                        f,
+                        "  delete range [{}, {})@{}",
+                        entry.key, entry.value, entry.ts
+                    )?;
+                } else {
+                    writeln!(f, "  {}@{} => {}", entry.key, entry.ts, entry.value)?;
+                }
+            }
+        }
+        writeln!(
+            f,
+            "{} batches, {} entries",
+            self.num_batches, self.num_entries
+        )?;
+        if let Some(corruption) = &self.corruption {
+            writeln!(f, "corrupted at {}", corruption)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,72 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! LEB128 variable-length integers, 7 bits per byte with the high bit as the continuation flag.
+
+use bytes::{Buf, BufMut};
+
+/// Number of bytes `v` takes when encoded as a varint.
+pub(crate) fn uvarint_len(mut v: u64) -> usize {
+    let mut len = 1;
+    while v >= 0x80 {
+        v >>= 7;
+        len += 1;
+    }
+    len
+}
+
+pub(crate) fn put_uvarint(buf: &mut impl BufMut, mut v: u64) {
+    while v >= 0x80 {
+        buf.put_u8((v as u8) | 0x80);
+        v >>= 7;
+    }
+    buf.put_u8(v as u8);
+}
+
+/// Decode a varint. Like the `get_*` functions of `Buf`, panics if the buffer is too short.
+pub(crate) fn get_uvarint(buf: &mut impl Buf) -> u64 {
+    let mut v = 0;
+    let mut shift = 0;
+    loop {
+        let byte = buf.get_u8();
+        v |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return v;
+        }
+        shift += 7;
+    }
+}
+
+/// Decode a varint, or return `None` if the buffer ends in the middle of it or it is longer than
+/// any 64-bit varint.
+pub(crate) fn try_get_uvarint(buf: &mut &[u8]) -> Option<u64> {
+    let mut v = 0;
+    for (idx, byte) in buf.iter().enumerate().take(10) {
+        v |= ((byte & 0x7f) as u64) << (7 * idx);
+        if byte & 0x80 == 0 {
+            *buf = &buf[idx + 1..];
+            return Some(v);
+        }
+    }
+    None
+}
+
+/// Map a signed integer to an unsigned one so that small negative numbers also encode into few bytes.
+pub(crate) fn zigzag_encode(v: i64) -> u64 {
+    ((v << 1) ^ (v >> 63)) as u64
+}
+
+pub(crate) fn zigzag_decode(v: u64) -> i64 {
+    ((v >> 1) as i64) ^ -((v & 1) as i64)
+}
@@ -0,0 +1,513 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Key-value separation in the style of WiscKey. When a memtable is flushed, values that are at
+//! least `value_log_threshold` bytes long are appended to a value log file (which shares its id
+//! with the SST), and the SST only stores a [`ValuePointer`] to the record. Compaction then only
+//! moves the pointers around.
+
+use std::collections::HashMap;
+use std::ops::Bound;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{Context, Result, anyhow, bail};
+use bytes::{Buf, BufMut, Bytes};
+
+use crate::env::{DiskFileSystem, FileSystem, ReadBackend};
+use crate::iterators::StorageIterator;
+use crate::key::{KeySlice, KeyVec};
+use crate::lsm_storage::LsmStorageInner;
+use crate::manifest::VersionEdit;
+use crate::table::FileObject;
+use crate::varint::{put_uvarint, try_get_uvarint};
+
+/// A value log file is garbage collected once at least this fraction of it is known to be dead.
+pub const VLOG_GC_DISCARD_RATIO: f64 = 0.5;
+
+/// The number of bytes a scan of a value log reads from the file at a time.
+const VLOG_SCAN_CHUNK_SIZE: u64 = 64 << 10;
+
+/// The longest encoding of a 64-bit varint.
+const MAX_VARINT_LEN: u64 = 10;
+
+/// Points to a record in a value log file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ValuePointer {
+    pub file_id: usize,
+    pub offset: u64,
+    pub len: u32,
+}
+
+impl ValuePointer {
+    pub const ENCODED_SIZE: usize = 20;
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(Self::ENCODED_SIZE);
+        buf.put_u64(self.file_id as u64);
+        buf.put_u64(self.offset);
+        buf.put_u32(self.len);
+        buf
+    }
+
+    pub fn decode(mut buf: &[u8]) -> Result<Self> {
+        if buf.len() != Self::ENCODED_SIZE {
+            bail!("invalid value pointer of {} bytes", buf.len());
+        }
+        Ok(Self {
+            file_id: buf.get_u64() as usize,
+            offset: buf.get_u64(),
+            len: buf.get_u32(),
+        })
+    }
+}
+
+/// An immutable value log file. Each record has the following format:
+///
+/// ```text
+/// | key_len (varint) | key | ts (u64) | value_len (varint) | value | checksum (u32) |
+/// ```
+///
+/// The key is stored alongside the value so that the garbage collector can look it up in the LSM
+/// tree to decide whether the record is still live.
+pub struct ValueLog {
+    id: usize,
+    file: FileObject,
+}
+
+/// Verify the checksum of a record and split it into the key and the value.
+fn decode_record(record: &[u8]) -> Result<(KeySlice<'_>, &[u8])> {
+    if record.len() < 4 {
+        bail!("value log record too short");
+    }
+    let (mut data, mut checksum) = record.split_at(record.len() - 4);
+    if checksum.get_u32() != crc32fast::hash(data) {
+        bail!("value log record checksum mismatched");
+    }
+    let key_len = try_get_uvarint(&mut data).context("invalid value log record key length")?;
+    if (data.len() as u64) < key_len.saturating_add(8) {
+        bail!("value log record key is truncated");
+    }
+    let (key, mut data) = data.split_at(key_len as usize);
+    let ts = data.get_u64();
+    let value_len = try_get_uvarint(&mut data).context("invalid value log record value length")?;
+    if data.len() as u64 != value_len {
+        bail!("value log record length mismatched");
+    }
+    Ok((KeySlice::from_slice(key, ts), data))
+}
+
+impl ValueLog {
+    pub fn open(id: usize, file: FileObject) -> Self {
+        Self { id, file }
+    }
+
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    pub fn size(&self) -> u64 {
+        self.file.size()
+    }
+
+    /// Read the value that the pointer refers to.
+    pub fn read_value(&self, ptr: &ValuePointer) -> Result<Bytes> {
+        let record = self.file.read(ptr.offset, ptr.len as u64)?;
+        let (_, value) = decode_record(&record)?;
+        Ok(record.slice_ref(value))
+    }
+
+    /// Iterate over the records in the file, reading a chunk of the file at a time.
+    pub fn scan(&self) -> ValueLogScan<'_> {
+        ValueLogScan {
+            vlog: self,
+            buf: Bytes::new(),
+            offset: 0,
+            failed: false,
+        }
+    }
+}
+
+/// Yields the key, the value and a pointer of each record in a value log, and stops after the
+/// first error.
+pub struct ValueLogScan<'a> {
+    vlog: &'a ValueLog,
+    /// The file read ahead from `offset`.
+    buf: Bytes,
+    offset: u64,
+    failed: bool,
+}
+
+impl ValueLogScan<'_> {
+    /// Read ahead so that `buf` holds at least `len` bytes, or the rest of the file if it is
+    /// shorter.
+    fn fill_buf(&mut self, len: u64) -> Result<()> {
+        let remaining = self.vlog.size() - self.offset;
+        if self.buf.len() as u64 >= len.min(remaining) {
+            return Ok(());
+        }
+        let read_len = len.max(VLOG_SCAN_CHUNK_SIZE).min(remaining);
+        self.buf = self.vlog.file.read(self.offset, read_len)?;
+        Ok(())
+    }
+
+    fn next_record(&mut self) -> Result<(KeyVec, Bytes, ValuePointer)> {
+        let truncated = || anyhow!("value log {} is truncated", self.vlog.id);
+        self.fill_buf(MAX_VARINT_LEN)?;
+        let mut header = &self.buf[..];
+        let key_len = try_get_uvarint(&mut header).ok_or_else(truncated)?;
+        let value_len_offset = key_len
+            .saturating_add(8)
+            .saturating_add((self.buf.len() - header.len()) as u64);
+        self.fill_buf(value_len_offset.saturating_add(MAX_VARINT_LEN))?;
+        if self.buf.len() as u64 <= value_len_offset {
+            return Err(truncated());
+        }
+        let mut header = &self.buf[value_len_offset as usize..];
+        let value_len = try_get_uvarint(&mut header).ok_or_else(truncated)?;
+        let len = value_len
+            .saturating_add(4)
+            .saturating_add((self.buf.len() - header.len()) as u64);
+        self.fill_buf(len)?;
+        if (self.buf.len() as u64) < len {
+            return Err(truncated());
+        }
+        let record = self.buf.split_to(len as usize);
+        let (key, value) = decode_record(&record).with_context(|| {
+            format!(
+                "corrupted record at {} of value log {}",
+                self.offset, self.vlog.id
+            )
+        })?;
+        let ptr = ValuePointer {
+            file_id: self.vlog.id,
+            offset: self.offset,
+            len: len as u32,
+        };
+        let key = key.to_key_vec();
+        let value = record.slice_ref(value);
+        self.offset += len;
+        Ok((key, value, ptr))
+    }
+}
+
+impl Iterator for ValueLogScan<'_> {
+    type Item = Result<(KeyVec, Bytes, ValuePointer)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed || self.offset >= self.vlog.size() {
+            return None;
+        }
+        let record = self.next_record();
+        self.failed = record.is_err();
+        Some(record)
+    }
+}
+
+/// Builds a value log file.
+pub struct ValueLogBuilder {
+    id: usize,
+    data: Vec<u8>,
+}
+
+impl ValueLogBuilder {
+    pub fn new(id: usize) -> Self {
+        Self {
+            id,
+            data: Vec::new(),
+        }
+    }
+
+    /// Append a record to the value log, returning the pointer to it.
+    pub fn add(&mut self, key: KeySlice, value: &[u8]) -> ValuePointer {
+        let offset = self.data.len();
+        put_uvarint(&mut self.data, key.key_len() as u64);
+        self.data.put(key.key_ref());
+        self.data.put_u64(key.ts());
+        put_uvarint(&mut self.data, value.len() as u64);
+        self.data.put(value);
+        self.data.put_u32(crc32fast::hash(&self.data[offset..]));
+        ValuePointer {
+            file_id: self.id,
+            offset: offset as u64,
+            len: (self.data.len() - offset) as u32,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    pub fn build(self, path: impl AsRef<Path>) -> Result<ValueLog> {
+        self.build_with_fs(&DiskFileSystem, path, ReadBackend::default())
+    }
+
+    /// Write the value log to the file system and open it with the given read backend.
+    pub fn build_with_fs(
+        self,
+        fs: &dyn FileSystem,
+        path: impl AsRef<Path>,
+        read_backend: ReadBackend,
+    ) -> Result<ValueLog> {
+        let file = FileObject::create_with_fs(fs, path.as_ref(), self.data, read_backend)?;
+        Ok(ValueLog::open(self.id, file))
+    }
+}
+
+/// Tracks which value log files are worth garbage collecting. Both fields are recorded in the
+/// manifest, so that they survive a restart.
+#[derive(Default)]
+pub(crate) struct ValueLogGcState {
+    /// Number of bytes in each value log file that compaction has found to be dead.
+    discarded: HashMap<usize, u64>,
+    /// Value log files whose live records have been rewritten into the memtable with the given
+    /// id. They can be deleted once that memtable is flushed.
+    pending_deletion: Vec<(usize, usize)>,
+}
+
+impl ValueLogGcState {
+    pub(crate) fn new(
+        discarded: HashMap<usize, u64>,
+        pending_deletion: Vec<(usize, usize)>,
+    ) -> Self {
+        Self {
+            discarded,
+            pending_deletion,
+        }
+    }
+
+    /// Record the discarded bytes and the pending deletions in a snapshot of the manifest.
+    pub(crate) fn add_to_snapshot(&self, edit: &mut VersionEdit) {
+        edit.value_log_discards = self
+            .discarded
+            .iter()
+            .map(|(id, bytes)| (*id, *bytes))
+            .collect();
+        edit.value_log_discards.sort();
+        edit.rewritten_value_logs.clone_from(&self.pending_deletion);
+    }
+}
+
+/// Record that compaction dropped an entry whose value is in the value log.
+pub(crate) fn discard_value_pointer(
+    discarded: &mut HashMap<usize, u64>,
+    pointer: &[u8],
+) -> Result<()> {
+    let ptr = ValuePointer::decode(pointer)?;
+    *discarded.entry(ptr.file_id).or_default() += ptr.len as u64;
+    Ok(())
+}
+
+impl LsmStorageInner {
+    /// Count the dead bytes found by a compaction, once its version edit is recorded.
+    pub(crate) fn add_value_log_discards(&self, discarded: &[(usize, u64)]) {
+        let value_logs = self.value_logs.read();
+        let mut gc = self.vlog_gc.lock();
+        for (id, bytes) in discarded {
+            // the value log may have been collected while the compaction ran
+            if value_logs.contains_key(id) {
+                *gc.discarded.entry(*id).or_default() += bytes;
+            }
+        }
+    }
+
+    /// Check whether the record is still visible to some reader, i.e., the LSM tree still points
+    /// to it and there is no newer version of the key below the watermark.
+    fn is_value_pointer_live(
+        &self,
+        key: KeySlice,
+        ptr: &ValuePointer,
+        watermark: u64,
+    ) -> Result<bool> {
+        let snapshot = {
+            let guard = self.state.read();
+            Arc::clone(&guard)
+        };
+        // a range tombstone visible to all readers deletes the version
+        let range_tombstones = Self::collect_range_tombstones(
+            &snapshot,
+            Bound::Included(key.key_ref()),
+            Bound::Included(key.key_ref()),
+        );
+        if range_tombstones.covers(key, watermark) {
+            return Ok(false);
+        }
+        let mut iter = Self::key_versions_iter(&snapshot, key.key_ref())?;
+        while iter.is_valid() && iter.key().key_ref() == key.key_ref() {
+            if iter.key().ts() == key.ts() {
+                return Ok(iter.is_value_pointer() && ValuePointer::decode(iter.value())? == *ptr);
+            }
+            if iter.key().ts() <= watermark {
+                // a newer version is visible to all readers
+                return Ok(false);
+            }
+            iter.next()?;
+        }
+        Ok(false)
+    }
+
+    /// Rewrite the live records of a value log file into the memtable, with their original
+    /// timestamps, so that the file can be deleted.
+    fn gc_value_log(&self, id: usize) -> Result<()> {
+        let Some(vlog) = self.value_logs.read().get(&id).cloned() else {
+            return Ok(());
+        };
+        let watermark = self.mvcc().watermark();
+        let mut rewritten = 0;
+        for record in vlog.scan() {
+            let (key, value, ptr) = record?;
+            if !self.is_value_pointer_live(key.as_key_slice(), &ptr, watermark)? {
+                continue;
+            }
+            let size = {
+                let guard = self.state.read();
+                guard.memtable.put(key.as_key_slice(), &value)?;
+                guard.memtable.approximate_size()
+            };
+            self.try_freeze(size)?;
+            rewritten += 1;
+        }
+        // the file is deleted once nothing uses it
+        drop(vlog);
+
+        let state_lock = self.state_lock.lock();
+        if rewritten == 0 {
+            self.delete_value_logs(&state_lock, &[id])?;
+        } else {
+            let memtable_id = self.state.read().memtable.id();
+            // the rewrites are durable in the WAL once the memtable is frozen
+            self.force_freeze_memtable(&state_lock)?;
+            self.manifest().add_record(
+                &state_lock,
+                VersionEdit {
+                    rewritten_value_logs: vec![(id, memtable_id)],
+                    ..Default::default()
+                },
+            )?;
+            self.vlog_gc.lock().pending_deletion.push((id, memtable_id));
+        }
+        println!(
+            "value log gc: {}.vlog has {} live records rewritten",
+            id, rewritten
+        );
+        Ok(())
+    }
+
+    /// Garbage collect the value log files that are mostly dead.
+    pub(crate) fn trigger_vlog_gc(&self) -> Result<()> {
+        let _gc_lock = self.vlog_gc_lock.lock();
+        let candidates = {
+            let value_logs = self.value_logs.read().clone();
+            let gc = self.vlog_gc.lock();
+            value_logs
+                .values()
+                .filter(|vlog| {
+                    let pending = gc.pending_deletion.iter().any(|(id, _)| *id == vlog.id());
+                    let discarded = gc.discarded.get(&vlog.id()).copied().unwrap_or_default();
+                    !pending && discarded as f64 >= VLOG_GC_DISCARD_RATIO * vlog.size() as f64
+                })
+                .map(|vlog| vlog.id())
+                .collect::<Vec<_>>()
+        };
+        for id in candidates {
+            self.gc_value_log(id)?;
+        }
+        Ok(())
+    }
+
+    /// Delete the value log files that were waiting for the memtable with `flushed_id` to be
+    /// flushed.
+    pub(crate) fn delete_flushed_value_logs(
+        &self,
+        state_lock: &parking_lot::MutexGuard<'_, ()>,
+        flushed_id: usize,
+    ) -> Result<()> {
+        let ids = {
+            let mut gc = self.vlog_gc.lock();
+            let (ready, pending) = gc
+                .pending_deletion
+                .iter()
+                .partition::<Vec<_>, _>(|(_, memtable_id)| *memtable_id <= flushed_id);
+            gc.pending_deletion = pending;
+            ready.into_iter().map(|(id, _)| id).collect::<Vec<_>>()
+        };
+        if ids.is_empty() {
+            return Ok(());
+        }
+        self.delete_value_logs(state_lock, &ids)
+    }
+
+    pub(crate) fn delete_value_logs(
+        &self,
+        state_lock: &parking_lot::MutexGuard<'_, ()>,
+        ids: &[usize],
+    ) -> Result<()> {
+        let mut removed = Vec::with_capacity(ids.len());
+        {
+            let _guard = self.state.write();
+            let mut value_logs = self.value_logs.write();
+            for id in ids {
+                removed.extend(Arc::make_mut(&mut value_logs).remove(id));
+            }
+        }
+        self.manifest().add_record(
+            state_lock,
+            VersionEdit {
+                deleted_value_logs: ids.to_vec(),
+                ..Default::default()
+            },
+        )?;
+        for id in ids {
+            self.vlog_gc.lock().discarded.remove(id);
+        }
+        // readers holding an older snapshot keep using the files until they are done
+        self.add_obsolete_value_logs(removed);
+        self.delete_obsolete_files()
+    }
+
+    pub(crate) fn spawn_vlog_gc_thread(
+        self: &Arc<Self>,
+        rx: crossbeam_channel::Receiver<()>,
+    ) -> Result<Option<std::thread::JoinHandle<()>>> {
+        if self.options.value_log_threshold.is_none() {
+            return Ok(None);
+        }
+        let this = self.clone();
+        let handle = std::thread::spawn(move || {
+            let ticker = crossbeam_channel::tick(Duration::from_secs(1));
+            loop {
+                crossbeam_channel::select! {
+                    recv(ticker) -> _ => if let Err(e) = this.trigger_vlog_gc() {
+                        eprintln!("value log gc failed: {}", e);
+                    },
+                    recv(rx) -> _ => return
+                }
+            }
+        });
+        Ok(Some(handle))
+    }
+}
+
+/// Look up the value that a pointer read from the LSM tree refers to.
+pub(crate) fn resolve_value_pointer(
+    value_logs: &HashMap<usize, Arc<ValueLog>>,
+    pointer: &[u8],
+) -> Result<Bytes> {
+    let ptr = ValuePointer::decode(pointer)?;
+    value_logs
+        .get(&ptr.file_id)
+        .ok_or_else(|| anyhow!("value log {} not found", ptr.file_id))?
+        .read_value(&ptr)
+}
@@ -12,106 +12,144 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::fs::{File, OpenOptions};
 use std::hash::Hasher;
-use std::io::{BufWriter, Read, Write};
 use std::path::Path;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
 
 use anyhow::{Context, Result, bail};
 use bytes::{Buf, BufMut, Bytes};
-use crossbeam_skiplist::SkipMap;
 use parking_lot::Mutex;
 
-use crate::key::{KeyBytes, KeySlice};
+use crate::env::{FileSystem, WritableFile};
+use crate::key::KeySlice;
+use crate::mem_table::MemTableRep;
+use crate::range_tombstone::RangeTombstone;
+use crate::varint::{put_uvarint, try_get_uvarint};
+
+/// The magic number at the start of every WAL with a header, "\xffWAL" in ASCII. WALs written
+/// before the header existed start with the `u32` size of their first batch, which would have to
+/// be almost 4 GiB to be mistaken for the magic number.
+pub(crate) const WAL_MAGIC: u32 = 0xff57_414c;
+
+/// Version of the WALs written by this build, which have varint lengths and range tombstones.
+/// WALs without a header have `u16` lengths and no range tombstones.
+pub(crate) const WAL_FORMAT_VERSION: u32 = 1;
+
+/// Size of the header of a WAL: the magic number and the version.
+const WAL_HEADER_SIZE: usize = 8;
 
 pub struct Wal {
-    file: Arc<Mutex<BufWriter<File>>>,
+    file: Arc<Mutex<Box<dyn WritableFile>>>,
+    /// The bytes in the file, only updated while holding its lock.
+    size: AtomicU64,
+    /// Whether the WAL was written before the header existed, in which case nothing can be
+    /// appended to it.
+    legacy: bool,
 }
 
 impl Wal {
-    pub fn create(path: impl AsRef<Path>) -> Result<Self> {
+    /// Create a WAL. A WAL left behind by a crash before the manifest recorded its memtable holds
+    /// no acknowledged writes, so it is overwritten.
+    pub fn create(fs: &dyn FileSystem, path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let mut file = fs.create(path).context("failed to create WAL")?;
+        let mut header = Vec::with_capacity(WAL_HEADER_SIZE);
+        header.put_u32(WAL_MAGIC);
+        header.put_u32(WAL_FORMAT_VERSION);
+        file.append(&header)?;
         Ok(Self {
-            file: Arc::new(Mutex::new(BufWriter::new(
-                OpenOptions::new()
-                    .read(true)
-                    .create_new(true)
-                    .write(true)
-                    .open(path)
-                    .context("failed to create WAL")?,
-            ))),
+            file: Arc::new(Mutex::new(file)),
+            size: AtomicU64::new(WAL_HEADER_SIZE as u64),
+            legacy: false,
         })
     }
 
-    pub fn recover(path: impl AsRef<Path>, skiplist: &SkipMap<KeyBytes, Bytes>) -> Result<Self> {
+    /// Recover the key-value pairs into `rep` and the range tombstones into `range_tombstones`.
+    /// The last batch is dropped if it is incomplete or torn, which is left by a crash while it
+    /// was written and therefore not acknowledged.
+    pub fn recover(
+        fs: &dyn FileSystem,
+        path: impl AsRef<Path>,
+        rep: &dyn MemTableRep,
+        range_tombstones: &mut Vec<RangeTombstone>,
+    ) -> Result<Self> {
         let path = path.as_ref();
-        let mut file = OpenOptions::new()
-            .read(true)
-            .append(true)
-            .open(path)
-            .context("failed to recover from WAL")?;
-        let mut buf = Vec::new();
-        file.read_to_end(&mut buf)?;
-        let mut rbuf: &[u8] = buf.as_slice();
-        while rbuf.has_remaining() {
-            let batch_size = rbuf.get_u32() as usize;
-            if rbuf.remaining() < batch_size {
-                bail!("incomplete WAL");
-            }
-            let mut batch_buf = &rbuf[..batch_size];
-            let mut kv_pairs = Vec::new();
-            let mut hasher = crc32fast::Hasher::new();
-            // The checksum computed from the individual components should be the same as a direct checksum on the buffer.
-            // Students' implementation only needs to do a single checksum on the buffer. We compute both for verification purpose.
-            let single_checksum = crc32fast::hash(batch_buf);
-            while batch_buf.has_remaining() {
-                let key_len = batch_buf.get_u16() as usize;
-                hasher.write(&(key_len as u16).to_be_bytes());
-                let key = Bytes::copy_from_slice(&batch_buf[..key_len]);
-                hasher.write(&key);
-                batch_buf.advance(key_len);
-                let ts = batch_buf.get_u64();
-                hasher.write(&ts.to_be_bytes());
-                let value_len = batch_buf.get_u16() as usize;
-                hasher.write(&(value_len as u16).to_be_bytes());
-                let value = Bytes::copy_from_slice(&batch_buf[..value_len]);
-                hasher.write(&value);
-                kv_pairs.push((key, ts, value));
-                batch_buf.advance(value_len);
-            }
-            rbuf.advance(batch_size);
-            let expected_checksum = rbuf.get_u32();
-            let component_checksum = hasher.finalize();
-            assert_eq!(component_checksum, single_checksum);
-            if single_checksum != expected_checksum {
-                bail!("checksum mismatch");
-            }
-            for (key, ts, value) in kv_pairs {
-                skiplist.insert(KeyBytes::from_bytes_with_ts(key, ts), value);
+        let buf = fs.read(path).context("failed to recover from WAL")?;
+        let mut file = fs.open_append(path).context("failed to recover from WAL")?;
+        // after a crash of the process, the batches may not be durable yet
+        file.sync()?;
+        let (batches, corruption) = decode_batches(&buf);
+        if let Some(corruption) = corruption
+            && !corruption.is_torn_tail()
+        {
+            bail!(
+                "failed to recover from WAL {}: {} in batch {}",
+                path.display(),
+                corruption,
+                batches.len()
+            );
+        }
+        for batch in batches {
+            for entry in batch.entries {
+                if entry.is_range_tombstone {
+                    range_tombstones.push(RangeTombstone {
+                        start: entry.key,
+                        end: entry.value,
+                        ts: entry.ts,
+                    });
+                } else {
+                    rep.insert(KeySlice::from_slice(&entry.key, entry.ts), &entry.value);
+                }
             }
         }
         Ok(Self {
-            file: Arc::new(Mutex::new(BufWriter::new(file))),
+            file: Arc::new(Mutex::new(file)),
+            size: AtomicU64::new(buf.len() as u64),
+            legacy: is_legacy(&buf),
         })
     }
 
     /// Implement this in week 3, day 5.
     pub fn put_batch(&self, data: &[(KeySlice, &[u8])]) -> Result<()> {
+        self.write_batch(data, &[])
+    }
+
+    /// Log key-value pairs and range tombstones as one batch.
+    pub fn write_batch(
+        &self,
+        data: &[(KeySlice, &[u8])],
+        range_tombstones: &[RangeTombstone],
+    ) -> Result<()> {
+        if self.legacy {
+            bail!("cannot append to a WAL written before WALs had a header");
+        }
         let mut file = self.file.lock();
         let mut buf = Vec::<u8>::new();
         for (key, value) in data {
-            buf.put_u16(key.key_len() as u16);
+            put_uvarint(&mut buf, key.key_len() as u64);
             buf.put_slice(key.key_ref());
             buf.put_u64(key.ts());
-            buf.put_u16(value.len() as u16);
+            put_uvarint(&mut buf, (value.len() as u64) << 1);
             buf.put_slice(value);
         }
+        for tombstone in range_tombstones {
+            put_uvarint(&mut buf, tombstone.start.len() as u64);
+            buf.put_slice(&tombstone.start);
+            buf.put_u64(tombstone.ts);
+            put_uvarint(&mut buf, ((tombstone.end.len() as u64) << 1) | 1);
+            buf.put_slice(&tombstone.end);
+        }
+        if buf.len() > u32::MAX as usize {
+            bail!("WAL batch too large: {} bytes", buf.len());
+        }
         // write batch_size header (u32)
-        file.write_all(&(buf.len() as u32).to_be_bytes())?;
+        file.append(&(buf.len() as u32).to_be_bytes())?;
         // write key-value pairs body
-        file.write_all(&buf)?;
+        file.append(&buf)?;
         // write checksum (u32)
-        file.write_all(&crc32fast::hash(&buf).to_be_bytes())?;
+        file.append(&crc32fast::hash(&buf).to_be_bytes())?;
+        self.size.fetch_add(buf.len() as u64 + 8, Ordering::Relaxed);
         Ok(())
     }
 
@@ -120,9 +158,233 @@ impl Wal {
     }
 
     pub fn sync(&self) -> Result<()> {
-        let mut file = self.file.lock();
-        file.flush()?;
-        file.get_mut().sync_all()?;
-        Ok(())
+        self.file.lock().sync()
+    }
+
+    /// The size of the WAL in bytes.
+    pub fn size(&self) -> u64 {
+        self.size.load(Ordering::Relaxed)
+    }
+}
+
+/// Why decoding a WAL or the manifest stopped before the end of the file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LogCorruption {
+    /// The file ends in the middle of the record at `offset`.
+    Truncated { offset: u64 },
+    /// The checksum of the record at `offset` mismatched. `last` is whether the record ends the
+    /// file.
+    ChecksumMismatch {
+        offset: u64,
+        expected: u32,
+        actual: u32,
+        last: bool,
+    },
+    /// The record at `offset` passed its checksum but cannot be parsed.
+    Malformed { offset: u64, reason: String },
+}
+
+impl LogCorruption {
+    pub fn offset(&self) -> u64 {
+        match self {
+            LogCorruption::Truncated { offset }
+            | LogCorruption::ChecksumMismatch { offset, .. }
+            | LogCorruption::Malformed { offset, .. } => *offset,
+        }
+    }
+
+    /// Whether the corruption is what a crash while appending the last record leaves behind,
+    /// in which case the record was never acknowledged and can be dropped.
+    pub fn is_torn_tail(&self) -> bool {
+        matches!(
+            self,
+            LogCorruption::Truncated { .. } | LogCorruption::ChecksumMismatch { last: true, .. }
+        )
+    }
+}
+
+impl std::fmt::Display for LogCorruption {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LogCorruption::Truncated { offset } => {
+                write!(f, "truncated record at offset {}", offset)
+            }
+            LogCorruption::ChecksumMismatch {
+                offset,
+                expected,
+                actual,
+                ..
+            } => write!(
+                f,
+                "checksum mismatch at offset {}: expected {:#010x}, got {:#010x}",
+                offset, expected, actual
+            ),
+            LogCorruption::Malformed { offset, reason } => {
+                write!(f, "malformed record at offset {}: {}", offset, reason)
+            }
+        }
+    }
+}
+
+/// A key-value pair or a range tombstone logged in a WAL batch.
+#[derive(Debug, Clone)]
+pub struct WalEntry {
+    pub key: Bytes,
+    pub ts: u64,
+    /// The value, or the end key of a range tombstone.
+    pub value: Bytes,
+    pub is_range_tombstone: bool,
+}
+
+/// A batch decoded from a WAL, which starts at `offset` and spans `len` bytes with its header and
+/// checksum.
+#[derive(Debug, Clone)]
+pub struct WalBatch {
+    pub offset: u64,
+    pub len: u64,
+    pub entries: Vec<WalEntry>,
+}
+
+/// Whether a WAL was written before WALs had a header.
+fn is_legacy(buf: &[u8]) -> bool {
+    buf.len() >= 4 && buf[..4] != WAL_MAGIC.to_be_bytes()
+}
+
+/// Decode the batches of a WAL, stopping at the first one that is truncated or corrupted. The
+/// offsets are from the start of the file, including the header.
+pub fn decode_batches(buf: &[u8]) -> (Vec<WalBatch>, Option<LogCorruption>) {
+    let legacy = is_legacy(buf);
+    let mut rbuf: &[u8] = buf;
+    if !legacy && !buf.is_empty() {
+        if buf.len() < WAL_HEADER_SIZE {
+            return (Vec::new(), Some(LogCorruption::Truncated { offset: 0 }));
+        }
+        let version = (&buf[4..]).get_u32();
+        if version != WAL_FORMAT_VERSION {
+            let reason = format!("unsupported WAL format version {}", version);
+            return (
+                Vec::new(),
+                Some(LogCorruption::Malformed { offset: 0, reason }),
+            );
+        }
+        rbuf.advance(WAL_HEADER_SIZE);
+    }
+    let mut batches = Vec::new();
+    while rbuf.has_remaining() {
+        let offset = (buf.len() - rbuf.remaining()) as u64;
+        if rbuf.remaining() < 4 {
+            return (batches, Some(LogCorruption::Truncated { offset }));
+        }
+        let batch_size = (&rbuf[..4]).get_u32() as usize;
+        if rbuf.remaining() - 4 < batch_size.saturating_add(4) {
+            return (batches, Some(LogCorruption::Truncated { offset }));
+        }
+        let last = rbuf.remaining() == 4 + batch_size + 4;
+        rbuf.advance(4);
+        let expected_checksum = (&rbuf[batch_size..]).get_u32();
+        let single_checksum = crc32fast::hash(&rbuf[..batch_size]);
+        if single_checksum != expected_checksum {
+            let corruption = LogCorruption::ChecksumMismatch {
+                offset,
+                expected: expected_checksum,
+                actual: single_checksum,
+                last,
+            };
+            return (batches, Some(corruption));
+        }
+        let entries = if legacy {
+            decode_legacy_batch(&rbuf[..batch_size])
+        } else {
+            decode_batch(&rbuf[..batch_size])
+        };
+        let entries = match entries {
+            Ok(entries) => entries,
+            Err(e) => {
+                let reason = e.to_string();
+                return (batches, Some(LogCorruption::Malformed { offset, reason }));
+            }
+        };
+        rbuf.advance(batch_size + 4);
+        batches.push(WalBatch {
+            offset,
+            len: 4 + batch_size as u64 + 4,
+            entries,
+        });
+    }
+    (batches, None)
+}
+
+fn decode_batch(mut batch_buf: &[u8]) -> Result<Vec<WalEntry>> {
+    let mut entries = Vec::new();
+    let mut hasher = crc32fast::Hasher::new();
+    // The checksum computed from the individual components should be the same as a direct checksum on the buffer.
+    // Students' implementation only needs to do a single checksum on the buffer. We compute both for verification purpose.
+    let single_checksum = crc32fast::hash(batch_buf);
+    while batch_buf.has_remaining() {
+        let len_buf = batch_buf;
+        let key_len = try_get_uvarint(&mut batch_buf).context("invalid key length")? as usize;
+        hasher.write(&len_buf[..len_buf.len() - batch_buf.len()]);
+        if batch_buf.remaining() < key_len.saturating_add(8) {
+            bail!("key of {} bytes overflows the batch", key_len);
+        }
+        let key = Bytes::copy_from_slice(&batch_buf[..key_len]);
+        hasher.write(&key);
+        batch_buf.advance(key_len);
+        let ts = batch_buf.get_u64();
+        hasher.write(&ts.to_be_bytes());
+        if !batch_buf.has_remaining() {
+            bail!("entry ends before its value");
+        }
+        let len_buf = batch_buf;
+        let value_len = try_get_uvarint(&mut batch_buf).context("invalid value length")?;
+        hasher.write(&len_buf[..len_buf.len() - batch_buf.len()]);
+        // the lowest bit of the length marks a range tombstone, whose value is the end key
+        let is_range_tombstone = value_len & 1 == 1;
+        let value_len = (value_len >> 1) as usize;
+        if batch_buf.remaining() < value_len {
+            bail!("value of {} bytes overflows the batch", value_len);
+        }
+        let value = Bytes::copy_from_slice(&batch_buf[..value_len]);
+        hasher.write(&value);
+        entries.push(WalEntry {
+            key,
+            ts,
+            value,
+            is_range_tombstone,
+        });
+        batch_buf.advance(value_len);
+    }
+    assert_eq!(hasher.finalize(), single_checksum);
+    Ok(entries)
+}
+
+/// Decode a batch of a WAL without a header, whose entries are
+/// `| key len u16 | key | ts u64 | value len u16 | value |`.
+fn decode_legacy_batch(mut batch_buf: &[u8]) -> Result<Vec<WalEntry>> {
+    let mut entries = Vec::new();
+    while batch_buf.has_remaining() {
+        if batch_buf.remaining() < 2 {
+            bail!("entry ends before its key");
+        }
+        let key_len = batch_buf.get_u16() as usize;
+        if batch_buf.remaining() < key_len + 8 + 2 {
+            bail!("key of {} bytes overflows the batch", key_len);
+        }
+        let key = Bytes::copy_from_slice(&batch_buf[..key_len]);
+        batch_buf.advance(key_len);
+        let ts = batch_buf.get_u64();
+        let value_len = batch_buf.get_u16() as usize;
+        if batch_buf.remaining() < value_len {
+            bail!("value of {} bytes overflows the batch", value_len);
+        }
+        let value = Bytes::copy_from_slice(&batch_buf[..value_len]);
+        batch_buf.advance(value_len);
+        entries.push(WalEntry {
+            key,
+            ts,
+            value,
+            is_range_tombstone: false,
+        });
     }
+    Ok(entries)
 }
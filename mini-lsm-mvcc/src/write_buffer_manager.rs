@@ -0,0 +1,247 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A memory budget for the memtables of several storage engines in one process.
+
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+
+use anyhow::Result;
+use parking_lot::{Condvar, Mutex};
+
+use crate::lsm_storage::LsmStorageInner;
+
+/// How long a stalled writer waits for memtables to be freed before it checks whether another
+/// memtable should be frozen.
+const STALL_RETRY_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Limits the memory of the memtables and immutable memtables of all the storage engines it is
+/// shared with, and the size of their WALs.
+///
+/// Once the mutable memtables take most of the budget, or the memtables exceed it and the mutable
+/// ones take half of it, the largest mutable memtable is frozen. The same goes for the WALs and
+/// `max_total_wal_size`. The flush threads of all the storage engines flush their immutable
+/// memtables while the budget is exceeded. With `allow_stall`, writers wait while the memtables
+/// exceed the budget.
+pub struct WriteBufferManager {
+    buffer_size: usize,
+    max_total_wal_size: Option<usize>,
+    allow_stall: bool,
+    memory_usage: AtomicUsize,
+    mutable_memory_usage: AtomicUsize,
+    wal_size: AtomicUsize,
+    mutable_wal_size: AtomicUsize,
+    storages: Mutex<Vec<Weak<LsmStorageInner>>>,
+    /// Held while freezing a memtable, so that concurrent writers do not freeze one each.
+    freeze_lock: Mutex<()>,
+    stall_lock: Mutex<()>,
+    stall_cv: Condvar,
+}
+
+impl std::fmt::Debug for WriteBufferManager {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("WriteBufferManager")
+            .field("buffer_size", &self.buffer_size)
+            .field("max_total_wal_size", &self.max_total_wal_size)
+            .field("allow_stall", &self.allow_stall)
+            .field("memory_usage", &self.memory_usage())
+            .field("wal_size", &self.wal_size())
+            .finish()
+    }
+}
+
+impl WriteBufferManager {
+    pub fn new(buffer_size: usize, max_total_wal_size: Option<usize>, allow_stall: bool) -> Self {
+        Self {
+            buffer_size,
+            max_total_wal_size,
+            allow_stall,
+            memory_usage: AtomicUsize::new(0),
+            mutable_memory_usage: AtomicUsize::new(0),
+            wal_size: AtomicUsize::new(0),
+            mutable_wal_size: AtomicUsize::new(0),
+            storages: Mutex::new(Vec::new()),
+            freeze_lock: Mutex::new(()),
+            stall_lock: Mutex::new(()),
+            stall_cv: Condvar::new(),
+        }
+    }
+
+    /// The bytes taken by the memtables and immutable memtables.
+    pub fn memory_usage(&self) -> usize {
+        self.memory_usage.load(Ordering::Relaxed)
+    }
+
+    /// The bytes taken by the memtables that are still written to.
+    pub fn mutable_memory_usage(&self) -> usize {
+        self.mutable_memory_usage.load(Ordering::Relaxed)
+    }
+
+    /// The bytes of the WALs of the memtables and immutable memtables.
+    pub fn wal_size(&self) -> usize {
+        self.wal_size.load(Ordering::Relaxed)
+    }
+
+    pub fn buffer_size(&self) -> usize {
+        self.buffer_size
+    }
+
+    pub(crate) fn register(&self, storage: &Arc<LsmStorageInner>) {
+        let mut storages = self.storages.lock();
+        storages.retain(|x| x.strong_count() > 0);
+        storages.push(Arc::downgrade(storage));
+    }
+
+    /// Whether a mutable memtable should be frozen so that it can be flushed.
+    fn should_freeze(&self) -> bool {
+        let mutable = self.mutable_memory_usage();
+        if mutable > self.buffer_size / 8 * 7
+            || (self.memory_usage() >= self.buffer_size && mutable >= self.buffer_size / 2)
+        {
+            return true;
+        }
+        self.max_total_wal_size.is_some_and(|limit| {
+            self.wal_size() > limit && self.mutable_wal_size.load(Ordering::Relaxed) >= limit / 2
+        })
+    }
+
+    /// Whether the immutable memtables should be flushed regardless of their number.
+    pub(crate) fn is_over_budget(&self) -> bool {
+        self.memory_usage() >= self.buffer_size
+            || self
+                .max_total_wal_size
+                .is_some_and(|limit| self.wal_size() > limit)
+    }
+
+    /// Freeze the largest mutable memtable of the storage engines if they take too much memory.
+    pub(crate) fn maybe_freeze(&self) -> Result<()> {
+        if !self.should_freeze() {
+            return Ok(());
+        }
+        // another writer is freezing one
+        let Some(_freeze_lock) = self.freeze_lock.try_lock() else {
+            return Ok(());
+        };
+        if !self.should_freeze() {
+            return Ok(());
+        }
+        let storages = self
+            .storages
+            .lock()
+            .iter()
+            .filter_map(Weak::upgrade)
+            .collect::<Vec<_>>();
+        let largest = storages
+            .iter()
+            .max_by_key(|x| x.state.read().memtable.approximate_size());
+        if let Some(storage) = largest {
+            let state_lock = storage.state_lock.lock();
+            if !storage.state.read().memtable.is_empty() {
+                storage.force_freeze_memtable(&state_lock)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Wait while the memtables exceed the budget if stalls are allowed.
+    pub(crate) fn maybe_stall(&self) -> Result<()> {
+        if !self.allow_stall {
+            return Ok(());
+        }
+        while self.memory_usage() >= self.buffer_size {
+            self.maybe_freeze()?;
+            let mut guard = self.stall_lock.lock();
+            if self.memory_usage() >= self.buffer_size {
+                self.stall_cv.wait_for(&mut guard, STALL_RETRY_INTERVAL);
+            }
+        }
+        Ok(())
+    }
+
+    fn release(&self, memory: usize, wal_size: usize) {
+        self.memory_usage.fetch_sub(memory, Ordering::Relaxed);
+        self.wal_size.fetch_sub(wal_size, Ordering::Relaxed);
+        let _guard = self.stall_lock.lock();
+        self.stall_cv.notify_all();
+    }
+}
+
+/// The memory and WAL bytes of a memtable charged to a [`WriteBufferManager`], which are released
+/// when the memtable is dropped.
+pub(crate) struct WriteBufferCharge {
+    manager: Arc<WriteBufferManager>,
+    memory: AtomicUsize,
+    wal_size: AtomicUsize,
+    immutable: AtomicBool,
+}
+
+impl WriteBufferCharge {
+    pub fn new(manager: Arc<WriteBufferManager>) -> Self {
+        Self {
+            manager,
+            memory: AtomicUsize::new(0),
+            wal_size: AtomicUsize::new(0),
+            immutable: AtomicBool::new(false),
+        }
+    }
+
+    /// Charge the growth of the memtable since the last update. Concurrent writers may report
+    /// their sizes out of order, so only the largest ones are charged.
+    pub fn update(&self, memory: usize, wal_size: usize) {
+        let manager = &self.manager;
+        let charged = self.memory.fetch_max(memory, Ordering::Relaxed);
+        if memory > charged {
+            manager
+                .memory_usage
+                .fetch_add(memory - charged, Ordering::Relaxed);
+            manager
+                .mutable_memory_usage
+                .fetch_add(memory - charged, Ordering::Relaxed);
+        }
+        let charged = self.wal_size.fetch_max(wal_size, Ordering::Relaxed);
+        if wal_size > charged {
+            manager
+                .wal_size
+                .fetch_add(wal_size - charged, Ordering::Relaxed);
+            manager
+                .mutable_wal_size
+                .fetch_add(wal_size - charged, Ordering::Relaxed);
+        }
+    }
+
+    /// Move the charge to the immutable memtables once the memtable is frozen.
+    pub fn mark_immutable(&self) {
+        if self.immutable.swap(true, Ordering::Relaxed) {
+            return;
+        }
+        let manager = &self.manager;
+        manager
+            .mutable_memory_usage
+            .fetch_sub(self.memory.load(Ordering::Relaxed), Ordering::Relaxed);
+        manager
+            .mutable_wal_size
+            .fetch_sub(self.wal_size.load(Ordering::Relaxed), Ordering::Relaxed);
+    }
+}
+
+impl Drop for WriteBufferCharge {
+    fn drop(&mut self) {
+        self.mark_immutable();
+        self.manager.release(
+            self.memory.load(Ordering::Relaxed),
+            self.wal_size.load(Ordering::Relaxed),
+        );
+    }
+}
@@ -326,6 +326,7 @@ impl ReplBuilder {
     }
 }
 
+#[allow(clippy::needless_update)]
 fn main() -> Result<()> {
     let args = Args::parse();
     let lsm = MiniLsm::open(
@@ -361,6 +362,8 @@ fn main() -> Result<()> {
             },
             enable_wal: args.enable_wal,
             serializable: args.serializable,
+            // the reference solutions may have extra options
+            ..LsmStorageOptions::default_for_week1_test()
         },
     )?;
 
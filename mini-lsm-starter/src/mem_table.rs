@@ -159,7 +159,7 @@ impl StorageIterator for MemTableIterator {
         unimplemented!()
     }
 
-    fn key(&self) -> KeySlice {
+    fn key(&self) -> KeySlice<'_> {
         unimplemented!()
     }
 